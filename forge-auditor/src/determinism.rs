@@ -0,0 +1,288 @@
+//! Automated determinism audit.
+//!
+//! [`DeterminismAudit`] executes a block repeatedly through a
+//! [`BlockRunner`], alternating fresh boots with restores of one snapshot by
+//! default, and turns the runner's [`DeterminismReport`] into findings:
+//!
+//! ```text
+//! D001  runs disagree                                  error
+//! D002  fresh boots and restores disagree, each        error
+//!       agreeing among themselves
+//! D003  every run agrees, but on a non-zero exit code  warning
+//! D004  the backend cannot snapshot, so only fresh     info
+//!       boots were audited
+//! ```
+//!
+//! The agreement score is the fraction of runs that produced the first
+//! run's output, as in [`DeterminismReport::agreement`]; a deterministic
+//! block scores 1.0.
+
+use std::fmt::Write as _;
+
+use forge_core::block::Block;
+use forge_executor::{
+    BlockRunner, BootKind, BootMode, DeterminismReport, Divergence, ExecutorError, VmmBackend,
+};
+
+use crate::report::{AuditReport, Rule};
+use crate::AuditError;
+
+/// Runs a [`DeterminismAudit`] makes unless told otherwise: three fresh
+/// boots and three restores.
+pub const DEFAULT_RUNS: usize = 6;
+
+/// Fewest runs an audit makes; one run cannot disagree with anything.
+pub const MIN_RUNS: usize = 2;
+
+/// Settings for auditing a block's determinism.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeterminismAudit {
+    runs: usize,
+    mode: BootMode,
+}
+
+/// What a [`DeterminismAudit`] found.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DeterminismOutcome {
+    /// Findings of the `D` rules.
+    pub report: AuditReport,
+    /// Fraction of runs that agree with the first, in `[0.0, 1.0]`.
+    pub agreement: f64,
+    /// The runner's report, with every run's record.
+    pub determinism: DeterminismReport,
+}
+
+impl DeterminismAudit {
+    /// An audit of [`DEFAULT_RUNS`] runs alternating between fresh boots
+    /// and snapshot restores.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { runs: DEFAULT_RUNS, mode: BootMode::Alternating }
+    }
+
+    /// Make `runs` runs, at least [`MIN_RUNS`].
+    #[must_use]
+    pub const fn with_runs(mut self, runs: usize) -> Self {
+        self.runs = if runs < MIN_RUNS { MIN_RUNS } else { runs };
+        self
+    }
+
+    /// Start each run's VM as `mode` dictates.
+    #[must_use]
+    pub const fn with_boot_mode(mut self, mode: BootMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Execute `block` with `input` through `runner` and grade the outputs.
+    ///
+    /// If the backend cannot snapshot, the runs are repeated on fresh boots
+    /// and [`Rule::RestoresNotAudited`] is reported.
+    ///
+    /// # Errors
+    /// Returns [`AuditError::Executor`] if a run fails to execute; a block
+    /// whose runs merely disagree is a finding, not an error.
+    pub async fn run<B: VmmBackend>(
+        &self,
+        runner: &BlockRunner<B>,
+        block: &Block,
+        input: &[u8],
+    ) -> Result<DeterminismOutcome, AuditError> {
+        let manifest = &block.manifest;
+        let mut report = AuditReport::new(block.id, manifest.name.clone(), manifest.version);
+        let determinism =
+            match runner.verify_determinism_with(block, input, self.runs, self.mode).await {
+                Err(ExecutorError::Unsupported(reason)) if self.mode != BootMode::Fresh => {
+                    report.push(
+                        Rule::RestoresNotAudited,
+                        format!("only fresh boots were audited: {reason}"),
+                    );
+                    runner.verify_determinism_with(block, input, self.runs, BootMode::Fresh).await?
+                }
+                result => result?,
+            };
+
+        let agreement = determinism.agreement();
+        if determinism.is_deterministic() {
+            if let Some(reference) = determinism.reference().filter(|r| r.exit_code != 0) {
+                report.push(
+                    Rule::FailingRuns,
+                    format!(
+                        "all {} runs agree, but exit with code {}",
+                        determinism.runs.len(),
+                        reference.exit_code
+                    ),
+                );
+            }
+        } else {
+            let (rule, mut message) = if splits_by_boot(&determinism) {
+                (
+                    Rule::BootDependent,
+                    "fresh boots and snapshot restores produce different outputs".to_owned(),
+                )
+            } else {
+                (
+                    Rule::Nondeterministic,
+                    format!(
+                        "{} runs produced {} distinct outputs",
+                        determinism.runs.len(),
+                        determinism.outputs.len()
+                    ),
+                )
+            };
+            let _ = write!(message, "; agreement {agreement:.2}");
+            for divergence in determinism.diagnose() {
+                let _ = write!(message, "; {}", describe(&divergence));
+            }
+            report.push(rule, message);
+        }
+
+        tracing::info!(
+            block = %manifest.name,
+            agreement,
+            distinct_outputs = determinism.outputs.len(),
+            passed = report.passed(),
+            "determinism audit complete"
+        );
+        Ok(DeterminismOutcome { report, agreement, determinism })
+    }
+}
+
+impl Default for DeterminismAudit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether the outputs differ exactly along the line between fresh boots
+/// and restores: both were used and no output came from both.
+fn splits_by_boot(report: &DeterminismReport) -> bool {
+    let kinds = |runs: &[usize]| -> Vec<BootKind> {
+        let mut kinds: Vec<BootKind> = Vec::new();
+        for &run in runs {
+            let boot = report.runs[run].boot;
+            if !kinds.contains(&boot) {
+                kinds.push(boot);
+            }
+        }
+        kinds
+    };
+    let all: Vec<usize> = (0..report.runs.len()).collect();
+    kinds(&all).len() > 1 && report.outputs.iter().all(|output| kinds(&output.runs).len() == 1)
+}
+
+/// Summarise how a divergent output differs from the reference.
+fn describe(divergence: &Divergence) -> String {
+    let mut text = format!("runs {:?} diverge", divergence.runs);
+    for (stream, diff) in [("stdout", &divergence.stdout), ("stderr", &divergence.stderr)] {
+        if let Some(diff) = diff {
+            let _ = write!(text, " in {stream} from byte {}", diff.first_difference);
+        }
+    }
+    if let Some((reference, divergent)) = divergence.exit_codes {
+        let _ = write!(text, " with exit code {divergent} instead of {reference}");
+    }
+    for hint in &divergence.hints {
+        let _ = write!(text, ", suspected {:?} in {:?}", hint.source, hint.stream);
+        if let Some(line) = hint.line {
+            let _ = write!(text, " line {line}");
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use forge_core::examples::example_blocks;
+    use forge_executor::mock::{MockBackend, MockOperation, MockResponse};
+    use forge_executor::VmConfig;
+
+    use super::*;
+
+    /// What the runner executes for `block`: its name, echoed.
+    fn command(block: &Block) -> String {
+        format!("echo '{}'", block.manifest.name)
+    }
+
+    fn runner(backend: MockBackend) -> BlockRunner<MockBackend> {
+        BlockRunner::new(backend, VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r")))
+    }
+
+    async fn audit(runner: &BlockRunner<MockBackend>, block: &Block) -> DeterminismOutcome {
+        DeterminismAudit::new()
+            .run(runner, block, b"")
+            .await
+            .unwrap_or_else(|e| panic!("audit failed: {e}"))
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn deterministic_blocks_pass_across_boots_and_restores() {
+        let block = &example_blocks()[0];
+        let runner = runner(MockBackend::new().with_fallback(MockResponse::success("2.43.0\n")));
+        let outcome = audit(&runner, block).await;
+        assert!(outcome.report.findings.is_empty(), "{:?}", outcome.report.findings);
+        assert!((outcome.agreement - 1.0).abs() < f64::EPSILON);
+        assert_eq!(outcome.determinism.runs.len(), DEFAULT_RUNS);
+        assert_eq!(runner_restores(&runner).await, DEFAULT_RUNS / 2);
+    }
+
+    async fn runner_restores(runner: &BlockRunner<MockBackend>) -> usize {
+        runner.backend().call_count(MockOperation::Restore).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn divergent_runs_are_an_error_with_their_agreement() {
+        let block = &example_blocks()[0];
+        let backend = MockBackend::new()
+            .on_command(command(block), MockResponse::success("built at 1700000001\n"))
+            .on_command(command(block), MockResponse::success("built at 1700000001\n"))
+            .on_command(command(block), MockResponse::success("built at 1700000001\n"))
+            .on_command(command(block), MockResponse::success("built at 1700000002\n"));
+        let outcome = audit(&runner(backend), block).await;
+        assert!((outcome.agreement - 0.5).abs() < f64::EPSILON, "{}", outcome.agreement);
+        assert!(!outcome.report.passed());
+        assert_eq!(outcome.report.findings.len(), 1);
+        let finding = &outcome.report.findings[0];
+        assert_eq!(finding.rule, Rule::Nondeterministic, "{finding}");
+        assert!(finding.message.contains("agreement 0.50"), "{finding}");
+        assert!(finding.message.contains("runs [3, 4, 5] diverge"), "{finding}");
+        assert!(finding.message.contains("Timestamp"), "{finding}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn outputs_split_by_boot_kind_are_blamed_on_restores() {
+        let block = &example_blocks()[0];
+        let mut backend = MockBackend::new();
+        for index in 0..DEFAULT_RUNS {
+            let stdout = if index % 2 == 0 { "fresh\n" } else { "restored\n" };
+            backend = backend.on_command(command(block), MockResponse::success(stdout));
+        }
+        let outcome = audit(&runner(backend), block).await;
+        assert_eq!(outcome.report.findings.len(), 1);
+        assert_eq!(outcome.report.findings[0].rule, Rule::BootDependent);
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn backends_without_snapshots_are_audited_on_fresh_boots() {
+        let block = &example_blocks()[0];
+        let backend = MockBackend::new()
+            .with_fallback(MockResponse::exit(1))
+            .fail_operation(MockOperation::Snapshot, || {
+                ExecutorError::Unsupported("snapshots".to_owned())
+            });
+        let runner = runner(backend);
+        let outcome = audit(&runner, block).await;
+        let rules: Vec<Rule> = outcome.report.findings.iter().map(|f| f.rule).collect();
+        assert_eq!(rules, [Rule::RestoresNotAudited, Rule::FailingRuns]);
+        assert!(outcome.report.passed());
+        assert!(outcome.determinism.runs.iter().all(|run| run.boot == BootKind::Fresh));
+        assert_eq!(runner_restores(&runner).await, 0);
+    }
+}
@@ -0,0 +1,19 @@
+//! Error types for the auditor crate.
+
+/// Errors that can occur while auditing blocks.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum AuditError {
+    /// A dependency's version requirement cannot be parsed.
+    #[error("invalid version requirement {requirement:?}: {reason}")]
+    InvalidVersionReq {
+        /// The rejected requirement.
+        requirement: String,
+        /// Why it was rejected.
+        reason: String,
+    },
+
+    /// An audited execution failed to run.
+    #[error("executor error: {0}")]
+    Executor(#[from] forge_executor::ExecutorError),
+}
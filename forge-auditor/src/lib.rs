@@ -2,3 +2,29 @@
 //!
 //! Validates execution records, computes trust scores, and enforces
 //! the trust level policy for block composition.
+//!
+//! [`audit_manifest`] runs the static checks on a block before it is
+//! published: required fields, name charset, version and derivation
+//! consistency, dependency satisfiability against the registry, trust level
+//! sanity and suspicious descriptions. Each check is a [`Rule`] with a
+//! stable identifier, and the result is an [`AuditReport`] of [`Finding`]s
+//! graded by [`Severity`].
+//!
+//! [`DeterminismAudit`] executes a block repeatedly through a
+//! `BlockRunner`, across fresh boots and snapshot restores, and reports an
+//! agreement score and a finding when the output hashes diverge.
+//! [`audit_for_publish`] runs both.
+
+pub mod determinism;
+pub mod error;
+pub mod manifest;
+pub mod publish;
+pub mod report;
+pub mod requirement;
+
+pub use determinism::{DeterminismAudit, DeterminismOutcome, DEFAULT_RUNS, MIN_RUNS};
+pub use error::AuditError;
+pub use manifest::{audit_manifest, MAX_DESCRIPTION_LEN, MAX_NAME_LEN};
+pub use publish::audit_for_publish;
+pub use report::{AuditReport, Finding, Rule, Severity};
+pub use requirement::VersionReq;
@@ -0,0 +1,75 @@
+//! The audit a block must pass before it is published.
+
+use forge_core::block::Block;
+use forge_executor::{BlockRunner, VmmBackend};
+
+use crate::determinism::DeterminismAudit;
+use crate::manifest::audit_manifest;
+use crate::report::AuditReport;
+use crate::AuditError;
+
+/// Audit `block` for publication into `registry`: its manifest, then, if
+/// the manifest passes, its determinism on `input` with the default
+/// [`DeterminismAudit`].
+///
+/// A block whose manifest fails is never executed; its report holds only
+/// the manifest findings.
+///
+/// # Errors
+/// See [`DeterminismAudit::run`].
+pub async fn audit_for_publish<B: VmmBackend>(
+    runner: &BlockRunner<B>,
+    block: &Block,
+    registry: &[Block],
+    input: &[u8],
+) -> Result<AuditReport, AuditError> {
+    let mut report = audit_manifest(block, registry);
+    if report.passed() {
+        let outcome = DeterminismAudit::new().run(runner, block, input).await?;
+        report.merge(outcome.report);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use forge_core::examples::example_blocks;
+    use forge_executor::mock::{MockBackend, MockOperation, MockResponse};
+    use forge_executor::VmConfig;
+
+    use super::*;
+    use crate::Rule;
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn only_blocks_with_valid_manifests_are_executed() {
+        let registry = example_blocks();
+        let backend = MockBackend::new()
+            .on_command("echo 'git-env'", MockResponse::success("git version 2.43.0\n"))
+            .on_command("echo 'git-env'", MockResponse::success("git version 2.43.1\n"));
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let runner = BlockRunner::new(backend, config);
+
+        let report = audit_for_publish(&runner, &registry[0], &registry, b"")
+            .await
+            .unwrap_or_else(|e| panic!("audit failed: {e}"));
+        assert!(report.has(Rule::Nondeterministic), "{:?}", report.findings);
+        assert!(!report.passed());
+
+        let mut malformed = registry[0].clone();
+        malformed.manifest.name = "git env".to_owned();
+        let executions = runner.backend().call_count(MockOperation::ExecuteCommand).await;
+        let report = audit_for_publish(&runner, &malformed, &registry, b"")
+            .await
+            .unwrap_or_else(|e| panic!("audit failed: {e}"));
+        assert_eq!(report.findings.len(), 1, "{:?}", report.findings);
+        assert!(report.has(Rule::InvalidName));
+        assert_eq!(
+            runner.backend().call_count(MockOperation::ExecuteCommand).await,
+            executions,
+            "a malformed block must not be executed"
+        );
+    }
+}
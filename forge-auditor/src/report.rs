@@ -0,0 +1,327 @@
+//! Audit findings and reports.
+//!
+//! Every check the auditor runs is a [`Rule`] with a stable identifier, so
+//! findings can be suppressed, tracked and compared across releases: `M`
+//! rules check the manifest, `D` rules the block's executions. A rule
+//! always reports at the same [`Severity`]; a block passes its audit if no
+//! finding is an [`Severity::Error`].
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use forge_core::id::BlockId;
+use forge_core::trust::SemVer;
+
+/// How serious a finding is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Severity {
+    /// Worth knowing; no action required.
+    Info,
+    /// Probably a mistake; the block may still be published.
+    Warning,
+    /// The block must not be published until this is fixed.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// A check the auditor runs.
+///
+/// Rules serialise as their identifier. Identifiers are never reused: a
+/// retired rule keeps its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Rule {
+    /// `M001`: the manifest has no name.
+    #[serde(rename = "M001")]
+    MissingName,
+    /// `M002`: the name is not lowercase ASCII letters, digits and single
+    /// inner hyphens of at most [`MAX_NAME_LEN`](crate::MAX_NAME_LEN)
+    /// characters. Names are interpolated into guest commands, so this is a
+    /// security boundary, not a style rule.
+    #[serde(rename = "M002")]
+    InvalidName,
+    /// `M003`: the manifest has no description.
+    #[serde(rename = "M003")]
+    MissingDescription,
+    /// `M004`: a block that is not a composition provides no capabilities,
+    /// so nothing can depend on it.
+    #[serde(rename = "M004")]
+    NoCapabilities,
+    /// `M005`: a capability's name is invalid or provided twice.
+    #[serde(rename = "M005")]
+    InvalidCapability,
+    /// `M006`: a dependency's name is invalid.
+    #[serde(rename = "M006")]
+    InvalidDependency,
+    /// `M007`: a dependency's version requirement does not parse.
+    #[serde(rename = "M007")]
+    InvalidVersionReq,
+    /// `M008`: no block in the registry provides a dependency. It may be a
+    /// system tool.
+    #[serde(rename = "M008")]
+    UnresolvedDependency,
+    /// `M009`: blocks provide a dependency, but none in a version the
+    /// requirement accepts.
+    #[serde(rename = "M009")]
+    UnsatisfiedDependency,
+    /// `M010`: the block requires a capability it provides itself.
+    #[serde(rename = "M010")]
+    SelfDependency,
+    /// `M011`: another block has the same name and version but a different
+    /// derivation: the contents changed without a version bump.
+    #[serde(rename = "M011")]
+    VersionReused,
+    /// `M012`: another version of the block has the same derivation: the
+    /// version was bumped without a rebuild.
+    #[serde(rename = "M012")]
+    DerivationReused,
+    /// `M013`: the block was updated before it was created.
+    #[serde(rename = "M013")]
+    InvertedTimestamps,
+    /// `M014`: a stage of the composition is not in the registry.
+    #[serde(rename = "M014")]
+    MissingStage,
+    /// `M015`: the block is one of its own stages.
+    #[serde(rename = "M015")]
+    RecursiveComposition,
+    /// `M016`: a composition requires a lower trust level than one of its
+    /// stages, so it would let users run that stage below its level.
+    #[serde(rename = "M016")]
+    TrustBelowStages,
+    /// `M017`: the trust level is lower than the block's cognitive load
+    /// calls for.
+    #[serde(rename = "M017")]
+    TrustBelowLoad,
+    /// `M018`: the name or description contains control, zero-width or
+    /// bidirectional formatting characters, which can make text read
+    /// differently from what it is.
+    #[serde(rename = "M018")]
+    HiddenCharacters,
+    /// `M019`: the description contains something that does not belong in
+    /// a description: shell pipelines, destructive commands, plain-HTTP
+    /// URLs or instructions aimed at automated readers.
+    #[serde(rename = "M019")]
+    SuspiciousDescription,
+    /// `M020`: the description is longer than
+    /// [`MAX_DESCRIPTION_LEN`](crate::MAX_DESCRIPTION_LEN) characters.
+    #[serde(rename = "M020")]
+    LongDescription,
+    /// `M021`: a canonicalisation rule would be rejected at run time.
+    #[serde(rename = "M021")]
+    InvalidCanonicalization,
+    /// `M022`: a declared output cannot be collected from the guest.
+    #[serde(rename = "M022")]
+    InvalidOutputPath,
+    /// `D001`: repeated runs of the block produced different outputs.
+    #[serde(rename = "D001")]
+    Nondeterministic,
+    /// `D002`: fresh boots and snapshot restores produced different outputs,
+    /// though each agreed among themselves, so the block depends on state
+    /// a restore does not reproduce.
+    #[serde(rename = "D002")]
+    BootDependent,
+    /// `D003`: every run agreed, but the block failed.
+    #[serde(rename = "D003")]
+    FailingRuns,
+    /// `D004`: the backend cannot snapshot, so restores were not audited.
+    #[serde(rename = "D004")]
+    RestoresNotAudited,
+}
+
+impl Rule {
+    /// The rule's stable identifier (e.g. `"M002"`).
+    #[must_use]
+    pub const fn id(self) -> &'static str {
+        match self {
+            Self::MissingName => "M001",
+            Self::InvalidName => "M002",
+            Self::MissingDescription => "M003",
+            Self::NoCapabilities => "M004",
+            Self::InvalidCapability => "M005",
+            Self::InvalidDependency => "M006",
+            Self::InvalidVersionReq => "M007",
+            Self::UnresolvedDependency => "M008",
+            Self::UnsatisfiedDependency => "M009",
+            Self::SelfDependency => "M010",
+            Self::VersionReused => "M011",
+            Self::DerivationReused => "M012",
+            Self::InvertedTimestamps => "M013",
+            Self::MissingStage => "M014",
+            Self::RecursiveComposition => "M015",
+            Self::TrustBelowStages => "M016",
+            Self::TrustBelowLoad => "M017",
+            Self::HiddenCharacters => "M018",
+            Self::SuspiciousDescription => "M019",
+            Self::LongDescription => "M020",
+            Self::InvalidCanonicalization => "M021",
+            Self::InvalidOutputPath => "M022",
+            Self::Nondeterministic => "D001",
+            Self::BootDependent => "D002",
+            Self::FailingRuns => "D003",
+            Self::RestoresNotAudited => "D004",
+        }
+    }
+
+    /// The severity the rule reports at.
+    #[must_use]
+    pub const fn severity(self) -> Severity {
+        match self {
+            Self::LongDescription | Self::RestoresNotAudited => Severity::Info,
+            Self::NoCapabilities
+            | Self::UnresolvedDependency
+            | Self::SelfDependency
+            | Self::DerivationReused
+            | Self::TrustBelowLoad
+            | Self::SuspiciousDescription
+            | Self::FailingRuns => Severity::Warning,
+            Self::MissingName
+            | Self::InvalidName
+            | Self::MissingDescription
+            | Self::InvalidCapability
+            | Self::InvalidDependency
+            | Self::InvalidVersionReq
+            | Self::UnsatisfiedDependency
+            | Self::VersionReused
+            | Self::InvertedTimestamps
+            | Self::MissingStage
+            | Self::RecursiveComposition
+            | Self::TrustBelowStages
+            | Self::HiddenCharacters
+            | Self::InvalidCanonicalization
+            | Self::InvalidOutputPath
+            | Self::Nondeterministic
+            | Self::BootDependent => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// One problem the auditor found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Finding {
+    /// The rule that found it.
+    pub rule: Rule,
+    /// The rule's severity.
+    pub severity: Severity,
+    /// What exactly is wrong, naming the offending field or value.
+    pub message: String,
+}
+
+impl Finding {
+    /// A finding of `rule`, at the rule's severity.
+    #[must_use]
+    pub fn new(rule: Rule, message: impl Into<String>) -> Self {
+        Self { rule, severity: rule.severity(), message: message.into() }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] {}", self.severity, self.rule, self.message)
+    }
+}
+
+/// Everything the auditor found in one block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct AuditReport {
+    /// The audited block.
+    pub block_id: BlockId,
+    /// The block's name, as declared.
+    pub name: String,
+    /// The block's version.
+    pub version: SemVer,
+    /// Findings in the order the checks ran.
+    pub findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    /// An empty report on a block.
+    #[must_use]
+    pub const fn new(block_id: BlockId, name: String, version: SemVer) -> Self {
+        Self { block_id, name, version, findings: Vec::new() }
+    }
+
+    /// Append the findings of `other`, a report on the same block.
+    pub fn merge(&mut self, other: Self) {
+        self.findings.extend(other.findings);
+    }
+
+    /// Record a finding of `rule`.
+    pub fn push(&mut self, rule: Rule, message: impl Into<String>) {
+        self.findings.push(Finding::new(rule, message));
+    }
+
+    /// Whether no finding is an error.
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.at(Severity::Error).next().is_none()
+    }
+
+    /// The most severe finding's severity, or `None` for a clean report.
+    #[must_use]
+    pub fn worst(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+
+    /// Whether `rule` found anything.
+    #[must_use]
+    pub fn has(&self, rule: Rule) -> bool {
+        self.findings.iter().any(|finding| finding.rule == rule)
+    }
+
+    /// The findings at `severity`.
+    pub fn at(&self, severity: Severity) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(move |finding| finding.severity == severity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_serialise_as_their_stable_ids() {
+        let finding = Finding::new(Rule::InvalidName, "name \"Git Env\" contains ' '");
+        let json = serde_json::to_value(&finding).unwrap_or_else(|e| panic!("serialise: {e}"));
+        assert_eq!(json["rule"], "M002");
+        assert_eq!(json["severity"], "Error");
+        let back: Finding =
+            serde_json::from_value(json).unwrap_or_else(|e| panic!("deserialise: {e}"));
+        assert_eq!(back, finding);
+        assert_eq!(finding.to_string(), "error [M002] name \"Git Env\" contains ' '");
+    }
+
+    #[test]
+    fn reports_pass_unless_an_error_was_found() {
+        let mut report =
+            AuditReport::new(BlockId::new(), "git-env".to_owned(), SemVer::new(1, 0, 0));
+        assert!(report.passed());
+        assert_eq!(report.worst(), None);
+        report.push(Rule::LongDescription, "description is 400 characters");
+        report.push(Rule::UnresolvedDependency, "nothing provides \"git-cli\"");
+        assert!(report.passed(), "warnings must not fail an audit");
+        report.push(Rule::MissingDescription, "description is empty");
+        assert!(!report.passed());
+        assert_eq!(report.worst(), Some(Severity::Error));
+        assert!(report.has(Rule::MissingDescription));
+        assert_eq!(report.at(Severity::Warning).count(), 1);
+    }
+}
@@ -0,0 +1,230 @@
+//! Version requirements on capabilities.
+//!
+//! A [`Dependency`](forge_core::block::Dependency) names the versions it
+//! accepts with a Cargo-style requirement: comma-separated comparators, all
+//! of which must hold.
+//!
+//! ```text
+//! >= 2.40      at least 2.40.0
+//! ^1.93        1.93.0 up to, not including, 2.0.0 (also written `1.93`)
+//! ~1.2         1.2.0 up to, not including, 1.3.0
+//! = 1.2        any 1.2.x
+//! > 1, < 3     2.x
+//! *            any version
+//! ```
+//!
+//! Versions may omit the minor or patch component; as in Cargo, a missing
+//! component matches anything where that makes sense (`<= 1.2` accepts
+//! 1.2.9) and zero otherwise (`>= 1.2` starts at 1.2.0).
+
+use std::fmt;
+use std::str::FromStr;
+
+use forge_core::trust::SemVer;
+
+use crate::AuditError;
+
+/// A parsed version requirement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+    source: String,
+}
+
+/// How a [`Comparator`] compares a version to its operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+/// One operator applied to a possibly partial version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    major: u32,
+    minor: Option<u32>,
+    patch: Option<u32>,
+}
+
+type Triple = (u32, u32, u32);
+
+impl VersionReq {
+    /// Parse `requirement`.
+    ///
+    /// # Errors
+    /// Returns [`AuditError::InvalidVersionReq`] if `requirement` is empty
+    /// or a comparator is not an operator followed by a version of one to
+    /// three numeric components.
+    pub fn parse(requirement: &str) -> Result<Self, AuditError> {
+        let invalid = |reason: String| AuditError::InvalidVersionReq {
+            requirement: requirement.to_owned(),
+            reason,
+        };
+        let trimmed = requirement.trim();
+        if trimmed.is_empty() {
+            return Err(invalid("requirement is empty".to_owned()));
+        }
+        let comparators = if trimmed == "*" {
+            Vec::new()
+        } else {
+            trimmed
+                .split(',')
+                .map(|part| Comparator::parse(part.trim()))
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?
+        };
+        Ok(Self { comparators, source: trimmed.to_owned() })
+    }
+
+    /// Whether `version` satisfies every comparator.
+    #[must_use]
+    pub fn matches(&self, version: SemVer) -> bool {
+        let version = (version.major, version.minor, version.patch);
+        self.comparators.iter().all(|comparator| comparator.matches(version))
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = AuditError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Comparator {
+    fn parse(text: &str) -> Result<Self, String> {
+        let (op, version) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Exact),
+            ("~", Op::Tilde),
+            ("^", Op::Caret),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| text.strip_prefix(prefix).map(|rest| (op, rest.trim_start())))
+        .unwrap_or((Op::Caret, text));
+        if version.is_empty() {
+            return Err(format!("{text:?} has no version"));
+        }
+        let components = version
+            .split('.')
+            .map(|c| c.parse::<u32>().map_err(|_| format!("{c:?} is not a version number")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (major, minor, patch) = match components[..] {
+            [major] => (major, None, None),
+            [major, minor] => (major, Some(minor), None),
+            [major, minor, patch] => (major, Some(minor), Some(patch)),
+            _ => return Err(format!("{version:?} has more than three components")),
+        };
+        Ok(Self { op, major, minor, patch })
+    }
+
+    /// The smallest version the operand names, with missing components
+    /// zero.
+    fn lower(self) -> Triple {
+        (self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0))
+    }
+
+    /// The first version past every version the operand names.
+    const fn past(self) -> Triple {
+        match (self.minor, self.patch) {
+            (None, _) => (self.major.saturating_add(1), 0, 0),
+            (Some(minor), None) => (self.major, minor.saturating_add(1), 0),
+            (Some(minor), Some(patch)) => (self.major, minor, patch.saturating_add(1)),
+        }
+    }
+
+    fn matches(self, version: Triple) -> bool {
+        let lower = self.lower();
+        match self.op {
+            Op::Exact => lower <= version && version < self.past(),
+            Op::Greater => version >= self.past(),
+            Op::GreaterEq => version >= lower,
+            Op::Less => version < lower,
+            Op::LessEq => version < self.past(),
+            Op::Tilde => {
+                let upper = self.minor.map_or((self.major.saturating_add(1), 0, 0), |minor| {
+                    (self.major, minor.saturating_add(1), 0)
+                });
+                lower <= version && version < upper
+            }
+            Op::Caret => {
+                let upper = match (self.major, self.minor, self.patch) {
+                    (0, Some(0), Some(patch)) => (0, 0, patch.saturating_add(1)),
+                    (0, Some(minor), _) => (0, minor.saturating_add(1), 0),
+                    (major, _, _) => (major.saturating_add(1), 0, 0),
+                };
+                lower <= version && version < upper
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(requirement: &str) -> VersionReq {
+        VersionReq::parse(requirement).unwrap_or_else(|e| panic!("parse failed: {e}"))
+    }
+
+    #[test]
+    fn requirements_match_like_cargo() {
+        let cases = [
+            (">= 2.40", "2.40.0", true),
+            (">= 2.40", "2.39.9", false),
+            ("^1.93", "1.99.1", true),
+            ("^1.93", "2.0.0", false),
+            ("1.93", "1.92.0", false),
+            ("^0.2", "0.2.7", true),
+            ("^0.2", "0.3.0", false),
+            ("^0.0.3", "0.0.4", false),
+            ("~1.2", "1.2.9", true),
+            ("~1.2", "1.3.0", false),
+            ("= 1.2", "1.2.5", true),
+            ("=1.2.3", "1.2.4", false),
+            ("> 1.2", "1.2.9", false),
+            ("> 1.2", "1.3.0", true),
+            ("<= 1.2", "1.2.9", true),
+            ("< 1.2", "1.1.9", true),
+            ("> 1, < 3", "2.5.0", true),
+            ("> 1, < 3", "3.0.0", false),
+            ("*", "0.0.1", true),
+        ];
+        for (requirement, version, expected) in cases {
+            let [major, minor, patch] = [0, 1, 2]
+                .map(|i| version.split('.').nth(i).and_then(|c| c.parse().ok()).unwrap_or(0));
+            assert_eq!(
+                req(requirement).matches(SemVer::new(major, minor, patch)),
+                expected,
+                "{requirement:?} against {version}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_requirements_are_rejected() {
+        for requirement in ["", ">=", "latest", "1.2.3.4", ">= 1.x", "1,,2"] {
+            assert!(
+                matches!(VersionReq::parse(requirement), Err(AuditError::InvalidVersionReq { .. })),
+                "{requirement:?} must be rejected"
+            );
+        }
+        assert_eq!(req(" >= 2.40 ").to_string(), ">= 2.40");
+    }
+}
@@ -47,6 +47,18 @@ pub struct BlockManifest {
     pub cognitive_load: CognitiveLoad,
     /// Minimum trust level required to use this block.
     pub minimum_trust_level: TrustLevel,
+    /// Rules applied to the block's output, in order, before it is hashed
+    /// for determinism checks.
+    ///
+    /// Manifests from before this field existed hash their raw output.
+    #[serde(default)]
+    pub canonicalization: Vec<CanonicalizationRule>,
+    /// Absolute paths of files inside the guest that the block produces.
+    ///
+    /// Each file is collected after the run and stored as an artifact; its
+    /// hash is part of the execution's output hash.
+    #[serde(default)]
+    pub outputs: Vec<String>,
 }
 
 /// A dependency on another block or system capability.
@@ -61,6 +73,18 @@ pub struct Dependency {
     pub kind: DependencyKind,
 }
 
+impl Dependency {
+    /// Creates a dependency on `version_req` of `name`.
+    #[must_use]
+    pub fn new(
+        name: impl Into<String>,
+        version_req: impl Into<String>,
+        kind: DependencyKind,
+    ) -> Self {
+        Self { name: name.into(), version_req: version_req.into(), kind }
+    }
+}
+
 /// When a dependency is needed during the block lifecycle.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -81,6 +105,14 @@ pub struct Capability {
     pub version: SemVer,
 }
 
+impl Capability {
+    /// Creates a capability `name` at `version`.
+    #[must_use]
+    pub fn new(name: impl Into<String>, version: SemVer) -> Self {
+        Self { name: name.into(), version }
+    }
+}
+
 /// Estimated cognitive overhead for users composing with a block.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -92,3 +124,36 @@ pub enum CognitiveLoad {
     /// Complex composition; requires understanding of internals.
     High,
 }
+
+/// A transformation of a block's output that removes run-to-run entropy
+/// before the output is hashed.
+///
+/// Rules are declared in the manifest so they are reviewed with the block;
+/// [`ExecutionRecord`](crate::execution::ExecutionRecord) keeps both the raw
+/// and the canonical hash so the effect of the rules stays auditable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum CanonicalizationRule {
+    /// Replace every match of a regular expression.
+    Redact {
+        /// Regular expression to match, in Rust `regex` syntax.
+        pattern: String,
+        /// Replacement text; may refer to capture groups as `$1` or `$name`.
+        replacement: String,
+    },
+    /// Sort lines, for tools whose output order is not stable.
+    SortLines,
+    /// Replace the first path component after each prefix with `_`, so
+    /// randomly named directories such as `/tmp/tmp.Xa81kq/` compare equal.
+    NormalizePaths {
+        /// Directory prefixes, each ending in `/` (e.g. `"/tmp/"`).
+        prefixes: Vec<String>,
+    },
+    /// Drop standard error entirely.
+    IgnoreStderr,
+    /// Re-serialise JSON with object keys in sorted order. Applies to the
+    /// whole stream if it is one JSON document, and otherwise to each line
+    /// that is.
+    SortJsonKeys,
+}
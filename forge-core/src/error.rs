@@ -24,4 +24,13 @@ pub enum CoreError {
         /// The reason the field failed validation.
         reason: String,
     },
+
+    /// A Nix store path hash part failed validation.
+    #[error("invalid derivation hash {value:?}: {reason}")]
+    InvalidDerivationHash {
+        /// The rejected value.
+        value: String,
+        /// The reason the value is invalid.
+        reason: String,
+    },
 }
@@ -12,7 +12,8 @@
 /// Returns the three canonical example blocks.
 ///
 /// # Panics
-/// Never panics — all trust scores are hard-coded valid values.
+/// Never panics — all trust scores and derivation hashes are hard-coded
+/// valid values.
 #[must_use]
 pub fn example_blocks() -> Vec<Block> {
     let now = Utc::now();
@@ -30,12 +31,15 @@ pub fn example_blocks() -> Vec<Block> {
             }],
             cognitive_load: CognitiveLoad::Low,
             minimum_trust_level: TrustLevel::Zero,
+            canonicalization: vec![],
+            outputs: vec![],
         },
         composed_of: None,
         #[expect(clippy::unwrap_used, reason = "0.9 is a valid trust score")]
         trust_score: TrustScore::new(0.9).unwrap(),
         author: ContributorId::new("forge-team"),
-        nix_derivation: DerivationHash::new("ywi5ib7yrjba3k3b26yfnbx7gappr3dg"),
+        #[expect(clippy::unwrap_used, reason = "a valid nixbase32 hash part")]
+        nix_derivation: DerivationHash::new("ywi5ib7yrjba3k3b26yfnbx7gappr3dg").unwrap(),
         created_at: now,
         updated_at: now,
     };
@@ -58,12 +62,15 @@ pub fn example_blocks() -> Vec<Block> {
             ],
             cognitive_load: CognitiveLoad::Medium,
             minimum_trust_level: TrustLevel::One,
+            canonicalization: vec![],
+            outputs: vec![],
         },
         composed_of: None,
         #[expect(clippy::unwrap_used, reason = "0.85 is a valid trust score")]
         trust_score: TrustScore::new(0.85).unwrap(),
         author: ContributorId::new("forge-team"),
-        nix_derivation: DerivationHash::new("3b26yfnbx7gappr3dgywi5ib7yrjba3k"),
+        #[expect(clippy::unwrap_used, reason = "a valid nixbase32 hash part")]
+        nix_derivation: DerivationHash::new("3b26yfnbx7gappr3dgywi5ib7yrjba3k").unwrap(),
         created_at: now,
         updated_at: now,
     };
@@ -93,12 +100,15 @@ pub fn example_blocks() -> Vec<Block> {
             }],
             cognitive_load: CognitiveLoad::High,
             minimum_trust_level: TrustLevel::Two,
+            canonicalization: vec![],
+            outputs: vec![],
         },
         composed_of: None,
         #[expect(clippy::unwrap_used, reason = "0.7 is a valid trust score")]
         trust_score: TrustScore::new(0.7).unwrap(),
         author: ContributorId::new("forge-team"),
-        nix_derivation: DerivationHash::new("pr3dgywi5ib7yrjba3k3b26yfnbx7gap"),
+        #[expect(clippy::unwrap_used, reason = "a valid nixbase32 hash part")]
+        nix_derivation: DerivationHash::new("pr3dgywi5ib7yrjba3k3b26yfnbx7gap").unwrap(),
         created_at: now,
         updated_at: now,
     };
@@ -20,8 +20,24 @@ pub struct ExecutionRecord {
     pub user_id: UserId,
     /// SHA-256 hash of the execution input.
     pub input_hash: ContentHash,
-    /// SHA-256 hash of the execution output.
+    /// SHA-256 hash of the raw execution output.
     pub output_hash: ContentHash,
+    /// SHA-256 hash of the output after the block's canonicalisation rules,
+    /// or `None` if the block declares none.
+    ///
+    /// Determinism is judged on [`Self::canonical_hash`].
+    #[serde(default)]
+    pub canonical_output_hash: Option<ContentHash>,
+    /// How `input_hash`, `output_hash` and `canonical_output_hash` were
+    /// computed.
+    ///
+    /// Records from before this field existed used [`HashScheme::Legacy`].
+    #[serde(default)]
+    pub hash_scheme: HashScheme,
+    /// Files the execution produced, in the order the manifest declares
+    /// them.
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
     /// When execution began.
     pub started_at: DateTime<Utc>,
     /// Wall-clock duration of the execution.
@@ -30,6 +46,36 @@ pub struct ExecutionRecord {
     pub vm_snapshot_id: Option<SnapshotId>,
     /// Final status of the execution.
     pub status: ExecutionStatus,
+    /// Isolation boundary the execution ran behind.
+    ///
+    /// Records from before this field existed were all produced in microVMs.
+    #[serde(default)]
+    pub isolation: IsolationLevel,
+    /// Whether this record was served from an execution cache instead of
+    /// a fresh run; all other fields are those of the original run.
+    #[serde(default)]
+    pub cached: bool,
+    /// Records of the stages a composite block's execution ran, in the
+    /// order the block lists them; empty for non-composite blocks.
+    #[serde(default)]
+    pub children: Vec<ExecutionId>,
+    /// SHA-256 of the kernel image the VM booted, if it was content
+    /// addressed.
+    #[serde(default)]
+    pub kernel_hash: Option<ContentHash>,
+    /// SHA-256 of the rootfs image the VM booted, if it was content
+    /// addressed.
+    #[serde(default)]
+    pub rootfs_hash: Option<ContentHash>,
+    /// Clock, seed and identity the guest was given; `None` for records
+    /// from before these were fixed, and for composite records, whose
+    /// stages each record their own. This does not make the run hermetic:
+    /// see [`GuestEnvironment`].
+    #[serde(default)]
+    pub guest: Option<GuestEnvironment>,
+    /// Hash of the CPU template the VM's CPU was pinned to, if any.
+    #[serde(default)]
+    pub cpu_template_hash: Option<ContentHash>,
 }
 
 impl ExecutionRecord {
@@ -60,12 +106,215 @@ pub fn new(
             user_id,
             input_hash,
             output_hash,
+            canonical_output_hash: None,
+            hash_scheme: HashScheme::Legacy,
+            artifacts: Vec::new(),
             started_at,
             duration,
             vm_snapshot_id: None,
             status,
+            isolation: IsolationLevel::MicroVm,
+            cached: false,
+            children: Vec::new(),
+            kernel_hash: None,
+            rootfs_hash: None,
+            guest: None,
+            cpu_template_hash: None,
         }
     }
+
+    /// Record the hash of the canonicalised output.
+    #[must_use]
+    pub const fn with_canonical_output_hash(mut self, hash: ContentHash) -> Self {
+        self.canonical_output_hash = Some(hash);
+        self
+    }
+
+    /// The hash determinism is judged on: the canonical output hash if the
+    /// block declares canonicalisation rules, otherwise the raw one.
+    #[must_use]
+    pub fn canonical_hash(&self) -> ContentHash {
+        self.canonical_output_hash.unwrap_or(self.output_hash)
+    }
+
+    /// Record the scheme the hashes were computed with.
+    #[must_use]
+    pub const fn with_hash_scheme(mut self, scheme: HashScheme) -> Self {
+        self.hash_scheme = scheme;
+        self
+    }
+
+    /// Record the files the execution produced.
+    #[must_use]
+    pub fn with_artifacts(mut self, artifacts: Vec<Artifact>) -> Self {
+        self.artifacts = artifacts;
+        self
+    }
+
+    /// Record the isolation boundary the execution ran behind.
+    #[must_use]
+    pub const fn with_isolation(mut self, isolation: IsolationLevel) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Record the stage executions a composite execution consists of.
+    #[must_use]
+    pub fn with_children(mut self, children: Vec<ExecutionId>) -> Self {
+        self.children = children;
+        self
+    }
+
+    /// Record the hashes of the kernel and rootfs images the VM booted.
+    #[must_use]
+    pub const fn with_image_hashes(
+        mut self,
+        kernel: Option<ContentHash>,
+        rootfs: Option<ContentHash>,
+    ) -> Self {
+        self.kernel_hash = kernel;
+        self.rootfs_hash = rootfs;
+        self
+    }
+
+    /// Record the hash of the CPU template the VM's CPU was pinned to.
+    #[must_use]
+    pub const fn with_cpu_template_hash(mut self, hash: Option<ContentHash>) -> Self {
+        self.cpu_template_hash = hash;
+        self
+    }
+
+    /// Whether this record and `other` ran on the same execution platform
+    /// (isolation, kernel, rootfs and CPU template) so their outputs can be
+    /// compared. Differing outputs from different platforms say nothing
+    /// about a block's determinism.
+    #[must_use]
+    pub fn same_platform(&self, other: &Self) -> bool {
+        self.isolation == other.isolation
+            && self.kernel_hash == other.kernel_hash
+            && self.rootfs_hash == other.rootfs_hash
+            && self.cpu_template_hash == other.cpu_template_hash
+    }
+
+    /// Record the environment the guest ran with.
+    #[must_use]
+    pub fn with_guest_environment(mut self, guest: GuestEnvironment) -> Self {
+        self.guest = Some(guest);
+        self
+    }
+
+    /// Mark the record as served from an execution cache.
+    #[must_use]
+    pub const fn with_cached(mut self) -> Self {
+        self.cached = true;
+        self
+    }
+}
+
+/// A file produced by an execution, kept in a content-addressed store.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Artifact {
+    /// Path of the file inside the guest, as declared in the manifest.
+    pub path: String,
+    /// SHA-256 of the file's contents; its address in the artifact store.
+    pub hash: ContentHash,
+    /// Size of the file in bytes.
+    pub size: u64,
+}
+
+impl Artifact {
+    /// Creates a new `Artifact`.
+    #[must_use]
+    pub const fn new(path: String, hash: ContentHash, size: u64) -> Self {
+        Self { path, hash, size }
+    }
+}
+
+/// The fixed parameters a guest runs a block with, so that programs reading
+/// the start time, a random seed or the host's identity see the same values
+/// on every run.
+///
+/// They do not make the guest hermetic. The clock is set, not frozen: it
+/// starts at `epoch` and advances while the block runs, so elapsed time
+/// still reaches the guest. Kernel entropy (`/dev/urandom`,
+/// `getrandom(2)`) is not seeded; `seed` only reaches programs that accept
+/// one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct GuestEnvironment {
+    /// Seconds since the Unix epoch `SOURCE_DATE_EPOCH` names, and the
+    /// guest clock is set to if [`clock_set`](Self::clock_set).
+    pub epoch: i64,
+    /// Whether the guest clock was set to `epoch` before the block ran.
+    /// False for process sandboxes, which share the host clock, and for
+    /// records from before this was tracked.
+    #[serde(default)]
+    pub clock_set: bool,
+    /// Seed offered to the guest, derived from the input hash so every run
+    /// on the same input gets the same one.
+    pub seed: ContentHash,
+    /// Hostname the guest reports.
+    pub hostname: String,
+    /// Locale, as `LANG` and `LC_ALL`.
+    pub locale: String,
+    /// Timezone, as `TZ`.
+    pub timezone: String,
+}
+
+impl GuestEnvironment {
+    /// An environment with `SOURCE_DATE_EPOCH` at `epoch`, offering `seed`
+    /// and reporting `hostname`, `locale` and `timezone`; the guest clock
+    /// is set to `epoch` only if `clock_set`.
+    #[must_use]
+    pub const fn new(
+        epoch: i64,
+        clock_set: bool,
+        seed: ContentHash,
+        hostname: String,
+        locale: String,
+        timezone: String,
+    ) -> Self {
+        Self { epoch, clock_set, seed, hostname, locale, timezone }
+    }
+}
+
+/// A versioned scheme for hashing execution inputs and outputs.
+///
+/// Hashes are only comparable within one scheme. Older schemes stay
+/// supported so historical records can still be verified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum HashScheme {
+    /// `SHA-256(stdout || stderr)`, and `SHA-256(input)` for inputs.
+    ///
+    /// Ambiguous: `("ab", "c")` and `("a", "bc")` collide, and neither the
+    /// exit code nor produced files are covered.
+    #[default]
+    Legacy,
+    /// Domain-separated, length-prefixed SHA-256 over stdout, stderr, exit
+    /// code and produced files.
+    V1,
+}
+
+impl HashScheme {
+    /// The scheme new records are hashed with.
+    pub const CURRENT: Self = Self::V1;
+}
+
+/// The isolation boundary an execution ran behind.
+///
+/// Only [`IsolationLevel::MicroVm`] executions carry the full isolation
+/// guarantees; consumers such as trust scoring should treat anything weaker
+/// accordingly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum IsolationLevel {
+    /// Hardware-virtualised guest with its own kernel.
+    #[default]
+    MicroVm,
+    /// Host process confined by Linux namespaces, sharing the host kernel.
+    ProcessSandbox,
 }
 
 /// The outcome of a block execution.
@@ -1,8 +1,11 @@
 use std::fmt;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::error::CoreError;
+
 /// Unique identifier for a block in the Forge registry.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -144,17 +147,67 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     }
 }
 
+/// The 32 characters of Nix's base-32 encoding, in digit order: `0`–`9`
+/// and the lowercase letters except `e`, `o`, `t` and `u`.
+pub const NIXBASE32_ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
 /// A Nix store path hash identifying a reproducible derivation.
 ///
 /// Format: 32-character base32 Nix hash (e.g. `ywi5ib7yrjba3k3b26yfnbx7gappr3dg`).
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Validated on construction and deserialisation.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 #[non_exhaustive]
 pub struct DerivationHash(pub String);
 
 impl DerivationHash {
-    /// Creates a `DerivationHash` from any string-like value.
-    pub fn new(hash: impl Into<String>) -> Self {
-        Self(hash.into())
+    /// Length of a store path hash part: 160 bits in nixbase32.
+    pub const LEN: usize = 32;
+
+    /// Creates a `DerivationHash` from a store path hash part.
+    ///
+    /// # Errors
+    /// Returns [`CoreError::InvalidDerivationHash`] unless `hash` is
+    /// [`Self::LEN`] characters of [`NIXBASE32_ALPHABET`].
+    pub fn new(hash: impl Into<String>) -> Result<Self, CoreError> {
+        let hash = hash.into();
+        let invalid =
+            |reason: String| CoreError::InvalidDerivationHash { value: hash.clone(), reason };
+        if hash.len() != Self::LEN {
+            return Err(invalid(format!("expected {} characters, got {}", Self::LEN, hash.len())));
+        }
+        if let Some(c) = hash.bytes().find(|c| !NIXBASE32_ALPHABET.contains(c)) {
+            return Err(invalid(format!("{:?} is not a nixbase32 character", char::from(c))));
+        }
+        Ok(Self(hash))
+    }
+
+    /// Returns the hash part as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for DerivationHash {
+    type Error = CoreError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl FromStr for DerivationHash {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl From<DerivationHash> for String {
+    fn from(hash: DerivationHash) -> Self {
+        hash.0
     }
 }
 
@@ -18,11 +18,17 @@
 /// Trust score and level types.
 pub mod trust;
 
-pub use block::{Block, BlockManifest, Capability, CognitiveLoad, Dependency, DependencyKind};
+pub use block::{
+    Block, BlockManifest, CanonicalizationRule, Capability, CognitiveLoad, Dependency,
+    DependencyKind,
+};
 pub use error::CoreError;
-pub use execution::{ExecutionRecord, ExecutionStatus};
+pub use execution::{
+    Artifact, ExecutionRecord, ExecutionStatus, GuestEnvironment, HashScheme, IsolationLevel,
+};
 pub use id::{
     BlockId, ContentHash, ContributorId, DerivationHash, ExecutionId, SnapshotId, UserId,
+    NIXBASE32_ALPHABET,
 };
 pub use trust::{SemVer, TrustLevel, TrustScore};
 
@@ -84,6 +90,13 @@ fn example_blocks_all_valid() {
         assert_eq!(bose.manifest.requires.len(), 2);
     }
 
+    #[test]
+    fn trust_levels_are_ordered() {
+        assert!(TrustLevel::Zero < TrustLevel::One);
+        assert!(TrustLevel::Two < TrustLevel::Three);
+        assert_eq!([TrustLevel::Two, TrustLevel::Zero].iter().max(), Some(&TrustLevel::Two));
+    }
+
     #[test]
     fn semver_display_formats_correctly() {
         let v = SemVer::new(1, 2, 3);
@@ -162,6 +175,146 @@ fn execution_record_new_sets_correct_fields() {
         assert_eq!(record.output_hash, output_hash);
         assert_eq!(record.duration, duration);
         assert!(record.vm_snapshot_id.is_none(), "vm_snapshot_id must default to None");
+        assert_eq!(record.isolation, IsolationLevel::MicroVm, "isolation must default to MicroVm");
+    }
+
+    #[test]
+    fn execution_record_without_isolation_deserializes_as_micro_vm() {
+        use crate::execution::ExecutionStatus;
+        use crate::id::{BlockId, ContentHash, UserId};
+        use chrono::Utc;
+
+        let record = ExecutionRecord::new(
+            BlockId::new(),
+            UserId::new("test-user"),
+            ContentHash::new([0u8; 32]),
+            ContentHash::new([1u8; 32]),
+            Utc::now(),
+            std::time::Duration::from_millis(5),
+            ExecutionStatus::Succeeded,
+        )
+        .with_isolation(IsolationLevel::ProcessSandbox);
+
+        let mut json = match serde_json::to_value(&record) {
+            Ok(v) => v,
+            Err(e) => panic!("serialization failed: {e}"),
+        };
+        assert_eq!(json["isolation"], "ProcessSandbox");
+        if let Some(fields) = json.as_object_mut() {
+            fields.remove("isolation");
+            fields.remove("hash_scheme");
+            fields.remove("artifacts");
+            fields.remove("cached");
+            fields.remove("children");
+            fields.remove("kernel_hash");
+            fields.remove("rootfs_hash");
+            fields.remove("guest");
+            fields.remove("cpu_template_hash");
+        }
+        let legacy: ExecutionRecord = match serde_json::from_value(json) {
+            Ok(r) => r,
+            Err(e) => panic!("deserialization failed: {e}"),
+        };
+        assert_eq!(legacy.isolation, IsolationLevel::MicroVm);
+        assert_eq!(legacy.hash_scheme, HashScheme::Legacy);
+        assert!(legacy.artifacts.is_empty());
+        assert!(!legacy.cached);
+        assert!(legacy.children.is_empty());
+        assert_eq!((legacy.kernel_hash, legacy.rootfs_hash), (None, None));
+        assert!(legacy.guest.is_none());
+        assert!(legacy.cpu_template_hash.is_none());
+    }
+
+    #[test]
+    fn canonical_hash_falls_back_to_raw_output_hash() {
+        use crate::execution::ExecutionStatus;
+        use crate::id::{BlockId, ContentHash, UserId};
+        use chrono::Utc;
+
+        let raw = ContentHash::new([1u8; 32]);
+        let record = ExecutionRecord::new(
+            BlockId::new(),
+            UserId::new("test-user"),
+            ContentHash::new([0u8; 32]),
+            raw,
+            Utc::now(),
+            std::time::Duration::from_millis(5),
+            ExecutionStatus::Succeeded,
+        );
+        assert_eq!(record.canonical_hash(), raw);
+
+        let canonical = ContentHash::new([2u8; 32]);
+        let record = record.with_canonical_output_hash(canonical);
+        assert_eq!(record.output_hash, raw, "raw hash must be kept alongside the canonical one");
+        assert_eq!(record.canonical_hash(), canonical);
+    }
+
+    #[test]
+    fn records_from_different_cpu_templates_are_not_comparable() {
+        use crate::execution::ExecutionStatus;
+        use crate::id::{BlockId, ContentHash, UserId};
+        use chrono::Utc;
+
+        let record = ExecutionRecord::new(
+            BlockId::new(),
+            UserId::new("test-user"),
+            ContentHash::new([0u8; 32]),
+            ContentHash::new([1u8; 32]),
+            Utc::now(),
+            std::time::Duration::from_millis(5),
+            ExecutionStatus::Succeeded,
+        );
+        let pinned = record.clone().with_cpu_template_hash(Some(ContentHash::new([3u8; 32])));
+        assert!(record.same_platform(&record.clone()));
+        assert!(pinned.same_platform(&pinned.clone()));
+        assert!(!record.same_platform(&pinned), "a pinned CPU is a different platform");
+        let sandboxed = record.clone().with_isolation(IsolationLevel::ProcessSandbox);
+        assert!(!record.same_platform(&sandboxed));
+    }
+
+    #[test]
+    fn manifest_without_newer_fields_deserializes_with_defaults() {
+        let block = &example_blocks()[0];
+        let mut json = match serde_json::to_value(&block.manifest) {
+            Ok(v) => v,
+            Err(e) => panic!("serialization failed: {e}"),
+        };
+        if let Some(fields) = json.as_object_mut() {
+            fields.remove("canonicalization");
+            fields.remove("outputs");
+        }
+        let legacy: BlockManifest = match serde_json::from_value(json) {
+            Ok(m) => m,
+            Err(e) => panic!("deserialization failed: {e}"),
+        };
+        assert!(legacy.canonicalization.is_empty());
+        assert!(legacy.outputs.is_empty());
+    }
+
+    #[test]
+    fn derivation_hash_accepts_only_nixbase32_hash_parts() {
+        assert!(DerivationHash::new("ywi5ib7yrjba3k3b26yfnbx7gappr3dg").is_ok());
+        assert!(DerivationHash::new("ywi5ib7yrjba3k3b26yfnbx7gappr3d").is_err(), "too short");
+        assert!(DerivationHash::new("ywi5ib7yrjba3k3b26yfnbx7gappr3de").is_err(), "`e` excluded");
+        assert!(DerivationHash::new("YWI5IB7YRJBA3K3B26YFNBX7GAPPR3DG").is_err(), "uppercase");
+        let parsed: Result<DerivationHash, _> = "drv".parse();
+        assert!(matches!(parsed, Err(CoreError::InvalidDerivationHash { .. })));
+    }
+
+    #[test]
+    fn block_with_malformed_derivation_hash_is_rejected_on_load() {
+        let block = &example_blocks()[0];
+        let mut json = match serde_json::to_value(block) {
+            Ok(v) => v,
+            Err(e) => panic!("serialization failed: {e}"),
+        };
+        assert_eq!(json["nix_derivation"], "ywi5ib7yrjba3k3b26yfnbx7gappr3dg");
+        json["nix_derivation"] = serde_json::Value::from("not-a-nix-hash");
+        let result = serde_json::from_value::<Block>(json);
+        assert!(
+            result.as_ref().is_err_and(|e| e.to_string().contains("invalid derivation hash")),
+            "malformed derivation hashes must fail to load, got {result:?}"
+        );
     }
 
     #[test]
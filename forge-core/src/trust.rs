@@ -32,8 +32,9 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 
 /// Trust level required to use or compose a block.
 ///
-/// Higher levels unlock more powerful but potentially risky operations.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Higher levels unlock more powerful but potentially risky operations, and
+/// compare greater than lower ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum TrustLevel {
     /// Level 0 — can only use pre-approved block combinations.
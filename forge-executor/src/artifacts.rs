@@ -0,0 +1,134 @@
+//! Content-addressed store for files produced by executions.
+//!
+//! Each artifact is stored once under the SHA-256 of its contents:
+//!
+//! ```text
+//! <dir>/<sha256>
+//! ```
+//!
+//! [`BlockRunner`](crate::BlockRunner) collects the files a block's manifest
+//! declares as outputs and puts them here; the
+//! [`Artifact`](forge_core::execution::Artifact) entries in the
+//! [`ExecutionRecord`](forge_core::execution::ExecutionRecord) name them by
+//! hash. Contents are verified against their hash on every read.
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use forge_core::id::ContentHash;
+
+use crate::host::write_atomic;
+use crate::ExecutorError;
+
+/// A directory of artifacts addressed by content hash.
+#[derive(Debug, Clone)]
+pub struct ArtifactStore {
+    dir: PathBuf,
+}
+
+impl ArtifactStore {
+    /// Open the store in `dir`. The directory is created on the first write.
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Directory holding the store.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the artifact with `hash` is, or would be, stored.
+    #[must_use]
+    pub fn path(&self, hash: &ContentHash) -> PathBuf {
+        self.dir.join(hash.to_string())
+    }
+
+    /// Store `contents`, returning their hash. Storing contents that are
+    /// already present is a no-op.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the file cannot be written.
+    pub async fn put(&self, contents: &[u8]) -> Result<ContentHash, ExecutorError> {
+        let hash = ContentHash::new(Sha256::digest(contents).into());
+        let path = self.path(&hash);
+        if !tokio::fs::try_exists(&path).await? {
+            write_atomic(&path, contents).await?;
+        }
+        Ok(hash)
+    }
+
+    /// Read the artifact with `hash`.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the artifact is missing or unreadable,
+    /// with kind `InvalidData` if its contents no longer match the hash.
+    pub async fn get(&self, hash: &ContentHash) -> Result<Vec<u8>, ExecutorError> {
+        let contents = tokio::fs::read(self.path(hash)).await?;
+        let actual = ContentHash::new(Sha256::digest(&contents).into());
+        if actual != *hash {
+            return Err(ExecutorError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("artifact {hash} is corrupt (hashes to {actual})"),
+            )));
+        }
+        Ok(contents)
+    }
+
+    /// Whether an artifact with `hash` is stored.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the store cannot be inspected.
+    pub async fn contains(&self, hash: &ContentHash) -> Result<bool, ExecutorError> {
+        Ok(tokio::fs::try_exists(self.path(hash)).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn temp_store() -> ArtifactStore {
+        ArtifactStore::new(
+            std::env::temp_dir().join(format!("forge-artifacts-test-{}", Uuid::new_v4())),
+        )
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn put_then_get_roundtrips_and_dedupes() {
+        let store = temp_store();
+        let first = store.put(b"artifact").await.unwrap_or_else(|e| panic!("put failed: {e}"));
+        let second = store.put(b"artifact").await.unwrap_or_else(|e| panic!("put failed: {e}"));
+        assert_eq!(first, second, "identical contents must share an address");
+
+        let contents = store.get(&first).await.unwrap_or_else(|e| panic!("get failed: {e}"));
+        assert_eq!(contents, b"artifact");
+        let files = std::fs::read_dir(store.dir()).map_or(0, Iterator::count);
+        assert_eq!(files, 1, "identical contents must be stored once");
+
+        let _ = std::fs::remove_dir_all(store.dir());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn corrupt_artifact_is_rejected() {
+        let store = temp_store();
+        let hash = store.put(b"original").await.unwrap_or_else(|e| panic!("put failed: {e}"));
+        if let Err(e) = std::fs::write(store.path(&hash), b"tampered") {
+            panic!("tamper failed: {e}");
+        }
+
+        let result = store.get(&hash).await;
+        assert!(
+            matches!(&result, Err(ExecutorError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData),
+            "tampered artifact must fail verification, got {result:?}"
+        );
+
+        let _ = std::fs::remove_dir_all(store.dir());
+    }
+}
@@ -6,6 +6,7 @@
 use std::time::Duration;
 
 use async_trait::async_trait;
+use forge_core::execution::IsolationLevel;
 
 use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
 
@@ -42,12 +43,44 @@ pub trait VmmBackend: Send + Sync {
     /// Returns [`ExecutorError::SnapshotFailed`] if the snapshot API call fails.
     async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError>;
 
+    /// Create a diff snapshot holding only memory pages dirtied since the VM's
+    /// previous snapshot, or since it was restored.
+    ///
+    /// The VM must have been booted with
+    /// [`VmConfig::track_dirty_pages`] enabled. Restoring a diff snapshot
+    /// replays its whole chain back to the nearest full snapshot.
+    ///
+    /// The default implementation returns [`ExecutorError::Unsupported`].
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotFailed`] if the VM has no parent
+    /// snapshot, does not track dirty pages, or the snapshot API call fails.
+    async fn diff_snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("diff_snapshot of VM {}", handle.id)))
+    }
+
     /// Restore a VM from a snapshot.
     ///
+    /// Every restored VM writes to its own copy of the snapshot's disk state,
+    /// so VMs restored from the same snapshot, and the VM it was taken of,
+    /// never see each other's writes.
+    ///
     /// # Errors
-    /// Returns [`ExecutorError::RestoreFailed`] if the snapshot file is missing or corrupt.
+    /// Returns [`ExecutorError::RestoreFailed`] if the snapshot file is missing or corrupt,
+    /// including when it no longer matches the hashes recorded at snapshot time.
     async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError>;
 
+    /// Delete a snapshot and any files or metadata the backend keeps for it.
+    ///
+    /// The default implementation returns [`ExecutorError::Unsupported`].
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotNotFound`] if the snapshot does not exist.
+    /// Returns [`ExecutorError::Unsupported`] if the backend cannot delete snapshots.
+    async fn delete_snapshot(&self, snapshot_id: &SnapshotId) -> Result<(), ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("delete_snapshot for {snapshot_id}")))
+    }
+
     /// Terminate a running VM and clean up resources.
     ///
     /// # Errors
@@ -78,4 +111,33 @@ async fn execute_command(
         command: &str,
         timeout: Duration,
     ) -> Result<ExecutionOutput, ExecutorError>;
+
+    /// Run `command` to completion inside an already-running VM.
+    ///
+    /// Used to serve executions from VMs restored out of a snapshot, where the
+    /// boot args can no longer carry the command. The VM keeps running after
+    /// the command exits; the caller decides whether to reuse or terminate it.
+    ///
+    /// The default implementation returns [`ExecutorError::Unsupported`].
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Unsupported`] if the backend or VM has no guest channel.
+    /// Returns [`ExecutorError::Io`] on timeout or guest channel failure.
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let _ = (command, timeout);
+        Err(ExecutorError::Unsupported(format!("execute_in_vm on VM {}", handle.id)))
+    }
+
+    /// The isolation boundary executions on this backend run behind.
+    ///
+    /// Recorded on every [`ExecutionRecord`](forge_core::execution::ExecutionRecord)
+    /// the backend produces. The default is [`IsolationLevel::MicroVm`].
+    fn isolation(&self) -> IsolationLevel {
+        IsolationLevel::MicroVm
+    }
 }
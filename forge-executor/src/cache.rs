@@ -0,0 +1,471 @@
+//! Execution result cache — skipping VM boots for work already done.
+//!
+//! Execution is deterministic, so a block run on the same input in the same
+//! environment must produce the same record. [`CacheKey`] names that
+//! environment:
+//!
+//! ```text
+//! (block content hash, derivation hash, kernel hash, rootfs hash,
+//!  layer hashes, CPU template hash, input hash, VM config hash,
+//!  isolation level)
+//! ```
+//!
+//! Images the configuration names only by path are hashed by contents, so
+//! an image rebuilt in place never serves a stale record. An
+//! [`ExecutionCache`] remembers those hashes, and re-reads an image only
+//! once its inode, size or modification time changes.
+//!
+//! [`BlockRunner`](crate::BlockRunner) looks the key up before booting a VM
+//! and, on a hit, returns the stored record marked
+//! [`cached`](ExecutionRecord::cached). The record's artifacts stay in the
+//! [`ArtifactStore`](crate::ArtifactStore); the runner treats an entry
+//! whose artifacts have been removed from the store as a miss.
+//!
+//! The cache is bounded by total entry size (serialised record plus
+//! artifact bytes) and evicts least recently used entries first.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use forge_core::block::Block;
+use forge_core::execution::{ExecutionRecord, IsolationLevel};
+use forge_core::id::{BlockId, ContentHash, DerivationHash};
+
+use crate::catalog::hash_file;
+use crate::images::ImageHashes;
+use crate::{ExecutorError, VmConfig};
+
+/// Domain tag prefixed to the block content hash.
+const BLOCK_DOMAIN: &[u8] = b"forge.block.v1\0";
+
+/// Domain tag prefixed to the VM config hash.
+const VM_CONFIG_DOMAIN: &[u8] = b"forge.vm-config.v1\0";
+
+/// Everything that determines the outcome of an execution.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// SHA-256 of the block's manifest and composition.
+    pub block: ContentHash,
+
+    /// Nix derivation the block's environment is built from.
+    pub derivation: DerivationHash,
+
+    /// SHA-256 of the kernel image's contents; the config's
+    /// [`kernel_hash`](VmConfig::kernel_hash) if it names one.
+    pub kernel: ContentHash,
+
+    /// SHA-256 of the rootfs image's contents; the config's
+    /// [`rootfs_hash`](VmConfig::rootfs_hash) if it names one.
+    pub rootfs: ContentHash,
+
+    /// SHA-256 of each of the config's
+    /// [`layer_paths`](VmConfig::layer_paths), in order.
+    pub layers: Vec<ContentHash>,
+
+    /// [Hash](crate::CpuTemplate::hash) of the config's CPU template, if it
+    /// sets one. Covers the contents of a custom template file, which the
+    /// config names only by path.
+    pub cpu_template: Option<ContentHash>,
+
+    /// Hash of the execution input, as recorded in
+    /// [`ExecutionRecord::input_hash`].
+    pub input: ContentHash,
+
+    /// SHA-256 of the VM configuration.
+    pub vm_config: ContentHash,
+
+    /// Isolation level of the backend the block runs on. It decides the
+    /// guest environment, and the same block may behave differently behind
+    /// a weaker boundary.
+    pub isolation: IsolationLevel,
+}
+
+impl CacheKey {
+    /// Build the key for running `block` on the input hashing to `input`
+    /// in VMs configured by `vm_config`, on a backend with the given
+    /// `isolation`.
+    ///
+    /// Every image the configuration names only by path is read and hashed;
+    /// [`ExecutionCache::key`] reuses the hashes of unchanged images.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the kernel, rootfs or a layer image
+    /// must be hashed and cannot be read, or the block or configuration
+    /// cannot be serialised, and propagates errors hashing the CPU template.
+    pub async fn new(
+        block: &Block,
+        input: ContentHash,
+        vm_config: &VmConfig,
+        isolation: IsolationLevel,
+    ) -> Result<Self, ExecutorError> {
+        Self::build(block, input, vm_config, isolation, None).await
+    }
+
+    async fn build(
+        block: &Block,
+        input: ContentHash,
+        vm_config: &VmConfig,
+        isolation: IsolationLevel,
+        hashes: Option<&ImageHashes>,
+    ) -> Result<Self, ExecutorError> {
+        let mut layers = Vec::with_capacity(vm_config.layer_paths.len());
+        for layer in &vm_config.layer_paths {
+            layers.push(image_hash(layer, None, hashes).await?);
+        }
+        Ok(Self {
+            block: block_hash(block)?,
+            derivation: block.nix_derivation.clone(),
+            kernel: image_hash(&vm_config.kernel_path, vm_config.kernel_hash, hashes).await?,
+            rootfs: image_hash(&vm_config.rootfs_path, vm_config.rootfs_hash, hashes).await?,
+            layers,
+            cpu_template: vm_config.cpu_template_hash().await?,
+            input,
+            vm_config: domain_hash(VM_CONFIG_DOMAIN, &to_json(vm_config)?),
+            isolation,
+        })
+    }
+}
+
+/// The hash of the image at `path`: `pinned` if the configuration names
+/// one, its contents' SHA-256 otherwise, taken from `hashes` if the image
+/// has not changed since they last saw it.
+async fn image_hash(
+    path: &Path,
+    pinned: Option<ContentHash>,
+    hashes: Option<&ImageHashes>,
+) -> std::io::Result<ContentHash> {
+    match (pinned, hashes) {
+        (Some(hash), _) => Ok(hash),
+        (None, Some(hashes)) => hashes.hash(path).await,
+        (None, None) => Ok(hash_file(path.to_path_buf()).await?.0),
+    }
+}
+
+/// Hash a block's manifest and composition. The id, trust score and
+/// timestamps do not affect execution and are left out.
+fn block_hash(block: &Block) -> Result<ContentHash, ExecutorError> {
+    let content = to_json(&(&block.manifest, &block.composed_of))?;
+    Ok(domain_hash(BLOCK_DOMAIN, &content))
+}
+
+fn to_json(value: &impl serde::Serialize) -> std::io::Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(std::io::Error::other)
+}
+
+fn domain_hash(domain: &[u8], content: &[u8]) -> ContentHash {
+    let mut hasher = Sha256::new();
+    hasher.update(domain);
+    hasher.update(content);
+    ContentHash::new(hasher.finalize().into())
+}
+
+/// Counters describing cache effectiveness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that returned a record.
+    pub hits: u64,
+    /// Lookups that found nothing.
+    pub misses: u64,
+    /// Entries dropped to stay within the size bound.
+    pub evictions: u64,
+    /// Entries currently held.
+    pub entries: usize,
+    /// Total size of the entries currently held, in bytes.
+    pub size_bytes: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    record: ExecutionRecord,
+    size: u64,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// `last_used` tick of every entry, oldest first.
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    size_bytes: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &CacheKey) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.last_used);
+            entry.last_used = tick;
+            self.recency.insert(tick, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        let Some(entry) = self.entries.remove(key) else {
+            return false;
+        };
+        self.recency.remove(&entry.last_used);
+        self.size_bytes -= entry.size;
+        true
+    }
+}
+
+/// Size-bounded, least-recently-used cache of execution records.
+#[derive(Debug)]
+pub struct ExecutionCache {
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+    image_hashes: ImageHashes,
+}
+
+impl ExecutionCache {
+    /// Create an empty cache holding at most `max_bytes` of entries.
+    #[must_use]
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+            image_hashes: ImageHashes::default(),
+        }
+    }
+
+    /// As [`CacheKey::new`], hashing only the images that changed since
+    /// this cache last hashed them.
+    ///
+    /// # Errors
+    /// See [`CacheKey::new`].
+    pub async fn key(
+        &self,
+        block: &Block,
+        input: ContentHash,
+        vm_config: &VmConfig,
+        isolation: IsolationLevel,
+    ) -> Result<CacheKey, ExecutorError> {
+        CacheKey::build(block, input, vm_config, isolation, Some(&self.image_hashes)).await
+    }
+
+    /// Image hashes remembered by this cache, for checking content-addressed
+    /// images without re-reading unchanged ones.
+    pub(crate) const fn image_hashes(&self) -> &ImageHashes {
+        &self.image_hashes
+    }
+
+    /// The record stored under `key`, marked as cached.
+    pub async fn get(&self, key: &CacheKey) -> Option<ExecutionRecord> {
+        let mut state = self.state.lock().await;
+        let record = state.entries.get(key).map(|entry| entry.record.clone().with_cached());
+        if record.is_some() {
+            state.hits += 1;
+            state.touch(key);
+        } else {
+            state.misses += 1;
+        }
+        record
+    }
+
+    /// Store `record` under `key`, replacing any previous entry and
+    /// evicting least recently used entries until the cache fits its bound.
+    /// Records larger than the whole bound are not stored.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the record cannot be serialised to
+    /// measure it.
+    pub async fn insert(
+        &self,
+        key: CacheKey,
+        record: ExecutionRecord,
+    ) -> Result<(), ExecutorError> {
+        let size = entry_size(&record)?;
+        let mut state = self.state.lock().await;
+        state.remove(&key);
+        if size > self.max_bytes {
+            tracing::debug!(size, max_bytes = self.max_bytes, "execution too large to cache");
+            return Ok(());
+        }
+
+        while state.size_bytes + size > self.max_bytes {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&oldest) {
+                state.size_bytes -= entry.size;
+                state.evictions += 1;
+            }
+        }
+
+        state.tick += 1;
+        let tick = state.tick;
+        state.recency.insert(tick, key.clone());
+        state.entries.insert(key, CacheEntry { record, size, last_used: tick });
+        state.size_bytes += size;
+        drop(state);
+        Ok(())
+    }
+
+    /// Drop the entry under `key`. Returns whether there was one.
+    pub async fn invalidate(&self, key: &CacheKey) -> bool {
+        self.state.lock().await.remove(key)
+    }
+
+    /// Drop every entry for `block_id`, returning how many there were.
+    pub async fn invalidate_block(&self, block_id: BlockId) -> usize {
+        let mut state = self.state.lock().await;
+        let keys: Vec<_> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.record.block_id == block_id)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            state.remove(key);
+        }
+        drop(state);
+        keys.len()
+    }
+
+    /// Drop every entry.
+    pub async fn clear(&self) {
+        let mut state = self.state.lock().await;
+        state.entries.clear();
+        state.recency.clear();
+        state.size_bytes = 0;
+    }
+
+    /// Current counters.
+    pub async fn stats(&self) -> CacheStats {
+        let state = self.state.lock().await;
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            evictions: state.evictions,
+            entries: state.entries.len(),
+            size_bytes: state.size_bytes,
+        }
+    }
+}
+
+/// What an entry counts against the bound: the serialised record plus the
+/// artifacts it keeps alive.
+fn entry_size(record: &ExecutionRecord) -> std::io::Result<u64> {
+    let record_size = to_json(record)?.len() as u64;
+    Ok(record.artifacts.iter().fold(record_size, |size, a| size.saturating_add(a.size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use forge_core::execution::{Artifact, ExecutionStatus};
+    use forge_core::id::UserId;
+
+    use super::*;
+    use crate::compute_hash;
+
+    fn key(input: &[u8]) -> CacheKey {
+        CacheKey {
+            block: compute_hash(b"block", b""),
+            derivation: DerivationHash::new("ywi5ib7yrjba3k3b26yfnbx7gappr3dg")
+                .unwrap_or_else(|e| panic!("invalid hash: {e}")),
+            kernel: compute_hash(b"kernel", b""),
+            rootfs: compute_hash(b"rootfs", b""),
+            layers: Vec::new(),
+            cpu_template: None,
+            input: compute_hash(input, b""),
+            vm_config: compute_hash(b"config", b""),
+            isolation: IsolationLevel::MicroVm,
+        }
+    }
+
+    fn record(block_id: BlockId, artifact_size: u64) -> ExecutionRecord {
+        ExecutionRecord::new(
+            block_id,
+            UserId::new("test"),
+            compute_hash(b"", b""),
+            compute_hash(b"out", b""),
+            Utc::now(),
+            Duration::ZERO,
+            ExecutionStatus::Succeeded,
+        )
+        .with_artifacts(vec![Artifact::new(
+            "/out/a".to_owned(),
+            compute_hash(b"a", b""),
+            artifact_size,
+        )])
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn hits_are_marked_cached_and_counted() {
+        let cache = ExecutionCache::new(1 << 20);
+        let stored = record(BlockId::new(), 10);
+        if let Err(e) = cache.insert(key(b"in"), stored.clone()).await {
+            panic!("insert failed: {e}");
+        }
+
+        let hit = cache.get(&key(b"in")).await.unwrap_or_else(|| panic!("expected a hit"));
+        assert!(hit.cached);
+        assert!(!stored.cached);
+        assert_eq!(hit.id, stored.id);
+        assert!(cache.get(&key(b"other")).await.is_none());
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn least_recently_used_entries_are_evicted_first() {
+        let block = BlockId::new();
+        let size = entry_size(&record(block, 1000)).unwrap_or_else(|e| panic!("size: {e}"));
+        let cache = ExecutionCache::new(size * 2);
+        for input in [b"a", b"b"] {
+            if let Err(e) = cache.insert(key(input), record(block, 1000)).await {
+                panic!("insert failed: {e}");
+            }
+        }
+        assert!(cache.get(&key(b"a")).await.is_some(), "touch a so b is oldest");
+        if let Err(e) = cache.insert(key(b"c"), record(block, 1000)).await {
+            panic!("insert failed: {e}");
+        }
+
+        assert!(cache.get(&key(b"a")).await.is_some());
+        assert!(cache.get(&key(b"b")).await.is_none(), "b must be evicted");
+        assert!(cache.get(&key(b"c")).await.is_some());
+        let stats = cache.stats().await;
+        assert_eq!(stats.evictions, 1);
+        assert!(stats.size_bytes <= size * 2);
+
+        if let Err(e) = cache.insert(key(b"huge"), record(block, size * 3)).await {
+            panic!("insert failed: {e}");
+        }
+        assert!(cache.get(&key(b"huge")).await.is_none(), "oversized records are not stored");
+        assert_eq!(cache.stats().await.entries, 2, "oversized records evict nothing");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn invalidation_drops_entries() {
+        let cache = ExecutionCache::new(1 << 20);
+        let block = BlockId::new();
+        let other = BlockId::new();
+        for (input, id) in [(b"a", block), (b"b", block), (b"c", other)] {
+            if let Err(e) = cache.insert(key(input), record(id, 0)).await {
+                panic!("insert failed: {e}");
+            }
+        }
+
+        assert!(cache.invalidate(&key(b"c")).await);
+        assert!(!cache.invalidate(&key(b"c")).await);
+        assert_eq!(cache.invalidate_block(block).await, 2);
+        let stats = cache.stats().await;
+        assert_eq!((stats.entries, stats.size_bytes), (0, 0));
+    }
+}
@@ -0,0 +1,213 @@
+//! Output canonicalisation — removing run-to-run entropy before hashing.
+//!
+//! A block's manifest declares [`CanonicalizationRule`]s; [`canonicalize`]
+//! applies them in order to both streams of an [`ExecutionOutput`].
+//! [`BlockRunner`](crate::BlockRunner) hashes the result as the record's
+//! canonical output hash, next to the hash of the raw output.
+
+use regex::bytes::Regex;
+
+use forge_core::block::CanonicalizationRule;
+
+use crate::backend::ExecutionOutput;
+use crate::ExecutorError;
+
+/// What [`CanonicalizationRule::NormalizePaths`] replaces path components
+/// with.
+const PATH_PLACEHOLDER: &[u8] = b"_";
+
+/// Apply `rules` in order to a copy of `output`.
+///
+/// The exit code is never changed.
+///
+/// # Errors
+/// Returns [`ExecutorError::InvalidCanonicalization`] if a
+/// [`CanonicalizationRule::Redact`] pattern does not compile or a
+/// [`CanonicalizationRule::NormalizePaths`] prefix is empty, or for rules
+/// this version does not implement.
+pub fn canonicalize(
+    rules: &[CanonicalizationRule],
+    output: &ExecutionOutput,
+) -> Result<ExecutionOutput, ExecutorError> {
+    let mut canonical = output.clone();
+    for (index, rule) in rules.iter().enumerate() {
+        let invalid = |reason: String| ExecutorError::InvalidCanonicalization { index, reason };
+        match rule {
+            CanonicalizationRule::Redact { pattern, replacement } => {
+                let regex = Regex::new(pattern).map_err(|e| invalid(e.to_string()))?;
+                for stream in [&mut canonical.stdout, &mut canonical.stderr] {
+                    *stream = regex.replace_all(stream, replacement.as_bytes()).into_owned();
+                }
+            }
+            CanonicalizationRule::SortLines => {
+                for stream in [&mut canonical.stdout, &mut canonical.stderr] {
+                    *stream = sort_lines(stream);
+                }
+            }
+            CanonicalizationRule::NormalizePaths { prefixes } => {
+                if prefixes.iter().any(String::is_empty) {
+                    return Err(invalid("path prefixes must not be empty".to_owned()));
+                }
+                for stream in [&mut canonical.stdout, &mut canonical.stderr] {
+                    *stream = normalize_paths(stream, prefixes);
+                }
+            }
+            CanonicalizationRule::IgnoreStderr => canonical.stderr.clear(),
+            CanonicalizationRule::SortJsonKeys => {
+                for stream in [&mut canonical.stdout, &mut canonical.stderr] {
+                    *stream = sort_json_keys(stream);
+                }
+            }
+            // Skipping a rule would silently change what the hash means.
+            _ => return Err(invalid(format!("unsupported rule {rule:?}"))),
+        }
+    }
+    Ok(canonical)
+}
+
+/// Sort the lines of `stream`, keeping a trailing newline if there was one.
+fn sort_lines(stream: &[u8]) -> Vec<u8> {
+    let (body, terminated) =
+        stream.strip_suffix(b"\n").map_or((stream, false), |body| (body, true));
+    if body.is_empty() {
+        return stream.to_vec();
+    }
+    let mut lines: Vec<&[u8]> = body.split(|&b| b == b'\n').collect();
+    lines.sort_unstable();
+    let mut sorted = lines.join(&b'\n');
+    if terminated {
+        sorted.push(b'\n');
+    }
+    sorted
+}
+
+/// Replace the path component following each occurrence of a prefix with
+/// [`PATH_PLACEHOLDER`]. A component ends at `/`, whitespace, or a quote.
+fn normalize_paths(stream: &[u8], prefixes: &[String]) -> Vec<u8> {
+    let is_boundary = |b: u8| b == b'/' || b.is_ascii_whitespace() || b == b'"' || b == b'\'';
+    let mut normalized = Vec::with_capacity(stream.len());
+    let mut rest = stream;
+    while !rest.is_empty() {
+        let Some(prefix) = prefixes.iter().find(|p| rest.starts_with(p.as_bytes())) else {
+            normalized.push(rest[0]);
+            rest = &rest[1..];
+            continue;
+        };
+        normalized.extend_from_slice(prefix.as_bytes());
+        rest = &rest[prefix.len()..];
+        let component = rest.iter().position(|&b| is_boundary(b)).unwrap_or(rest.len());
+        if component > 0 {
+            normalized.extend_from_slice(PATH_PLACEHOLDER);
+        }
+        rest = &rest[component..];
+    }
+    normalized
+}
+
+/// Re-serialise JSON with sorted object keys: the whole stream if it parses
+/// as one document, otherwise each line that does.
+fn sort_json_keys(stream: &[u8]) -> Vec<u8> {
+    // `serde_json::Value` keeps object keys in a `BTreeMap`, so a parse and
+    // re-serialise sorts them.
+    let reserialize = |json: &[u8]| {
+        serde_json::from_slice::<serde_json::Value>(json)
+            .ok()
+            .and_then(|value| serde_json::to_vec(&value).ok())
+    };
+
+    if let Some(mut sorted) = reserialize(stream) {
+        if stream.ends_with(b"\n") {
+            sorted.push(b'\n');
+        }
+        return sorted;
+    }
+
+    let mut sorted = Vec::with_capacity(stream.len());
+    for (i, line) in stream.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            sorted.push(b'\n');
+        }
+        match reserialize(line) {
+            Some(json) if !line.is_empty() => sorted.extend_from_slice(&json),
+            _ => sorted.extend_from_slice(line),
+        }
+    }
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(stdout: &[u8], stderr: &[u8]) -> ExecutionOutput {
+        ExecutionOutput { stdout: stdout.to_vec(), stderr: stderr.to_vec(), exit_code: 0 }
+    }
+
+    fn apply(rules: &[CanonicalizationRule], stdout: &[u8]) -> Vec<u8> {
+        match canonicalize(rules, &output(stdout, b"")) {
+            Ok(canonical) => canonical.stdout,
+            Err(e) => panic!("canonicalisation failed: {e}"),
+        }
+    }
+
+    #[test]
+    fn redaction_replaces_every_match() {
+        let rule = CanonicalizationRule::Redact {
+            pattern: r"\d{2}:\d{2}:\d{2}".to_owned(),
+            replacement: "<time>".to_owned(),
+        };
+        assert_eq!(
+            apply(&[rule], b"start 10:00:01\nend 10:00:09\n"),
+            b"start <time>\nend <time>\n"
+        );
+    }
+
+    #[test]
+    fn invalid_pattern_names_the_rule() {
+        let rules = [
+            CanonicalizationRule::SortLines,
+            CanonicalizationRule::Redact { pattern: "(".to_owned(), replacement: String::new() },
+        ];
+        let result = canonicalize(&rules, &output(b"", b""));
+        assert!(
+            matches!(result, Err(ExecutorError::InvalidCanonicalization { index: 1, .. })),
+            "bad pattern must be reported with its index, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn sort_lines_keeps_trailing_newline() {
+        assert_eq!(apply(&[CanonicalizationRule::SortLines], b"b\nc\na\n"), b"a\nb\nc\n");
+        assert_eq!(apply(&[CanonicalizationRule::SortLines], b"b\na"), b"a\nb");
+    }
+
+    #[test]
+    fn normalize_paths_replaces_random_components() {
+        let rule = CanonicalizationRule::NormalizePaths { prefixes: vec!["/tmp/".to_owned()] };
+        assert_eq!(
+            apply(&[rule], b"wrote /tmp/tmp.Xa81kq/out and \"/tmp/build-7\"\n"),
+            b"wrote /tmp/_/out and \"/tmp/_\"\n"
+        );
+    }
+
+    #[test]
+    fn ignore_stderr_clears_only_stderr() {
+        let canonical =
+            match canonicalize(&[CanonicalizationRule::IgnoreStderr], &output(b"out", b"noise")) {
+                Ok(canonical) => canonical,
+                Err(e) => panic!("canonicalisation failed: {e}"),
+            };
+        assert_eq!(canonical.stdout, b"out");
+        assert!(canonical.stderr.is_empty());
+    }
+
+    #[test]
+    fn json_keys_are_sorted_per_document_or_line() {
+        let rule = CanonicalizationRule::SortJsonKeys;
+        assert_eq!(apply(&[rule.clone()], b"{\"b\":1,\n \"a\":2}\n"), b"{\"a\":2,\"b\":1}\n");
+        assert_eq!(
+            apply(&[rule], b"{\"z\":0,\"y\":1}\nplain text\n"),
+            b"{\"y\":1,\"z\":0}\nplain text\n"
+        );
+    }
+}
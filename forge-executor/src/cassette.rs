@@ -0,0 +1,602 @@
+//! Record-and-replay of executions.
+//!
+//! [`RecordingBackend`] wraps any [`VmmBackend`] and writes every successful
+//! [`VmmBackend::execute_command`] to a [`Cassette`]. [`ReplayBackend`] serves
+//! executions back out of a cassette without any VM, and fails with
+//! [`ExecutorError::UnrecordedExecution`] on anything it has not seen, so
+//! runner and gateway behaviour can be regression-tested offline.
+//!
+//! # Layout
+//! A cassette is a content-addressed directory:
+//!
+//! ```text
+//! <dir>/requests/<request-key>.json   one CassetteEntry per request
+//! <dir>/blobs/<sha256>                stdout and stderr bodies
+//! ```
+//!
+//! The request key is the SHA-256 of the JSON encoding of the
+//! [`VmConfig`] and command, so the same request always maps to the same
+//! entry and re-recording it replaces the previous take. The timeout is not
+//! part of the key. Identical outputs share one blob.
+//!
+//! Each entry also records the [`IsolationLevel`] of the backend that served
+//! it. The guest environment, and with it the command line, depends on that
+//! level, so a replay reports the level the cassette was recorded at; a
+//! cassette mixing levels cannot be replayed.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use forge_core::execution::IsolationLevel;
+use forge_core::id::ContentHash;
+
+use crate::backend::{ExecutionOutput, VmmBackend};
+use crate::host::write_atomic;
+use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
+
+/// One recorded execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct CassetteEntry {
+    /// Configuration the command ran under.
+    pub config: VmConfig,
+
+    /// The command line.
+    pub command: String,
+
+    /// SHA-256 of the captured stdout, naming its blob.
+    pub stdout_sha256: ContentHash,
+
+    /// SHA-256 of the captured stderr, naming its blob.
+    pub stderr_sha256: ContentHash,
+
+    /// Exit code of the command.
+    pub exit_code: i32,
+
+    /// Wall-clock time the recorded execution took.
+    pub duration: Duration,
+
+    /// Isolation level of the backend that ran the command. Entries recorded
+    /// before this was stored ran in a microVM.
+    #[serde(default)]
+    pub isolation: IsolationLevel,
+
+    /// When the execution was recorded.
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// A directory of recorded executions.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    dir: PathBuf,
+}
+
+impl Cassette {
+    /// Open the cassette stored in `dir`. The directory is created on the
+    /// first recording.
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Directory holding the cassette.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Content address of a request: SHA-256 over its config and command.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the config cannot be encoded.
+    pub fn request_key(config: &VmConfig, command: &str) -> Result<ContentHash, ExecutorError> {
+        let encoded = serde_json::to_vec(&(config, command)).map_err(invalid_data)?;
+        Ok(ContentHash::new(Sha256::digest(&encoded).into()))
+    }
+
+    fn entry_path(&self, key: &ContentHash) -> PathBuf {
+        self.dir.join("requests").join(format!("{key}.json"))
+    }
+
+    fn blob_path(&self, hash: &ContentHash) -> PathBuf {
+        self.dir.join("blobs").join(hash.to_string())
+    }
+
+    /// Record `output` as the response to running `command` under `config`
+    /// on a backend with the given `isolation`.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the cassette cannot be written.
+    pub async fn record(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        output: &ExecutionOutput,
+        duration: Duration,
+        isolation: IsolationLevel,
+    ) -> Result<CassetteEntry, ExecutorError> {
+        let stdout_sha256 = self.write_blob(&output.stdout).await?;
+        let stderr_sha256 = self.write_blob(&output.stderr).await?;
+        let entry = CassetteEntry {
+            config: config.clone(),
+            command: command.to_owned(),
+            stdout_sha256,
+            stderr_sha256,
+            exit_code: output.exit_code,
+            duration,
+            isolation,
+            recorded_at: Utc::now(),
+        };
+
+        let key = Self::request_key(config, command)?;
+        let json = serde_json::to_vec_pretty(&entry).map_err(invalid_data)?;
+        write_atomic(&self.entry_path(&key), &json).await?;
+
+        tracing::info!(request = %key, %command, "execution recorded");
+
+        Ok(entry)
+    }
+
+    /// Look up the recorded response to running `command` under `config`.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the entry or its blobs cannot be read,
+    /// or a blob no longer matches its hash.
+    pub async fn lookup(
+        &self,
+        config: &VmConfig,
+        command: &str,
+    ) -> Result<Option<(CassetteEntry, ExecutionOutput)>, ExecutorError> {
+        let key = Self::request_key(config, command)?;
+        let json = match tokio::fs::read(self.entry_path(&key)).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let entry: CassetteEntry = serde_json::from_slice(&json).map_err(invalid_data)?;
+
+        let output = ExecutionOutput {
+            stdout: self.read_blob(&entry.stdout_sha256).await?,
+            stderr: self.read_blob(&entry.stderr_sha256).await?,
+            exit_code: entry.exit_code,
+        };
+        Ok(Some((entry, output)))
+    }
+
+    /// Every recorded entry, oldest first.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the cassette cannot be read.
+    pub async fn entries(&self) -> Result<Vec<CassetteEntry>, ExecutorError> {
+        let mut entries = Vec::new();
+        let mut dir = match tokio::fs::read_dir(self.dir.join("requests")).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(file) = dir.next_entry().await? {
+            if file.path().extension().is_some_and(|ext| ext == "json") {
+                let json = tokio::fs::read(file.path()).await?;
+                entries.push(serde_json::from_slice(&json).map_err(invalid_data)?);
+            }
+        }
+        entries.sort_by_key(|e: &CassetteEntry| e.recorded_at);
+        Ok(entries)
+    }
+
+    /// The isolation level every entry was recorded at, or `None` if the
+    /// cassette is empty.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the cassette cannot be read, or its
+    /// entries were recorded at different isolation levels.
+    pub async fn isolation(&self) -> Result<Option<IsolationLevel>, ExecutorError> {
+        let mut levels = self.entries().await?.into_iter().map(|e| e.isolation);
+        let Some(first) = levels.next() else { return Ok(None) };
+        if let Some(other) = levels.find(|level| *level != first) {
+            return Err(ExecutorError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("cassette {} mixes {first:?} and {other:?} entries", self.dir.display()),
+            )));
+        }
+        Ok(Some(first))
+    }
+
+    async fn write_blob(&self, bytes: &[u8]) -> Result<ContentHash, ExecutorError> {
+        let hash = ContentHash::new(Sha256::digest(bytes).into());
+        let path = self.blob_path(&hash);
+        if !tokio::fs::try_exists(&path).await? {
+            write_atomic(&path, bytes).await?;
+        }
+        Ok(hash)
+    }
+
+    async fn read_blob(&self, hash: &ContentHash) -> Result<Vec<u8>, ExecutorError> {
+        let bytes = tokio::fs::read(self.blob_path(hash)).await?;
+        let actual = ContentHash::new(Sha256::digest(&bytes).into());
+        if actual != *hash {
+            return Err(ExecutorError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("cassette blob {hash} is corrupt (hashes to {actual})"),
+            )));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Backend wrapper that records every successful execution to a cassette.
+///
+/// Failed executions are passed through unrecorded. All other operations
+/// are delegated unchanged.
+#[derive(Debug)]
+pub struct RecordingBackend<B: VmmBackend> {
+    inner: B,
+    cassette: Cassette,
+}
+
+impl<B: VmmBackend> RecordingBackend<B> {
+    /// Record executions served by `inner` into `cassette`.
+    #[must_use]
+    pub const fn new(inner: B, cassette: Cassette) -> Self {
+        Self { inner, cassette }
+    }
+
+    /// The wrapped backend.
+    #[must_use]
+    pub const fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// The cassette being recorded into.
+    #[must_use]
+    pub const fn cassette(&self) -> &Cassette {
+        &self.cassette
+    }
+}
+
+#[async_trait]
+impl<B: VmmBackend> VmmBackend for RecordingBackend<B> {
+    async fn spawn(&self, config: &VmConfig) -> Result<VmHandle, ExecutorError> {
+        self.inner.spawn(config).await
+    }
+
+    async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        self.inner.snapshot(handle).await
+    }
+
+    async fn diff_snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        self.inner.diff_snapshot(handle).await
+    }
+
+    async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        self.inner.restore(snapshot_id).await
+    }
+
+    async fn delete_snapshot(&self, snapshot_id: &SnapshotId) -> Result<(), ExecutorError> {
+        self.inner.delete_snapshot(snapshot_id).await
+    }
+
+    async fn terminate(&self, handle: VmHandle) -> Result<(), ExecutorError> {
+        self.inner.terminate(handle).await
+    }
+
+    async fn health_check(&self) -> Result<(), ExecutorError> {
+        self.inner.health_check().await
+    }
+
+    async fn execute_command(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let started = Instant::now();
+        let output = self.inner.execute_command(config, command, timeout).await?;
+        self.cassette
+            .record(config, command, &output, started.elapsed(), self.inner.isolation())
+            .await?;
+        Ok(output)
+    }
+
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        self.inner.execute_in_vm(handle, command, timeout).await
+    }
+
+    fn isolation(&self) -> IsolationLevel {
+        self.inner.isolation()
+    }
+}
+
+/// Backend that serves executions from a cassette instead of running them.
+///
+/// Only [`VmmBackend::execute_command`] is supported; there are no VMs to
+/// spawn, snapshot, or run commands in. [`VmmBackend::isolation`] reports the
+/// level the cassette was recorded at.
+#[derive(Debug, Clone)]
+pub struct ReplayBackend {
+    cassette: Cassette,
+    isolation: IsolationLevel,
+    recorded_timing: bool,
+}
+
+impl ReplayBackend {
+    /// Replay executions recorded in `cassette`, responding immediately.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the cassette cannot be read, or its
+    /// entries were recorded at different isolation levels.
+    pub async fn open(cassette: Cassette) -> Result<Self, ExecutorError> {
+        let isolation = cassette.isolation().await?.unwrap_or_default();
+        Ok(Self { cassette, isolation, recorded_timing: false })
+    }
+
+    /// Take as long to respond as the recorded execution did, and time out
+    /// like it would have if that exceeds the caller's timeout.
+    #[must_use]
+    pub const fn with_recorded_timing(mut self) -> Self {
+        self.recorded_timing = true;
+        self
+    }
+
+    /// The cassette being replayed.
+    #[must_use]
+    pub const fn cassette(&self) -> &Cassette {
+        &self.cassette
+    }
+}
+
+#[async_trait]
+impl VmmBackend for ReplayBackend {
+    async fn spawn(&self, _config: &VmConfig) -> Result<VmHandle, ExecutorError> {
+        Err(ExecutorError::Unsupported("spawn during replay".to_owned()))
+    }
+
+    async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("snapshot of VM {} during replay", handle.id)))
+    }
+
+    async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("restore from {snapshot_id} during replay")))
+    }
+
+    async fn terminate(&self, handle: VmHandle) -> Result<(), ExecutorError> {
+        Err(ExecutorError::VmNotFound(handle.id))
+    }
+
+    async fn health_check(&self) -> Result<(), ExecutorError> {
+        tokio::fs::metadata(self.cassette.dir()).await?;
+        Ok(())
+    }
+
+    async fn execute_command(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let Some((entry, output)) = self.cassette.lookup(config, command).await? else {
+            let request_key = Cassette::request_key(config, command)?.to_string();
+            tracing::error!(%command, request = %request_key, "unrecorded execution during replay");
+            return Err(ExecutorError::UnrecordedExecution {
+                command: command.to_owned(),
+                request_key,
+            });
+        };
+
+        if self.recorded_timing {
+            tokio::time::sleep(entry.duration.min(timeout)).await;
+            if entry.duration > timeout {
+                return Err(ExecutorError::Io(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("command did not complete within {}s", timeout.as_secs()),
+                )));
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+}
+
+/// Wrap a JSON (de)serialisation failure as an `InvalidData` I/O error.
+fn invalid_data(e: serde_json::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::mock::{MockBackend, MockResponse};
+
+    fn temp_cassette() -> Cassette {
+        Cassette::new(std::env::temp_dir().join(format!("forge-cassette-test-{}", Uuid::new_v4())))
+    }
+
+    fn test_config() -> VmConfig {
+        VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"))
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn open_replay(cassette: &Cassette) -> ReplayBackend {
+        ReplayBackend::open(cassette.clone()).await.unwrap_or_else(|e| panic!("open failed: {e}"))
+    }
+
+    async fn record_outputs(cassette: &Cassette, commands: &[&str]) {
+        let backend = MockBackend::new()
+            .on_command("build", MockResponse::exit(2).with_stderr("missing dep\n"));
+        let recorder = RecordingBackend::new(backend, cassette.clone());
+        for command in commands {
+            if let Err(e) = recorder.execute_command(&test_config(), command, TIMEOUT).await {
+                panic!("recording {command} failed: {e}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn replay_serves_recorded_outputs() {
+        let cassette = temp_cassette();
+        record_outputs(&cassette, &["echo hi", "build"]).await;
+
+        let replay = open_replay(&cassette).await;
+        let echoed = replay.execute_command(&test_config(), "echo hi", TIMEOUT).await;
+        let built = replay.execute_command(&test_config(), "build", TIMEOUT).await;
+
+        match (echoed, built) {
+            (Ok(echoed), Ok(built)) => {
+                assert_eq!(echoed.stdout, b"echo hi");
+                assert_eq!((built.exit_code, built.stderr), (2, b"missing dep\n".to_vec()));
+            }
+            other => panic!("replay failed: {other:?}"),
+        }
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn sandbox_recordings_replay_through_the_runner() {
+        use forge_core::examples::example_blocks;
+
+        use crate::BlockRunner;
+
+        let cassette = temp_cassette();
+        let block = &example_blocks()[0];
+        let backend = MockBackend::new().with_isolation(IsolationLevel::ProcessSandbox);
+        let recorder =
+            BlockRunner::new(RecordingBackend::new(backend, cassette.clone()), test_config());
+        let original = recorder
+            .execute(block, b"in")
+            .await
+            .unwrap_or_else(|e| panic!("recording failed: {e}"));
+
+        let replay = open_replay(&cassette).await;
+        assert_eq!(replay.isolation(), IsolationLevel::ProcessSandbox);
+        let replayed = BlockRunner::new(replay, test_config())
+            .execute(block, b"in")
+            .await
+            .unwrap_or_else(|e| panic!("replay failed: {e}"));
+        assert_eq!(replayed.output_hash, original.output_hash);
+        assert_eq!(replayed.isolation, IsolationLevel::ProcessSandbox);
+        assert_eq!(replayed.guest, original.guest);
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn cassettes_mixing_isolation_levels_are_rejected() {
+        let cassette = temp_cassette();
+        let output = ExecutionOutput { stdout: Vec::new(), stderr: Vec::new(), exit_code: 0 };
+        for (command, isolation) in
+            [("a", IsolationLevel::MicroVm), ("b", IsolationLevel::ProcessSandbox)]
+        {
+            if let Err(e) =
+                cassette.record(&test_config(), command, &output, Duration::ZERO, isolation).await
+            {
+                panic!("record failed: {e}");
+            }
+        }
+
+        let result = ReplayBackend::open(cassette.clone()).await;
+        assert!(
+            matches!(&result, Err(ExecutorError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData),
+            "a mixed cassette must be rejected, got {result:?}"
+        );
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn replay_fails_loudly_on_unrecorded_request() {
+        let cassette = temp_cassette();
+        record_outputs(&cassette, &["echo hi"]).await;
+
+        let replay = open_replay(&cassette).await;
+        let mut other_config = test_config();
+        other_config.vcpu_count = 2;
+
+        let unknown_command = replay.execute_command(&test_config(), "echo bye", TIMEOUT).await;
+        let unknown_config = replay.execute_command(&other_config, "echo hi", TIMEOUT).await;
+        for result in [unknown_command, unknown_config] {
+            assert!(
+                matches!(result, Err(ExecutorError::UnrecordedExecution { .. })),
+                "unrecorded request must fail, got {result:?}"
+            );
+        }
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn identical_outputs_share_a_blob() {
+        let cassette = temp_cassette();
+        // Both stderrs are empty; the two stdouts differ.
+        record_outputs(&cassette, &["a", "b", "a"]).await;
+
+        let blobs = std::fs::read_dir(cassette.dir().join("blobs")).map_or(0, Iterator::count);
+        assert_eq!(blobs, 3, "empty stderr and the two stdouts are stored once each");
+        let entries = cassette.entries().await.unwrap_or_else(|e| panic!("entries: {e}"));
+        assert_eq!(entries.len(), 2, "re-recording a request replaces its entry");
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn corrupt_blob_is_rejected() {
+        let cassette = temp_cassette();
+        record_outputs(&cassette, &["echo hi"]).await;
+
+        let blob = cassette.blob_path(&ContentHash::new(Sha256::digest(b"echo hi").into()));
+        if let Err(e) = tokio::fs::write(&blob, b"tampered").await {
+            panic!("failed to tamper with blob: {e}");
+        }
+        let result = cassette.lookup(&test_config(), "echo hi").await;
+        assert!(
+            matches!(&result, Err(ExecutorError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData),
+            "tampered blob must be rejected, got {result:?}"
+        );
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn recorded_timing_reproduces_timeouts() {
+        let cassette = temp_cassette();
+        let output = ExecutionOutput { stdout: Vec::new(), stderr: Vec::new(), exit_code: 0 };
+        if let Err(e) = cassette
+            .record(
+                &test_config(),
+                "slow",
+                &output,
+                Duration::from_secs(60),
+                IsolationLevel::MicroVm,
+            )
+            .await
+        {
+            panic!("record failed: {e}");
+        }
+
+        let replay = open_replay(&cassette).await.with_recorded_timing();
+        let result = replay.execute_command(&test_config(), "slow", TIMEOUT).await;
+        assert!(
+            matches!(&result, Err(ExecutorError::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut),
+            "recording slower than the timeout must time out, got {result:?}"
+        );
+        let _ = tokio::fs::remove_dir_all(cassette.dir()).await;
+    }
+}
@@ -0,0 +1,1022 @@
+//! Snapshot catalogue: metadata, integrity verification, and retention.
+//!
+//! Every snapshot in a snapshot directory is a pair of files,
+//! `<id>.mem` and `<id>.state`. The catalogue adds a `<id>.json` sidecar
+//! recording where the snapshot came from and the SHA-256 of both files, so
+//! a restore can refuse snapshots that were truncated or tampered with, and
+//! old snapshots can be garbage collected by age, count, or total size.
+//!
+//! A snapshot of a VM whose root disk is writable also has a `<id>.rootfs`:
+//! a copy of that disk taken while the VM was paused. It is catalogued and
+//! verified with the other two files, so that every VM restored from the
+//! snapshot can start from a private clone of the disk the memory expects.
+//!
+//! Diff snapshots only contain the memory pages dirtied since their parent.
+//! Their memory file is sparse: pages that were not dirtied are holes. The
+//! catalogue links each diff to its parent and records the diff's data
+//! regions when it is catalogued, so a later copy that fills the holes with
+//! zeros cannot change what the diff dirties; a filesystem that does not
+//! report holes is refused then. [`SnapshotCatalog::merge`] flattens a chain
+//! into a standalone full snapshot by writing every diff's data regions over
+//! a copy of the base memory file, and [`SnapshotCatalog::flattened`] keeps
+//! that flattening for the next restore.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use forge_core::id::ContentHash;
+
+use crate::{ExecutorError, SnapshotId, VmConfig};
+
+/// Catalogue entry describing a single snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct SnapshotMetadata {
+    /// The snapshot this entry describes.
+    pub id: SnapshotId,
+
+    /// ID of the VM the snapshot was taken from.
+    pub source_vm: Uuid,
+
+    /// Configuration the source VM was booted with, if known.
+    pub source_config: Option<VmConfig>,
+
+    /// For diff snapshots, the snapshot whose memory this one's dirty pages
+    /// apply on top of. `None` for full snapshots.
+    #[serde(default)]
+    pub parent: Option<SnapshotId>,
+
+    /// When the snapshot was written.
+    pub created_at: DateTime<Utc>,
+
+    /// Size of the guest memory file in bytes.
+    pub mem_size: u64,
+
+    /// Size of the VM state file in bytes.
+    pub state_size: u64,
+
+    /// SHA-256 of the guest memory file.
+    pub mem_sha256: ContentHash,
+
+    /// SHA-256 of the VM state file.
+    pub state_sha256: ContentHash,
+
+    /// Size of the root disk copy in bytes, or 0 if there is none.
+    #[serde(default)]
+    pub disk_size: u64,
+
+    /// SHA-256 of the root disk copy, if the source VM's root disk was writable.
+    #[serde(default)]
+    pub disk_sha256: Option<ContentHash>,
+
+    /// For diff snapshots, the data regions of the memory file as
+    /// `(offset, length)` pairs: the pages the diff dirtied. `None` for full
+    /// snapshots and for diffs catalogued before regions were recorded.
+    #[serde(default)]
+    pub dirty_regions: Option<Vec<(u64, u64)>>,
+
+    /// For a flattening kept by [`SnapshotCatalog::flattened`], the diff
+    /// snapshot it flattens. It is deleted along with that snapshot.
+    #[serde(default)]
+    pub flattened_from: Option<SnapshotId>,
+}
+
+impl SnapshotMetadata {
+    /// Whether this is a diff snapshot that depends on a parent.
+    #[must_use]
+    pub const fn is_diff(&self) -> bool {
+        self.parent.is_some()
+    }
+
+    /// Whether the snapshot carries a copy of its source VM's root disk.
+    #[must_use]
+    pub const fn has_disk(&self) -> bool {
+        self.disk_sha256.is_some()
+    }
+
+    /// Combined on-disk size of the snapshot files.
+    #[must_use]
+    pub const fn total_size(&self) -> u64 {
+        self.mem_size + self.state_size + self.disk_size
+    }
+}
+
+/// Limits applied by [`SnapshotCatalog::gc`].
+///
+/// Each limit is optional; the default policy keeps everything. Snapshots are
+/// considered newest first, so count and size limits evict the oldest. A
+/// snapshot that is the parent of a retained diff is always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RetentionPolicy {
+    /// Delete snapshots older than this.
+    pub max_age: Option<Duration>,
+
+    /// Keep at most this many snapshots.
+    pub max_count: Option<usize>,
+
+    /// Keep at most this many bytes of snapshot files.
+    pub max_total_bytes: Option<u64>,
+
+    /// Snapshots that are never deleted, e.g. warm pool base images in use.
+    pub pinned: HashSet<SnapshotId>,
+}
+
+/// Outcome of a [`SnapshotCatalog::gc`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GcReport {
+    /// Snapshots that were deleted.
+    pub deleted: Vec<SnapshotId>,
+
+    /// Bytes freed by the deletions.
+    pub freed_bytes: u64,
+
+    /// Number of snapshots left in the catalogue.
+    pub retained: usize,
+}
+
+/// On-disk catalogue of snapshots in a single directory.
+#[derive(Debug, Clone)]
+pub struct SnapshotCatalog {
+    dir: PathBuf,
+}
+
+impl SnapshotCatalog {
+    /// Create a catalogue over `dir`. The directory is created lazily.
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The directory holding snapshot files and their metadata.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the guest memory file for `snapshot_id`.
+    #[must_use]
+    pub fn mem_path(&self, snapshot_id: SnapshotId) -> PathBuf {
+        self.dir.join(format!("{snapshot_id}.mem"))
+    }
+
+    /// Path of the VM state file for `snapshot_id`.
+    #[must_use]
+    pub fn state_path(&self, snapshot_id: SnapshotId) -> PathBuf {
+        self.dir.join(format!("{snapshot_id}.state"))
+    }
+
+    /// Path of the root disk copy for `snapshot_id`, if it has one.
+    #[must_use]
+    pub fn disk_path(&self, snapshot_id: SnapshotId) -> PathBuf {
+        self.dir.join(format!("{snapshot_id}.rootfs"))
+    }
+
+    fn metadata_path(&self, snapshot_id: SnapshotId) -> PathBuf {
+        self.dir.join(format!("{snapshot_id}.json"))
+    }
+
+    /// Hash the snapshot files for `snapshot_id` and write its metadata entry.
+    ///
+    /// Must be called after both files, and the root disk copy if there is
+    /// one, have been fully written. Pass the parent snapshot for diff
+    /// snapshots, `None` for full ones.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotNotFound`] if `parent` is not catalogued.
+    /// Returns [`ExecutorError::Io`] if the files cannot be read or the entry
+    /// cannot be written, or a diff's filesystem does not report holes.
+    pub async fn record(
+        &self,
+        snapshot_id: SnapshotId,
+        source_vm: Uuid,
+        source_config: Option<VmConfig>,
+        parent: Option<SnapshotId>,
+    ) -> Result<SnapshotMetadata, ExecutorError> {
+        let dirty_regions = match parent {
+            Some(parent) => {
+                self.get(parent).await?;
+                let mem_path = self.mem_path(snapshot_id);
+                let regions = tokio::task::spawn_blocking(move || dirty_regions(&mem_path))
+                    .await
+                    .map_err(std::io::Error::other)??;
+                Some(regions)
+            }
+            None => None,
+        };
+
+        let (mem_sha256, mem_size) = hash_file(self.mem_path(snapshot_id)).await?;
+        let (state_sha256, state_size) = hash_file(self.state_path(snapshot_id)).await?;
+        let disk_path = self.disk_path(snapshot_id);
+        let (disk_sha256, disk_size) = if tokio::fs::try_exists(&disk_path).await? {
+            let (hash, size) = hash_file(disk_path).await?;
+            (Some(hash), size)
+        } else {
+            (None, 0)
+        };
+
+        let metadata = SnapshotMetadata {
+            id: snapshot_id,
+            source_vm,
+            source_config,
+            parent,
+            created_at: Utc::now(),
+            mem_size,
+            state_size,
+            mem_sha256,
+            state_sha256,
+            disk_size,
+            disk_sha256,
+            dirty_regions,
+            flattened_from: None,
+        };
+        self.write_entry(&metadata).await?;
+
+        tracing::debug!(
+            snapshot_id = %snapshot_id,
+            bytes = metadata.total_size(),
+            "snapshot recorded in catalogue"
+        );
+
+        Ok(metadata)
+    }
+
+    async fn write_entry(&self, metadata: &SnapshotMetadata) -> Result<(), ExecutorError> {
+        let json = serde_json::to_vec_pretty(metadata).map_err(invalid_data)?;
+        tokio::fs::write(self.metadata_path(metadata.id), json).await?;
+        Ok(())
+    }
+
+    /// Look up the metadata entry for `snapshot_id`.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotNotFound`] if there is no entry.
+    /// Returns [`ExecutorError::Io`] if the entry cannot be read or parsed.
+    pub async fn get(&self, snapshot_id: SnapshotId) -> Result<SnapshotMetadata, ExecutorError> {
+        let json = match tokio::fs::read(self.metadata_path(snapshot_id)).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ExecutorError::SnapshotNotFound(snapshot_id.0));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&json).map_err(|e| invalid_data(e).into())
+    }
+
+    /// List every catalogued snapshot, oldest first.
+    ///
+    /// Entries that cannot be parsed are skipped with a warning.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the directory cannot be read.
+    pub async fn list(&self) -> Result<Vec<SnapshotMetadata>, ExecutorError> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut snapshots = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let parsed = tokio::fs::read(&path)
+                .await
+                .map_err(ExecutorError::from)
+                .and_then(|json| serde_json::from_slice(&json).map_err(|e| invalid_data(e).into()));
+            match parsed {
+                Ok(metadata) => snapshots.push(metadata),
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), error = %e, "skipping catalogue entry");
+                }
+            }
+        }
+
+        snapshots.sort_by_key(|m: &SnapshotMetadata| m.created_at);
+        Ok(snapshots)
+    }
+
+    /// Check that the snapshot files still match their recorded hashes.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotNotFound`] if there is no entry.
+    /// Returns [`ExecutorError::SnapshotIntegrity`] if a file is missing or
+    /// its size or SHA-256 differs from the catalogue.
+    pub async fn verify(&self, snapshot_id: SnapshotId) -> Result<SnapshotMetadata, ExecutorError> {
+        let metadata = self.get(snapshot_id).await?;
+
+        let mut files = vec![
+            ("memory", self.mem_path(snapshot_id), metadata.mem_sha256, metadata.mem_size),
+            ("state", self.state_path(snapshot_id), metadata.state_sha256, metadata.state_size),
+        ];
+        if let Some(disk_sha256) = metadata.disk_sha256 {
+            files.push(("root disk", self.disk_path(snapshot_id), disk_sha256, metadata.disk_size));
+        }
+        for (kind, path, expected_hash, expected_size) in files {
+            let (hash, size) =
+                hash_file(path.clone()).await.map_err(|e| ExecutorError::SnapshotIntegrity {
+                    snapshot_id: snapshot_id.0,
+                    reason: format!("{kind} file {}: {e}", path.display()),
+                })?;
+            if size != expected_size || hash != expected_hash {
+                return Err(ExecutorError::SnapshotIntegrity {
+                    snapshot_id: snapshot_id.0,
+                    reason: format!(
+                        "{kind} file hash mismatch: expected {expected_hash} ({expected_size} \
+                         bytes), found {hash} ({size} bytes)"
+                    ),
+                });
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// The chain ending at `snapshot_id`, starting from its full base snapshot.
+    ///
+    /// A full snapshot's chain is just itself.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotNotFound`] if any link is missing.
+    /// Returns [`ExecutorError::SnapshotIntegrity`] if the chain loops.
+    pub async fn chain(
+        &self,
+        snapshot_id: SnapshotId,
+    ) -> Result<Vec<SnapshotMetadata>, ExecutorError> {
+        let mut chain = vec![self.get(snapshot_id).await?];
+        while let Some(parent) = chain.last().and_then(|m| m.parent) {
+            if chain.iter().any(|m| m.id == parent) {
+                return Err(ExecutorError::SnapshotIntegrity {
+                    snapshot_id: snapshot_id.0,
+                    reason: format!("snapshot chain loops back to {parent}"),
+                });
+            }
+            chain.push(self.get(parent).await?);
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Diff snapshots whose parent is `snapshot_id`, oldest first.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the catalogue cannot be listed.
+    pub async fn children(
+        &self,
+        snapshot_id: SnapshotId,
+    ) -> Result<Vec<SnapshotMetadata>, ExecutorError> {
+        let mut snapshots = self.list().await?;
+        snapshots.retain(|m| m.parent == Some(snapshot_id));
+        Ok(snapshots)
+    }
+
+    /// Flatten the chain ending at `snapshot_id` into a new full snapshot.
+    ///
+    /// Every link is verified first. The new snapshot takes the tip's state
+    /// file, root disk copy and source, and its memory is the base memory with each diff's
+    /// dirty pages applied in order. The original chain is left in place.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotNotFound`] or
+    /// [`ExecutorError::SnapshotIntegrity`] if the chain is incomplete or corrupt.
+    /// Returns [`ExecutorError::Io`] if the merged files cannot be written.
+    pub async fn merge(&self, snapshot_id: SnapshotId) -> Result<SnapshotMetadata, ExecutorError> {
+        let chain = self.chain(snapshot_id).await?;
+        for link in &chain {
+            self.verify(link.id).await?;
+        }
+        let Some((base, tip)) = chain.first().zip(chain.last()) else {
+            return Err(ExecutorError::SnapshotNotFound(snapshot_id.0));
+        };
+
+        let merged_id = SnapshotId::new();
+        let merged_mem = self.mem_path(merged_id);
+        let base_mem = self.mem_path(base.id);
+        let diffs: Vec<_> =
+            chain[1..].iter().map(|m| (self.mem_path(m.id), m.dirty_regions.clone())).collect();
+
+        let written = tokio::task::spawn_blocking({
+            let merged_mem = merged_mem.clone();
+            move || -> std::io::Result<()> {
+                std::fs::copy(&base_mem, &merged_mem)?;
+                let mut target = std::fs::OpenOptions::new().write(true).open(&merged_mem)?;
+                for (diff, regions) in &diffs {
+                    apply_diff(diff, regions.as_deref(), &mut target)?;
+                }
+                target.sync_all()
+            }
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r);
+
+        let copied = match written {
+            Ok(()) => self.copy_tip_files(tip, merged_id).await,
+            Err(e) => Err(e),
+        };
+        let recorded = match copied {
+            Ok(()) => self.record(merged_id, tip.source_vm, tip.source_config.clone(), None).await,
+            Err(e) => Err(e.into()),
+        };
+        if recorded.is_err() {
+            let _ = tokio::fs::remove_file(&merged_mem).await;
+            let _ = tokio::fs::remove_file(self.state_path(merged_id)).await;
+            let _ = tokio::fs::remove_file(self.disk_path(merged_id)).await;
+        }
+        let merged = recorded?;
+
+        tracing::info!(
+            snapshot_id = %snapshot_id,
+            merged_id = %merged_id,
+            links = chain.len(),
+            "snapshot chain merged into full snapshot"
+        );
+        Ok(merged)
+    }
+
+    /// A verified full snapshot equivalent to `snapshot_id`: the snapshot
+    /// itself if it is full, otherwise the flattening of its chain.
+    ///
+    /// The first flattening of a diff is kept in the catalogue, marked
+    /// [`flattened_from`](SnapshotMetadata::flattened_from) the diff, and
+    /// reused while it still verifies, so restoring the same diff again does
+    /// not merge its chain again.
+    ///
+    /// # Errors
+    /// As [`Self::verify`] and [`Self::merge`].
+    pub async fn flattened(
+        &self,
+        snapshot_id: SnapshotId,
+    ) -> Result<SnapshotMetadata, ExecutorError> {
+        let metadata = self.get(snapshot_id).await?;
+        if !metadata.is_diff() {
+            return self.verify(snapshot_id).await;
+        }
+
+        let kept = self.list().await?.into_iter().find(|m| m.flattened_from == Some(snapshot_id));
+        if let Some(kept) = kept {
+            match self.verify(kept.id).await {
+                Ok(kept) => return Ok(kept),
+                Err(e) => {
+                    tracing::warn!(snapshot_id = %kept.id, error = %e, "discarding corrupt flattening");
+                    self.remove_files(kept.id).await?;
+                }
+            }
+        }
+
+        let mut merged = self.merge(snapshot_id).await?;
+        merged.flattened_from = Some(snapshot_id);
+        self.write_entry(&merged).await?;
+        Ok(merged)
+    }
+
+    /// Copy the state file and any root disk copy of `tip` to `merged_id`.
+    async fn copy_tip_files(
+        &self,
+        tip: &SnapshotMetadata,
+        merged_id: SnapshotId,
+    ) -> std::io::Result<()> {
+        tokio::fs::copy(self.state_path(tip.id), self.state_path(merged_id)).await?;
+        if tip.has_disk() {
+            tokio::fs::copy(self.disk_path(tip.id), self.disk_path(merged_id)).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete a snapshot's files and its metadata entry, along with any
+    /// flattening of it kept by [`Self::flattened`].
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::SnapshotInUse`] if diff snapshots still depend on it.
+    /// Returns [`ExecutorError::SnapshotNotFound`] if neither the entry nor
+    /// any snapshot file exists.
+    /// Returns [`ExecutorError::Io`] if a file cannot be removed.
+    pub async fn delete(&self, snapshot_id: SnapshotId) -> Result<(), ExecutorError> {
+        let children = self.children(snapshot_id).await?;
+        if !children.is_empty() {
+            return Err(ExecutorError::SnapshotInUse {
+                snapshot_id: snapshot_id.0,
+                reason: format!("parent of {} diff snapshot(s)", children.len()),
+            });
+        }
+
+        for kept in self.list().await? {
+            if kept.flattened_from == Some(snapshot_id) {
+                self.remove_files(kept.id).await?;
+            }
+        }
+
+        if !self.remove_files(snapshot_id).await? {
+            return Err(ExecutorError::SnapshotNotFound(snapshot_id.0));
+        }
+
+        tracing::info!(snapshot_id = %snapshot_id, "snapshot deleted");
+        Ok(())
+    }
+
+    /// Remove whichever of a snapshot's files exist, returning whether any did.
+    async fn remove_files(&self, snapshot_id: SnapshotId) -> Result<bool, ExecutorError> {
+        let mut removed_any = false;
+        for path in [
+            self.metadata_path(snapshot_id),
+            self.mem_path(snapshot_id),
+            self.state_path(snapshot_id),
+            self.disk_path(snapshot_id),
+        ] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => removed_any = true,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(removed_any)
+    }
+
+    /// Delete snapshots that fall outside `policy`.
+    ///
+    /// Flattenings kept by [`Self::flattened`] are not weighed on their own;
+    /// they go when the snapshot they flatten does.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the catalogue cannot be listed or a
+    /// snapshot cannot be deleted. Snapshots deleted before the failure stay
+    /// deleted.
+    pub async fn gc(&self, policy: &RetentionPolicy) -> Result<GcReport, ExecutorError> {
+        let mut snapshots = self.list().await?;
+        snapshots.retain(|m| m.flattened_from.is_none());
+        snapshots.reverse();
+
+        let now = Utc::now();
+        let mut report = GcReport::default();
+        let mut kept_count = 0usize;
+        let mut kept_bytes = 0u64;
+        // Parents of retained diffs. Children are always newer than their
+        // parent, so they are decided first.
+        let mut required = HashSet::new();
+
+        for metadata in snapshots {
+            let age = (now - metadata.created_at).to_std().unwrap_or_default();
+            let keep = policy.pinned.contains(&metadata.id)
+                || required.contains(&metadata.id)
+                || (policy.max_age.is_none_or(|max| age <= max)
+                    && policy.max_count.is_none_or(|max| kept_count < max)
+                    && policy
+                        .max_total_bytes
+                        .is_none_or(|max| kept_bytes + metadata.total_size() <= max));
+
+            if keep {
+                kept_count += 1;
+                kept_bytes += metadata.total_size();
+                required.extend(metadata.parent);
+            } else {
+                self.delete(metadata.id).await?;
+                report.freed_bytes += metadata.total_size();
+                report.deleted.push(metadata.id);
+            }
+        }
+
+        report.retained = kept_count;
+        tracing::info!(
+            deleted = report.deleted.len(),
+            freed_bytes = report.freed_bytes,
+            retained = report.retained,
+            "snapshot garbage collection complete"
+        );
+        Ok(report)
+    }
+}
+
+/// Stream a file through SHA-256, returning its hash and length.
+///
+/// Runs on the blocking pool; snapshot memory files can be gigabytes.
+pub(crate) async fn hash_file(path: PathBuf) -> std::io::Result<(ContentHash, u64)> {
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 1 << 16];
+        let mut len = 0u64;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            len += n as u64;
+        }
+        Ok((ContentHash::new(hasher.finalize().into()), len))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// Copy the dirty `regions` of the diff memory file at `diff` to the same
+/// offsets in `target`, or every data region the filesystem reports if the
+/// regions were not recorded.
+///
+/// Everything else in the diff is pages that were not dirtied and are left
+/// untouched.
+fn apply_diff(
+    diff: &Path,
+    regions: Option<&[(u64, u64)]>,
+    target: &mut File,
+) -> std::io::Result<()> {
+    let mut source = File::open(diff)?;
+    let regions = match regions {
+        Some(regions) => regions.to_vec(),
+        None => data_regions(&source)?,
+    };
+
+    for (offset, len) in regions {
+        source.seek(SeekFrom::Start(offset))?;
+        target.seek(SeekFrom::Start(offset))?;
+        std::io::copy(&mut (&mut source).take(len), target)?;
+    }
+    Ok(())
+}
+
+/// The data regions of the sparse diff memory file at `path`.
+///
+/// A filesystem that does not report holes reports the whole file as data,
+/// including pages that take up no space. Those regions would overwrite the
+/// parent's memory with zeros, so such a file is refused.
+fn dirty_regions(path: &Path) -> std::io::Result<Vec<(u64, u64)>> {
+    let file = File::open(path)?;
+    let regions = data_regions(&file)?;
+    let data: u64 = regions.iter().map(|(_, len)| len).sum();
+    let allocated = std::os::unix::fs::MetadataExt::blocks(&file.metadata()?) * 512;
+    if data > allocated {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "{} reports {data} bytes of data in {allocated} allocated bytes; diff \
+                 snapshots need a filesystem that reports holes",
+                path.display()
+            ),
+        ));
+    }
+    Ok(regions)
+}
+
+/// The data regions of `file` as `(offset, length)` pairs, found with
+/// `SEEK_DATA` and `SEEK_HOLE`.
+fn data_regions(file: &File) -> std::io::Result<Vec<(u64, u64)>> {
+    let len = file.metadata()?.len();
+    let mut regions = Vec::new();
+    let mut offset = 0u64;
+
+    while offset < len {
+        let data_start = match rustix::fs::seek(file, rustix::fs::SeekFrom::Data(offset)) {
+            Ok(pos) => pos,
+            // No data past `offset`: the rest of the file is a hole.
+            Err(rustix::io::Errno::NXIO) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let data_end = rustix::fs::seek(file, rustix::fs::SeekFrom::Hole(data_start))?;
+        regions.push((data_start, data_end - data_start));
+        offset = data_end;
+    }
+    Ok(regions)
+}
+
+fn invalid_data(e: serde_json::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A catalogue in a fresh directory under the system temp dir.
+    struct TempCatalog(SnapshotCatalog);
+
+    impl TempCatalog {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("forge-catalog-test-{}", Uuid::new_v4()));
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                panic!("failed to create {}: {e}", dir.display());
+            }
+            Self(SnapshotCatalog::new(dir))
+        }
+
+        async fn write_snapshot(&self, mem: &[u8], state: &[u8]) -> SnapshotMetadata {
+            let id = SnapshotId::new();
+            if let Err(e) = std::fs::write(self.0.mem_path(id), mem) {
+                panic!("failed to write mem file: {e}");
+            }
+            if let Err(e) = std::fs::write(self.0.state_path(id), state) {
+                panic!("failed to write state file: {e}");
+            }
+            match self.0.record(id, Uuid::new_v4(), None, None).await {
+                Ok(m) => m,
+                Err(e) => panic!("record failed: {e}"),
+            }
+        }
+
+        /// Write a diff of `parent` whose memory file is all holes except
+        /// for the given pages.
+        async fn write_diff(
+            &self,
+            parent: SnapshotId,
+            len: u64,
+            pages: &[(u64, u8)],
+        ) -> SnapshotMetadata {
+            let id = SnapshotId::new();
+            let written = File::create(self.0.mem_path(id)).and_then(|mut file| {
+                file.set_len(len)?;
+                for &(offset, byte) in pages {
+                    file.seek(SeekFrom::Start(offset))?;
+                    std::io::Write::write_all(&mut file, &[byte; PAGE])?;
+                }
+                std::fs::write(self.0.state_path(id), [byte_of(pages); 4])
+            });
+            if let Err(e) = written {
+                panic!("failed to write diff files: {e}");
+            }
+            match self.0.record(id, Uuid::new_v4(), None, Some(parent)).await {
+                Ok(m) => m,
+                Err(e) => panic!("record failed: {e}"),
+            }
+        }
+    }
+
+    const PAGE: usize = 4096;
+
+    fn byte_of(pages: &[(u64, u8)]) -> u8 {
+        pages.first().map_or(0, |&(_, b)| b)
+    }
+
+    impl Drop for TempCatalog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.0.dir());
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn record_then_get_roundtrips_metadata() {
+        let cat = TempCatalog::new();
+        let recorded = cat.write_snapshot(b"memory", b"state").await;
+        assert_eq!(recorded.mem_size, 6);
+        assert_eq!(recorded.state_size, 5);
+        assert_ne!(recorded.mem_sha256, recorded.state_sha256);
+
+        let fetched = match cat.0.get(recorded.id).await {
+            Ok(m) => m,
+            Err(e) => panic!("get failed: {e}"),
+        };
+        assert_eq!(fetched, recorded, "catalogue entry must round-trip");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn verify_detects_modified_memory_file() {
+        let cat = TempCatalog::new();
+        let recorded = cat.write_snapshot(b"memory", b"state").await;
+        assert!(cat.0.verify(recorded.id).await.is_ok(), "untouched snapshot must verify");
+
+        if let Err(e) = std::fs::write(cat.0.mem_path(recorded.id), b"MEMORY") {
+            panic!("failed to tamper with mem file: {e}");
+        }
+        let result = cat.0.verify(recorded.id).await;
+        assert!(
+            matches!(result, Err(ExecutorError::SnapshotIntegrity { .. })),
+            "tampered snapshot must fail verification, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn delete_removes_files_and_entry() {
+        let cat = TempCatalog::new();
+        let recorded = cat.write_snapshot(b"m", b"s").await;
+        if let Err(e) = cat.0.delete(recorded.id).await {
+            panic!("delete failed: {e}");
+        }
+        assert!(!cat.0.mem_path(recorded.id).exists(), "mem file must be removed");
+        assert!(matches!(cat.0.get(recorded.id).await, Err(ExecutorError::SnapshotNotFound(_))));
+        assert!(
+            matches!(cat.0.delete(recorded.id).await, Err(ExecutorError::SnapshotNotFound(_))),
+            "deleting twice must report not found"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn gc_keeps_newest_and_pinned_within_count() {
+        let cat = TempCatalog::new();
+        let oldest = cat.write_snapshot(b"1", b"1").await;
+        let middle = cat.write_snapshot(b"2", b"2").await;
+        let newest = cat.write_snapshot(b"3", b"3").await;
+
+        let policy = RetentionPolicy {
+            max_count: Some(1),
+            pinned: HashSet::from([oldest.id]),
+            ..RetentionPolicy::default()
+        };
+
+        let report = match cat.0.gc(&policy).await {
+            Ok(r) => r,
+            Err(e) => panic!("gc failed: {e}"),
+        };
+        assert_eq!(report.deleted, vec![middle.id], "only the unpinned older snapshot goes");
+        assert_eq!(report.freed_bytes, 2);
+        assert_eq!(report.retained, 2);
+
+        let remaining: Vec<_> = match cat.0.list().await {
+            Ok(list) => list.into_iter().map(|m| m.id).collect(),
+            Err(e) => panic!("list failed: {e}"),
+        };
+        assert_eq!(remaining, vec![oldest.id, newest.id]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn chain_walks_back_to_full_base() {
+        let cat = TempCatalog::new();
+        let base = cat.write_snapshot(&[0u8; PAGE * 4], b"s0").await;
+        let d1 = cat.write_diff(base.id, (PAGE * 4) as u64, &[(PAGE as u64, 1)]).await;
+        let d2 = cat.write_diff(d1.id, (PAGE * 4) as u64, &[(0, 2)]).await;
+
+        let chain: Vec<_> = match cat.0.chain(d2.id).await {
+            Ok(c) => c.into_iter().map(|m| m.id).collect(),
+            Err(e) => panic!("chain failed: {e}"),
+        };
+        assert_eq!(chain, vec![base.id, d1.id, d2.id]);
+        assert!(d2.is_diff() && !base.is_diff());
+
+        let children = match cat.0.children(base.id).await {
+            Ok(c) => c,
+            Err(e) => panic!("children failed: {e}"),
+        };
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id, d1.id);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn merge_applies_diffs_in_order_over_base() {
+        let cat = TempCatalog::new();
+        let base = cat.write_snapshot(&[7u8; PAGE * 4], b"s0").await;
+        let d1 = cat.write_diff(base.id, (PAGE * 4) as u64, &[(PAGE as u64, 1), (0, 1)]).await;
+        let d2 = cat.write_diff(d1.id, (PAGE * 4) as u64, &[(0, 2)]).await;
+
+        let merged = match cat.0.merge(d2.id).await {
+            Ok(m) => m,
+            Err(e) => panic!("merge failed: {e}"),
+        };
+        assert!(!merged.is_diff(), "merged snapshot must be full");
+
+        let mem = match std::fs::read(cat.0.mem_path(merged.id)) {
+            Ok(m) => m,
+            Err(e) => panic!("failed to read merged memory: {e}"),
+        };
+        assert_eq!(mem.len(), PAGE * 4);
+        assert!(mem[..PAGE].iter().all(|&b| b == 2), "page 0 comes from the newest diff");
+        assert!(mem[PAGE..PAGE * 2].iter().all(|&b| b == 1), "page 1 comes from the first diff");
+        assert!(mem[PAGE * 2..].iter().all(|&b| b == 7), "untouched pages keep base memory");
+
+        let state = std::fs::read(cat.0.state_path(merged.id)).unwrap_or_default();
+        assert_eq!(state, [2u8; 4], "merged state comes from the chain tip");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn merge_applies_the_regions_recorded_with_a_diff() {
+        let cat = TempCatalog::new();
+        let base = cat.write_snapshot(&[7u8; PAGE * 4], b"s0").await;
+        let diff = cat.write_diff(base.id, (PAGE * 4) as u64, &[(PAGE as u64, 1)]).await;
+        assert_eq!(diff.dirty_regions, Some(vec![(PAGE as u64, PAGE as u64)]));
+
+        // A copy that fills the holes with zeros leaves the hash unchanged.
+        let dense = std::fs::read(cat.0.mem_path(diff.id))
+            .and_then(|mem| std::fs::write(cat.0.mem_path(diff.id), mem));
+        if let Err(e) = dense {
+            panic!("failed to densify the diff: {e}");
+        }
+
+        let merged = cat.0.merge(diff.id).await.unwrap_or_else(|e| panic!("merge failed: {e}"));
+        let mem = std::fs::read(cat.0.mem_path(merged.id)).unwrap_or_default();
+        assert!(mem[PAGE..PAGE * 2].iter().all(|&b| b == 1), "the dirty page is applied");
+        assert!(
+            mem[..PAGE].iter().chain(&mem[PAGE * 2..]).all(|&b| b == 7),
+            "clean pages keep base memory"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn flattenings_are_kept_reused_and_deleted_with_their_diff() {
+        let cat = TempCatalog::new();
+        let base = cat.write_snapshot(&[0u8; PAGE], b"s0").await;
+        let diff = cat.write_diff(base.id, PAGE as u64, &[(0, 1)]).await;
+
+        let catalog = &cat.0;
+        let flatten = |id| async move {
+            catalog.flattened(id).await.unwrap_or_else(|e| panic!("flattened failed: {e}"))
+        };
+        assert_eq!(flatten(base.id).await.id, base.id, "a full snapshot is its own flattening");
+        let first = flatten(diff.id).await;
+        assert_eq!(first.flattened_from, Some(diff.id));
+        assert_eq!(flatten(diff.id).await.id, first.id, "the flattening must be reused");
+
+        let report = cat
+            .0
+            .gc(&RetentionPolicy::default())
+            .await
+            .unwrap_or_else(|e| panic!("gc failed: {e}"));
+        assert_eq!(report.retained, 2, "flattenings are not weighed on their own");
+
+        if let Err(e) = cat.0.delete(diff.id).await {
+            panic!("delete failed: {e}");
+        }
+        assert!(matches!(cat.0.get(first.id).await, Err(ExecutorError::SnapshotNotFound(_))));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn root_disk_copies_are_verified_merged_and_deleted() {
+        let cat = TempCatalog::new();
+        let base = cat.write_snapshot(&[0u8; PAGE], b"s0").await;
+        let id = SnapshotId::new();
+        let written = std::fs::write(cat.0.mem_path(id), [1u8; PAGE])
+            .and_then(|()| std::fs::write(cat.0.state_path(id), b"s1"))
+            .and_then(|()| std::fs::write(cat.0.disk_path(id), b"disk"));
+        if let Err(e) = written {
+            panic!("failed to write snapshot files: {e}");
+        }
+        let tip = cat
+            .0
+            .record(id, Uuid::new_v4(), None, Some(base.id))
+            .await
+            .unwrap_or_else(|e| panic!("record failed: {e}"));
+        assert!(tip.has_disk() && !base.has_disk());
+        assert_eq!(tip.total_size(), PAGE as u64 + 2 + 4);
+
+        let merged = cat.0.merge(tip.id).await.unwrap_or_else(|e| panic!("merge failed: {e}"));
+        assert_eq!(merged.disk_sha256, tip.disk_sha256, "merged snapshot takes the tip's disk");
+        if let Err(e) = cat.0.delete(merged.id).await {
+            panic!("delete failed: {e}");
+        }
+        assert!(!cat.0.disk_path(merged.id).exists(), "disk copy must be removed");
+
+        if let Err(e) = std::fs::write(cat.0.disk_path(tip.id), b"DISK") {
+            panic!("failed to tamper with disk copy: {e}");
+        }
+        let result = cat.0.verify(tip.id).await;
+        assert!(
+            matches!(result, Err(ExecutorError::SnapshotIntegrity { .. })),
+            "tampered disk copy must fail verification, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn delete_refuses_parent_and_gc_keeps_it() {
+        let cat = TempCatalog::new();
+        let base = cat.write_snapshot(&[0u8; PAGE], b"s0").await;
+        let diff = cat.write_diff(base.id, PAGE as u64, &[(0, 1)]).await;
+
+        assert!(
+            matches!(cat.0.delete(base.id).await, Err(ExecutorError::SnapshotInUse { .. })),
+            "deleting a parent of a diff must be refused"
+        );
+
+        let policy = RetentionPolicy { max_count: Some(1), ..RetentionPolicy::default() };
+        let report = match cat.0.gc(&policy).await {
+            Ok(r) => r,
+            Err(e) => panic!("gc failed: {e}"),
+        };
+        assert!(report.deleted.is_empty(), "gc must keep the parent of a retained diff");
+        assert_eq!(report.retained, 2);
+
+        if let Err(e) = cat.0.delete(diff.id).await {
+            panic!("deleting the diff failed: {e}");
+        }
+        assert!(cat.0.delete(base.id).await.is_ok(), "parent is deletable once childless");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn gc_default_policy_keeps_everything() {
+        let cat = TempCatalog::new();
+        cat.write_snapshot(b"a", b"b").await;
+        cat.write_snapshot(b"c", b"d").await;
+        let report = match cat.0.gc(&RetentionPolicy::default()).await {
+            Ok(r) => r,
+            Err(e) => panic!("gc failed: {e}"),
+        };
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.retained, 2);
+    }
+}
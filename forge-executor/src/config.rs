@@ -3,10 +3,16 @@
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use forge_core::id::ContentHash;
+
+use crate::images::{verify_image, ImageHashes, ImageStore};
+use crate::ExecutorError;
+
 /// Configuration for spawning a new microVM.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
 pub struct VmConfig {
     /// Path to the Linux kernel image (vmlinux or bzImage).
@@ -15,6 +21,14 @@ pub struct VmConfig {
     /// Path to the root filesystem image (ext4).
     pub rootfs_path: PathBuf,
 
+    /// Read-only layer images the rootfs's init overlays onto `/nix/store`,
+    /// as built by `forge_nix::RootfsBuilder::build_layered`. Attached in
+    /// order after the rootfs, so layer `N` is `/dev/vd<b + N>`. Guests
+    /// with layers must boot through that init; see [`Self::init_args`].
+    /// Layers are not hash-checked before boot.
+    #[serde(default)]
+    pub layer_paths: Vec<PathBuf>,
+
     /// Number of virtual CPUs to allocate.
     pub vcpu_count: u8,
 
@@ -23,6 +37,35 @@ pub struct VmConfig {
 
     /// Kernel boot arguments.
     pub boot_args: String,
+
+    /// Track dirty guest memory pages so the VM supports diff snapshots.
+    ///
+    /// Costs some runtime overhead; leave disabled unless the VM will be
+    /// checkpointed repeatedly.
+    #[serde(default)]
+    pub track_dirty_pages: bool,
+
+    /// SHA-256 the kernel image must hash to before a VM boots from it;
+    /// `None` if the kernel is referenced only by path.
+    #[serde(default)]
+    pub kernel_hash: Option<ContentHash>,
+
+    /// SHA-256 the rootfs image must hash to before a VM boots from it;
+    /// `None` if the rootfs is referenced only by path. A content-addressed
+    /// rootfs is attached read-only, so no guest can change the stored
+    /// image.
+    ///
+    /// The hash proves only that the image is unchanged, not what it holds.
+    /// Nothing here checks its contents against a derivation; callers who
+    /// need that run `forge_nix::RootfsBuilder::verify` on the image before
+    /// importing it and pinning it here.
+    #[serde(default)]
+    pub rootfs_hash: Option<ContentHash>,
+
+    /// CPU template masking the host CPU's features, so the guest sees the
+    /// same CPU on every host; `None` passes the host's features through.
+    #[serde(default)]
+    pub cpu_template: Option<CpuTemplate>,
 }
 
 impl VmConfig {
@@ -36,13 +79,189 @@ pub fn new(kernel_path: PathBuf, rootfs_path: PathBuf) -> Self {
         Self {
             kernel_path,
             rootfs_path,
+            layer_paths: Vec::new(),
             vcpu_count: 1,
             mem_size_mib: 128,
             boot_args: "console=ttyS0 reboot=k panic=1 pci=off".to_owned(),
+            track_dirty_pages: false,
+            kernel_hash: None,
+            rootfs_hash: None,
+            cpu_template: None,
+        }
+    }
+
+    /// Pin the CPU the guest sees to `template`.
+    #[must_use]
+    pub fn with_cpu_template(mut self, template: CpuTemplate) -> Self {
+        self.cpu_template = Some(template);
+        self
+    }
+
+    /// Create a config booting the kernel and rootfs stored in `images`
+    /// under `kernel` and `rootfs`, with the defaults of [`VmConfig::new`].
+    #[must_use]
+    pub fn from_images(images: &ImageStore, kernel: ContentHash, rootfs: ContentHash) -> Self {
+        let mut config = Self::new(images.path(&kernel), images.path(&rootfs));
+        config.kernel_hash = Some(kernel);
+        config.rootfs_hash = Some(rootfs);
+        config
+    }
+
+    /// The kernel argument that makes `argv` (e.g. `/bin/sh -c "…"`) the
+    /// guest's first process.
+    ///
+    /// With [`layer_paths`](Self::layer_paths), `argv` is passed to the
+    /// rootfs's `/init`, which mounts the layers over `/nix/store` and then
+    /// execs it; booting `argv` directly would leave the layered paths
+    /// missing from the store.
+    #[must_use]
+    pub fn init_args(&self, argv: &str) -> String {
+        if self.layer_paths.is_empty() {
+            format!("init={argv}")
+        } else {
+            format!("init=/init -- {argv}")
+        }
+    }
+
+    /// Check the kernel and rootfs against the hashes the config names
+    /// them by. Images referenced only by path are not checked.
+    ///
+    /// # Errors
+    /// See [`verify_image`].
+    pub async fn verify_images(&self) -> Result<(), ExecutorError> {
+        if let Some(hash) = &self.kernel_hash {
+            verify_image(&self.kernel_path, hash).await?;
+        }
+        if let Some(hash) = &self.rootfs_hash {
+            verify_image(&self.rootfs_path, hash).await?;
+        }
+        Ok(())
+    }
+
+    /// As [`Self::verify_images`], without re-reading images `hashes` has
+    /// seen unchanged.
+    pub(crate) async fn verify_images_with(
+        &self,
+        hashes: &ImageHashes,
+    ) -> Result<(), ExecutorError> {
+        if let Some(hash) = &self.kernel_hash {
+            hashes.verify(&self.kernel_path, hash).await?;
+        }
+        if let Some(hash) = &self.rootfs_hash {
+            hashes.verify(&self.rootfs_path, hash).await?;
+        }
+        Ok(())
+    }
+
+    /// The [hash](CpuTemplate::hash) of the CPU template, if one is set.
+    ///
+    /// # Errors
+    /// See [`CpuTemplate::hash`].
+    pub async fn cpu_template_hash(&self) -> Result<Option<ContentHash>, ExecutorError> {
+        match &self.cpu_template {
+            Some(template) => Ok(Some(template.hash().await?)),
+            None => Ok(None),
         }
     }
 }
 
+/// Domain tag prefixed to CPU template hashes.
+const CPU_TEMPLATE_DOMAIN: &[u8] = b"forge.cpu-template.v1\0";
+
+/// A Firecracker CPU template: the CPUID and MSR values, or on aarch64 the
+/// system registers, a guest sees in place of the host's.
+///
+/// Results are only comparable between executions with the same template;
+/// its [hash](Self::hash) is part of every
+/// [`ExecutionRecord`](forge_core::execution::ExecutionRecord) and
+/// [`CacheKey`](crate::CacheKey).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum CpuTemplate {
+    /// A template built into Firecracker.
+    Static(StaticCpuTemplate),
+    /// A custom template: a JSON file in Firecracker's `/cpu-config` format.
+    Custom(PathBuf),
+}
+
+/// The CPU templates built into Firecracker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum StaticCpuTemplate {
+    /// Intel: an AWS C3 instance.
+    C3,
+    /// Intel: an AWS T2 instance.
+    T2,
+    /// Intel: T2, also safe to restore snapshots across Skylake and Cascade
+    /// Lake hosts.
+    T2S,
+    /// Intel: T2 for Cascade Lake and newer.
+    T2CL,
+    /// AMD: T2 for Milan.
+    T2A,
+    /// Arm: Neoverse V1 presented as Neoverse N1.
+    V1N1,
+}
+
+impl StaticCpuTemplate {
+    /// The name Firecracker's `/machine-config` takes.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::C3 => "C3",
+            Self::T2 => "T2",
+            Self::T2S => "T2S",
+            Self::T2CL => "T2CL",
+            Self::T2A => "T2A",
+            Self::V1N1 => "V1N1",
+        }
+    }
+}
+
+impl CpuTemplate {
+    /// SHA-256 identifying the template: over the name of a static
+    /// template, or the contents of a custom one with its JSON keys
+    /// sorted, so reformatting the file does not change it.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if a custom template cannot be read and
+    /// [`ExecutorError::InvalidCpuTemplate`] if it is not JSON.
+    pub async fn hash(&self) -> Result<ContentHash, ExecutorError> {
+        let mut hasher = Sha256::new();
+        hasher.update(CPU_TEMPLATE_DOMAIN);
+        match self {
+            Self::Static(template) => {
+                hasher.update(b"static\0");
+                hasher.update(template.name());
+            }
+            Self::Custom(path) => {
+                hasher.update(b"custom\0");
+                hasher.update(custom_template(path).await?.to_string());
+            }
+        }
+        Ok(ContentHash::new(hasher.finalize().into()))
+    }
+}
+
+/// Read the custom CPU template at `path`.
+///
+/// # Errors
+/// Returns [`ExecutorError::Io`] if it cannot be read and
+/// [`ExecutorError::InvalidCpuTemplate`] if it is not a JSON object.
+pub(crate) async fn custom_template(
+    path: &std::path::Path,
+) -> Result<serde_json::Value, ExecutorError> {
+    let invalid =
+        |reason: String| ExecutorError::InvalidCpuTemplate { path: path.to_owned(), reason };
+    let contents = tokio::fs::read(path).await?;
+    let template: serde_json::Value =
+        serde_json::from_slice(&contents).map_err(|e| invalid(e.to_string()))?;
+    if !template.is_object() {
+        return Err(invalid("not a JSON object".to_owned()));
+    }
+    Ok(template)
+}
+
 /// Opaque identifier for a VM snapshot.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -100,6 +319,55 @@ fn vm_config_serialization_roundtrip() {
         assert_eq!(config.mem_size_mib, restored.mem_size_mib);
     }
 
+    #[test]
+    fn layered_guests_boot_through_the_rootfs_init() {
+        let mut config =
+            VmConfig::new(PathBuf::from("/tmp/vmlinux"), PathBuf::from("/tmp/rootfs.ext4"));
+        assert_eq!(config.init_args("/bin/sh"), "init=/bin/sh");
+        config.layer_paths = vec![PathBuf::from("/tmp/layer-0.ext4")];
+        assert_eq!(config.init_args("/bin/sh -c \"ls\""), "init=/init -- /bin/sh -c \"ls\"");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn cpu_template_hashes_identify_the_template() {
+        let hash = |template: CpuTemplate| async move {
+            template.hash().await.unwrap_or_else(|e| panic!("hash failed: {e}"))
+        };
+        let t2 = hash(CpuTemplate::Static(StaticCpuTemplate::T2)).await;
+        assert_eq!(t2, hash(CpuTemplate::Static(StaticCpuTemplate::T2)).await);
+        assert_ne!(t2, hash(CpuTemplate::Static(StaticCpuTemplate::T2S)).await);
+
+        let dir = std::env::temp_dir().join(format!("forge-cpu-template-{}", Uuid::new_v4()));
+        let (compact, spaced, invalid) = (dir.join("a.json"), dir.join("b.json"), dir.join("c"));
+        let written = std::fs::create_dir_all(&dir)
+            .and_then(|()| {
+                std::fs::write(&compact, r#"{"kvm_capabilities":[],"cpuid_modifiers":[]}"#)
+            })
+            .and_then(|()| {
+                std::fs::write(
+                    &spaced,
+                    "{\n  \"cpuid_modifiers\": [],\n  \"kvm_capabilities\": []\n}\n",
+                )
+            })
+            .and_then(|()| std::fs::write(&invalid, "[]"));
+        if let Err(e) = written {
+            panic!("template write failed: {e}");
+        }
+        let custom = hash(CpuTemplate::Custom(compact)).await;
+        assert_eq!(custom, hash(CpuTemplate::Custom(spaced)).await, "formatting must not matter");
+        assert_ne!(custom, t2);
+        let result = CpuTemplate::Custom(invalid).hash().await;
+        assert!(matches!(result, Err(ExecutorError::InvalidCpuTemplate { .. })), "got {result:?}");
+
+        let config = VmConfig::new(PathBuf::from("/tmp/vmlinux"), PathBuf::from("/tmp/rootfs"));
+        assert_eq!(config.cpu_template_hash().await.ok(), Some(None));
+        let pinned = config.with_cpu_template(CpuTemplate::Static(StaticCpuTemplate::T2));
+        assert_eq!(pinned.cpu_template_hash().await.ok(), Some(Some(t2)));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn snapshot_id_equality_same_uuid() {
         use uuid::Uuid;
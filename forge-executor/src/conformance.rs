@@ -0,0 +1,201 @@
+//! Conformance suite for [`VmmBackend`] implementations.
+//!
+//! Each check drives a backend through one part of the trait contract and
+//! panics with a descriptive message on violation, so it can be called
+//! directly from a `#[tokio::test]`. [`run_all`] runs every check in turn.
+//!
+//! The guest image must provide a POSIX shell with `mktemp`, `base64`,
+//! `printf` and `sleep`. Checks that use [`VmmBackend::spawn`] also expect
+//! the booted guest to run a shell on its console; for Firecracker, append
+//! [`VmConfig::init_args`]`("/bin/sh")` to [`VmConfig::boot_args`].
+//!
+//! Capabilities a backend reports as [`ExecutorError::Unsupported`] are
+//! skipped rather than failed.
+//!
+//! Available with the `testing` feature.
+
+use std::time::Duration;
+
+use crate::{ExecutorError, VmConfig, VmmBackend};
+
+/// Timeout given to every command that is expected to complete.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The backend reports itself healthy.
+///
+/// # Panics
+/// Panics if [`VmmBackend::health_check`] fails.
+pub async fn health_check_passes<B: VmmBackend + ?Sized>(backend: &B) {
+    if let Err(e) = backend.health_check().await {
+        panic!("health check failed: {e}");
+    }
+}
+
+/// Standard output is captured byte for byte, with a zero exit code.
+///
+/// # Panics
+/// Panics if execution fails or the output differs.
+pub async fn stdout_is_captured<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let output = match backend.execute_command(config, "echo forge", COMMAND_TIMEOUT).await {
+        Ok(output) => output,
+        Err(e) => panic!("execute_command failed: {e}"),
+    };
+    assert_eq!(output.stdout, b"forge\n", "stdout must be captured exactly");
+    assert!(output.stderr.is_empty(), "stderr must be empty when nothing is written to it");
+    assert_eq!(output.exit_code, 0, "successful command must exit 0");
+}
+
+/// Standard error is captured separately from standard output.
+///
+/// # Panics
+/// Panics if execution fails or the streams are mixed.
+pub async fn stderr_is_separated<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let command = "echo out; echo err 1>&2";
+    let output = match backend.execute_command(config, command, COMMAND_TIMEOUT).await {
+        Ok(output) => output,
+        Err(e) => panic!("execute_command failed: {e}"),
+    };
+    assert_eq!(output.stdout, b"out\n", "stdout must hold only stdout");
+    assert_eq!(output.stderr, b"err\n", "stderr must hold only stderr");
+}
+
+/// A non-zero exit code is reported as an output, not an error.
+///
+/// The command exits from a subshell, since `exit` at the top level would
+/// also end the capture wrapper before it reports anything.
+///
+/// # Panics
+/// Panics if execution fails or the exit code is not propagated.
+pub async fn exit_code_is_propagated<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let output = match backend.execute_command(config, "(exit 3)", COMMAND_TIMEOUT).await {
+        Ok(output) => output,
+        Err(e) => panic!("execute_command failed: {e}"),
+    };
+    assert_eq!(output.exit_code, 3, "guest exit code must be propagated");
+}
+
+/// Non-UTF-8 output survives the transport unchanged.
+///
+/// # Panics
+/// Panics if execution fails or the bytes are altered.
+pub async fn binary_output_roundtrips<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let command = r"printf '\000\001\015\012\377'";
+    let output = match backend.execute_command(config, command, COMMAND_TIMEOUT).await {
+        Ok(output) => output,
+        Err(e) => panic!("execute_command failed: {e}"),
+    };
+    assert_eq!(output.stdout, [0x00, 0x01, 0x0d, 0x0a, 0xff], "binary stdout must be preserved");
+}
+
+/// Running the same command twice yields identical output.
+///
+/// # Panics
+/// Panics if either execution fails or the outputs differ.
+pub async fn repeated_runs_are_identical<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let command = "ls / | sort; echo done";
+    let mut outputs = Vec::new();
+    for run in 0..2 {
+        match backend.execute_command(config, command, COMMAND_TIMEOUT).await {
+            Ok(output) => outputs.push((output.stdout, output.stderr, output.exit_code)),
+            Err(e) => panic!("execute_command run {run} failed: {e}"),
+        }
+    }
+    assert_eq!(outputs[0], outputs[1], "repeated runs must produce identical output");
+}
+
+/// A command that outlives its timeout fails instead of hanging.
+///
+/// # Panics
+/// Panics if the command does not fail within a bounded time.
+pub async fn timeout_is_enforced<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let timeout = Duration::from_secs(2);
+    let started = std::time::Instant::now();
+    let result = backend.execute_command(config, "sleep 60", timeout).await;
+    assert!(result.is_err(), "command exceeding its timeout must fail, got {result:?}");
+    assert!(started.elapsed() < COMMAND_TIMEOUT, "timeout must be enforced promptly");
+}
+
+/// A spawned VM runs commands sent to it and can then be terminated.
+///
+/// Skipped if the backend does not support [`VmmBackend::execute_in_vm`].
+///
+/// # Panics
+/// Panics if spawning, execution, or termination fails.
+pub async fn spawned_vm_runs_commands<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let mut handle = match backend.spawn(config).await {
+        Ok(handle) => handle,
+        Err(e) => panic!("spawn failed: {e}"),
+    };
+
+    let result = backend.execute_in_vm(&mut handle, "echo first", COMMAND_TIMEOUT).await;
+    let second = match result {
+        Ok(output) => {
+            assert_eq!(output.stdout, b"first\n", "first command output must be captured");
+            backend.execute_in_vm(&mut handle, "(exit 4)", COMMAND_TIMEOUT).await
+        }
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = backend.terminate(handle).await {
+        panic!("terminate failed: {e}");
+    }
+
+    match second {
+        Ok(output) => assert_eq!(output.exit_code, 4, "VM must stay usable between commands"),
+        Err(ExecutorError::Unsupported(_)) => {}
+        Err(e) => panic!("execute_in_vm failed: {e}"),
+    }
+}
+
+/// A VM restored from a snapshot is running and can be terminated.
+///
+/// Skipped if the backend does not support snapshots.
+///
+/// # Panics
+/// Panics if any supported step fails.
+pub async fn snapshot_restore_roundtrip<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    let handle = match backend.spawn(config).await {
+        Ok(handle) => handle,
+        Err(e) => panic!("spawn failed: {e}"),
+    };
+
+    let snapshot = backend.snapshot(&handle).await;
+    if let Err(e) = backend.terminate(handle).await {
+        panic!("terminate failed: {e}");
+    }
+    let snapshot_id = match snapshot {
+        Ok(id) => id,
+        Err(ExecutorError::Unsupported(_)) => return,
+        Err(e) => panic!("snapshot failed: {e}"),
+    };
+
+    let restored = match backend.restore(&snapshot_id).await {
+        Ok(handle) => handle,
+        Err(e) => panic!("restore from {snapshot_id} failed: {e}"),
+    };
+    assert_eq!(restored.restored_from, Some(snapshot_id), "restored VM must record its snapshot");
+    if let Err(e) = backend.terminate(restored).await {
+        panic!("terminate of restored VM failed: {e}");
+    }
+
+    match backend.delete_snapshot(&snapshot_id).await {
+        Ok(()) | Err(ExecutorError::Unsupported(_)) => {}
+        Err(e) => panic!("delete_snapshot failed: {e}"),
+    }
+}
+
+/// Run every conformance check against `backend`.
+///
+/// # Panics
+/// Panics on the first check that fails.
+pub async fn run_all<B: VmmBackend + ?Sized>(backend: &B, config: &VmConfig) {
+    health_check_passes(backend).await;
+    stdout_is_captured(backend, config).await;
+    stderr_is_separated(backend, config).await;
+    exit_code_is_propagated(backend, config).await;
+    binary_output_roundtrips(backend, config).await;
+    repeated_runs_are_identical(backend, config).await;
+    timeout_is_enforced(backend, config).await;
+    spawned_vm_runs_commands(backend, config).await;
+    snapshot_restore_roundtrip(backend, config).await;
+}
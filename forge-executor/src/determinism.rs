@@ -0,0 +1,260 @@
+//! Determinism verification reports.
+//!
+//! [`BlockRunner::verify_determinism`](crate::BlockRunner::verify_determinism)
+//! executes a block several times and collects a [`DeterminismReport`]: one
+//! [`DeterminismRun`] per execution and one [`ObservedOutput`] per distinct
+//! canonical output hash. A block is deterministic when every run agrees;
+//! when it is not, [`DeterminismReport::diagnose`] explains how the outputs
+//! differ.
+
+use serde::{Deserialize, Serialize};
+
+use forge_core::execution::ExecutionRecord;
+use forge_core::id::{BlockId, ContentHash};
+
+use crate::diagnosis::Divergence;
+
+/// How the VM for a verification run is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum BootKind {
+    /// A freshly booted VM, via [`VmmBackend::execute_command`](crate::VmmBackend::execute_command).
+    Fresh,
+    /// A VM restored from a snapshot of a booted base VM.
+    Restored,
+}
+
+/// Which boots a verification spreads its runs across.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum BootMode {
+    /// Every run boots a fresh VM.
+    #[default]
+    Fresh,
+    /// Every run restores the same snapshot.
+    Restored,
+    /// Runs alternate between fresh boots and restores, starting fresh.
+    Alternating,
+}
+
+impl BootMode {
+    /// The boot used for run number `index`.
+    #[must_use]
+    pub const fn boot_for(self, index: usize) -> BootKind {
+        match self {
+            Self::Alternating if index % 2 == 0 => BootKind::Fresh,
+            Self::Restored | Self::Alternating => BootKind::Restored,
+            Self::Fresh => BootKind::Fresh,
+        }
+    }
+
+    /// Whether any run needs a base snapshot.
+    #[must_use]
+    pub const fn needs_snapshot(self, runs: usize) -> bool {
+        match self {
+            Self::Fresh => false,
+            Self::Restored => runs > 0,
+            Self::Alternating => runs > 1,
+        }
+    }
+}
+
+/// One execution within a verification.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct DeterminismRun {
+    /// How the VM for this run was started.
+    pub boot: BootKind,
+
+    /// The record of the execution, including its output hash and timing.
+    pub record: ExecutionRecord,
+}
+
+/// A distinct output seen during a verification, with the runs that
+/// produced it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ObservedOutput {
+    /// Hash of `stdout` and `stderr`, as in
+    /// [`ExecutionRecord::canonical_hash`].
+    pub output_hash: ContentHash,
+
+    /// Standard output after the block's canonicalisation rules.
+    pub stdout: Vec<u8>,
+
+    /// Standard error after the block's canonicalisation rules.
+    pub stderr: Vec<u8>,
+
+    /// Exit code of the guest command.
+    pub exit_code: i32,
+
+    /// Indices into [`DeterminismReport::runs`] that produced this output.
+    pub runs: Vec<usize>,
+}
+
+/// Outcome of executing a block repeatedly and comparing the outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct DeterminismReport {
+    /// The block that was verified.
+    pub block_id: BlockId,
+
+    /// Hash of the input every run was given.
+    pub input_hash: ContentHash,
+
+    /// Every run, in execution order.
+    pub runs: Vec<DeterminismRun>,
+
+    /// Each distinct output, in the order first seen. The first entry is the
+    /// reference output the others diverge from.
+    pub outputs: Vec<ObservedOutput>,
+}
+
+impl DeterminismReport {
+    /// Create an empty report for `block_id`.
+    #[must_use]
+    pub const fn new(block_id: BlockId, input_hash: ContentHash) -> Self {
+        Self { block_id, input_hash, runs: Vec::new(), outputs: Vec::new() }
+    }
+
+    /// Add a run and the canonicalised output it produced.
+    pub fn push(
+        &mut self,
+        boot: BootKind,
+        record: ExecutionRecord,
+        stdout: &[u8],
+        stderr: &[u8],
+        exit_code: i32,
+    ) {
+        let index = self.runs.len();
+        let output_hash = record.canonical_hash();
+        self.runs.push(DeterminismRun { boot, record });
+
+        if let Some(observed) = self
+            .outputs
+            .iter_mut()
+            .find(|o| o.output_hash == output_hash && o.exit_code == exit_code)
+        {
+            observed.runs.push(index);
+        } else {
+            self.outputs.push(ObservedOutput {
+                output_hash,
+                stdout: stdout.to_vec(),
+                stderr: stderr.to_vec(),
+                exit_code,
+                runs: vec![index],
+            });
+        }
+    }
+
+    /// Whether every run produced the same output and exit code.
+    ///
+    /// A report with no runs is not deterministic: nothing was verified.
+    #[must_use]
+    pub fn is_deterministic(&self) -> bool {
+        self.outputs.len() == 1
+    }
+
+    /// The output of the first run, which the others are compared against.
+    #[must_use]
+    pub fn reference(&self) -> Option<&ObservedOutput> {
+        self.outputs.first()
+    }
+
+    /// Outputs that differ from the reference.
+    #[must_use]
+    pub fn divergent(&self) -> &[ObservedOutput] {
+        self.outputs.get(1..).unwrap_or_default()
+    }
+
+    /// Explain how each divergent output differs from the reference.
+    ///
+    /// Returns one [`Divergence`] per entry of [`Self::divergent`], in the
+    /// same order.
+    #[must_use]
+    pub fn diagnose(&self) -> Vec<Divergence> {
+        self.reference().map_or_else(Vec::new, |reference| {
+            self.divergent().iter().map(|output| Divergence::between(reference, output)).collect()
+        })
+    }
+
+    /// Fraction of runs that agree with the reference output, in `[0.0, 1.0]`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn agreement(&self) -> f64 {
+        self.reference()
+            .map_or(0.0, |reference| reference.runs.len() as f64 / self.runs.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use forge_core::execution::ExecutionStatus;
+    use forge_core::id::UserId;
+
+    use super::*;
+    use crate::compute_hash;
+
+    fn push_run(report: &mut DeterminismReport, boot: BootKind, stdout: &[u8]) {
+        let record = ExecutionRecord::new(
+            report.block_id,
+            UserId::new("test"),
+            report.input_hash,
+            compute_hash(stdout, b""),
+            Utc::now(),
+            Duration::from_millis(5),
+            ExecutionStatus::Succeeded,
+        );
+        report.push(boot, record, stdout, b"", 0);
+    }
+
+    #[test]
+    fn agreeing_runs_are_deterministic() {
+        let mut report = DeterminismReport::new(BlockId::new(), compute_hash(b"", b""));
+        for _ in 0..3 {
+            push_run(&mut report, BootKind::Fresh, b"same\n");
+        }
+        assert!(report.is_deterministic());
+        assert!(report.divergent().is_empty());
+        assert_eq!(report.outputs[0].runs, [0, 1, 2]);
+        assert!((report.agreement() - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn divergent_outputs_are_grouped_by_hash() {
+        let mut report = DeterminismReport::new(BlockId::new(), compute_hash(b"", b""));
+        push_run(&mut report, BootKind::Fresh, b"a\n");
+        push_run(&mut report, BootKind::Restored, b"b\n");
+        push_run(&mut report, BootKind::Fresh, b"a\n");
+        push_run(&mut report, BootKind::Restored, b"b\n");
+
+        assert!(!report.is_deterministic());
+        assert_eq!(report.reference().map(|o| o.stdout.as_slice()), Some(&b"a\n"[..]));
+        assert_eq!(report.divergent().len(), 1);
+        assert_eq!(report.divergent()[0].stdout, b"b\n");
+        assert_eq!(report.divergent()[0].runs, [1, 3]);
+        assert!((report.agreement() - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn empty_report_is_not_deterministic() {
+        let report = DeterminismReport::new(BlockId::new(), compute_hash(b"", b""));
+        assert!(!report.is_deterministic());
+        assert!(report.agreement().abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn alternating_mode_starts_fresh() {
+        let boots: Vec<_> = (0..4).map(|i| BootMode::Alternating.boot_for(i)).collect();
+        assert_eq!(
+            boots,
+            [BootKind::Fresh, BootKind::Restored, BootKind::Fresh, BootKind::Restored]
+        );
+        assert!(!BootMode::Alternating.needs_snapshot(1));
+        assert!(BootMode::Restored.needs_snapshot(1));
+        assert!(!BootMode::Fresh.needs_snapshot(5));
+    }
+}
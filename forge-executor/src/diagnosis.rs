@@ -0,0 +1,466 @@
+//! Non-determinism diagnosis — diffs of divergent outputs.
+//!
+//! When a [`DeterminismReport`](crate::DeterminismReport) holds more than one
+//! distinct output, [`DeterminismReport::diagnose`](crate::DeterminismReport::diagnose)
+//! compares each divergent output against the reference and explains the
+//! difference: the first differing byte, a line diff of each stream, and
+//! [`EntropyHint`]s naming the likely source of entropy.
+//!
+//! Hints are heuristics over the changed lines, not proofs. They flag
+//! differing tokens that look like timestamps, process IDs or temporary
+//! paths, and streams whose lines are the same but reordered.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use forge_core::id::ContentHash;
+
+use crate::determinism::ObservedOutput;
+
+/// Largest line-diff table computed, in cells (`reference × divergent`
+/// lines). Beyond this the differing region is reported as one replacement.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Largest PID recognised, in digits (Linux `pid_max` is at most 4194304).
+const MAX_PID_DIGITS: usize = 7;
+
+/// Directory prefixes whose paths are treated as temporary.
+const TEMP_PATH_MARKERS: [&str; 4] = ["/tmp/", "/var/tmp/", "/var/folders/", "/dev/shm/"];
+
+/// One of the two captured streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum OutputStream {
+    /// Standard output.
+    Stdout,
+    /// Standard error.
+    Stderr,
+}
+
+/// Likely cause of a difference between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum EntropySource {
+    /// Wall-clock dates, times or Unix epochs.
+    Timestamp,
+    /// Process IDs.
+    ProcessId,
+    /// Randomly named temporary files or directories.
+    TempPath,
+    /// The same lines in a different order, e.g. from directory listing or
+    /// hash-map iteration.
+    Ordering,
+}
+
+/// A suspected source of entropy, with where it was seen.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct EntropyHint {
+    /// What the difference looks like.
+    pub source: EntropySource,
+
+    /// The stream it was seen in.
+    pub stream: OutputStream,
+
+    /// First 1-based line of the reference output it was seen on, or `None`
+    /// for whole-stream hints such as [`EntropySource::Ordering`].
+    pub line: Option<usize>,
+}
+
+/// One line present in only one of the two outputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum LineChange {
+    /// A reference line missing from the divergent output.
+    Removed {
+        /// 1-based line number in the reference output.
+        line: usize,
+        /// The line, without its terminator, decoded lossily as UTF-8.
+        text: String,
+    },
+    /// A divergent line missing from the reference output.
+    Added {
+        /// 1-based line number in the divergent output.
+        line: usize,
+        /// The line, without its terminator, decoded lossily as UTF-8.
+        text: String,
+    },
+}
+
+/// How one stream of a divergent output differs from the reference.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct StreamDiff {
+    /// Byte offset of the first difference. Equal to the shorter length when
+    /// one stream is a prefix of the other.
+    pub first_difference: usize,
+
+    /// Length of the reference stream in bytes.
+    pub reference_len: usize,
+
+    /// Length of the divergent stream in bytes.
+    pub divergent_len: usize,
+
+    /// Changed lines in output order, each run of removals followed by the
+    /// additions that replace it.
+    pub changes: Vec<LineChange>,
+
+    /// Whether the differing region was too large to diff line by line and
+    /// is reported as a single replacement.
+    pub truncated: bool,
+}
+
+/// How one divergent output differs from the reference output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Divergence {
+    /// Output hash of the divergent output.
+    pub output_hash: ContentHash,
+
+    /// Runs that produced the divergent output.
+    pub runs: Vec<usize>,
+
+    /// Difference in standard output, if any.
+    pub stdout: Option<StreamDiff>,
+
+    /// Difference in standard error, if any.
+    pub stderr: Option<StreamDiff>,
+
+    /// Reference and divergent exit codes, if they differ.
+    pub exit_codes: Option<(i32, i32)>,
+
+    /// Suspected sources of the difference, at most one per source and
+    /// stream.
+    pub hints: Vec<EntropyHint>,
+}
+
+impl Divergence {
+    /// Compare `divergent` against `reference`.
+    #[must_use]
+    pub fn between(reference: &ObservedOutput, divergent: &ObservedOutput) -> Self {
+        let mut hints = Vec::new();
+        let stdout = diff_stream(&reference.stdout, &divergent.stdout);
+        if let Some(diff) = &stdout {
+            classify(&reference.stdout, &divergent.stdout, diff, OutputStream::Stdout, &mut hints);
+        }
+        let stderr = diff_stream(&reference.stderr, &divergent.stderr);
+        if let Some(diff) = &stderr {
+            classify(&reference.stderr, &divergent.stderr, diff, OutputStream::Stderr, &mut hints);
+        }
+
+        Self {
+            output_hash: divergent.output_hash,
+            runs: divergent.runs.clone(),
+            stdout,
+            stderr,
+            exit_codes: (reference.exit_code != divergent.exit_code)
+                .then_some((reference.exit_code, divergent.exit_code)),
+            hints,
+        }
+    }
+}
+
+/// Diff two versions of a stream, or `None` if they are identical.
+///
+/// # Complexity
+/// O(n·m) in the lines of the differing region, bounded by 4,000,000
+/// cells; larger regions are reported as a single replacement. The common
+/// prefix and suffix are skipped in O(n).
+#[must_use]
+pub fn diff_stream(reference: &[u8], divergent: &[u8]) -> Option<StreamDiff> {
+    let first_difference = reference
+        .iter()
+        .zip(divergent)
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| reference.len().min(divergent.len()));
+    if first_difference == reference.len() && first_difference == divergent.len() {
+        return None;
+    }
+
+    let old = lines(reference);
+    let new = lines(divergent);
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let truncated = old_middle.len().saturating_mul(new_middle.len()) > MAX_DIFF_CELLS;
+    let changes = if truncated {
+        replacement(old_middle, new_middle, prefix)
+    } else {
+        lcs_diff(old_middle, new_middle, prefix)
+    };
+
+    Some(StreamDiff {
+        first_difference,
+        reference_len: reference.len(),
+        divergent_len: divergent.len(),
+        changes,
+        truncated,
+    })
+}
+
+/// Split a stream into lines without their `\n` terminators.
+fn lines(stream: &[u8]) -> Vec<&[u8]> {
+    if stream.is_empty() {
+        return Vec::new();
+    }
+    let body = stream.strip_suffix(b"\n").unwrap_or(stream);
+    body.split(|&b| b == b'\n').collect()
+}
+
+fn removed(line: usize, text: &[u8]) -> LineChange {
+    LineChange::Removed { line, text: String::from_utf8_lossy(text).into_owned() }
+}
+
+fn added(line: usize, text: &[u8]) -> LineChange {
+    LineChange::Added { line, text: String::from_utf8_lossy(text).into_owned() }
+}
+
+/// Every old line removed, then every new line added. `offset` is the number
+/// of lines before the region.
+fn replacement(old: &[&[u8]], new: &[&[u8]], offset: usize) -> Vec<LineChange> {
+    let removals = old.iter().enumerate().map(|(i, text)| removed(offset + i + 1, text));
+    let additions = new.iter().enumerate().map(|(i, text)| added(offset + i + 1, text));
+    removals.chain(additions).collect()
+}
+
+/// Line diff via longest common subsequence. `offset` is the number of lines
+/// before the region.
+fn lcs_diff(old: &[&[u8]], new: &[&[u8]], offset: usize) -> Vec<LineChange> {
+    let width = new.len() + 1;
+    // suffix_lcs[i * width + j] is the LCS length of old[i..] and new[j..].
+    let mut suffix_lcs = vec![0_usize; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            suffix_lcs[i * width + j] = if old[i] == new[j] {
+                suffix_lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                suffix_lcs[(i + 1) * width + j].max(suffix_lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut pending_additions = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.append(&mut pending_additions);
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && suffix_lcs[(i + 1) * width + j] >= suffix_lcs[i * width + j + 1])
+        {
+            changes.push(removed(offset + i + 1, old[i]));
+            i += 1;
+        } else {
+            pending_additions.push(added(offset + j + 1, new[j]));
+            j += 1;
+        }
+    }
+    changes.append(&mut pending_additions);
+    changes
+}
+
+/// Add hints explaining `diff` to `hints`.
+fn classify(
+    reference: &[u8],
+    divergent: &[u8],
+    diff: &StreamDiff,
+    stream: OutputStream,
+    hints: &mut Vec<EntropyHint>,
+) {
+    let mut hint = |source, line| {
+        if !hints.iter().any(|h| h.source == source && h.stream == stream) {
+            hints.push(EntropyHint { source, stream, line });
+        }
+    };
+
+    let mut old_sorted = lines(reference);
+    let mut new_sorted = lines(divergent);
+    old_sorted.sort_unstable();
+    new_sorted.sort_unstable();
+    if old_sorted == new_sorted {
+        hint(EntropySource::Ordering, None);
+        return;
+    }
+
+    // Pair each removed line with the addition at the same position in the
+    // run that replaces it, then compare the two token by token.
+    let mut removals = VecDeque::new();
+    let mut replacing = false;
+    for change in &diff.changes {
+        match change {
+            LineChange::Removed { line, text } => {
+                let contiguous = removals.back().is_some_and(|&(last, _)| last + 1 == *line);
+                if replacing || !contiguous {
+                    removals.clear();
+                    replacing = false;
+                }
+                removals.push_back((*line, text.as_str()));
+            }
+            LineChange::Added { text, .. } => {
+                replacing = true;
+                if let Some((line, old_text)) = removals.pop_front() {
+                    for source in differing_tokens(old_text, text) {
+                        hint(source, Some(line));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Classify the tokens that differ between two versions of a line.
+fn differing_tokens(old: &str, new: &str) -> Vec<EntropySource> {
+    let mentions_pid = old.to_ascii_lowercase().contains("pid");
+    let mut sources = Vec::new();
+    for (a, b) in old.split_whitespace().zip(new.split_whitespace()) {
+        if a == b {
+            continue;
+        }
+        let source = if is_timestamp(a) && is_timestamp(b) {
+            Some(EntropySource::Timestamp)
+        } else if is_temp_path(a) && is_temp_path(b) {
+            Some(EntropySource::TempPath)
+        } else if is_pid(a, mentions_pid) && is_pid(b, mentions_pid) {
+            Some(EntropySource::ProcessId)
+        } else {
+            None
+        };
+        if let Some(source) = source.filter(|s| !sources.contains(s)) {
+            sources.push(source);
+        }
+    }
+    sources
+}
+
+/// `HH:MM`, `YYYY-MM-DD`, or a 10- or 13-digit Unix epoch.
+fn is_timestamp(token: &str) -> bool {
+    let bytes = token.as_bytes();
+    let digit = |b: &u8| b.is_ascii_digit();
+    let clock = bytes
+        .windows(5)
+        .any(|w| digit(&w[0]) && digit(&w[1]) && w[2] == b':' && digit(&w[3]) && digit(&w[4]));
+    let date = bytes.windows(10).any(|w| {
+        w[..4].iter().all(digit)
+            && w[4] == b'-'
+            && w[5..7].iter().all(digit)
+            && w[7] == b'-'
+            && w[8..].iter().all(digit)
+    });
+    let digits = token.trim_matches(|c: char| !c.is_ascii_digit());
+    let epoch = matches!(digits.len(), 10 | 13) && digits.bytes().all(|b| b.is_ascii_digit());
+    clock || date || epoch
+}
+
+fn is_temp_path(token: &str) -> bool {
+    TEMP_PATH_MARKERS.iter().any(|marker| token.contains(marker))
+}
+
+/// A bare number on a line mentioning PIDs, or a bracketed number such as
+/// the `sshd[1234]:` of syslog.
+fn is_pid(token: &str, mentions_pid: bool) -> bool {
+    let digits = token.trim_matches(|c: char| !c.is_ascii_digit());
+    let numeric = !digits.is_empty()
+        && digits.len() <= MAX_PID_DIGITS
+        && digits.bytes().all(|b| b.is_ascii_digit());
+    let bracketed = token.contains(&format!("[{digits}]"));
+    numeric && (mentions_pid || bracketed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hint_sources(reference: &str, divergent: &str) -> Vec<EntropySource> {
+        let diff = diff_stream(reference.as_bytes(), divergent.as_bytes())
+            .unwrap_or_else(|| panic!("outputs must differ"));
+        let mut hints = Vec::new();
+        classify(
+            reference.as_bytes(),
+            divergent.as_bytes(),
+            &diff,
+            OutputStream::Stdout,
+            &mut hints,
+        );
+        hints.into_iter().map(|h| h.source).collect()
+    }
+
+    #[test]
+    fn identical_streams_have_no_diff() {
+        assert_eq!(diff_stream(b"same\n", b"same\n"), None);
+    }
+
+    #[test]
+    fn diff_reports_first_offset_and_changed_lines() {
+        let diff = diff_stream(b"a\nb\nc\n", b"a\nB\nc\nd\n")
+            .unwrap_or_else(|| panic!("outputs must differ"));
+        assert_eq!(diff.first_difference, 2);
+        assert!(!diff.truncated);
+        assert_eq!(
+            diff.changes,
+            [
+                LineChange::Removed { line: 2, text: "b".to_owned() },
+                LineChange::Added { line: 2, text: "B".to_owned() },
+                LineChange::Added { line: 4, text: "d".to_owned() },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_difference_is_at_shorter_length() {
+        let diff = diff_stream(b"abc", b"abcdef").unwrap_or_else(|| panic!("outputs must differ"));
+        assert_eq!(diff.first_difference, 3);
+        assert_eq!((diff.reference_len, diff.divergent_len), (3, 6));
+    }
+
+    #[test]
+    fn reordered_lines_are_an_ordering_hint() {
+        assert_eq!(hint_sources("a\nb\nc\n", "c\na\nb\n"), [EntropySource::Ordering]);
+    }
+
+    #[test]
+    fn timestamps_pids_and_temp_paths_are_recognised() {
+        assert_eq!(
+            hint_sources("built at 2024-01-01T10:00:00Z\n", "built at 2024-01-01T10:00:07Z\n"),
+            [EntropySource::Timestamp]
+        );
+        assert_eq!(
+            hint_sources("worker pid 4312\n", "worker pid 4377\n"),
+            [EntropySource::ProcessId]
+        );
+        assert_eq!(hint_sources("cc[812]: done\n", "cc[907]: done\n"), [EntropySource::ProcessId]);
+        assert_eq!(
+            hint_sources("wrote /tmp/tmp.Xa81kq/out\n", "wrote /tmp/tmp.Lp02zc/out\n"),
+            [EntropySource::TempPath]
+        );
+    }
+
+    #[test]
+    fn unrelated_changes_have_no_hint() {
+        assert!(hint_sources("result: 41\n", "result: 42\n").is_empty());
+    }
+
+    #[test]
+    fn divergence_compares_streams_and_exit_codes() {
+        let output = |stdout: &[u8], exit_code| ObservedOutput {
+            output_hash: crate::compute_hash(stdout, b""),
+            stdout: stdout.to_vec(),
+            stderr: Vec::new(),
+            exit_code,
+            runs: vec![0],
+        };
+        let divergence = Divergence::between(&output(b"x\n", 0), &output(b"y\n", 1));
+        assert!(divergence.stdout.is_some());
+        assert!(divergence.stderr.is_none(), "identical stderr must not be diffed");
+        assert_eq!(divergence.exit_codes, Some((0, 1)));
+    }
+}
@@ -4,12 +4,15 @@
 
 use uuid::Uuid;
 
+use forge_core::execution::HashScheme;
+use forge_core::id::ContentHash;
+
 /// Errors that can occur during VM lifecycle operations.
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum ExecutorError {
-    /// Firecracker binary not found at the configured path.
-    #[error("firecracker binary not found at {path}")]
+    /// A required host binary (the VMM, a launcher or a tool) was not found.
+    #[error("binary not found at {path}")]
     BinaryNotFound {
         /// The path where the binary was expected.
         path: PathBuf,
@@ -44,10 +47,94 @@ pub enum ExecutorError {
         reason: String,
     },
 
+    /// No catalogue entry or files exist for the snapshot.
+    #[error("snapshot not found: {0}")]
+    SnapshotNotFound(Uuid),
+
+    /// Snapshot files no longer match the hashes recorded in the catalogue.
+    #[error("snapshot {snapshot_id} failed integrity check: {reason}")]
+    SnapshotIntegrity {
+        /// The ID of the snapshot that failed verification.
+        snapshot_id: Uuid,
+        /// Which file failed and how.
+        reason: String,
+    },
+
+    /// The snapshot cannot be removed because other state depends on it.
+    #[error("snapshot {snapshot_id} is in use: {reason}")]
+    SnapshotInUse {
+        /// The ID of the snapshot that is still referenced.
+        snapshot_id: Uuid,
+        /// What depends on the snapshot.
+        reason: String,
+    },
+
     /// Firecracker API request failed.
     #[error("API request failed: {0}")]
     ApiError(String),
 
+    /// The backend does not implement the requested operation.
+    #[error("operation not supported: {0}")]
+    Unsupported(String),
+
+    /// A replayed execution was never recorded in the cassette.
+    #[error("no recording of {command:?} in cassette (request {request_key})")]
+    UnrecordedExecution {
+        /// The command that was requested.
+        command: String,
+        /// Content address of the request the cassette was searched for.
+        request_key: String,
+    },
+
+    /// A block's canonicalisation rule cannot be applied.
+    #[error("invalid canonicalization rule {index}: {reason}")]
+    InvalidCanonicalization {
+        /// Position of the rule in the manifest.
+        index: usize,
+        /// Why the rule is invalid.
+        reason: String,
+    },
+
+    /// A manifest output path cannot be collected from the guest.
+    #[error(
+        "invalid output path {0:?}: must be absolute, without `..`, and need no shell quoting"
+    )]
+    InvalidOutputPath(String),
+
+    /// A composite block's stages cannot be run as a pipeline.
+    #[error("invalid pipeline {block}: {reason}")]
+    InvalidPipeline {
+        /// Name of the composite block.
+        block: String,
+        /// Why the pipeline was rejected.
+        reason: String,
+    },
+
+    /// A kernel or rootfs image no longer hashes to the hash the VM
+    /// configuration names it by.
+    #[error("image {} hashes to {actual}, expected {expected}", path.display())]
+    ImageHashMismatch {
+        /// Where the image was read from.
+        path: PathBuf,
+        /// The hash the configuration names.
+        expected: ContentHash,
+        /// The hash of the image's contents.
+        actual: ContentHash,
+    },
+
+    /// A custom CPU template is not a valid template file.
+    #[error("invalid CPU template {}: {reason}", path.display())]
+    InvalidCpuTemplate {
+        /// Where the template was read from.
+        path: PathBuf,
+        /// Why it was rejected.
+        reason: String,
+    },
+
+    /// A record names a hash scheme this version cannot compute.
+    #[error("unsupported hash scheme {0:?}")]
+    UnsupportedHashScheme(HashScheme),
+
     /// VM not found in the active registry.
     #[error("VM not found: {0}")]
     VmNotFound(Uuid),
@@ -11,13 +11,16 @@
 use std::time::Duration;
 
 use async_trait::async_trait;
-use base64::Engine as _;
 use hyper::Method;
 use tokio::io::AsyncReadExt;
 use tokio::process::Command;
 use uuid::Uuid;
 
 use crate::backend::{ExecutionOutput, VmmBackend};
+use crate::catalog::SnapshotCatalog;
+use crate::config::{custom_template, CpuTemplate};
+use crate::guest::{capture_script, parse_execution_output, run_on_console};
+use crate::host::{check_kvm, which_binary};
 use crate::unix_client::api_request;
 use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
 
@@ -33,8 +36,8 @@ pub struct FirecrackerBackend {
     /// Directory where per-VM Unix sockets are created.
     socket_dir: PathBuf,
 
-    /// Directory where snapshot files are stored.
-    snapshot_dir: PathBuf,
+    /// Catalogue of snapshot files and their metadata.
+    catalog: SnapshotCatalog,
 }
 
 impl FirecrackerBackend {
@@ -46,7 +49,7 @@ impl FirecrackerBackend {
     /// - `snapshot_dir`: directory for snapshot state files (must be writable)
     #[must_use]
     pub const fn new(binary_path: PathBuf, socket_dir: PathBuf, snapshot_dir: PathBuf) -> Self {
-        Self { binary_path, socket_dir, snapshot_dir }
+        Self { binary_path, socket_dir, catalog: SnapshotCatalog::new(snapshot_dir) }
     }
 
     /// Create a backend using system defaults.
@@ -62,16 +65,154 @@ pub fn with_defaults() -> Self {
         )
     }
 
+    /// The catalogue of snapshots written by this backend.
+    ///
+    /// Use it to list snapshots, inspect their metadata, and run retention
+    /// garbage collection.
+    #[must_use]
+    pub const fn catalog(&self) -> &SnapshotCatalog {
+        &self.catalog
+    }
+
     fn socket_path(&self, vm_id: Uuid) -> PathBuf {
         self.socket_dir.join(format!("{vm_id}.sock"))
     }
 
-    fn snapshot_mem_path(&self, snapshot_id: SnapshotId) -> PathBuf {
-        self.snapshot_dir.join(format!("{snapshot_id}.mem"))
+    /// Private copy of the root disk a restored VM writes to.
+    fn rootfs_clone_path(&self, vm_id: Uuid) -> PathBuf {
+        self.socket_dir.join(format!("{vm_id}.rootfs"))
+    }
+
+    /// Pause the VM, write a full or diff snapshot, resume, and catalogue it.
+    ///
+    /// A writable root disk is copied next to the snapshot while the VM is
+    /// paused, so restored VMs start from the disk the memory expects.
+    async fn create_snapshot(
+        &self,
+        handle: &VmHandle,
+        parent: Option<SnapshotId>,
+    ) -> Result<SnapshotId, ExecutorError> {
+        let snapshot_id = SnapshotId::new();
+
+        tokio::fs::create_dir_all(self.catalog.dir()).await?;
+
+        let mem_path = self.catalog.mem_path(snapshot_id);
+        let state_path = self.catalog.state_path(snapshot_id);
+
+        tracing::info!(
+            vm_id = %handle.id,
+            snapshot_id = %snapshot_id,
+            diff = parent.is_some(),
+            "creating VM snapshot"
+        );
+
+        // Firecracker requires the VM to be paused before snapshotting.
+        let pause_body = serde_json::json!({ "state": "Paused" });
+        api_request(&handle.socket_path, Method::PATCH, "/vm", Some(pause_body.to_string()))
+            .await
+            .map_err(|e| ExecutorError::SnapshotFailed {
+                vm_id: handle.id,
+                reason: format!("pause failed: {e}"),
+            })?;
+
+        let body = serde_json::json!({
+            "snapshot_type": if parent.is_some() { "Diff" } else { "Full" },
+            "snapshot_path": state_path,
+            "mem_file_path": mem_path,
+        });
+
+        let mut result = api_request(
+            &handle.socket_path,
+            Method::PUT,
+            "/snapshot/create",
+            Some(body.to_string()),
+        )
+        .await
+        .map(drop);
+        let writable_rootfs =
+            handle.config.as_ref().filter(|c| c.rootfs_hash.is_none()).map(|c| &c.rootfs_path);
+        if let (Ok(()), Some(rootfs)) = (&result, writable_rootfs) {
+            // std::fs::copy clones extents where the filesystem supports it.
+            result = tokio::fs::copy(rootfs, self.catalog.disk_path(snapshot_id))
+                .await
+                .map(drop)
+                .map_err(ExecutorError::from);
+        }
+
+        // Always attempt to resume, even if snapshot failed.
+        let resume_body = serde_json::json!({ "state": "Resumed" });
+        let _ =
+            api_request(&handle.socket_path, Method::PATCH, "/vm", Some(resume_body.to_string()))
+                .await;
+
+        if let Err(e) = result {
+            let _ = tokio::fs::remove_file(self.catalog.disk_path(snapshot_id)).await;
+            return Err(ExecutorError::SnapshotFailed { vm_id: handle.id, reason: e.to_string() });
+        }
+
+        self.catalog.record(snapshot_id, handle.id, handle.config.clone(), parent).await.map_err(
+            |e| ExecutorError::SnapshotFailed {
+                vm_id: handle.id,
+                reason: format!("catalogue record failed: {e}"),
+            },
+        )?;
+
+        tracing::info!(snapshot_id = %snapshot_id, "snapshot created");
+
+        Ok(snapshot_id)
     }
 
-    fn snapshot_state_path(&self, snapshot_id: SnapshotId) -> PathBuf {
-        self.snapshot_dir.join(format!("{snapshot_id}.state"))
+    /// Load a snapshot paused, repoint its root drive at a private clone of
+    /// the snapshot's root disk copy, and resume it. Returns the clone's path.
+    ///
+    /// Firecracker reopens the drive at the path the source VM used, so if
+    /// that disk is gone the copy stands in for it until the drive is
+    /// repointed. Nothing writes to it before then.
+    async fn load_with_private_rootfs(
+        &self,
+        snapshot_id: SnapshotId,
+        vm_id: Uuid,
+        source_rootfs: &Path,
+        load_body: &serde_json::Value,
+    ) -> Result<PathBuf, ExecutorError> {
+        let disk = self.catalog.disk_path(snapshot_id);
+        let clone = self.rootfs_clone_path(vm_id);
+        tokio::fs::copy(&disk, &clone).await?;
+
+        let stand_in = match tokio::fs::hard_link(&disk, source_rootfs).await {
+            Ok(()) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => false,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&clone).await;
+                return Err(e.into());
+            }
+        };
+
+        let socket_path = self.socket_path(vm_id);
+        let drive_body = serde_json::json!({ "drive_id": "rootfs", "path_on_host": clone });
+        let resume_body = serde_json::json!({ "state": "Resumed" });
+        let loaded = async {
+            api_request(&socket_path, Method::PUT, "/snapshot/load", Some(load_body.to_string()))
+                .await?;
+            api_request(
+                &socket_path,
+                Method::PATCH,
+                "/drives/rootfs",
+                Some(drive_body.to_string()),
+            )
+            .await?;
+            api_request(&socket_path, Method::PATCH, "/vm", Some(resume_body.to_string())).await
+        }
+        .await;
+
+        if stand_in {
+            let _ = tokio::fs::remove_file(source_rootfs).await;
+        }
+        if let Err(e) = loaded {
+            let _ = tokio::fs::remove_file(&clone).await;
+            return Err(e);
+        }
+        Ok(clone)
     }
 
     /// Wait for the Firecracker API socket to become available.
@@ -101,24 +242,53 @@ async fn configure_and_boot(
         api_request(socket_path, Method::PUT, "/boot-source", Some(kernel_body.to_string()))
             .await?;
 
-        // Set rootfs
+        // Set rootfs; a content-addressed image must stay immutable
         let rootfs_body = serde_json::json!({
             "drive_id": "rootfs",
             "path_on_host": config.rootfs_path,
             "is_root_device": true,
-            "is_read_only": false,
+            "is_read_only": config.rootfs_hash.is_some(),
         });
         api_request(socket_path, Method::PUT, "/drives/rootfs", Some(rootfs_body.to_string()))
             .await?;
 
+        // Set read-only store layers, in the order the guest init expects
+        for (index, layer) in config.layer_paths.iter().enumerate() {
+            let drive_id = format!("layer{index}");
+            let layer_body = serde_json::json!({
+                "drive_id": drive_id,
+                "path_on_host": layer,
+                "is_root_device": false,
+                "is_read_only": true,
+            });
+            api_request(
+                socket_path,
+                Method::PUT,
+                &format!("/drives/{drive_id}"),
+                Some(layer_body.to_string()),
+            )
+            .await?;
+        }
+
         // Set machine config
-        let machine_body = serde_json::json!({
+        let mut machine_body = serde_json::json!({
             "vcpu_count": config.vcpu_count,
             "mem_size_mib": config.mem_size_mib,
+            "track_dirty_pages": config.track_dirty_pages,
         });
+        if let Some(CpuTemplate::Static(template)) = &config.cpu_template {
+            machine_body["cpu_template"] = template.name().into();
+        }
         api_request(socket_path, Method::PUT, "/machine-config", Some(machine_body.to_string()))
             .await?;
 
+        // Set custom CPU template
+        if let Some(CpuTemplate::Custom(path)) = &config.cpu_template {
+            let template = custom_template(path).await?;
+            api_request(socket_path, Method::PUT, "/cpu-config", Some(template.to_string()))
+                .await?;
+        }
+
         // Boot
         let boot_body = serde_json::json!({ "action_type": "InstanceStart" });
         api_request(socket_path, Method::PUT, "/actions", Some(boot_body.to_string())).await?;
@@ -166,65 +336,43 @@ async fn spawn(&self, config: &VmConfig) -> Result<VmHandle, ExecutorError> {
 
         tracing::info!(vm_id = %vm_id, "VM booted successfully");
 
-        Ok(VmHandle::new(vm_id, socket_path, process))
+        Ok(VmHandle::new(vm_id, socket_path, process).with_config(config.clone()))
     }
 
     async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
-        let snapshot_id = SnapshotId::new();
-
-        tokio::fs::create_dir_all(&self.snapshot_dir).await?;
-
-        let mem_path = self.snapshot_mem_path(snapshot_id);
-        let state_path = self.snapshot_state_path(snapshot_id);
+        self.create_snapshot(handle, None).await
+    }
 
-        tracing::info!(
-            vm_id = %handle.id,
-            snapshot_id = %snapshot_id,
-            "creating VM snapshot"
-        );
+    async fn diff_snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        if handle.config.as_ref().is_some_and(|c| !c.track_dirty_pages) {
+            return Err(ExecutorError::SnapshotFailed {
+                vm_id: handle.id,
+                reason: "VM was booted without dirty page tracking".to_owned(),
+            });
+        }
 
-        // Firecracker requires the VM to be paused before snapshotting.
-        let pause_body = serde_json::json!({ "state": "Paused" });
-        api_request(&handle.socket_path, Method::PATCH, "/vm", Some(pause_body.to_string()))
-            .await
-            .map_err(|e| ExecutorError::SnapshotFailed {
+        // The diff is relative to this VM's most recent snapshot, or to the
+        // snapshot it was restored from if it has not been snapshotted yet.
+        let latest_own = self
+            .catalog
+            .list()
+            .await?
+            .into_iter()
+            .rev()
+            .find(|m| m.source_vm == handle.id && m.flattened_from.is_none())
+            .map(|m| m.id);
+        let parent =
+            latest_own.or(handle.restored_from).ok_or_else(|| ExecutorError::SnapshotFailed {
                 vm_id: handle.id,
-                reason: format!("pause failed: {e}"),
+                reason: "no parent snapshot; take a full snapshot first".to_owned(),
             })?;
 
-        let body = serde_json::json!({
-            "snapshot_type": "Full",
-            "snapshot_path": state_path,
-            "mem_file_path": mem_path,
-        });
-
-        let result = api_request(
-            &handle.socket_path,
-            Method::PUT,
-            "/snapshot/create",
-            Some(body.to_string()),
-        )
-        .await;
-
-        // Always attempt to resume, even if snapshot failed.
-        let resume_body = serde_json::json!({ "state": "Resumed" });
-        let _ =
-            api_request(&handle.socket_path, Method::PATCH, "/vm", Some(resume_body.to_string()))
-                .await;
-
-        result.map_err(|e| ExecutorError::SnapshotFailed {
-            vm_id: handle.id,
-            reason: e.to_string(),
-        })?;
-
-        tracing::info!(snapshot_id = %snapshot_id, "snapshot created");
-
-        Ok(snapshot_id)
+        self.create_snapshot(handle, Some(parent)).await
     }
 
     async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
-        let mem_path = self.snapshot_mem_path(*snapshot_id);
-        let state_path = self.snapshot_state_path(*snapshot_id);
+        let mem_path = self.catalog.mem_path(*snapshot_id);
+        let state_path = self.catalog.state_path(*snapshot_id);
 
         if !mem_path.exists() || !state_path.exists() {
             return Err(ExecutorError::RestoreFailed {
@@ -233,6 +381,16 @@ async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorEr
             });
         }
 
+        // Refuse to boot from snapshot files that changed since they were
+        // written. A diff snapshot's memory is incomplete on its own, so it
+        // boots from the catalogue's flattening of its chain. Firecracker maps
+        // the memory file privately, so the flattening stays reusable.
+        let metadata = self.catalog.flattened(*snapshot_id).await.map_err(|e| {
+            ExecutorError::RestoreFailed { snapshot_id: snapshot_id.0, reason: e.to_string() }
+        })?;
+        let (mem_path, state_path) =
+            (self.catalog.mem_path(metadata.id), self.catalog.state_path(metadata.id));
+
         let vm_id = Uuid::new_v4();
         let socket_path = self.socket_path(vm_id);
 
@@ -244,9 +402,13 @@ async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorEr
             "restoring VM from snapshot"
         );
 
+        // Pipe the serial console so commands can be sent to the restored
+        // guest via `execute_in_vm`.
         let process = Command::new(&self.binary_path)
             .arg("--api-sock")
             .arg(&socket_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
             .kill_on_drop(true)
             .spawn()
             .map_err(|e| ExecutorError::RestoreFailed {
@@ -259,26 +421,57 @@ async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorEr
             reason: e.to_string(),
         })?;
 
+        // A writable root disk is cloned for this VM alone; the source VM and
+        // every other VM restored from the snapshot keep writing to their own.
+        let source_rootfs = metadata
+            .source_config
+            .as_ref()
+            .filter(|_| metadata.has_disk())
+            .map(|c| c.rootfs_path.clone());
         let body = serde_json::json!({
             "snapshot_path": state_path,
             "mem_backend": {
                 "backend_path": mem_path,
                 "backend_type": "File",
             },
-            "enable_diff_snapshots": false,
-            "resume_vm": true,
+            "enable_diff_snapshots": metadata.source_config.as_ref().is_some_and(|c| c.track_dirty_pages),
+            "resume_vm": source_rootfs.is_none(),
         });
 
-        api_request(&socket_path, Method::PUT, "/snapshot/load", Some(body.to_string()))
-            .await
-            .map_err(|e| ExecutorError::RestoreFailed {
-                snapshot_id: snapshot_id.0,
-                reason: e.to_string(),
-            })?;
+        let loaded = match &source_rootfs {
+            Some(source_rootfs) => self
+                .load_with_private_rootfs(metadata.id, vm_id, source_rootfs, &body)
+                .await
+                .map(Some),
+            None => {
+                api_request(&socket_path, Method::PUT, "/snapshot/load", Some(body.to_string()))
+                    .await
+                    .map(|_| None)
+            }
+        };
+
+        let rootfs = loaded.map_err(|e| ExecutorError::RestoreFailed {
+            snapshot_id: snapshot_id.0,
+            reason: e.to_string(),
+        })?;
 
         tracing::info!(vm_id = %vm_id, "VM restored from snapshot");
 
-        Ok(VmHandle::new(vm_id, socket_path, process))
+        let mut handle = VmHandle::new(vm_id, socket_path, process);
+        handle.restored_from = Some(*snapshot_id);
+        Ok(match metadata.source_config {
+            Some(mut config) => {
+                if let Some(rootfs) = rootfs {
+                    config.rootfs_path = rootfs;
+                }
+                handle.with_config(config)
+            }
+            None => handle,
+        })
+    }
+
+    async fn delete_snapshot(&self, snapshot_id: &SnapshotId) -> Result<(), ExecutorError> {
+        self.catalog.delete(*snapshot_id).await
     }
 
     async fn terminate(&self, mut handle: VmHandle) -> Result<(), ExecutorError> {
@@ -286,6 +479,7 @@ async fn terminate(&self, mut handle: VmHandle) -> Result<(), ExecutorError> {
 
         handle.process.kill().await?;
         let _ = tokio::fs::remove_file(&handle.socket_path).await;
+        let _ = tokio::fs::remove_file(self.rootfs_clone_path(handle.id)).await;
 
         tracing::info!(vm_id = %handle.id, "VM terminated");
 
@@ -293,15 +487,7 @@ async fn terminate(&self, mut handle: VmHandle) -> Result<(), ExecutorError> {
     }
 
     async fn health_check(&self) -> Result<(), ExecutorError> {
-        // Check KVM
-        if !Path::new("/dev/kvm").exists() {
-            return Err(ExecutorError::KvmUnavailable { reason: "/dev/kvm not found".to_owned() });
-        }
-
-        // Check KVM is readable
-        tokio::fs::metadata("/dev/kvm").await.map_err(|_| ExecutorError::KvmUnavailable {
-            reason: "cannot access /dev/kvm (permission denied?)".to_owned(),
-        })?;
+        check_kvm().await?;
 
         // Check binary
         which_binary(&self.binary_path)?;
@@ -328,15 +514,9 @@ async fn execute_command(
         // Embed the command as the init process.
         // Separate stdout/stderr via temp files; base64-encode both to survive
         // the serial console's text transport without corruption.
-        let init_script = format!(
-            "SF=$(mktemp);EF=$(mktemp);eval \"{command}\" >\"$SF\" 2>\"$EF\";EC=$?;\
-             echo FORGE_STDOUT_B64_START;base64 \"$SF\";echo FORGE_STDOUT_B64_END;\
-             echo FORGE_STDERR_B64_START;base64 \"$EF\";echo FORGE_STDERR_B64_END;\
-             echo FORGE_EXIT:$EC;poweroff -f 2>/dev/null||reboot -f"
-        );
-        let boot_args = format!(
-            "console=ttyS0 reboot=k panic=1 pci=off quiet init=/bin/sh -c \"{init_script}\""
-        );
+        let init_script = format!("{};poweroff -f 2>/dev/null||reboot -f", capture_script(command));
+        let init = config.init_args(&format!("/bin/sh -c \"{init_script}\""));
+        let boot_args = format!("console=ttyS0 reboot=k panic=1 pci=off quiet {init}");
 
         let mut exec_config = config.clone();
         exec_config.boot_args = boot_args;
@@ -392,192 +572,30 @@ async fn execute_command(
 
         Ok(ExecutionOutput { stdout, stderr, exit_code })
     }
-}
-
-/// Verify a binary exists either at the given path or in PATH.
-fn which_binary(path: &Path) -> Result<(), ExecutorError> {
-    if path.is_absolute() {
-        if path.exists() {
-            return Ok(());
-        }
-        return Err(ExecutorError::BinaryNotFound { path: path.to_owned() });
-    }
-
-    // Relative or bare name — check PATH
-    let found = std::env::var("PATH")
-        .unwrap_or_default()
-        .split(':')
-        .map(|dir| Path::new(dir).join(path))
-        .any(|p| p.exists());
-
-    if found {
-        Ok(())
-    } else {
-        Err(ExecutorError::BinaryNotFound { path: path.to_owned() })
-    }
-}
-
-/// Parse stdout, stderr, and exit code from raw serial console output.
-///
-/// Expects the output to contain base64-encoded sections delimited by:
-/// - `FORGE_STDOUT_B64_START` / `FORGE_STDOUT_B64_END`
-/// - `FORGE_STDERR_B64_START` / `FORGE_STDERR_B64_END`
-/// - `FORGE_EXIT:<code>`
-///
-/// # Returns
-/// `(stdout, stderr, exit_code)`. Falls back to `(raw, [], -1)` when markers
-/// are absent (e.g. rootfs does not support the init script pattern).
-fn parse_execution_output(raw: &[u8]) -> (Vec<u8>, Vec<u8>, i32) {
-    let stdout = extract_b64_section(raw, b"FORGE_STDOUT_B64_START", b"FORGE_STDOUT_B64_END");
-    let stderr = extract_b64_section(raw, b"FORGE_STDERR_B64_START", b"FORGE_STDERR_B64_END");
-    let exit_code = extract_exit_code(raw);
-
-    match (stdout, stderr, exit_code) {
-        (Some(out), Some(err), Some(code)) => (out, err, code),
-        _ => (raw.to_vec(), Vec::new(), -1),
-    }
-}
-
-/// Extract and base64-decode a section delimited by `start_marker` / `end_marker`.
-///
-/// Markers are expected to be followed by `\r\n` (serial console line endings).
-/// Returns `None` if either marker is absent or the base64 payload is invalid.
-fn extract_b64_section(raw: &[u8], start_marker: &[u8], end_marker: &[u8]) -> Option<Vec<u8>> {
-    let start_with_crlf: Vec<u8> = [start_marker, b"\r\n"].concat();
-
-    let content_start = raw
-        .windows(start_with_crlf.len())
-        .position(|w| w == start_with_crlf.as_slice())
-        .map(|p| p + start_with_crlf.len())?;
-
-    let content_end = raw[content_start..]
-        .windows(end_marker.len())
-        .position(|w| w == end_marker)
-        .map(|p| p + content_start)?;
-
-    // Strip \r and \n before decoding — base64 lines are split by the shell.
-    let b64_clean: Vec<u8> = raw[content_start..content_end]
-        .iter()
-        .copied()
-        .filter(|&b| b != b'\r' && b != b'\n')
-        .collect();
-
-    base64::engine::general_purpose::STANDARD.decode(&b64_clean).ok()
-}
-
-/// Extract the integer exit code from a `FORGE_EXIT:<N>` line.
-///
-/// Returns `None` if the marker is absent or the value cannot be parsed.
-fn extract_exit_code(raw: &[u8]) -> Option<i32> {
-    let marker = b"FORGE_EXIT:";
-    let value_start =
-        raw.windows(marker.len()).position(|w| w == marker).map(|p| p + marker.len())?;
-
-    let rest = &raw[value_start..];
-    let value_end = rest.iter().position(|&b| b == b'\r' || b == b'\n').unwrap_or(rest.len());
-
-    std::str::from_utf8(&rest[..value_end]).ok()?.trim().parse().ok()
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn make_b64_output(stdout: &[u8], stderr: &[u8], exit_code: i32) -> Vec<u8> {
-        use base64::Engine as _;
-        let stdout_b64 = base64::engine::general_purpose::STANDARD.encode(stdout);
-        let stderr_b64 = base64::engine::general_purpose::STANDARD.encode(stderr);
-        format!(
-            "kernel boot noise\r\nFORGE_STDOUT_B64_START\r\n{stdout_b64}\r\nFORGE_STDOUT_B64_END\r\n\
-             FORGE_STDERR_B64_START\r\n{stderr_b64}\r\nFORGE_STDERR_B64_END\r\n\
-             FORGE_EXIT:{exit_code}\r\n"
-        )
-        .into_bytes()
-    }
-
-    #[test]
-    fn parse_execution_output_extracts_stdout_and_stderr() {
-        let raw = make_b64_output(b"hello stdout\n", b"hello stderr\n", 0);
-        let (stdout, stderr, _) = parse_execution_output(&raw);
-        assert_eq!(stdout, b"hello stdout\n");
-        assert_eq!(stderr, b"hello stderr\n");
-    }
-
-    #[test]
-    fn parse_execution_output_decodes_base64() {
-        let binary_payload: Vec<u8> = (0u8..=255).collect();
-        let raw = make_b64_output(&binary_payload, b"", 0);
-        let (stdout, _, _) = parse_execution_output(&raw);
-        assert_eq!(stdout, binary_payload, "binary payload must survive base64 round-trip");
-    }
 
-    #[test]
-    fn parse_execution_output_fallback_on_missing_markers() {
-        let raw = b"raw serial output without any markers";
-        let (stdout, stderr, exit_code) = parse_execution_output(raw);
-        assert_eq!(stdout, raw, "fallback stdout must equal raw input");
-        assert!(stderr.is_empty(), "fallback stderr must be empty");
-        assert_eq!(exit_code, -1, "fallback exit code must be -1");
-    }
-
-    #[test]
-    fn parse_execution_output_extracts_exit_code() {
-        let raw = make_b64_output(b"out", b"err", 42);
-        let (_, _, exit_code) = parse_execution_output(&raw);
-        assert_eq!(exit_code, 42, "exit code must be extracted from FORGE_EXIT marker");
-    }
-
-    #[test]
-    fn extract_exit_code_negative_value_is_parsed() {
-        let raw = b"FORGE_EXIT:-1\r\n";
-        let code = extract_exit_code(raw);
-        assert_eq!(code, Some(-1), "negative exit code must be parsed correctly");
-    }
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let (Some(console_in), Some(console_out)) =
+            (handle.process.stdin.as_mut(), handle.process.stdout.as_mut())
+        else {
+            return Err(ExecutorError::Unsupported(format!(
+                "VM {} has no serial console attached",
+                handle.id
+            )));
+        };
 
-    #[test]
-    fn extract_exit_code_missing_marker_returns_none() {
-        let raw = b"no marker here";
-        assert_eq!(extract_exit_code(raw), None, "missing marker must return None");
-    }
+        tracing::info!(vm_id = %handle.id, %command, "executing command over serial console");
 
-    #[test]
-    fn extract_b64_section_missing_start_returns_none() {
-        let raw = b"FORGE_STDOUT_B64_END\r\n";
-        let result = extract_b64_section(raw, b"FORGE_STDOUT_B64_START", b"FORGE_STDOUT_B64_END");
-        assert!(result.is_none(), "missing start marker must return None");
-    }
+        let raw_output = run_on_console(console_in, console_out, command, timeout).await?;
 
-    #[test]
-    fn parse_execution_output_empty_stdout_and_stderr() {
-        let raw = make_b64_output(b"", b"", 0);
-        let (stdout, stderr, exit_code) = parse_execution_output(&raw);
-        assert!(stdout.is_empty(), "empty stdout must decode to empty vec");
-        assert!(stderr.is_empty(), "empty stderr must decode to empty vec");
-        assert_eq!(exit_code, 0);
-    }
+        tracing::info!(vm_id = %handle.id, bytes = raw_output.len(), "serial execution complete");
 
-    proptest::proptest! {
-        #[test]
-        #[cfg_attr(miri, ignore)]
-        fn proptest_exit_code_roundtrip(code in proptest::prelude::any::<i32>()) {
-            let raw = format!("FORGE_EXIT:{code}\r\n").into_bytes();
-            let extracted = extract_exit_code(&raw);
-            proptest::prop_assert_eq!(extracted, Some(code));
-        }
+        let (stdout, stderr, exit_code) = parse_execution_output(&raw_output);
 
-        #[test]
-        #[cfg_attr(miri, ignore)]
-        fn proptest_base64_roundtrip(
-            data in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..512usize)
-        ) {
-            use base64::Engine as _;
-            let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
-            let decoded = base64::engine::general_purpose::STANDARD
-                .decode(&encoded)
-                .map_err(|e| proptest::test_runner::TestCaseError::fail(
-                    format!("base64 decode of freshly encoded data failed: {e}")
-                ))?;
-            proptest::prop_assert_eq!(decoded, data, "base64 encode→decode must be identity");
-        }
+        Ok(ExecutionOutput { stdout, stderr, exit_code })
     }
 }
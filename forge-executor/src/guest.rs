@@ -0,0 +1,460 @@
+//! Guest command protocol shared by all backends.
+//!
+//! A command runs under a small shell wrapper that captures its stdout and
+//! stderr into temp files, then prints both base64-encoded between marker
+//! lines, followed by the exit code:
+//!
+//! ```text
+//! FORGE_STDOUT_B64_START
+//! <base64 stdout>
+//! FORGE_STDOUT_B64_END
+//! FORGE_STDERR_B64_START
+//! <base64 stderr>
+//! FORGE_STDERR_B64_END
+//! FORGE_EXIT:<code>
+//! ```
+//!
+//! The wrapper only needs a POSIX shell, `mktemp` and `base64` in the guest,
+//! and the framing survives any text transport (serial console, virtio
+//! console, or a plain pipe), with `\r\n` or `\n` line endings.
+//!
+//! # Output files
+//! [`collect_outputs_command`] extends a command so that, after it runs, the
+//! files it produced are appended to its stdout behind a separator line,
+//! each base64-encoded between `FORGE_FILE:<path>` and `FORGE_FILE_END`
+//! lines. [`split_collected_outputs`] takes them back off. Because the files
+//! travel inside stdout, this works over every backend and is captured by
+//! record-and-replay unchanged.
+
+use std::time::Duration;
+
+use base64::Engine as _;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::ExecutorError;
+
+/// Build the shell wrapper that runs `command` and prints the framed output.
+///
+/// The wrapper ends after printing the exit marker; callers that boot a
+/// dedicated guest append their own shutdown step.
+pub fn capture_script(command: &str) -> String {
+    format!(
+        "SF=$(mktemp);EF=$(mktemp);eval \"{command}\" >\"$SF\" 2>\"$EF\";EC=$?;\
+         echo FORGE_STDOUT_B64_START;base64 \"$SF\";echo FORGE_STDOUT_B64_END;\
+         echo FORGE_STDERR_B64_START;base64 \"$EF\";echo FORGE_STDERR_B64_END;\
+         echo FORGE_EXIT:$EC"
+    )
+}
+
+/// Separates a command's own stdout from the files collected after it.
+const OUTPUTS_SEPARATOR: &[u8] = b"\nFORGE_OUTPUTS_START\n";
+
+/// Whether `path` can be collected from the guest: absolute, without `..`
+/// components, and made only of characters that need no shell quoting.
+#[must_use]
+pub fn is_collectable_path(path: &str) -> bool {
+    path.len() > 1
+        && path.starts_with('/')
+        && path.bytes().all(|b| b.is_ascii_alphanumeric() || b"/._-+@,=:".contains(&b))
+        && !path.split('/').any(|component| component == "..")
+}
+
+/// Extend `command` to print the files at `outputs` after it finishes.
+///
+/// The command runs in a subshell and its exit code is preserved. Missing
+/// files are skipped. Every path must satisfy [`is_collectable_path`].
+pub fn collect_outputs_command(command: &str, outputs: &[String]) -> String {
+    // The wrappers evaluate the command inside double quotes, so every `$`
+    // meant for the guest is escaped.
+    let mut script = format!("( {command} );FORGE_EC=\\$?;printf '\\nFORGE_OUTPUTS_START\\n';");
+    for path in outputs {
+        script.push_str(&format!(
+            "if [ -f {path} ];then echo FORGE_FILE:{path};base64 {path};echo FORGE_FILE_END;fi;"
+        ));
+    }
+    script.push_str("(exit \\$FORGE_EC)");
+    script
+}
+
+/// Remove the files appended by [`collect_outputs_command`] from `stdout`,
+/// returning each as `(path, contents)` in the order collected.
+///
+/// Leaves `stdout` untouched and returns nothing if no files were appended.
+/// Files whose payload is not valid base64 are dropped.
+pub fn split_collected_outputs(stdout: &mut Vec<u8>) -> Vec<(String, Vec<u8>)> {
+    let Some(start) = stdout.windows(OUTPUTS_SEPARATOR.len()).rposition(|w| w == OUTPUTS_SEPARATOR)
+    else {
+        return Vec::new();
+    };
+    let section = stdout.split_off(start);
+
+    let mut files = Vec::new();
+    let mut current: Option<(String, Vec<u8>)> = None;
+    for line in section[OUTPUTS_SEPARATOR.len()..].split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if let Some(path) = line.strip_prefix(b"FORGE_FILE:") {
+            current = Some((String::from_utf8_lossy(path).into_owned(), Vec::new()));
+        } else if line == b"FORGE_FILE_END" {
+            if let Some((path, b64)) = current.take() {
+                match base64::engine::general_purpose::STANDARD.decode(&b64) {
+                    Ok(contents) => files.push((path, contents)),
+                    Err(e) => tracing::warn!(%path, error = %e, "dropping undecodable output file"),
+                }
+            }
+        } else if let Some((_, b64)) = current.as_mut() {
+            b64.extend_from_slice(line);
+        }
+    }
+    files
+}
+
+/// Build the command line sent to a guest shell listening on the serial console.
+///
+/// Mirrors [`capture_script`], but splits each marker through a shell
+/// variable so the console's echo of this line never contains a literal
+/// marker. The guest is left running afterwards.
+pub fn serial_script(command: &str) -> String {
+    format!(
+        "M=FORGE;SF=$(mktemp);EF=$(mktemp);eval \"{command}\" >\"$SF\" 2>\"$EF\";EC=$?;\
+         echo ${{M}}_STDOUT_B64_START;base64 \"$SF\";echo ${{M}}_STDOUT_B64_END;\
+         echo ${{M}}_STDERR_B64_START;base64 \"$EF\";echo ${{M}}_STDERR_B64_END;\
+         echo ${{M}}_EXIT:$EC;rm -f \"$SF\" \"$EF\"\n"
+    )
+}
+
+/// Run `command` through a guest shell attached to `console_in`/`console_out`.
+///
+/// Writes [`serial_script`] to the shell and reads until the exit marker line
+/// arrives, since the shell keeps running afterwards.
+///
+/// # Errors
+/// Returns [`ExecutorError::Io`] on timeout or console failure.
+pub async fn run_on_console<W, R>(
+    console_in: &mut W,
+    console_out: &mut R,
+    command: &str,
+    timeout: Duration,
+) -> Result<Vec<u8>, ExecutorError>
+where
+    W: AsyncWrite + Unpin + Send,
+    R: AsyncRead + Unpin + Send,
+{
+    console_in.write_all(serial_script(command).as_bytes()).await?;
+    console_in.flush().await?;
+
+    let read_future = async {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while !has_exit_line(&buf) {
+            let n = console_out.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        Ok::<_, std::io::Error>(buf)
+    };
+
+    tokio::time::timeout(timeout, read_future)
+        .await
+        .map_err(|_| timed_out(timeout))?
+        .map_err(Into::into)
+}
+
+/// Read `reader` to EOF, failing with a timeout error after `timeout`.
+///
+/// # Errors
+/// Returns [`ExecutorError::Io`] on timeout or read failure.
+pub async fn read_to_end_within<R>(reader: R, timeout: Duration) -> Result<Vec<u8>, ExecutorError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let read_future = async {
+        let mut buf = Vec::new();
+        let mut reader = tokio::io::BufReader::new(reader);
+        reader.read_to_end(&mut buf).await.map(|_| buf)
+    };
+
+    tokio::time::timeout(timeout, read_future)
+        .await
+        .map_err(|_| timed_out(timeout))?
+        .map_err(Into::into)
+}
+
+fn timed_out(timeout: Duration) -> ExecutorError {
+    ExecutorError::Io(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!("command did not complete within {}s", timeout.as_secs()),
+    ))
+}
+
+/// Return `true` once a complete `FORGE_EXIT:<N>` line has been received.
+pub fn has_exit_line(raw: &[u8]) -> bool {
+    let marker = b"FORGE_EXIT:";
+    raw.windows(marker.len())
+        .position(|w| w == marker)
+        .is_some_and(|p| raw[p + marker.len()..].iter().any(|&b| b == b'\r' || b == b'\n'))
+}
+
+/// Parse stdout, stderr, and exit code from raw guest console output.
+///
+/// Expects the output to contain base64-encoded sections delimited by:
+/// - `FORGE_STDOUT_B64_START` / `FORGE_STDOUT_B64_END`
+/// - `FORGE_STDERR_B64_START` / `FORGE_STDERR_B64_END`
+/// - `FORGE_EXIT:<code>`
+///
+/// # Returns
+/// `(stdout, stderr, exit_code)`. Falls back to `(raw, [], -1)` when markers
+/// are absent (e.g. rootfs does not support the init script pattern).
+pub fn parse_execution_output(raw: &[u8]) -> (Vec<u8>, Vec<u8>, i32) {
+    let stdout = extract_b64_section(raw, b"FORGE_STDOUT_B64_START", b"FORGE_STDOUT_B64_END");
+    let stderr = extract_b64_section(raw, b"FORGE_STDERR_B64_START", b"FORGE_STDERR_B64_END");
+    let exit_code = extract_exit_code(raw);
+
+    match (stdout, stderr, exit_code) {
+        (Some(out), Some(err), Some(code)) => (out, err, code),
+        _ => (raw.to_vec(), Vec::new(), -1),
+    }
+}
+
+/// Extract and base64-decode a section delimited by `start_marker` / `end_marker`.
+///
+/// The start marker must end its line, with either `\r\n` (serial console)
+/// or `\n` (pipe) line endings.
+/// Returns `None` if either marker is absent or the base64 payload is invalid.
+fn extract_b64_section(raw: &[u8], start_marker: &[u8], end_marker: &[u8]) -> Option<Vec<u8>> {
+    let content_start = raw
+        .windows(start_marker.len())
+        .enumerate()
+        .filter(|(_, w)| *w == start_marker)
+        .map(|(p, _)| p + start_marker.len())
+        .find_map(|end| match &raw[end..] {
+            [b'\r', b'\n', ..] => Some(end + 2),
+            [b'\n', ..] => Some(end + 1),
+            _ => None,
+        })?;
+
+    let content_end = raw[content_start..]
+        .windows(end_marker.len())
+        .position(|w| w == end_marker)
+        .map(|p| p + content_start)?;
+
+    // Strip \r and \n before decoding — base64 lines are split by the shell.
+    let b64_clean: Vec<u8> = raw[content_start..content_end]
+        .iter()
+        .copied()
+        .filter(|&b| b != b'\r' && b != b'\n')
+        .collect();
+
+    base64::engine::general_purpose::STANDARD.decode(&b64_clean).ok()
+}
+
+/// Extract the integer exit code from a `FORGE_EXIT:<N>` line.
+///
+/// Returns `None` if the marker is absent or the value cannot be parsed.
+fn extract_exit_code(raw: &[u8]) -> Option<i32> {
+    let marker = b"FORGE_EXIT:";
+    let value_start =
+        raw.windows(marker.len()).position(|w| w == marker).map(|p| p + marker.len())?;
+
+    let rest = &raw[value_start..];
+    let value_end = rest.iter().position(|&b| b == b'\r' || b == b'\n').unwrap_or(rest.len());
+
+    std::str::from_utf8(&rest[..value_end]).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_b64_output(stdout: &[u8], stderr: &[u8], exit_code: i32) -> Vec<u8> {
+        use base64::Engine as _;
+        let stdout_b64 = base64::engine::general_purpose::STANDARD.encode(stdout);
+        let stderr_b64 = base64::engine::general_purpose::STANDARD.encode(stderr);
+        format!(
+            "kernel boot noise\r\nFORGE_STDOUT_B64_START\r\n{stdout_b64}\r\nFORGE_STDOUT_B64_END\r\n\
+             FORGE_STDERR_B64_START\r\n{stderr_b64}\r\nFORGE_STDERR_B64_END\r\n\
+             FORGE_EXIT:{exit_code}\r\n"
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn parse_execution_output_extracts_stdout_and_stderr() {
+        let raw = make_b64_output(b"hello stdout\n", b"hello stderr\n", 0);
+        let (stdout, stderr, _) = parse_execution_output(&raw);
+        assert_eq!(stdout, b"hello stdout\n");
+        assert_eq!(stderr, b"hello stderr\n");
+    }
+
+    #[test]
+    fn parse_execution_output_decodes_base64() {
+        let binary_payload: Vec<u8> = (0u8..=255).collect();
+        let raw = make_b64_output(&binary_payload, b"", 0);
+        let (stdout, _, _) = parse_execution_output(&raw);
+        assert_eq!(stdout, binary_payload, "binary payload must survive base64 round-trip");
+    }
+
+    #[test]
+    fn parse_execution_output_fallback_on_missing_markers() {
+        let raw = b"raw serial output without any markers";
+        let (stdout, stderr, exit_code) = parse_execution_output(raw);
+        assert_eq!(stdout, raw, "fallback stdout must equal raw input");
+        assert!(stderr.is_empty(), "fallback stderr must be empty");
+        assert_eq!(exit_code, -1, "fallback exit code must be -1");
+    }
+
+    #[test]
+    fn parse_execution_output_extracts_exit_code() {
+        let raw = make_b64_output(b"out", b"err", 42);
+        let (_, _, exit_code) = parse_execution_output(&raw);
+        assert_eq!(exit_code, 42, "exit code must be extracted from FORGE_EXIT marker");
+    }
+
+    #[test]
+    fn extract_exit_code_negative_value_is_parsed() {
+        let raw = b"FORGE_EXIT:-1\r\n";
+        let code = extract_exit_code(raw);
+        assert_eq!(code, Some(-1), "negative exit code must be parsed correctly");
+    }
+
+    #[test]
+    fn extract_exit_code_missing_marker_returns_none() {
+        let raw = b"no marker here";
+        assert_eq!(extract_exit_code(raw), None, "missing marker must return None");
+    }
+
+    #[test]
+    fn extract_b64_section_missing_start_returns_none() {
+        let raw = b"FORGE_STDOUT_B64_END\r\n";
+        let result = extract_b64_section(raw, b"FORGE_STDOUT_B64_START", b"FORGE_STDOUT_B64_END");
+        assert!(result.is_none(), "missing start marker must return None");
+    }
+
+    #[test]
+    fn parse_execution_output_empty_stdout_and_stderr() {
+        let raw = make_b64_output(b"", b"", 0);
+        let (stdout, stderr, exit_code) = parse_execution_output(&raw);
+        assert!(stdout.is_empty(), "empty stdout must decode to empty vec");
+        assert!(stderr.is_empty(), "empty stderr must decode to empty vec");
+        assert_eq!(exit_code, 0);
+    }
+
+    #[test]
+    fn serial_script_echo_contains_no_literal_markers() {
+        let script = serial_script("echo hi");
+        assert!(!script.contains("FORGE_EXIT:"), "echoed script must not contain exit marker");
+        assert!(
+            !script.contains("FORGE_STDOUT_B64_START"),
+            "echoed script must not contain stdout marker"
+        );
+        assert!(script.ends_with('\n'), "script must be newline-terminated for the shell");
+    }
+
+    #[test]
+    fn parse_execution_output_accepts_pipe_line_endings() {
+        let raw = make_b64_output(b"piped\n", b"warn\n", 3);
+        let lf_only: Vec<u8> = raw.into_iter().filter(|&b| b != b'\r').collect();
+        let (stdout, stderr, exit_code) = parse_execution_output(&lf_only);
+        assert_eq!(stdout, b"piped\n", "LF-only stdout section must decode");
+        assert_eq!(stderr, b"warn\n", "LF-only stderr section must decode");
+        assert_eq!(exit_code, 3);
+    }
+
+    #[test]
+    fn capture_script_uses_literal_markers() {
+        let script = capture_script("true");
+        assert!(script.contains("echo FORGE_STDOUT_B64_START"), "markers must be literal");
+        assert!(script.ends_with("echo FORGE_EXIT:$EC"), "script must end at the exit marker");
+    }
+
+    #[test]
+    fn collectable_paths_need_no_quoting() {
+        assert!(is_collectable_path("/out/report.json"));
+        assert!(!is_collectable_path("relative/path"), "paths must be absolute");
+        assert!(!is_collectable_path("/out/../etc/passwd"), "paths must not escape");
+        assert!(!is_collectable_path("/out/$(reboot)"), "paths must not need quoting");
+        assert!(!is_collectable_path("/"), "the root is not a file");
+    }
+
+    #[test]
+    fn split_collected_outputs_restores_stdout_exactly() {
+        let mut stdout = b"no trailing newline\nFORGE_OUTPUTS_START\nFORGE_FILE:/out/a\naGVs\r\nbG8=\nFORGE_FILE_END\n".to_vec();
+        let files = split_collected_outputs(&mut stdout);
+        assert_eq!(stdout, b"no trailing newline");
+        assert_eq!(files, [("/out/a".to_owned(), b"hello".to_vec())]);
+
+        let mut plain = b"just output\n".to_vec();
+        assert!(split_collected_outputs(&mut plain).is_empty());
+        assert_eq!(plain, b"just output\n");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn collected_outputs_roundtrip_through_a_real_shell() {
+        let dir = std::env::temp_dir().join(format!("forge-guest-test-{}", uuid::Uuid::new_v4()));
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            panic!("create dir failed: {e}");
+        }
+        let produced = format!("{}/produced.bin", dir.display());
+        let missing = format!("{}/missing.bin", dir.display());
+        let command = format!(r"printf 'out'; printf '\\000\\377data' > {produced}; (exit 5)");
+        let script =
+            capture_script(&collect_outputs_command(&command, &[produced.clone(), missing]));
+
+        let raw = match tokio::process::Command::new("sh").arg("-c").arg(&script).output().await {
+            Ok(output) => output.stdout,
+            Err(e) => panic!("sh failed: {e}"),
+        };
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let (mut stdout, _, exit_code) = parse_execution_output(&raw);
+        let files = split_collected_outputs(&mut stdout);
+        assert_eq!(stdout, b"out", "command stdout must be restored exactly");
+        assert_eq!(exit_code, 5, "command exit code must be preserved");
+        assert_eq!(files, [(produced, b"\x00\xffdata".to_vec())], "missing files are skipped");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn read_to_end_within_times_out() {
+        let (_writer, reader) = tokio::io::duplex(64);
+        let result = read_to_end_within(reader, Duration::from_millis(10)).await;
+        assert!(
+            matches!(&result, Err(ExecutorError::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut),
+            "an open, silent stream must time out, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn has_exit_line_requires_line_ending() {
+        assert!(!has_exit_line(b"noise FORGE_EXIT:12"), "partial line must not count");
+        assert!(has_exit_line(b"noise FORGE_EXIT:12\r\n"), "complete line must count");
+        assert!(!has_exit_line(b"no marker\r\n"), "missing marker must not count");
+    }
+
+    proptest::proptest! {
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn proptest_exit_code_roundtrip(code in proptest::prelude::any::<i32>()) {
+            let raw = format!("FORGE_EXIT:{code}\r\n").into_bytes();
+            let extracted = extract_exit_code(&raw);
+            proptest::prop_assert_eq!(extracted, Some(code));
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn proptest_base64_roundtrip(
+            data in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..512usize)
+        ) {
+            use base64::Engine as _;
+            let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(&encoded)
+                .map_err(|e| proptest::test_runner::TestCaseError::fail(
+                    format!("base64 decode of freshly encoded data failed: {e}")
+                ))?;
+            proptest::prop_assert_eq!(decoded, data, "base64 encode→decode must be identity");
+        }
+    }
+}
@@ -5,6 +5,8 @@
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::{SnapshotId, VmConfig};
+
 /// A handle to a running Firecracker microVM.
 ///
 /// Dropping this handle does NOT terminate the VM. Call
@@ -23,12 +25,30 @@ pub struct VmHandle {
 
     /// Timestamp when the VM was created.
     pub created_at: DateTime<Utc>,
+
+    /// Configuration the VM was booted with, if known.
+    ///
+    /// Recorded in the snapshot catalogue so restored VMs can be traced back
+    /// to the image they came from.
+    pub config: Option<VmConfig>,
+
+    /// Snapshot this VM was restored from, or `None` for a fresh boot.
+    ///
+    /// Serves as the parent of the VM's first diff snapshot.
+    pub restored_from: Option<SnapshotId>,
 }
 
 impl VmHandle {
     /// Create a new VM handle.
     #[must_use]
     pub fn new(id: Uuid, socket_path: PathBuf, process: tokio::process::Child) -> Self {
-        Self { id, socket_path, process, created_at: Utc::now() }
+        Self { id, socket_path, process, created_at: Utc::now(), config: None, restored_from: None }
+    }
+
+    /// Attach the configuration this VM was booted with.
+    #[must_use]
+    pub fn with_config(mut self, config: VmConfig) -> Self {
+        self.config = Some(config);
+        self
     }
 }
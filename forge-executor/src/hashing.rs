@@ -0,0 +1,225 @@
+//! Versioned hashing of execution inputs and outputs.
+//!
+//! Every [`ExecutionRecord`] names the [`HashScheme`] its hashes were computed
+//! with. New records use [`HashScheme::CURRENT`]; [`verify_output_hash`]
+//! recomputes a hash under whichever scheme a historical record names. A
+//! scheme newer than this version is an error, never hashed as another.
+//!
+//! # `HashScheme::V1`
+//! SHA-256 over a self-delimiting encoding, so no two distinct outputs share
+//! a preimage:
+//!
+//! ```text
+//! "forge.output.v1\0"
+//! u64be(len(stdout)) || stdout
+//! u64be(len(stderr)) || stderr
+//! i32be(exit_code)
+//! u64be(file_count)
+//! for each file, sorted by path:
+//!     u64be(len(path)) || path || sha256(contents)
+//! ```
+//!
+//! Inputs are hashed as `"forge.input.v1\0" || u64be(len(input)) || input`,
+//! so an input can never collide with an output.
+//!
+//! # `HashScheme::Legacy`
+//! [`compute_hash`]: `SHA-256(stdout || stderr)`, ignoring the exit code and
+//! files.
+
+use sha2::{Digest, Sha256};
+
+use forge_core::execution::{ExecutionRecord, HashScheme};
+use forge_core::id::ContentHash;
+
+use crate::backend::ExecutionOutput;
+use crate::runner::compute_hash;
+use crate::ExecutorError;
+
+/// Domain tag prefixed to every V1 output hash.
+const OUTPUT_DOMAIN_V1: &[u8] = b"forge.output.v1\0";
+
+/// Domain tag prefixed to every V1 input hash.
+const INPUT_DOMAIN_V1: &[u8] = b"forge.input.v1\0";
+
+/// A file an execution produced, identified by its contents' digest.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileDigest {
+    /// Path of the file, relative to the guest's output directory.
+    pub path: String,
+
+    /// SHA-256 of the file's contents.
+    pub sha256: ContentHash,
+}
+
+impl FileDigest {
+    /// Digest `contents` as the file at `path`.
+    #[must_use]
+    pub fn of(path: impl Into<String>, contents: &[u8]) -> Self {
+        Self { path: path.into(), sha256: ContentHash::new(Sha256::digest(contents).into()) }
+    }
+}
+
+/// Hash the output of an execution and the files it produced under `scheme`.
+///
+/// # Errors
+/// Returns [`ExecutorError::UnsupportedHashScheme`] if `scheme` is newer
+/// than this version knows how to compute.
+///
+/// # Complexity
+/// O(n + f log f) where n is the total output length and f the file count.
+pub fn hash_output(
+    scheme: HashScheme,
+    output: &ExecutionOutput,
+    files: &[FileDigest],
+) -> Result<ContentHash, ExecutorError> {
+    Ok(match scheme {
+        HashScheme::V1 => {
+            let mut hasher = Sha256::new();
+            hasher.update(OUTPUT_DOMAIN_V1);
+            update_prefixed(&mut hasher, &output.stdout);
+            update_prefixed(&mut hasher, &output.stderr);
+            hasher.update(output.exit_code.to_be_bytes());
+
+            let mut sorted: Vec<_> = files.iter().collect();
+            sorted.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+            update_len(&mut hasher, sorted.len());
+            for file in sorted {
+                update_prefixed(&mut hasher, file.path.as_bytes());
+                hasher.update(file.sha256.as_bytes());
+            }
+            ContentHash::new(hasher.finalize().into())
+        }
+        HashScheme::Legacy => compute_hash(&output.stdout, &output.stderr),
+        unknown => return Err(ExecutorError::UnsupportedHashScheme(unknown)),
+    })
+}
+
+/// Hash the input of an execution under `scheme`.
+///
+/// # Errors
+/// Returns [`ExecutorError::UnsupportedHashScheme`] if `scheme` is newer
+/// than this version knows how to compute.
+pub fn hash_input(scheme: HashScheme, input: &[u8]) -> Result<ContentHash, ExecutorError> {
+    Ok(match scheme {
+        HashScheme::V1 => {
+            let mut hasher = Sha256::new();
+            hasher.update(INPUT_DOMAIN_V1);
+            update_prefixed(&mut hasher, input);
+            ContentHash::new(hasher.finalize().into())
+        }
+        HashScheme::Legacy => compute_hash(input, b""),
+        unknown => return Err(ExecutorError::UnsupportedHashScheme(unknown)),
+    })
+}
+
+/// Whether `record.output_hash` is the hash of `output` and `files` under
+/// the record's own [`HashScheme`].
+///
+/// # Errors
+/// Returns [`ExecutorError::UnsupportedHashScheme`] if the record names a
+/// scheme this version cannot compute, rather than guessing at one.
+pub fn verify_output_hash(
+    record: &ExecutionRecord,
+    output: &ExecutionOutput,
+    files: &[FileDigest],
+) -> Result<bool, ExecutorError> {
+    Ok(hash_output(record.hash_scheme, output, files)? == record.output_hash)
+}
+
+fn update_len(hasher: &mut Sha256, len: usize) {
+    // usize is at most 64 bits on every supported target.
+    hasher.update((len as u64).to_be_bytes());
+}
+
+fn update_prefixed(hasher: &mut Sha256, bytes: &[u8]) {
+    update_len(hasher, bytes.len());
+    hasher.update(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use forge_core::execution::ExecutionStatus;
+    use forge_core::id::{BlockId, UserId};
+
+    use super::*;
+
+    fn output(stdout: &[u8], stderr: &[u8], exit_code: i32) -> ExecutionOutput {
+        ExecutionOutput { stdout: stdout.to_vec(), stderr: stderr.to_vec(), exit_code }
+    }
+
+    fn v1(out: &ExecutionOutput, files: &[FileDigest]) -> ContentHash {
+        hash_output(HashScheme::V1, out, files).unwrap_or_else(|e| panic!("hash failed: {e}"))
+    }
+
+    fn verifies(record: &ExecutionRecord, out: &ExecutionOutput) -> bool {
+        verify_output_hash(record, out, &[]).unwrap_or_else(|e| panic!("verify failed: {e}"))
+    }
+
+    #[test]
+    fn v1_separates_stdout_from_stderr() {
+        let ab_c = v1(&output(b"ab", b"c", 0), &[]);
+        let a_bc = v1(&output(b"a", b"bc", 0), &[]);
+        assert_ne!(ab_c, a_bc, "moving bytes between streams must change the hash");
+        assert_eq!(
+            compute_hash(b"ab", b"c"),
+            compute_hash(b"a", b"bc"),
+            "the legacy scheme is ambiguous"
+        );
+    }
+
+    #[test]
+    fn v1_covers_exit_code_and_files() {
+        let base = v1(&output(b"out", b"", 0), &[]);
+        assert_ne!(base, v1(&output(b"out", b"", 1), &[]));
+        let with_file = v1(&output(b"out", b"", 0), &[FileDigest::of("a", b"1")]);
+        assert_ne!(base, with_file);
+        let other_contents = v1(&output(b"out", b"", 0), &[FileDigest::of("a", b"2")]);
+        assert_ne!(with_file, other_contents);
+    }
+
+    #[test]
+    fn v1_file_order_does_not_matter() {
+        let out = output(b"", b"", 0);
+        let files = [FileDigest::of("a", b"1"), FileDigest::of("b", b"2")];
+        let reversed = [files[1].clone(), files[0].clone()];
+        assert_eq!(v1(&out, &files), v1(&out, &reversed));
+    }
+
+    #[test]
+    fn inputs_and_outputs_are_domain_separated() {
+        let input = hash_input(HashScheme::V1, b"").unwrap_or_else(|e| panic!("hash failed: {e}"));
+        assert_ne!(input, v1(&output(b"", b"", 0), &[]));
+        let legacy =
+            hash_input(HashScheme::Legacy, b"x").unwrap_or_else(|e| panic!("hash failed: {e}"));
+        assert_eq!(legacy, compute_hash(b"x", b""));
+    }
+
+    #[test]
+    fn historical_records_verify_under_their_own_scheme() {
+        let out = output(b"v1\n", b"", 0);
+        let record = |scheme| {
+            let hash = |result: Result<ContentHash, ExecutorError>| {
+                result.unwrap_or_else(|e| panic!("hash failed: {e}"))
+            };
+            ExecutionRecord::new(
+                BlockId::new(),
+                UserId::new("test"),
+                hash(hash_input(scheme, b"")),
+                hash(hash_output(scheme, &out, &[])),
+                Utc::now(),
+                Duration::ZERO,
+                ExecutionStatus::Succeeded,
+            )
+            .with_hash_scheme(scheme)
+        };
+
+        let legacy = record(HashScheme::Legacy);
+        assert_eq!(legacy.output_hash, compute_hash(b"v1\n", b""));
+        assert!(verifies(&legacy, &out));
+        assert!(verifies(&record(HashScheme::V1), &out));
+        assert!(!verifies(&record(HashScheme::V1), &output(b"v2\n", b"", 0)));
+    }
+}
@@ -0,0 +1,150 @@
+//! Fixed guest environment.
+//!
+//! A block's output must not depend on when or where it ran.
+//! [`BlockRunner`](crate::BlockRunner) therefore runs every command behind
+//! a preamble that fixes much of what the guest would otherwise take from
+//! the outside world:
+//!
+//! ```text
+//! clock      set to GUEST_EPOCH in microVMs; SOURCE_DATE_EPOCH names the
+//!            same instant everywhere
+//! seed       SHA-256("forge.guest-seed.v1\0" || input hash), as FORGE_SEED,
+//!            with RANDOM and PYTHONHASHSEED derived from it
+//! hostname   GUEST_HOSTNAME, in the kernel and as HOSTNAME
+//! locale     GUEST_LOCALE, as LANG and LC_ALL
+//! timezone   GUEST_TIMEZONE, as TZ
+//! marker     FORGE_HERMETIC=1
+//! ```
+//!
+//! The parameters are recorded in every
+//! [`ExecutionRecord`](forge_core::execution::ExecutionRecord), so a run can
+//! be reproduced exactly.
+//!
+//! This is not a hermetic guest. Neither Firecracker nor the kernel offers a
+//! clock that stands still, so the guest clock is set, not frozen: it
+//! starts at the epoch when the command starts and advances from there, and
+//! elapsed time still leaks. A failure to set it is left on the command's
+//! stderr rather than hidden. Process sandboxes share the host clock and
+//! cannot set it, so there only `SOURCE_DATE_EPOCH` is fixed and the record
+//! says the clock was not set ([`GuestEnvironment::clock_set`]). Programs
+//! that read `/dev/urandom` or `getrandom(2)` directly still see real
+//! entropy; the seed is only for those that accept one. Blocks that depend
+//! on either are caught by the determinism audit, not prevented here.
+
+use sha2::{Digest, Sha256};
+
+use forge_core::execution::{GuestEnvironment, IsolationLevel};
+use forge_core::id::ContentHash;
+
+/// The instant guests start at: 1980-01-01T00:00:00Z, the earliest time a
+/// zip archive can record and the `SOURCE_DATE_EPOCH` Nix builds default to.
+pub const GUEST_EPOCH: i64 = 315_532_800;
+
+/// Hostname every guest reports.
+pub const GUEST_HOSTNAME: &str = "forge";
+
+/// Locale every guest runs in.
+pub const GUEST_LOCALE: &str = "C.UTF-8";
+
+/// Timezone every guest runs in.
+pub const GUEST_TIMEZONE: &str = "UTC";
+
+/// Domain tag prefixed to the input hash to derive the guest seed.
+const SEED_DOMAIN: &[u8] = b"forge.guest-seed.v1\0";
+
+/// Ends every preamble; guest programs can test `FORGE_HERMETIC` to tell
+/// they run behind it. The name predates the narrower guarantee above.
+const PREAMBLE_END: &str = "FORGE_HERMETIC=1;";
+
+/// The environment a guest isolated at `isolation` runs the execution with
+/// input hash `input_hash` in.
+#[must_use]
+pub fn guest_environment(input_hash: &ContentHash, isolation: IsolationLevel) -> GuestEnvironment {
+    let mut hasher = Sha256::new();
+    hasher.update(SEED_DOMAIN);
+    hasher.update(input_hash.as_bytes());
+    GuestEnvironment::new(
+        GUEST_EPOCH,
+        isolation != IsolationLevel::ProcessSandbox,
+        ContentHash::new(hasher.finalize().into()),
+        GUEST_HOSTNAME.to_owned(),
+        GUEST_LOCALE.to_owned(),
+        GUEST_TIMEZONE.to_owned(),
+    )
+}
+
+/// Prefix `command` with the preamble establishing `environment`.
+///
+/// The result is evaluated inside double quotes by the guest wrappers, so
+/// the preamble uses no `$` or `"`; the environment's strings must be
+/// plain words, as those from [`guest_environment`] are.
+pub(crate) fn hermetic_command(environment: &GuestEnvironment, command: &str) -> String {
+    let seed = environment.seed.as_bytes();
+    let random = u16::from_be_bytes([seed[0], seed[1]]) & 0x7fff;
+    let hash_seed = u32::from_be_bytes([seed[2], seed[3], seed[4], seed[5]]);
+    let GuestEnvironment { epoch, clock_set, hostname, locale, timezone, .. } = environment;
+    let set_clock =
+        if *clock_set { format!("date -s @{epoch} >/dev/null;") } else { String::new() };
+    format!(
+        "{set_clock}\
+         echo {hostname} 2>/dev/null >/proc/sys/kernel/hostname;\
+         export SOURCE_DATE_EPOCH={epoch} FORGE_SEED={} RANDOM={random} \
+         PYTHONHASHSEED={hash_seed} HOSTNAME={hostname} TZ={timezone} LANG={locale} \
+         LC_ALL={locale} {PREAMBLE_END}{command}",
+        environment.seed
+    )
+}
+
+/// The command [`hermetic_command`] wrapped, or `command` itself if it
+/// has no preamble.
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn strip_preamble(command: &str) -> &str {
+    command.split_once(PREAMBLE_END).map_or(command, |(_, command)| command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_depend_only_on_the_input() {
+        let input = ContentHash::new([7u8; 32]);
+        let environment = guest_environment(&input, IsolationLevel::MicroVm);
+        assert_eq!(environment, guest_environment(&input, IsolationLevel::MicroVm));
+        let other = guest_environment(&ContentHash::new([8u8; 32]), IsolationLevel::MicroVm);
+        assert_ne!(environment.seed, other.seed);
+        assert_ne!(environment.seed, input, "the seed must not reveal the input hash");
+        assert_eq!(environment.epoch, GUEST_EPOCH);
+    }
+
+    #[test]
+    fn process_sandboxes_do_not_claim_to_set_the_clock() {
+        let input = ContentHash::new([7u8; 32]);
+        let environment = guest_environment(&input, IsolationLevel::ProcessSandbox);
+        assert!(!environment.clock_set);
+        assert!(guest_environment(&input, IsolationLevel::MicroVm).clock_set);
+        let command = hermetic_command(&environment, "date");
+        assert!(!command.contains("date -s"), "{command:?}");
+        assert!(command.contains(&format!("SOURCE_DATE_EPOCH={GUEST_EPOCH} ")), "{command:?}");
+    }
+
+    #[test]
+    fn preamble_fixes_clock_seed_and_identity_before_the_command() {
+        let environment = guest_environment(&ContentHash::new([7u8; 32]), IsolationLevel::MicroVm);
+        let command = hermetic_command(&environment, "date");
+        for expected in [
+            format!("date -s @{GUEST_EPOCH} >/dev/null;"),
+            format!("SOURCE_DATE_EPOCH={GUEST_EPOCH} "),
+            format!("FORGE_SEED={} ", environment.seed),
+            "TZ=UTC ".to_owned(),
+            "LC_ALL=C.UTF-8 ".to_owned(),
+            "echo forge 2>/dev/null >/proc/sys/kernel/hostname;".to_owned(),
+        ] {
+            assert!(command.contains(&expected), "{expected:?} missing from {command:?}");
+        }
+        assert!(command.ends_with(";date"));
+        assert_eq!(strip_preamble(&command), "date");
+        assert_eq!(strip_preamble("date"), "date");
+        assert!(!command.contains(['$', '"']), "the preamble must survive double quoting");
+    }
+}
@@ -0,0 +1,79 @@
+//! Host environment checks and filesystem helpers shared across the crate.
+
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::ExecutorError;
+
+/// Verify that `/dev/kvm` exists and is accessible.
+///
+/// # Errors
+/// Returns [`ExecutorError::KvmUnavailable`] if the device is missing or unreadable.
+pub async fn check_kvm() -> Result<(), ExecutorError> {
+    if !Path::new("/dev/kvm").exists() {
+        return Err(ExecutorError::KvmUnavailable { reason: "/dev/kvm not found".to_owned() });
+    }
+
+    tokio::fs::metadata("/dev/kvm").await.map_err(|_| ExecutorError::KvmUnavailable {
+        reason: "cannot access /dev/kvm (permission denied?)".to_owned(),
+    })?;
+
+    Ok(())
+}
+
+/// Verify a binary exists either at the given path or in PATH.
+pub fn which_binary(path: &Path) -> Result<(), ExecutorError> {
+    if path.is_absolute() {
+        if path.exists() {
+            return Ok(());
+        }
+        return Err(ExecutorError::BinaryNotFound { path: path.to_owned() });
+    }
+
+    // Relative or bare name — check PATH
+    let found = std::env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .map(|dir| Path::new(dir).join(path))
+        .any(|p| p.exists());
+
+    if found {
+        Ok(())
+    } else {
+        Err(ExecutorError::BinaryNotFound { path: path.to_owned() })
+    }
+}
+
+/// Write `bytes` to `path` via a temp file and rename, so readers never see
+/// a partial file. Creates missing parent directories.
+pub async fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension(format!("tmp-{}", Uuid::new_v4()));
+    tokio::fs::write(&tmp, bytes).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn which_binary_finds_shell_in_path() {
+        assert!(which_binary(Path::new("sh")).is_ok(), "sh must be found in PATH");
+    }
+
+    #[test]
+    fn which_binary_missing_absolute_path_is_error() {
+        let missing = PathBuf::from("/nonexistent/forge-test-binary");
+        let result = which_binary(&missing);
+        assert!(
+            matches!(result, Err(ExecutorError::BinaryNotFound { ref path }) if *path == missing),
+            "missing binary must return BinaryNotFound, got {result:?}"
+        );
+    }
+}
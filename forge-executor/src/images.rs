@@ -0,0 +1,271 @@
+//! Content-addressed store for kernel and rootfs images.
+//!
+//! Each image is stored once under the SHA-256 of its contents:
+//!
+//! ```text
+//! <dir>/<sha256>
+//! ```
+//!
+//! A [`VmConfig`](crate::VmConfig) built with
+//! [`VmConfig::from_images`](crate::VmConfig::from_images) names its kernel and
+//! rootfs by hash. [`BlockRunner`](crate::BlockRunner) re-hashes both
+//! images before booting a VM from them and refuses to boot if either no
+//! longer matches, then records both hashes in every
+//! [`ExecutionRecord`](forge_core::execution::ExecutionRecord).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use forge_core::id::ContentHash;
+
+use crate::catalog::hash_file;
+use crate::ExecutorError;
+
+/// A directory of boot images addressed by content hash.
+#[derive(Debug, Clone)]
+pub struct ImageStore {
+    dir: PathBuf,
+}
+
+impl ImageStore {
+    /// Open the store in `dir`. The directory is created on the first import.
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Directory holding the store.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the image with `hash` is, or would be, stored.
+    #[must_use]
+    pub fn path(&self, hash: &ContentHash) -> PathBuf {
+        self.dir.join(hash.to_string())
+    }
+
+    /// Copy the image at `source` into the store, returning its hash.
+    /// Importing an image that is already present is a no-op.
+    ///
+    /// The copy is hashed rather than the source, so an image that changes
+    /// while it is imported is stored under the hash of what was copied.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if `source` cannot be read or the copy
+    /// cannot be written.
+    pub async fn import(&self, source: &Path) -> Result<ContentHash, ExecutorError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let tmp = self.dir.join(format!(".import-{}", Uuid::new_v4()));
+        let result = async {
+            tokio::fs::copy(source, &tmp).await?;
+            let (hash, _) = hash_file(tmp.clone()).await?;
+            let path = self.path(&hash);
+            if tokio::fs::try_exists(&path).await? {
+                tokio::fs::remove_file(&tmp).await?;
+            } else {
+                tokio::fs::rename(&tmp, &path).await?;
+            }
+            Ok::<_, std::io::Error>(hash)
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&tmp).await;
+        }
+        let hash = result?;
+        tracing::info!(source = %source.display(), %hash, "image imported");
+        Ok(hash)
+    }
+
+    /// Whether an image with `hash` is stored.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::Io`] if the store cannot be inspected.
+    pub async fn contains(&self, hash: &ContentHash) -> Result<bool, ExecutorError> {
+        Ok(tokio::fs::try_exists(self.path(hash)).await?)
+    }
+
+    /// Check the stored image with `hash` and return its path.
+    ///
+    /// # Errors
+    /// See [`verify_image`].
+    pub async fn verify(&self, hash: &ContentHash) -> Result<PathBuf, ExecutorError> {
+        let path = self.path(hash);
+        verify_image(&path, hash).await?;
+        Ok(path)
+    }
+}
+
+/// Check that the image at `path` hashes to `expected`.
+///
+/// # Errors
+/// Returns [`ExecutorError::Io`] if the image cannot be read and
+/// [`ExecutorError::ImageHashMismatch`] if its contents have changed.
+pub async fn verify_image(path: &Path, expected: &ContentHash) -> Result<(), ExecutorError> {
+    let (actual, _) = hash_file(path.to_owned()).await?;
+    if actual != *expected {
+        return Err(ExecutorError::ImageHashMismatch {
+            path: path.to_owned(),
+            expected: *expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Image hashes remembered by path, reused while the file looks unchanged.
+///
+/// A file counts as unchanged while its device, inode, size and
+/// modification time are, so an image rebuilt in place or replaced is
+/// hashed again. An image rewritten with the same size and its old
+/// modification time restored is not noticed.
+#[derive(Debug, Default)]
+pub(crate) struct ImageHashes {
+    known: Mutex<HashMap<PathBuf, (FileStamp, ContentHash)>>,
+}
+
+/// What [`ImageHashes`] compares to tell whether a file changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    modified: SystemTime,
+}
+
+impl ImageHashes {
+    /// The SHA-256 of the image at `path`, hashing it only if it changed
+    /// since it was last hashed.
+    pub(crate) async fn hash(&self, path: &Path) -> std::io::Result<ContentHash> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = tokio::fs::metadata(path).await?;
+        let stamp = FileStamp {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        };
+        let known = self.known.lock().await.get(path).copied();
+        if let Some((known, hash)) = known {
+            if known == stamp {
+                return Ok(hash);
+            }
+        }
+
+        // The stamp is taken first, so an image changing while it is
+        // hashed no longer matches it and is hashed again next time.
+        let (hash, _) = hash_file(path.to_owned()).await?;
+        self.known.lock().await.insert(path.to_owned(), (stamp, hash));
+        Ok(hash)
+    }
+
+    /// As [`verify_image`], without re-reading an image that has not
+    /// changed since it was last hashed.
+    pub(crate) async fn verify(
+        &self,
+        path: &Path,
+        expected: &ContentHash,
+    ) -> Result<(), ExecutorError> {
+        let actual = self.hash(path).await?;
+        if actual != *expected {
+            return Err(ExecutorError::ImageHashMismatch {
+                path: path.to_owned(),
+                expected: *expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VmConfig;
+
+    fn temp_store() -> ImageStore {
+        ImageStore::new(std::env::temp_dir().join(format!("forge-images-test-{}", Uuid::new_v4())))
+    }
+
+    async fn import(store: &ImageStore, name: &str, contents: &[u8]) -> ContentHash {
+        let source = store.dir().with_extension(name);
+        if let Err(e) = std::fs::write(&source, contents) {
+            panic!("source write failed: {e}");
+        }
+        let hash = store.import(&source).await.unwrap_or_else(|e| panic!("import failed: {e}"));
+        let _ = std::fs::remove_file(source);
+        hash
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn imports_are_addressed_by_content_and_deduplicated() {
+        let store = temp_store();
+        let first = import(&store, "a", b"vmlinux").await;
+        let second = import(&store, "b", b"vmlinux").await;
+        assert_eq!(first, second, "identical images must share an address");
+        assert!(store.contains(&first).await.unwrap_or(false));
+        let files = std::fs::read_dir(store.dir()).map_or(0, Iterator::count);
+        assert_eq!(files, 1, "identical images must be stored once");
+        assert_eq!(store.verify(&first).await.ok(), Some(store.path(&first)));
+
+        let _ = std::fs::remove_dir_all(store.dir());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn configs_from_images_refuse_tampered_images() {
+        let store = temp_store();
+        let kernel = import(&store, "kernel", b"vmlinux").await;
+        let rootfs = import(&store, "rootfs", b"rootfs").await;
+        let config = VmConfig::from_images(&store, kernel, rootfs);
+        assert_eq!(config.rootfs_path, store.path(&rootfs));
+        assert!(config.verify_images().await.is_ok());
+
+        if let Err(e) = std::fs::write(store.path(&rootfs), b"tampered") {
+            panic!("tamper failed: {e}");
+        }
+        let result = config.verify_images().await;
+        assert!(
+            matches!(&result, Err(ExecutorError::ImageHashMismatch { expected, .. }) if *expected == rootfs),
+            "a tampered rootfs must be refused, got {result:?}"
+        );
+
+        let _ = std::fs::remove_dir_all(store.dir());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn image_hashes_are_reused_until_the_file_changes() {
+        let path = std::env::temp_dir().join(format!("forge-image-hashes-test-{}", Uuid::new_v4()));
+        let rewrite = |contents: &[u8], modified: SystemTime| {
+            let written = std::fs::write(&path, contents).and_then(|()| {
+                std::fs::File::options().write(true).open(&path)?.set_modified(modified)
+            });
+            if let Err(e) = written {
+                panic!("rewrite failed: {e}");
+            }
+        };
+        let hashes = ImageHashes::default();
+        let hash =
+            || async { hashes.hash(&path).await.unwrap_or_else(|e| panic!("hash failed: {e}")) };
+        let epoch = SystemTime::UNIX_EPOCH;
+
+        rewrite(b"first", epoch);
+        let first = hash().await;
+        rewrite(b"other", epoch);
+        assert_eq!(hash().await, first, "an unchanged stamp must reuse the hash");
+        rewrite(b"other", epoch + std::time::Duration::from_secs(1));
+        let other = hash().await;
+        assert_ne!(other, first, "a new modification time must re-hash");
+        assert!(hashes.verify(&path, &other).await.is_ok());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -5,22 +5,62 @@
 //!
 //! See `docs/ARCHITECTURE.md` for design rationale.
 
+pub mod artifacts;
 pub mod backend;
+pub mod cache;
+pub mod canonical;
+pub mod cassette;
+pub mod catalog;
 pub mod config;
+#[cfg(feature = "testing")]
+pub mod conformance;
+pub mod determinism;
+pub mod diagnosis;
 pub mod error;
 pub mod firecracker;
+pub(crate) mod guest;
 pub mod handle;
+pub mod hashing;
+pub mod hermetic;
+pub(crate) mod host;
+pub mod images;
+pub mod libkrun;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
 pub mod orchestrator;
+pub mod pipeline;
+pub mod pool;
+pub(crate) mod rootfs;
 pub mod runner;
+pub mod sandbox;
+pub mod store;
 pub(crate) mod unix_client;
 
+pub use artifacts::ArtifactStore;
 pub use backend::{ExecutionOutput, VmmBackend};
-pub use config::{SnapshotId, VmConfig};
+pub use cache::{CacheKey, CacheStats, ExecutionCache};
+pub use canonical::canonicalize;
+pub use cassette::{Cassette, CassetteEntry, RecordingBackend, ReplayBackend};
+pub use catalog::{GcReport, RetentionPolicy, SnapshotCatalog, SnapshotMetadata};
+pub use config::{CpuTemplate, SnapshotId, StaticCpuTemplate, VmConfig};
+pub use determinism::{BootKind, BootMode, DeterminismReport, DeterminismRun, ObservedOutput};
+pub use diagnosis::{Divergence, EntropyHint, EntropySource, LineChange, OutputStream, StreamDiff};
 pub use error::ExecutorError;
 pub use firecracker::FirecrackerBackend;
+pub use guest::is_collectable_path;
 pub use handle::VmHandle;
-pub use orchestrator::VmOrchestrator;
+pub use hashing::{hash_input, hash_output, verify_output_hash, FileDigest};
+pub use hermetic::guest_environment;
+pub use images::{verify_image, ImageStore};
+pub use libkrun::LibkrunBackend;
+#[cfg(any(test, feature = "testing"))]
+pub use mock::{MockBackend, MockCall, MockOperation, MockResponse, MockSnapshot};
+pub use orchestrator::{Fork, ForkBranch, VmOrchestrator};
+pub use pipeline::{stage_input, Pipeline, PipelineExecution};
+pub use pool::{PoolMetrics, PoolSettings, RefillPolicy, WarmPool};
 pub use runner::{compute_hash, BlockRunner};
+pub use sandbox::SandboxBackend;
+pub use store::{ExecutionStore, InMemoryExecutionStore};
 
 #[cfg(test)]
 mod tests {
@@ -71,8 +111,7 @@ async fn health_check_fails_without_kvm() {
 
         // If KVM is unavailable, we get KvmUnavailable; otherwise BinaryNotFound
         match result {
-            Err(ExecutorError::KvmUnavailable { .. })
-            | Err(ExecutorError::BinaryNotFound { .. }) => {
+            Err(ExecutorError::KvmUnavailable { .. } | ExecutorError::BinaryNotFound { .. }) => {
                 // expected
             }
             Ok(()) => panic!("health_check should fail with nonexistent binary"),
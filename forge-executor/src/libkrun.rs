@@ -0,0 +1,320 @@
+//! libkrun VMM backend implementation.
+//!
+//! [libkrun](https://github.com/containers/libkrun) runs a guest directly from
+//! a host directory, with its own bundled kernel (libkrunfw), so the guest
+//! shares no block device with the host. The library is driven through a
+//! small launcher binary rather than linked in, which keeps this crate free of
+//! `unsafe` FFI.
+//!
+//! # Launcher contract
+//! The launcher is any krunvm-style wrapper around `krun_start_enter` invoked
+//! as:
+//!
+//! ```text
+//! <launcher> --cpus <N> --mem <MiB> <ROOT_DIR> <COMMAND> [ARGS...]
+//! ```
+//!
+//! It must run `COMMAND` as the guest's init process with `ROOT_DIR` as its
+//! root filesystem, and wire the guest console to its own stdin and stdout.
+//! The launcher exits when the guest does.
+//!
+//! # Root filesystem
+//! Each VM gets a private copy of [`VmConfig::rootfs_path`] extracted under
+//! the backend's work directory, removed again when the VM ends.
+//! [`VmConfig::kernel_path`], [`VmConfig::boot_args`] and
+//! [`VmConfig::cpu_template`] are ignored.
+//!
+//! # Snapshots
+//! libkrun has no snapshot support; [`VmmBackend::snapshot`] and
+//! [`VmmBackend::restore`] return [`ExecutorError::Unsupported`].
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::backend::{ExecutionOutput, VmmBackend};
+use crate::guest::{capture_script, parse_execution_output, read_to_end_within, run_on_console};
+use crate::host::{check_kvm, which_binary};
+use crate::rootfs::extract_layered_rootfs;
+use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
+
+/// libkrun VMM backend.
+///
+/// Spawns one launcher process per VM, communicating with the guest over
+/// the launcher's standard streams.
+#[derive(Debug, Clone)]
+pub struct LibkrunBackend {
+    /// Path to the libkrun launcher binary.
+    launcher_path: PathBuf,
+
+    /// Directory where per-VM root filesystems are extracted.
+    work_dir: PathBuf,
+}
+
+impl LibkrunBackend {
+    /// Create a new backend with the given paths.
+    ///
+    /// # Arguments
+    /// - `launcher_path`: path to the libkrun launcher (see the module docs)
+    /// - `work_dir`: directory for per-VM root filesystems (must be writable)
+    #[must_use]
+    pub const fn new(launcher_path: PathBuf, work_dir: PathBuf) -> Self {
+        Self { launcher_path, work_dir }
+    }
+
+    /// Create a backend using system defaults.
+    ///
+    /// Looks for `krun-launcher` in `$PATH` and uses `/tmp/forge-krun` as the
+    /// work directory.
+    #[must_use]
+    pub fn with_defaults() -> Self {
+        Self::new(PathBuf::from("krun-launcher"), PathBuf::from("/tmp/forge-krun"))
+    }
+
+    fn root_dir(&self, vm_id: Uuid) -> PathBuf {
+        self.work_dir.join(vm_id.to_string())
+    }
+
+    /// Extract the private root filesystem for `vm_id`, removing whatever
+    /// was extracted if extraction fails.
+    async fn extract_root(&self, config: &VmConfig, vm_id: Uuid) -> Result<PathBuf, ExecutorError> {
+        let root = self.root_dir(vm_id);
+        if let Err(e) =
+            extract_layered_rootfs(&config.rootfs_path, &config.layer_paths, &root).await
+        {
+            let _ = tokio::fs::remove_dir_all(&root).await;
+            return Err(e);
+        }
+        Ok(root)
+    }
+
+    /// Check the host, extract a private root filesystem for `vm_id`, and
+    /// build the launcher command that boots it.
+    async fn prepare(
+        &self,
+        config: &VmConfig,
+        vm_id: Uuid,
+    ) -> Result<(Command, PathBuf), ExecutorError> {
+        check_kvm().await?;
+        which_binary(&self.launcher_path)?;
+        let root = self.extract_root(config, vm_id).await?;
+
+        let mut command = Command::new(&self.launcher_path);
+        command
+            .arg("--cpus")
+            .arg(config.vcpu_count.to_string())
+            .arg("--mem")
+            .arg(config.mem_size_mib.to_string())
+            .arg(&root)
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+
+        Ok((command, root))
+    }
+
+    /// Boot the guest with `script` as init and collect its console output.
+    async fn run_to_completion(
+        mut command: Command,
+        script: &str,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ExecutorError> {
+        let mut process = command
+            .arg("/bin/sh")
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| ExecutorError::SpawnFailed(format!("exec libkrun launcher: {e}")))?;
+
+        let stdout_handle = process
+            .stdout
+            .take()
+            .ok_or_else(|| ExecutorError::SpawnFailed("stdout not piped".to_owned()))?;
+
+        let raw_output = read_to_end_within(stdout_handle, timeout).await?;
+        let _ = process.wait().await;
+
+        Ok(raw_output)
+    }
+}
+
+#[async_trait]
+impl VmmBackend for LibkrunBackend {
+    async fn spawn(&self, config: &VmConfig) -> Result<VmHandle, ExecutorError> {
+        let vm_id = Uuid::new_v4();
+        let (mut command, root) = self.prepare(config, vm_id).await?;
+
+        tracing::info!(vm_id = %vm_id, root = %root.display(), "spawning libkrun VM");
+
+        // The guest runs an interactive shell on its console so commands can
+        // be sent with `execute_in_vm`.
+        let process =
+            match command.arg("/bin/sh").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+                Ok(process) => process,
+                Err(e) => {
+                    let _ = tokio::fs::remove_dir_all(&root).await;
+                    return Err(ExecutorError::SpawnFailed(format!("exec libkrun launcher: {e}")));
+                }
+            };
+
+        tracing::info!(vm_id = %vm_id, "VM started");
+
+        // libkrun has no management socket.
+        Ok(VmHandle::new(vm_id, PathBuf::new(), process).with_config(config.clone()))
+    }
+
+    async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("libkrun snapshot of VM {}", handle.id)))
+    }
+
+    async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("libkrun restore from {snapshot_id}")))
+    }
+
+    async fn terminate(&self, mut handle: VmHandle) -> Result<(), ExecutorError> {
+        tracing::info!(vm_id = %handle.id, "terminating VM");
+
+        handle.process.kill().await?;
+        let _ = tokio::fs::remove_dir_all(self.root_dir(handle.id)).await;
+
+        tracing::info!(vm_id = %handle.id, "VM terminated");
+
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<(), ExecutorError> {
+        check_kvm().await?;
+        which_binary(&self.launcher_path)?;
+        which_binary(Path::new("debugfs"))?;
+
+        Ok(())
+    }
+
+    async fn execute_command(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let vm_id = Uuid::new_v4();
+        let (launcher, root) = self.prepare(config, vm_id).await?;
+
+        tracing::info!(vm_id = %vm_id, %command, "executing command in libkrun VM");
+
+        // The launcher exits with the guest, so no poweroff step is needed.
+        let result = Self::run_to_completion(launcher, &capture_script(command), timeout).await;
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let raw_output = result?;
+
+        tracing::info!(vm_id = %vm_id, bytes = raw_output.len(), "VM execution complete");
+
+        let (stdout, stderr, exit_code) = parse_execution_output(&raw_output);
+
+        Ok(ExecutionOutput { stdout, stderr, exit_code })
+    }
+
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let (Some(console_in), Some(console_out)) =
+            (handle.process.stdin.as_mut(), handle.process.stdout.as_mut())
+        else {
+            return Err(ExecutorError::Unsupported(format!(
+                "VM {} has no console attached",
+                handle.id
+            )));
+        };
+
+        tracing::info!(vm_id = %handle.id, %command, "executing command over console");
+
+        let raw_output = run_on_console(console_in, console_out, command, timeout).await?;
+
+        tracing::info!(vm_id = %handle.id, bytes = raw_output.len(), "console execution complete");
+
+        let (stdout, stderr, exit_code) = parse_execution_output(&raw_output);
+
+        Ok(ExecutionOutput { stdout, stderr, exit_code })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_backend() -> LibkrunBackend {
+        LibkrunBackend::new(
+            PathBuf::from("/nonexistent/krun-launcher"),
+            std::env::temp_dir().join(format!("forge-krun-test-{}", Uuid::new_v4())),
+        )
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn health_check_fails_without_launcher() {
+        let result = test_backend().health_check().await;
+        assert!(
+            matches!(
+                result,
+                Err(ExecutorError::KvmUnavailable { .. } | ExecutorError::BinaryNotFound { .. })
+            ),
+            "missing launcher must fail the health check, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn snapshots_are_unsupported() {
+        let backend = test_backend();
+        let child = tokio::process::Command::new("true")
+            .spawn()
+            .unwrap_or_else(|e| panic!("spawn failed: {e}"));
+        let mut handle = VmHandle::new(Uuid::new_v4(), PathBuf::new(), child);
+
+        let snapshot = backend.snapshot(&handle).await;
+        let diff = backend.diff_snapshot(&handle).await;
+        for (operation, result) in [("snapshot", snapshot), ("diff_snapshot", diff)] {
+            assert!(
+                matches!(result, Err(ExecutorError::Unsupported(_))),
+                "libkrun {operation} must be unsupported, got {result:?}"
+            );
+        }
+        let restore = backend.restore(&SnapshotId::new()).await;
+        assert!(
+            matches!(restore, Err(ExecutorError::Unsupported(_))),
+            "libkrun restore must be unsupported, got {restore:?}"
+        );
+        let _ = handle.process.wait().await;
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn failed_extraction_removes_the_partial_root() {
+        let backend = test_backend();
+        let vm_id = Uuid::new_v4();
+        let root = backend.root_dir(vm_id);
+        // What an extraction interrupted halfway leaves behind.
+        tokio::fs::create_dir_all(root.join("nix/store"))
+            .await
+            .unwrap_or_else(|e| panic!("mkdir failed: {e}"));
+        let image = backend.work_dir.join("corrupt.ext4");
+        tokio::fs::write(&image, b"not an ext4 image")
+            .await
+            .unwrap_or_else(|e| panic!("write failed: {e}"));
+
+        let config = VmConfig::new(PathBuf::from("/unused"), image);
+        let result = backend.extract_root(&config, vm_id).await;
+        assert!(result.is_err(), "a corrupt image must fail to extract");
+        assert!(root.parent().is_some_and(Path::exists), "the work dir must survive");
+        assert!(!root.exists(), "failed extraction must not leave a root filesystem behind");
+
+        let _ = tokio::fs::remove_dir_all(&backend.work_dir).await;
+    }
+}
@@ -0,0 +1,661 @@
+//! Scripted in-memory [`VmmBackend`] for tests.
+//!
+//! [`MockBackend`] lets tests of anything layered on [`VmmBackend`] (pools,
+//! orchestration, runners, the gateway) run deterministically without KVM:
+//!
+//! - commands get scripted [`MockResponse`]s with output, exit code, latency
+//!   or a failure; unscripted commands echo themselves back as stdout
+//! - whole operations can be made to fail with [`MockBackend::fail_operation`],
+//!   or to fail after some calls succeed with
+//!   [`MockBackend::fail_operation_after`]
+//! - every call is recorded as a [`MockCall`]
+//! - snapshots are tracked in memory, including diff snapshot parents, so
+//!   restores of unknown snapshots fail like they would on a real backend
+//!
+//! Latencies are slept with `tokio::time`, so tests can run them instantly
+//! under a paused clock.
+//!
+//! Available with the `testing` feature.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use forge_core::execution::IsolationLevel;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::backend::{ExecutionOutput, VmmBackend};
+use crate::hermetic::strip_preamble;
+use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
+
+/// Builds the error a scripted failure returns, once per call.
+type ErrorFactory = Arc<dyn Fn() -> ExecutorError + Send + Sync>;
+
+/// Scripted result of running one command.
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct MockResponse {
+    /// Bytes returned as the command's standard output.
+    pub stdout: Vec<u8>,
+    /// Bytes returned as the command's standard error.
+    pub stderr: Vec<u8>,
+    /// Exit code returned for the command.
+    pub exit_code: i32,
+    /// Simulated run time. Longer than the caller's timeout means the call
+    /// times out.
+    pub latency: Duration,
+    failure: Option<ErrorFactory>,
+}
+
+impl MockResponse {
+    /// A successful run printing `stdout`.
+    #[must_use]
+    pub fn success(stdout: impl Into<Vec<u8>>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+            exit_code: 0,
+            latency: Duration::ZERO,
+            failure: None,
+        }
+    }
+
+    /// A run that completes with `exit_code` and no output.
+    #[must_use]
+    pub fn exit(exit_code: i32) -> Self {
+        Self { exit_code, ..Self::success(Vec::new()) }
+    }
+
+    /// A call that fails with the error built by `error`.
+    #[must_use]
+    pub fn failure(error: impl Fn() -> ExecutorError + Send + Sync + 'static) -> Self {
+        Self { failure: Some(Arc::new(error)), ..Self::success(Vec::new()) }
+    }
+
+    /// Set the standard error output.
+    #[must_use]
+    pub fn with_stderr(mut self, stderr: impl Into<Vec<u8>>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    /// Set the exit code.
+    #[must_use]
+    pub const fn with_exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Set the simulated run time.
+    #[must_use]
+    pub const fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sleep for the latency, then produce the output or scripted failure.
+    async fn serve(&self, timeout: Duration) -> Result<ExecutionOutput, ExecutorError> {
+        if self.latency > timeout {
+            tokio::time::sleep(timeout).await;
+            return Err(ExecutorError::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("command did not complete within {}s", timeout.as_secs()),
+            )));
+        }
+        tokio::time::sleep(self.latency).await;
+
+        if let Some(error) = &self.failure {
+            return Err(error());
+        }
+        Ok(ExecutionOutput {
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            exit_code: self.exit_code,
+        })
+    }
+}
+
+impl fmt::Debug for MockResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockResponse")
+            .field("stdout", &String::from_utf8_lossy(&self.stdout))
+            .field("stderr", &String::from_utf8_lossy(&self.stderr))
+            .field("exit_code", &self.exit_code)
+            .field("latency", &self.latency)
+            .field("fails", &self.failure.is_some())
+            .finish()
+    }
+}
+
+/// A [`VmmBackend`] operation, for scripting failures and counting calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MockOperation {
+    /// [`VmmBackend::spawn`].
+    Spawn,
+    /// [`VmmBackend::snapshot`].
+    Snapshot,
+    /// [`VmmBackend::diff_snapshot`].
+    DiffSnapshot,
+    /// [`VmmBackend::restore`].
+    Restore,
+    /// [`VmmBackend::delete_snapshot`].
+    DeleteSnapshot,
+    /// [`VmmBackend::terminate`].
+    Terminate,
+    /// [`VmmBackend::health_check`].
+    HealthCheck,
+    /// [`VmmBackend::execute_command`].
+    ExecuteCommand,
+    /// [`VmmBackend::execute_in_vm`].
+    ExecuteInVm,
+}
+
+/// One recorded call to a [`MockBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MockCall {
+    /// A VM was spawned from `config`.
+    Spawn {
+        /// Requested configuration.
+        config: VmConfig,
+    },
+    /// A full snapshot of `vm_id` was requested.
+    Snapshot {
+        /// VM being snapshotted.
+        vm_id: Uuid,
+    },
+    /// A diff snapshot of `vm_id` was requested.
+    DiffSnapshot {
+        /// VM being snapshotted.
+        vm_id: Uuid,
+    },
+    /// A VM was restored from `snapshot_id`.
+    Restore {
+        /// Snapshot being restored.
+        snapshot_id: SnapshotId,
+    },
+    /// Deletion of `snapshot_id` was requested.
+    DeleteSnapshot {
+        /// Snapshot being deleted.
+        snapshot_id: SnapshotId,
+    },
+    /// VM `vm_id` was terminated.
+    Terminate {
+        /// VM being terminated.
+        vm_id: Uuid,
+    },
+    /// The backend health was checked.
+    HealthCheck,
+    /// `command` was run in a fresh VM booted from `config`.
+    ExecuteCommand {
+        /// Requested configuration.
+        config: VmConfig,
+        /// Command line.
+        command: String,
+        /// Caller's timeout.
+        timeout: Duration,
+    },
+    /// `command` was run inside the running VM `vm_id`.
+    ExecuteInVm {
+        /// Target VM.
+        vm_id: Uuid,
+        /// Command line.
+        command: String,
+        /// Caller's timeout.
+        timeout: Duration,
+    },
+}
+
+impl MockCall {
+    /// The operation this call was made to.
+    #[must_use]
+    pub const fn operation(&self) -> MockOperation {
+        match self {
+            Self::Spawn { .. } => MockOperation::Spawn,
+            Self::Snapshot { .. } => MockOperation::Snapshot,
+            Self::DiffSnapshot { .. } => MockOperation::DiffSnapshot,
+            Self::Restore { .. } => MockOperation::Restore,
+            Self::DeleteSnapshot { .. } => MockOperation::DeleteSnapshot,
+            Self::Terminate { .. } => MockOperation::Terminate,
+            Self::HealthCheck => MockOperation::HealthCheck,
+            Self::ExecuteCommand { .. } => MockOperation::ExecuteCommand,
+            Self::ExecuteInVm { .. } => MockOperation::ExecuteInVm,
+        }
+    }
+}
+
+/// A snapshot held by a [`MockBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MockSnapshot {
+    /// VM the snapshot was taken from.
+    pub source_vm: Uuid,
+    /// Configuration of the source VM.
+    pub source_config: Option<VmConfig>,
+    /// Parent snapshot for diff snapshots, `None` for full snapshots.
+    pub parent: Option<SnapshotId>,
+}
+
+/// Mutable state behind a [`MockBackend`].
+#[derive(Debug, Default)]
+struct MockState {
+    calls: Vec<MockCall>,
+    live_vms: HashSet<Uuid>,
+    snapshots: HashMap<SnapshotId, MockSnapshot>,
+    /// Most recent snapshot per VM, the parent of its next diff snapshot.
+    latest_snapshot: HashMap<Uuid, SnapshotId>,
+}
+
+/// Scripted in-memory VMM backend.
+///
+/// Handles it returns are backed by a short-lived `true` process, since
+/// [`VmHandle`] owns a child process; no VM is ever started.
+pub struct MockBackend {
+    responses: Mutex<HashMap<String, VecDeque<MockResponse>>>,
+    fallback: Option<MockResponse>,
+    failures: HashMap<MockOperation, (usize, ErrorFactory)>,
+    isolation: IsolationLevel,
+    state: Mutex<MockState>,
+}
+
+impl MockBackend {
+    /// Create a mock where every operation succeeds and every command echoes
+    /// itself back as stdout.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            responses: Mutex::new(HashMap::new()),
+            fallback: None,
+            failures: HashMap::new(),
+            isolation: IsolationLevel::MicroVm,
+            state: Mutex::new(MockState::default()),
+        }
+    }
+
+    /// Script the response to `command`.
+    ///
+    /// Responses scripted for the same command are served in order; the last
+    /// one repeats once the others are used up. A command run behind the
+    /// [hermetic](crate::hermetic) preamble matches the responses scripted
+    /// for it without the preamble.
+    #[must_use]
+    pub fn on_command(mut self, command: impl Into<String>, response: MockResponse) -> Self {
+        self.responses.get_mut().entry(command.into()).or_default().push_back(response);
+        self
+    }
+
+    /// Serve `response` for every command without a scripted response,
+    /// instead of echoing the command.
+    #[must_use]
+    pub fn with_fallback(mut self, response: MockResponse) -> Self {
+        self.fallback = Some(response);
+        self
+    }
+
+    /// Make every call to `operation` fail with the error built by `error`.
+    #[must_use]
+    pub fn fail_operation(
+        self,
+        operation: MockOperation,
+        error: impl Fn() -> ExecutorError + Send + Sync + 'static,
+    ) -> Self {
+        self.fail_operation_after(operation, 0, error)
+    }
+
+    /// Let the first `successes` calls to `operation` succeed and make every
+    /// later one fail with the error built by `error`.
+    #[must_use]
+    pub fn fail_operation_after(
+        mut self,
+        operation: MockOperation,
+        successes: usize,
+        error: impl Fn() -> ExecutorError + Send + Sync + 'static,
+    ) -> Self {
+        self.failures.insert(operation, (successes, Arc::new(error)));
+        self
+    }
+
+    /// Set the isolation level the mock reports.
+    #[must_use]
+    pub const fn with_isolation(mut self, isolation: IsolationLevel) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Every call made so far, in order.
+    pub async fn calls(&self) -> Vec<MockCall> {
+        self.state.lock().await.calls.clone()
+    }
+
+    /// Number of calls made so far to `operation`.
+    pub async fn call_count(&self, operation: MockOperation) -> usize {
+        self.state.lock().await.calls.iter().filter(|c| c.operation() == operation).count()
+    }
+
+    /// Commands run so far via either execution method, in order.
+    pub async fn commands(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .await
+            .calls
+            .iter()
+            .filter_map(|c| match c {
+                MockCall::ExecuteCommand { command, .. }
+                | MockCall::ExecuteInVm { command, .. } => Some(command.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Number of VMs spawned or restored and not yet terminated.
+    pub async fn live_vm_count(&self) -> usize {
+        self.state.lock().await.live_vms.len()
+    }
+
+    /// Snapshots currently held, keyed by ID.
+    pub async fn snapshots(&self) -> HashMap<SnapshotId, MockSnapshot> {
+        self.state.lock().await.snapshots.clone()
+    }
+
+    /// Record `call`, then fail it if its operation is scripted to fail.
+    async fn record(&self, call: MockCall) -> Result<(), ExecutorError> {
+        let operation = call.operation();
+        let previous = {
+            let mut state = self.state.lock().await;
+            state.calls.push(call);
+            state.calls.iter().filter(|call| call.operation() == operation).count() - 1
+        };
+        match self.failures.get(&operation) {
+            Some((successes, error)) if previous >= *successes => Err(error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The response to serve for `command`.
+    async fn response_for(&self, command: &str) -> MockResponse {
+        let scripted = {
+            let mut responses = self.responses.lock().await;
+            let key =
+                if responses.contains_key(command) { command } else { strip_preamble(command) };
+            responses.get_mut(key).and_then(|queue| {
+                if queue.len() > 1 {
+                    queue.pop_front()
+                } else {
+                    queue.front().cloned()
+                }
+            })
+        };
+        scripted
+            .or_else(|| self.fallback.clone())
+            .unwrap_or_else(|| MockResponse::success(command.as_bytes()))
+    }
+
+    /// Register a new live VM and build its handle.
+    async fn new_vm(&self, config: Option<VmConfig>) -> Result<VmHandle, ExecutorError> {
+        let process = tokio::process::Command::new("true")
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| ExecutorError::SpawnFailed(format!("exec true: {e}")))?;
+        let id = Uuid::new_v4();
+        self.state.lock().await.live_vms.insert(id);
+
+        let mut handle = VmHandle::new(id, PathBuf::from(format!("/tmp/mock-{id}.sock")), process);
+        handle.config = config;
+        Ok(handle)
+    }
+
+    /// Store a snapshot of `handle` and make it the VM's latest.
+    async fn take_snapshot(&self, handle: &VmHandle, parent: Option<SnapshotId>) -> SnapshotId {
+        let id = SnapshotId::new();
+        let mut state = self.state.lock().await;
+        state.snapshots.insert(
+            id,
+            MockSnapshot { source_vm: handle.id, source_config: handle.config.clone(), parent },
+        );
+        state.latest_snapshot.insert(handle.id, id);
+        id
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MockBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockBackend")
+            .field("failing_operations", &self.failures.keys().collect::<Vec<_>>())
+            .field("isolation", &self.isolation)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl VmmBackend for MockBackend {
+    async fn spawn(&self, config: &VmConfig) -> Result<VmHandle, ExecutorError> {
+        self.record(MockCall::Spawn { config: config.clone() }).await?;
+        self.new_vm(Some(config.clone())).await
+    }
+
+    async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        self.record(MockCall::Snapshot { vm_id: handle.id }).await?;
+        if !self.state.lock().await.live_vms.contains(&handle.id) {
+            return Err(ExecutorError::VmNotFound(handle.id));
+        }
+        Ok(self.take_snapshot(handle, None).await)
+    }
+
+    async fn diff_snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        self.record(MockCall::DiffSnapshot { vm_id: handle.id }).await?;
+        let parent = {
+            let state = self.state.lock().await;
+            if !state.live_vms.contains(&handle.id) {
+                return Err(ExecutorError::VmNotFound(handle.id));
+            }
+            state.latest_snapshot.get(&handle.id).copied().or(handle.restored_from)
+        };
+        let Some(parent) = parent else {
+            return Err(ExecutorError::SnapshotFailed {
+                vm_id: handle.id,
+                reason: "VM has no parent snapshot to diff against".to_owned(),
+            });
+        };
+        Ok(self.take_snapshot(handle, Some(parent)).await)
+    }
+
+    async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        self.record(MockCall::Restore { snapshot_id: *snapshot_id }).await?;
+        let Some(snapshot) = self.state.lock().await.snapshots.get(snapshot_id).cloned() else {
+            return Err(ExecutorError::RestoreFailed {
+                snapshot_id: snapshot_id.0,
+                reason: "snapshot not found".to_owned(),
+            });
+        };
+        let mut handle = self.new_vm(snapshot.source_config).await?;
+        handle.restored_from = Some(*snapshot_id);
+        Ok(handle)
+    }
+
+    async fn delete_snapshot(&self, snapshot_id: &SnapshotId) -> Result<(), ExecutorError> {
+        self.record(MockCall::DeleteSnapshot { snapshot_id: *snapshot_id }).await?;
+        let mut state = self.state.lock().await;
+        if state.snapshots.values().any(|s| s.parent == Some(*snapshot_id)) {
+            return Err(ExecutorError::SnapshotInUse {
+                snapshot_id: snapshot_id.0,
+                reason: "diff snapshots depend on it".to_owned(),
+            });
+        }
+        let removed = state.snapshots.remove(snapshot_id);
+        state.latest_snapshot.retain(|_, latest| latest != snapshot_id);
+        drop(state);
+
+        removed.map(|_| ()).ok_or(ExecutorError::SnapshotNotFound(snapshot_id.0))
+    }
+
+    async fn terminate(&self, handle: VmHandle) -> Result<(), ExecutorError> {
+        self.record(MockCall::Terminate { vm_id: handle.id }).await?;
+        let mut state = self.state.lock().await;
+        state.live_vms.remove(&handle.id);
+        state.latest_snapshot.remove(&handle.id);
+        drop(state);
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<(), ExecutorError> {
+        self.record(MockCall::HealthCheck).await
+    }
+
+    async fn execute_command(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        self.record(MockCall::ExecuteCommand {
+            config: config.clone(),
+            command: command.to_owned(),
+            timeout,
+        })
+        .await?;
+        self.response_for(command).await.serve(timeout).await
+    }
+
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        self.record(MockCall::ExecuteInVm {
+            vm_id: handle.id,
+            command: command.to_owned(),
+            timeout,
+        })
+        .await?;
+        if !self.state.lock().await.live_vms.contains(&handle.id) {
+            return Err(ExecutorError::VmNotFound(handle.id));
+        }
+        self.response_for(command).await.serve(timeout).await
+    }
+
+    fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> VmConfig {
+        VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"))
+    }
+
+    async fn run(backend: &MockBackend, command: &str) -> Result<ExecutionOutput, ExecutorError> {
+        backend.execute_command(&test_config(), command, Duration::from_secs(5)).await
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn unscripted_commands_echo_and_are_recorded() {
+        let backend = MockBackend::new();
+        let output = match run(&backend, "echo hi").await {
+            Ok(o) => o,
+            Err(e) => panic!("execution failed: {e}"),
+        };
+        assert_eq!(output.stdout, b"echo hi");
+        assert_eq!(output.exit_code, 0);
+        assert_eq!(backend.commands().await, vec!["echo hi".to_owned()]);
+        assert_eq!(backend.call_count(MockOperation::ExecuteCommand).await, 1);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn scripted_responses_are_served_in_order_and_last_repeats() {
+        let backend = MockBackend::new()
+            .on_command("build", MockResponse::exit(2).with_stderr("missing dep\n"))
+            .on_command("build", MockResponse::success("ok\n"));
+
+        let codes: Vec<_> = [
+            run(&backend, "build").await,
+            run(&backend, "build").await,
+            run(&backend, "build").await,
+        ]
+        .into_iter()
+        .map(|r| r.map(|o| (o.exit_code, o.stdout)).ok())
+        .collect();
+
+        assert_eq!(
+            codes,
+            vec![Some((2, Vec::new())), Some((0, b"ok\n".to_vec())), Some((0, b"ok\n".to_vec()))]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn latency_beyond_timeout_times_out() {
+        let backend = MockBackend::new().on_command(
+            "slow",
+            MockResponse::success("late").with_latency(Duration::from_secs(60)),
+        );
+
+        let result = run(&backend, "slow").await;
+        assert!(
+            matches!(&result, Err(ExecutorError::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut),
+            "latency beyond the timeout must time out, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn failed_operations_are_still_recorded() {
+        let backend = MockBackend::new().fail_operation(MockOperation::Spawn, || {
+            ExecutorError::SpawnFailed("scripted".to_owned())
+        });
+
+        let result = backend.spawn(&test_config()).await;
+        assert!(matches!(result, Err(ExecutorError::SpawnFailed(_))));
+        assert_eq!(backend.calls().await, vec![MockCall::Spawn { config: test_config() }]);
+        assert_eq!(backend.live_vm_count().await, 0);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn snapshots_chain_and_restore() {
+        let backend = MockBackend::new();
+        let vm = match backend.spawn(&test_config()).await {
+            Ok(h) => h,
+            Err(e) => panic!("spawn failed: {e}"),
+        };
+
+        let diff_without_base = backend.diff_snapshot(&vm).await;
+        assert!(matches!(diff_without_base, Err(ExecutorError::SnapshotFailed { .. })));
+
+        let base = backend.snapshot(&vm).await.unwrap_or_else(|e| panic!("snapshot: {e}"));
+        let diff = backend.diff_snapshot(&vm).await.unwrap_or_else(|e| panic!("diff: {e}"));
+        assert_eq!(backend.snapshots().await.get(&diff).and_then(|s| s.parent), Some(base));
+
+        let in_use = backend.delete_snapshot(&base).await;
+        assert!(matches!(in_use, Err(ExecutorError::SnapshotInUse { .. })));
+
+        let restored = backend.restore(&diff).await.unwrap_or_else(|e| panic!("restore: {e}"));
+        assert_eq!(restored.restored_from, Some(diff));
+        assert_eq!(restored.config, Some(test_config()));
+        assert_eq!(backend.live_vm_count().await, 2);
+
+        for handle in [vm, restored] {
+            backend.terminate(handle).await.unwrap_or_else(|e| panic!("terminate: {e}"));
+        }
+        assert_eq!(backend.live_vm_count().await, 0);
+
+        let unknown = backend.restore(&SnapshotId::new()).await;
+        assert!(matches!(unknown, Err(ExecutorError::RestoreFailed { .. })));
+    }
+}
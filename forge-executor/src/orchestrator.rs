@@ -1,15 +1,48 @@
 //! High-level VM orchestrator wrapping a [`VmmBackend`].
 //!
-//! Tracks active VMs and provides a safe interface for lifecycle operations.
+//! Tracks active VMs and provides a safe interface for lifecycle operations,
+//! including forking a running VM into speculative branches.
 
 use std::collections::BTreeSet;
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures_util::future::join_all;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+use crate::backend::ExecutionOutput;
 use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle, VmmBackend};
 
+/// One speculative child of a [`VmOrchestrator::fork`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ForkBranch {
+    /// The child VM, still running and registered with the orchestrator.
+    pub handle: VmHandle,
+    /// The command this branch ran.
+    pub command: String,
+    /// Output of the command, or the error that prevented it from completing.
+    pub result: Result<ExecutionOutput, ExecutorError>,
+}
+
+/// The children produced by forking a running VM.
+///
+/// Pass it back to [`VmOrchestrator::resolve_fork`] to keep one branch, or to
+/// [`VmOrchestrator::abandon_fork`] to discard them all.
+///
+/// Dropping a fork does NOT terminate its branches or delete its snapshot,
+/// just as dropping a [`VmHandle`] does not terminate its VM.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use = "a dropped fork leaks its branch VMs and snapshot; resolve or abandon it"]
+pub struct Fork {
+    /// Snapshot of the parent every branch was restored from.
+    pub snapshot_id: SnapshotId,
+    /// One branch per command, in the order the commands were given.
+    pub branches: Vec<ForkBranch>,
+}
+
 /// High-level orchestrator for VM lifecycle management.
 ///
 /// Wraps a [`VmmBackend`] and maintains a registry of active VMs.
@@ -48,6 +81,18 @@ pub async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorEr
         self.backend.snapshot(handle).await
     }
 
+    /// Create a diff snapshot of a running VM.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::VmNotFound`] if the VM is not registered.
+    /// Propagates errors from the underlying [`VmmBackend::diff_snapshot`].
+    pub async fn diff_snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        if !self.active_vms.lock().await.contains(&handle.id) {
+            return Err(ExecutorError::VmNotFound(handle.id));
+        }
+        self.backend.diff_snapshot(handle).await
+    }
+
     /// Restore a VM from a snapshot and register it.
     ///
     /// # Errors
@@ -73,6 +118,128 @@ pub async fn terminate(&self, handle: VmHandle) -> Result<(), ExecutorError> {
         Ok(())
     }
 
+    /// Fork a running VM into one child per command and run them concurrently.
+    ///
+    /// The parent is snapshotted once and keeps running untouched. Each child
+    /// is restored from that snapshot with its own copy of the parent's disk
+    /// state, registered, and runs its command via
+    /// [`VmmBackend::execute_in_vm`]. A failing command does not fail the fork;
+    /// its error is reported in [`ForkBranch::result`].
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::VmNotFound`] if the parent is not registered.
+    /// Propagates snapshot and restore errors; children restored before the
+    /// failure are terminated and the snapshot is deleted.
+    pub async fn fork(
+        &self,
+        parent: &VmHandle,
+        commands: &[&str],
+        timeout: Duration,
+    ) -> Result<Fork, ExecutorError> {
+        let snapshot_id = self.snapshot(parent).await?;
+
+        let restored = join_all(commands.iter().map(|_| self.restore(&snapshot_id))).await;
+        let mut children = Vec::with_capacity(restored.len());
+        let mut failure = None;
+        for child in restored {
+            match child {
+                Ok(handle) => children.push(handle),
+                Err(e) => failure = failure.or(Some(e)),
+            }
+        }
+        if let Some(e) = failure {
+            for child in children {
+                let _ = self.terminate(child).await;
+            }
+            self.discard_snapshot(&snapshot_id).await;
+            return Err(e);
+        }
+
+        tracing::info!(
+            vm_id = %parent.id,
+            snapshot_id = %snapshot_id,
+            branches = children.len(),
+            "VM forked"
+        );
+
+        let results = join_all(
+            children
+                .iter_mut()
+                .zip(commands)
+                .map(|(child, command)| self.backend.execute_in_vm(child, command, timeout)),
+        )
+        .await;
+
+        let branches = children
+            .into_iter()
+            .zip(commands)
+            .zip(results)
+            .map(|((handle, command), result)| ForkBranch {
+                handle,
+                command: (*command).to_owned(),
+                result,
+            })
+            .collect();
+
+        Ok(Fork { snapshot_id, branches })
+    }
+
+    /// Keep the branch whose VM has ID `keep` and terminate every other branch.
+    ///
+    /// The fork snapshot is deleted, so the kept VM no longer records it as
+    /// [`VmHandle::restored_from`]. Cleanup is best effort: branches that
+    /// fail to terminate are logged, and never cost the caller the kept VM.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::VmNotFound`] if no branch has ID `keep`; every
+    /// branch is terminated in that case.
+    pub async fn resolve_fork(&self, fork: Fork, keep: Uuid) -> Result<VmHandle, ExecutorError> {
+        let mut kept = None;
+        for branch in fork.branches {
+            if branch.handle.id == keep {
+                kept = Some(branch.handle);
+                continue;
+            }
+            let vm_id = branch.handle.id;
+            if let Err(e) = self.terminate(branch.handle).await {
+                tracing::warn!(%vm_id, error = %e, "failed to terminate discarded fork branch");
+            }
+        }
+        self.discard_snapshot(&fork.snapshot_id).await;
+
+        let mut kept = kept.ok_or(ExecutorError::VmNotFound(keep))?;
+        kept.restored_from = None;
+
+        tracing::info!(vm_id = %kept.id, snapshot_id = %fork.snapshot_id, "fork resolved");
+        Ok(kept)
+    }
+
+    /// Terminate every branch of a fork and delete its snapshot.
+    ///
+    /// # Errors
+    /// Propagates the first error from [`VmmBackend::terminate`]; the remaining
+    /// branches are still terminated.
+    pub async fn abandon_fork(&self, fork: Fork) -> Result<(), ExecutorError> {
+        let mut first_error = None;
+        for branch in fork.branches {
+            if let Err(e) = self.terminate(branch.handle).await {
+                first_error = first_error.or(Some(e));
+            }
+        }
+        self.discard_snapshot(&fork.snapshot_id).await;
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Best-effort deletion of a fork snapshot.
+    async fn discard_snapshot(&self, snapshot_id: &SnapshotId) {
+        match self.backend.delete_snapshot(snapshot_id).await {
+            Ok(()) | Err(ExecutorError::Unsupported(_)) => {}
+            Err(e) => {
+                tracing::warn!(snapshot_id = %snapshot_id, error = %e, "failed to delete fork snapshot");
+            }
+        }
+    }
+
     /// Return the number of currently active VMs.
     pub async fn active_count(&self) -> usize {
         self.active_vms.lock().await.len()
@@ -84,58 +251,129 @@ mod tests {
     use std::path::PathBuf;
     use std::time::Duration;
 
-    use async_trait::async_trait;
     use uuid::Uuid;
 
     use super::*;
-    use crate::backend::{ExecutionOutput, VmmBackend};
-    use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
+    use crate::mock::{MockBackend, MockOperation, MockResponse};
+    use crate::{ExecutorError, VmConfig, VmHandle};
 
-    struct AlwaysFailBackend;
+    /// Backend whose every spawn fails.
+    fn failing_backend() -> MockBackend {
+        MockBackend::new().fail_operation(MockOperation::Spawn, || {
+            ExecutorError::SpawnFailed("mock always fails".to_owned())
+        })
+    }
 
-    #[async_trait]
-    impl VmmBackend for AlwaysFailBackend {
-        async fn spawn(&self, _config: &VmConfig) -> Result<VmHandle, ExecutorError> {
-            Err(ExecutorError::SpawnFailed("mock always fails".to_owned()))
-        }
+    /// Backend whose commands echo back, failing the command `fail b`.
+    fn echo_backend() -> MockBackend {
+        MockBackend::new().on_command(
+            "fail b",
+            MockResponse::failure(|| ExecutorError::SpawnFailed("fail b".to_owned())),
+        )
+    }
 
-        async fn snapshot(&self, _handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
-            Err(ExecutorError::SpawnFailed("mock".to_owned()))
-        }
+    fn dummy_handle() -> VmHandle {
+        let child = match tokio::process::Command::new("true").spawn() {
+            Ok(c) => c,
+            Err(e) => panic!("failed to spawn true: {e}"),
+        };
+        VmHandle::new(Uuid::new_v4(), PathBuf::from("/tmp/test.sock"), child)
+    }
 
-        async fn restore(&self, _snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
-            Err(ExecutorError::SpawnFailed("mock".to_owned()))
+    async fn forked(orch: &VmOrchestrator<MockBackend>, commands: &[&str]) -> Fork {
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let parent = match orch.spawn(&config).await {
+            Ok(h) => h,
+            Err(e) => panic!("spawn failed: {e}"),
+        };
+        match orch.fork(&parent, commands, Duration::from_secs(1)).await {
+            Ok(f) => f,
+            Err(e) => panic!("fork failed: {e}"),
         }
+    }
 
-        async fn terminate(&self, _handle: VmHandle) -> Result<(), ExecutorError> {
-            Ok(())
-        }
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn fork_runs_each_command_in_its_own_child() {
+        let orch = VmOrchestrator::new(echo_backend());
+        let fork = forked(&orch, &["echo a", "fail b", "echo c"]).await;
 
-        async fn health_check(&self) -> Result<(), ExecutorError> {
-            Ok(())
+        assert_eq!(fork.branches.len(), 3);
+        assert_eq!(orch.active_count().await, 4, "parent plus three registered children");
+        for branch in &fork.branches {
+            assert_eq!(branch.handle.restored_from, Some(fork.snapshot_id));
         }
+        let outputs: Vec<_> = fork
+            .branches
+            .iter()
+            .map(|b| b.result.as_ref().ok().map(|o| o.stdout.clone()))
+            .collect();
+        assert_eq!(outputs, vec![Some(b"echo a".to_vec()), None, Some(b"echo c".to_vec())]);
+    }
 
-        async fn execute_command(
-            &self,
-            _config: &VmConfig,
-            _command: &str,
-            _timeout: Duration,
-        ) -> Result<ExecutionOutput, ExecutorError> {
-            Err(ExecutorError::SpawnFailed("mock".to_owned()))
-        }
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn resolve_fork_keeps_one_and_terminates_the_rest() {
+        let orch = VmOrchestrator::new(echo_backend());
+        let fork = forked(&orch, &["echo a", "echo b"]).await;
+        let keep = fork.branches[1].handle.id;
+
+        let kept = match orch.resolve_fork(fork, keep).await {
+            Ok(h) => h,
+            Err(e) => panic!("resolve failed: {e}"),
+        };
+        assert_eq!(kept.id, keep);
+        assert!(kept.restored_from.is_none(), "fork snapshot is discarded on resolve");
+        assert_eq!(orch.active_count().await, 2, "parent and the kept child remain");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn resolve_fork_keeps_the_branch_when_others_fail_to_terminate() {
+        let backend = echo_backend().fail_operation(MockOperation::Terminate, || {
+            ExecutorError::SpawnFailed("terminate failed".to_owned())
+        });
+        let orch = VmOrchestrator::new(backend);
+        let fork = forked(&orch, &["echo a", "echo b", "echo c"]).await;
+        let keep = fork.branches[0].handle.id;
+
+        let kept = match orch.resolve_fork(fork, keep).await {
+            Ok(h) => h,
+            Err(e) => panic!("an unrelated cleanup failure must not lose the kept VM: {e}"),
+        };
+        assert_eq!(kept.id, keep);
+        assert!(orch.backend.snapshots().await.is_empty(), "fork snapshot is still deleted");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn resolve_fork_unknown_branch_terminates_all() {
+        let orch = VmOrchestrator::new(echo_backend());
+        let fork = forked(&orch, &["echo a", "echo b"]).await;
+        let result = orch.resolve_fork(fork, Uuid::new_v4()).await;
+        assert!(matches!(result, Err(ExecutorError::VmNotFound(_))));
+        assert_eq!(orch.active_count().await, 1, "only the parent remains");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn fork_unregistered_parent_returns_vm_not_found() {
+        let orch = VmOrchestrator::new(echo_backend());
+        let result = orch.fork(&dummy_handle(), &["echo a"], Duration::from_secs(1)).await;
+        assert!(matches!(result, Err(ExecutorError::VmNotFound(_))));
     }
 
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn orchestrator_active_count_starts_at_zero() {
-        let orch = VmOrchestrator::new(AlwaysFailBackend);
+        let orch = VmOrchestrator::new(failing_backend());
         assert_eq!(orch.active_count().await, 0, "new orchestrator must have zero active VMs");
     }
 
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn orchestrator_spawn_propagates_backend_error() {
-        let orch = VmOrchestrator::new(AlwaysFailBackend);
+        let orch = VmOrchestrator::new(failing_backend());
         let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
         let result = orch.spawn(&config).await;
         assert!(
@@ -147,7 +385,7 @@ async fn orchestrator_spawn_propagates_backend_error() {
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn orchestrator_terminate_unregistered_returns_vm_not_found() {
-        let orch = VmOrchestrator::new(AlwaysFailBackend);
+        let orch = VmOrchestrator::new(failing_backend());
         // Spawn a real tokio child so we can build a VmHandle.
         let child = match tokio::process::Command::new("true").spawn() {
             Ok(c) => c,
@@ -164,7 +402,7 @@ async fn orchestrator_terminate_unregistered_returns_vm_not_found() {
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn orchestrator_snapshot_unregistered_returns_vm_not_found() {
-        let orch = VmOrchestrator::new(AlwaysFailBackend);
+        let orch = VmOrchestrator::new(failing_backend());
         let child = match tokio::process::Command::new("true").spawn() {
             Ok(c) => c,
             Err(e) => panic!("failed to spawn true: {e}"),
@@ -0,0 +1,250 @@
+//! Composite block pipelines — executing the blocks a block is composed of.
+//!
+//! A composite block lists its stages in [`Block::composed_of`]. The edges
+//! between them come from the stages' manifests: a stage depends on every
+//! other stage that provides a capability it requires. Requirements no
+//! stage provides are left to the stage's own environment.
+//!
+//! [`BlockRunner::execute_pipeline`](crate::BlockRunner::execute_pipeline)
+//! starts each stage as soon as everything it depends on has finished, so
+//! independent branches run concurrently. A stage without dependencies
+//! receives the pipeline input; any other stage receives its upstream
+//! stages' outputs, encoded by [`stage_input`].
+
+use forge_core::block::Block;
+use forge_core::execution::ExecutionRecord;
+
+use crate::backend::ExecutionOutput;
+use crate::ExecutorError;
+
+/// The stages of a composite block and the dependencies between them.
+#[derive(Debug)]
+pub struct Pipeline<'a> {
+    composite: &'a Block,
+    stages: Vec<&'a Block>,
+    upstream: Vec<Vec<usize>>,
+    downstream: Vec<Vec<usize>>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Resolve the stages of `composite` from `blocks` and order them.
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::InvalidPipeline`] if `composite` is not
+    /// composed of any blocks, a stage is missing from `blocks`, listed
+    /// twice, or itself composite, or the stages' dependencies form a cycle.
+    pub fn new(composite: &'a Block, blocks: &'a [Block]) -> Result<Self, ExecutorError> {
+        let invalid = |reason: String| ExecutorError::InvalidPipeline {
+            block: composite.manifest.name.clone(),
+            reason,
+        };
+
+        let ids = composite.composed_of.as_deref().unwrap_or_default();
+        if ids.is_empty() {
+            return Err(invalid("not composed of any blocks".to_owned()));
+        }
+        let mut stages = Vec::with_capacity(ids.len());
+        for (index, id) in ids.iter().enumerate() {
+            if ids[..index].contains(id) {
+                return Err(invalid(format!("stage {id} is listed twice")));
+            }
+            let stage = blocks
+                .iter()
+                .find(|block| block.id == *id)
+                .ok_or_else(|| invalid(format!("stage {id} not found")))?;
+            if stage.composed_of.is_some() {
+                return Err(invalid(format!("stage {} is itself composite", stage.manifest.name)));
+            }
+            stages.push(stage);
+        }
+
+        let mut upstream = vec![Vec::new(); stages.len()];
+        let mut downstream = vec![Vec::new(); stages.len()];
+        for (to, stage) in stages.iter().enumerate() {
+            for (from, provider) in stages.iter().enumerate() {
+                let provides =
+                    |name: &str| provider.manifest.provides.iter().any(|c| c.name == name);
+                if from != to && stage.manifest.requires.iter().any(|dep| provides(&dep.name)) {
+                    upstream[to].push(from);
+                    downstream[from].push(to);
+                }
+            }
+        }
+
+        let pipeline = Self { composite, stages, upstream, downstream };
+        if let Some(stage) = pipeline.cyclic_stage() {
+            return Err(invalid(format!(
+                "dependency cycle through stage {}",
+                pipeline.stages[stage].manifest.name
+            )));
+        }
+        Ok(pipeline)
+    }
+
+    /// The composite block.
+    #[must_use]
+    pub const fn composite(&self) -> &'a Block {
+        self.composite
+    }
+
+    /// The stages, in the order `composed_of` lists them.
+    #[must_use]
+    pub fn stages(&self) -> &[&'a Block] {
+        &self.stages
+    }
+
+    /// Indices of the stages `stage` depends on, in composition order.
+    #[must_use]
+    pub fn upstream(&self, stage: usize) -> &[usize] {
+        &self.upstream[stage]
+    }
+
+    /// Indices of the stages that depend on `stage`, in composition order.
+    #[must_use]
+    pub fn downstream(&self, stage: usize) -> &[usize] {
+        &self.downstream[stage]
+    }
+
+    /// Indices of the stages nothing depends on; their outputs are the
+    /// pipeline's output.
+    #[must_use]
+    pub fn sinks(&self) -> Vec<usize> {
+        (0..self.stages.len()).filter(|&stage| self.downstream[stage].is_empty()).collect()
+    }
+
+    /// A stage on a dependency cycle, if there is one (Kahn's algorithm).
+    fn cyclic_stage(&self) -> Option<usize> {
+        let mut waiting: Vec<usize> = self.upstream.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..waiting.len()).filter(|&s| waiting[s] == 0).collect();
+        while let Some(stage) = ready.pop() {
+            for &next in &self.downstream[stage] {
+                waiting[next] -= 1;
+                if waiting[next] == 0 {
+                    ready.push(next);
+                }
+            }
+        }
+        waiting.iter().position(|&count| count > 0)
+    }
+}
+
+/// Encode the outputs of `upstream` stages as the input of the stage that
+/// depends on them:
+///
+/// ```text
+/// u64be(stage_count)
+/// for each upstream stage, in composition order:
+///     u64be(len(name)) || name
+///     u64be(len(stdout)) || stdout
+///     u64be(artifact_count)
+///     for each artifact:
+///         u64be(len(path)) || path || sha256(contents)
+/// ```
+///
+/// `stdout` is the canonicalised output, so entropy a stage's rules remove
+/// does not reach, or change the input hash of, its dependents. Artifacts
+/// are passed by hash; their contents are in the
+/// [`ArtifactStore`](crate::ArtifactStore).
+#[must_use]
+pub fn stage_input(upstream: &[(&Block, &ExecutionRecord, &ExecutionOutput)]) -> Vec<u8> {
+    let mut input = Vec::new();
+    push_len(&mut input, upstream.len());
+    for (block, record, output) in upstream {
+        push_prefixed(&mut input, block.manifest.name.as_bytes());
+        push_prefixed(&mut input, &output.stdout);
+        push_len(&mut input, record.artifacts.len());
+        for artifact in &record.artifacts {
+            push_prefixed(&mut input, artifact.path.as_bytes());
+            input.extend_from_slice(artifact.hash.as_bytes());
+        }
+    }
+    input
+}
+
+/// The outcome of [`BlockRunner::execute_pipeline`](crate::BlockRunner::execute_pipeline).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PipelineExecution {
+    /// Aggregate record of the composite block. Its input hash is that of
+    /// the pipeline input, its output hash covers the outputs of the
+    /// [sink](Pipeline::sinks) stages encoded by [`stage_input`], and its
+    /// `children` name every stage record.
+    pub record: ExecutionRecord,
+
+    /// One record per stage, in composition order.
+    pub stages: Vec<ExecutionRecord>,
+}
+
+fn push_len(buf: &mut Vec<u8>, len: usize) {
+    // usize is at most 64 bits on every supported target.
+    buf.extend_from_slice(&(len as u64).to_be_bytes());
+}
+
+fn push_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+    push_len(buf, bytes.len());
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use forge_core::examples::example_blocks;
+    use forge_core::id::BlockId;
+
+    use super::*;
+
+    fn composite_of(blocks: &[Block]) -> Block {
+        let mut composite = blocks[0].clone();
+        composite.id = BlockId::new();
+        composite.manifest.name = "composite".to_owned();
+        composite.composed_of = Some(blocks.iter().map(|b| b.id).collect());
+        composite
+    }
+
+    fn error_of(result: Result<Pipeline<'_>, ExecutorError>) -> String {
+        match result {
+            Ok(pipeline) => panic!("expected an invalid pipeline, got {pipeline:?}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn edges_follow_required_capabilities() {
+        // git-env ← rust-dev-env ← bose-search
+        let blocks = example_blocks();
+        let composite = composite_of(&blocks);
+        let pipeline =
+            Pipeline::new(&composite, &blocks).unwrap_or_else(|e| panic!("pipeline rejected: {e}"));
+
+        assert!(pipeline.upstream(0).is_empty());
+        assert_eq!(pipeline.upstream(1), [0]);
+        assert_eq!(pipeline.downstream(0), [1]);
+        assert_eq!(pipeline.sinks(), [2]);
+    }
+
+    #[test]
+    fn missing_duplicate_and_nested_stages_are_rejected() {
+        let blocks = example_blocks();
+        let mut composite = composite_of(&blocks);
+        assert!(error_of(Pipeline::new(&composite, &blocks[..2])).contains("not found"));
+
+        composite.composed_of = Some(vec![blocks[0].id, blocks[0].id]);
+        assert!(error_of(Pipeline::new(&composite, &blocks)).contains("listed twice"));
+
+        composite.composed_of = Some(vec![]);
+        assert!(error_of(Pipeline::new(&composite, &blocks)).contains("not composed"));
+
+        let nested = [composite_of(&blocks[..1]), blocks[0].clone()];
+        let outer = composite_of(&nested[..1]);
+        assert!(error_of(Pipeline::new(&outer, &nested)).contains("itself composite"));
+    }
+
+    #[test]
+    fn dependency_cycles_are_rejected() {
+        let mut blocks = example_blocks();
+        let mut needs_rustc = blocks[1].manifest.requires[0].clone();
+        needs_rustc.name = "rustc".to_owned();
+        blocks[0].manifest.requires.push(needs_rustc);
+        let composite = composite_of(&blocks);
+        assert!(error_of(Pipeline::new(&composite, &blocks)).contains("cycle"));
+    }
+}
@@ -0,0 +1,589 @@
+//! Warm VM pool built from pre-booted snapshots.
+//!
+//! Cold-booting a microVM for every execution dominates latency for short
+//! commands. The pool boots a base VM once per [`VmConfig`], snapshots it, and
+//! serves executions from clones restored out of that snapshot. Each clone runs
+//! exactly one command against its own copy of the base VM's disk and is then
+//! terminated, so no state leaks between executions.
+//!
+//! [`WarmPool`] itself implements [`VmmBackend`], so it can be dropped into a
+//! [`BlockRunner`](crate::BlockRunner) in place of the backend it wraps.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use forge_core::execution::IsolationLevel;
+use tokio::sync::Mutex;
+
+use crate::backend::{ExecutionOutput, VmmBackend};
+use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
+
+/// Default time a base VM is given to finish booting before it is snapshotted.
+const DEFAULT_BOOT_SETTLE: Duration = Duration::from_secs(1);
+
+/// When consumed clones are replaced with freshly restored ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RefillPolicy {
+    /// Restore a replacement concurrently with every execution.
+    Eager,
+    /// Top the pool back up to its size once fewer than this many clones remain.
+    Watermark(usize),
+    /// Only refill when [`WarmPool::refill`] is called.
+    Manual,
+}
+
+/// Per-[`VmConfig`] pool settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolSettings {
+    /// Number of restored clones kept ready.
+    pub size: usize,
+
+    /// When consumed clones are replaced.
+    pub refill: RefillPolicy,
+
+    /// How long the base VM runs before it is snapshotted.
+    pub boot_settle: Duration,
+}
+
+impl PoolSettings {
+    /// Create settings for a pool of `size` clones with eager refill.
+    #[must_use]
+    pub const fn new(size: usize) -> Self {
+        Self { size, refill: RefillPolicy::Eager, boot_settle: DEFAULT_BOOT_SETTLE }
+    }
+}
+
+/// Hit-rate and restore-latency counters for a [`WarmPool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolMetrics {
+    /// Executions served by a clone that was already restored.
+    pub hits: u64,
+
+    /// Executions on a pooled config that had to restore a clone on demand.
+    pub misses: u64,
+
+    /// Executions on a config with no pool, served by a cold boot.
+    pub cold_boots: u64,
+
+    /// Number of clones restored from base snapshots.
+    pub restores: u64,
+
+    /// Sum of all restore latencies.
+    pub total_restore_latency: Duration,
+
+    /// Slowest restore observed.
+    pub max_restore_latency: Duration,
+}
+
+impl PoolMetrics {
+    /// Fraction of pooled executions served by a ready clone, in `[0.0, 1.0]`.
+    ///
+    /// Cold boots on unpooled configs are excluded. Returns `0.0` before the
+    /// first pooled execution.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+
+    /// Mean restore latency, or `None` if nothing has been restored yet.
+    #[must_use]
+    pub fn mean_restore_latency(&self) -> Option<Duration> {
+        let restores = u32::try_from(self.restores).ok().filter(|&n| n > 0)?;
+        Some(self.total_restore_latency / restores)
+    }
+
+    fn record_restore(&mut self, latency: Duration) {
+        self.restores += 1;
+        self.total_restore_latency += latency;
+        self.max_restore_latency = self.max_restore_latency.max(latency);
+    }
+}
+
+/// Ready clones and the base snapshot for a single [`VmConfig`].
+struct ConfigPool {
+    settings: PoolSettings,
+    base_snapshot: SnapshotId,
+    ready: VecDeque<VmHandle>,
+}
+
+/// A pool of pre-restored VMs, keyed by [`VmConfig`].
+///
+/// Configs must be registered with [`WarmPool::prepare`] before they are
+/// served from the pool; executions on any other config fall back to a cold
+/// boot through the wrapped backend.
+///
+/// # Cancel Safety
+/// Cancel safe. A clone taken from the pool is owned by the executing future
+/// and is killed on drop via `kill_on_drop`.
+pub struct WarmPool<B: VmmBackend> {
+    backend: B,
+    pools: Mutex<HashMap<VmConfig, ConfigPool>>,
+    metrics: Mutex<PoolMetrics>,
+}
+
+impl<B: VmmBackend> WarmPool<B> {
+    /// Create an empty pool wrapping the given backend.
+    #[must_use]
+    pub fn new(backend: B) -> Self {
+        Self { backend, pools: Mutex::new(HashMap::new()), metrics: Mutex::default() }
+    }
+
+    /// Return the wrapped backend.
+    #[must_use]
+    pub const fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Boot a base VM for `config`, snapshot it, and restore `settings.size` clones.
+    ///
+    /// The base VM is booted with `/bin/sh` as init so that restored clones
+    /// accept commands via [`VmmBackend::execute_in_vm`]. It is terminated once
+    /// the snapshot exists. Preparing an already-pooled config replaces its
+    /// settings and base snapshot, and terminates the old clones.
+    ///
+    /// # Errors
+    /// Propagates errors from the backend's spawn, snapshot, terminate, and
+    /// restore. If terminating the base VM or a restore fails, the clones
+    /// restored so far are terminated and the base snapshot is deleted before
+    /// returning.
+    pub async fn prepare(
+        &self,
+        config: &VmConfig,
+        settings: PoolSettings,
+    ) -> Result<SnapshotId, ExecutorError> {
+        let mut base_config = config.clone();
+        base_config.boot_args = format!("{} {}", config.boot_args, config.init_args("/bin/sh"));
+
+        let base = self.backend.spawn(&base_config).await?;
+        tokio::time::sleep(settings.boot_settle).await;
+        let snapshot = self.backend.snapshot(&base).await;
+        if let Err(e) = self.backend.terminate(base).await {
+            if let Ok(snapshot_id) = &snapshot {
+                self.discard_snapshot(snapshot_id).await;
+            }
+            return Err(e);
+        }
+        let base_snapshot = snapshot?;
+
+        tracing::info!(
+            snapshot_id = %base_snapshot,
+            size = settings.size,
+            "warm pool base snapshot created"
+        );
+
+        let mut ready = VecDeque::with_capacity(settings.size);
+        for _ in 0..settings.size {
+            match self.restore_clone(&base_snapshot).await {
+                Ok(clone) => ready.push_back(clone),
+                Err(e) => {
+                    // Nothing refers to the new pool yet, so tear it down
+                    // rather than leave its clones and snapshot behind.
+                    self.retire(ConfigPool { settings, base_snapshot, ready }).await;
+                    return Err(e);
+                }
+            }
+        }
+
+        let previous = self
+            .pools
+            .lock()
+            .await
+            .insert(config.clone(), ConfigPool { settings, base_snapshot, ready });
+        if let Some(previous) = previous {
+            self.retire(previous).await;
+        }
+
+        Ok(base_snapshot)
+    }
+
+    /// Restore clones for `config` until the pool is back at its configured size.
+    ///
+    /// Returns the number of clones restored. Configs without a pool are ignored.
+    ///
+    /// # Errors
+    /// Propagates errors from [`VmmBackend::restore`].
+    pub async fn refill(&self, config: &VmConfig) -> Result<usize, ExecutorError> {
+        let Some((base_snapshot, missing)) =
+            self.pools.lock().await.get(config).map(|pool| {
+                (pool.base_snapshot, pool.settings.size.saturating_sub(pool.ready.len()))
+            })
+        else {
+            return Ok(0);
+        };
+
+        for _ in 0..missing {
+            let clone = self.restore_clone(&base_snapshot).await?;
+            self.return_clone(config, clone).await;
+        }
+        Ok(missing)
+    }
+
+    /// Number of ready clones currently held for `config`.
+    pub async fn ready_count(&self, config: &VmConfig) -> usize {
+        self.pools.lock().await.get(config).map_or(0, |pool| pool.ready.len())
+    }
+
+    /// Return a snapshot of the hit-rate and restore-latency counters.
+    pub async fn metrics(&self) -> PoolMetrics {
+        *self.metrics.lock().await
+    }
+
+    /// Base snapshots of every pooled config.
+    ///
+    /// Pin these in a [`RetentionPolicy`](crate::RetentionPolicy) so garbage
+    /// collection does not delete snapshots the pool still restores from.
+    pub async fn base_snapshots(&self) -> Vec<SnapshotId> {
+        self.pools.lock().await.values().map(|pool| pool.base_snapshot).collect()
+    }
+
+    /// Terminate every ready clone, delete base snapshots, and forget all pooled configs.
+    pub async fn shutdown(&self) {
+        let pools: Vec<ConfigPool> = self.pools.lock().await.drain().map(|(_, p)| p).collect();
+        for pool in pools {
+            self.retire(pool).await;
+        }
+    }
+
+    /// Take a ready clone for `config`, restoring one on demand if the pool is empty.
+    ///
+    /// Returns `None` if `config` has no pool.
+    async fn acquire(&self, config: &VmConfig) -> Result<Option<VmHandle>, ExecutorError> {
+        let Some(taken) = self
+            .pools
+            .lock()
+            .await
+            .get_mut(config)
+            .map(|pool| pool.ready.pop_front().ok_or(pool.base_snapshot))
+        else {
+            return Ok(None);
+        };
+
+        match taken {
+            Ok(clone) => {
+                self.metrics.lock().await.hits += 1;
+                Ok(Some(clone))
+            }
+            Err(base_snapshot) => {
+                self.metrics.lock().await.misses += 1;
+                self.restore_clone(&base_snapshot).await.map(Some)
+            }
+        }
+    }
+
+    /// Refill `config` according to its policy after a clone was consumed.
+    async fn refill_after_use(&self, config: &VmConfig) -> Result<(), ExecutorError> {
+        let due =
+            self.pools.lock().await.get(config).is_some_and(|pool| match pool.settings.refill {
+                RefillPolicy::Eager => true,
+                RefillPolicy::Watermark(low) => pool.ready.len() < low,
+                RefillPolicy::Manual => false,
+            });
+        if due {
+            self.refill(config).await?;
+        }
+        Ok(())
+    }
+
+    async fn restore_clone(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        let start = Instant::now();
+        let clone = self.backend.restore(snapshot_id).await?;
+        let latency = start.elapsed();
+        self.metrics.lock().await.record_restore(latency);
+        tracing::debug!(
+            vm_id = %clone.id,
+            elapsed_ms = latency.as_millis(),
+            "warm pool clone restored"
+        );
+        Ok(clone)
+    }
+
+    /// Put a freshly restored clone into the pool, or terminate it if the pool
+    /// has since been removed or filled.
+    async fn return_clone(&self, config: &VmConfig, clone: VmHandle) {
+        let rejected = {
+            let mut pools = self.pools.lock().await;
+            match pools.get_mut(config) {
+                Some(pool) if pool.ready.len() < pool.settings.size => {
+                    pool.ready.push_back(clone);
+                    None
+                }
+                _ => Some(clone),
+            }
+        };
+        if let Some(clone) = rejected {
+            self.drain([clone]).await;
+        }
+    }
+
+    /// Terminate a replaced pool's clones and delete its base snapshot.
+    async fn retire(&self, pool: ConfigPool) {
+        self.drain(pool.ready).await;
+        self.discard_snapshot(&pool.base_snapshot).await;
+    }
+
+    async fn discard_snapshot(&self, snapshot_id: &SnapshotId) {
+        match self.backend.delete_snapshot(snapshot_id).await {
+            Ok(()) | Err(ExecutorError::Unsupported(_)) => {}
+            Err(e) => tracing::warn!(
+                snapshot_id = %snapshot_id,
+                error = %e,
+                "failed to delete warm pool base snapshot"
+            ),
+        }
+    }
+
+    async fn drain(&self, clones: impl IntoIterator<Item = VmHandle>) {
+        for clone in clones {
+            let vm_id = clone.id;
+            if let Err(e) = self.backend.terminate(clone).await {
+                tracing::warn!(vm_id = %vm_id, error = %e, "failed to terminate pooled clone");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<B: VmmBackend> VmmBackend for WarmPool<B> {
+    async fn spawn(&self, config: &VmConfig) -> Result<VmHandle, ExecutorError> {
+        self.backend.spawn(config).await
+    }
+
+    async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        self.backend.snapshot(handle).await
+    }
+
+    async fn diff_snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        self.backend.diff_snapshot(handle).await
+    }
+
+    async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        self.backend.restore(snapshot_id).await
+    }
+
+    async fn delete_snapshot(&self, snapshot_id: &SnapshotId) -> Result<(), ExecutorError> {
+        self.backend.delete_snapshot(snapshot_id).await
+    }
+
+    async fn terminate(&self, handle: VmHandle) -> Result<(), ExecutorError> {
+        self.backend.terminate(handle).await
+    }
+
+    async fn health_check(&self) -> Result<(), ExecutorError> {
+        self.backend.health_check().await
+    }
+
+    /// Serve the command from a pooled clone, falling back to a cold boot for
+    /// configs that were never [`prepare`](WarmPool::prepare)d.
+    async fn execute_command(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let Some(mut clone) = self.acquire(config).await? else {
+            self.metrics.lock().await.cold_boots += 1;
+            return self.backend.execute_command(config, command, timeout).await;
+        };
+
+        let run = async {
+            let output = self.backend.execute_in_vm(&mut clone, command, timeout).await;
+            self.drain([clone]).await;
+            output
+        };
+        let (output, refilled) = tokio::join!(run, self.refill_after_use(config));
+
+        if let Err(e) = refilled {
+            tracing::warn!(error = %e, "warm pool refill failed");
+        }
+        output
+    }
+
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        self.backend.execute_in_vm(handle, command, timeout).await
+    }
+
+    fn isolation(&self) -> IsolationLevel {
+        self.backend.isolation()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::mock::{MockBackend, MockCall, MockOperation};
+
+    fn test_config() -> VmConfig {
+        VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"))
+    }
+
+    const fn settings(size: usize, refill: RefillPolicy) -> PoolSettings {
+        let mut settings = PoolSettings::new(size);
+        settings.refill = refill;
+        settings.boot_settle = Duration::ZERO;
+        settings
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn prepare_boots_once_and_fills_pool() {
+        let pool = WarmPool::new(MockBackend::new());
+        let config = test_config();
+        if let Err(e) = pool.prepare(&config, settings(3, RefillPolicy::Manual)).await {
+            panic!("prepare failed: {e}");
+        }
+        assert_eq!(pool.backend().call_count(MockOperation::Spawn).await, 1, "base VM boots once");
+        assert_eq!(pool.ready_count(&config).await, 3, "pool must hold `size` clones");
+        assert_eq!(pool.metrics().await.restores, 3);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn layered_base_vms_boot_through_the_rootfs_init() {
+        let pool = WarmPool::new(MockBackend::new());
+        let mut config = test_config();
+        config.layer_paths = vec![PathBuf::from("/tmp/layer-0.ext4")];
+        if let Err(e) = pool.prepare(&config, settings(1, RefillPolicy::Manual)).await {
+            panic!("prepare failed: {e}");
+        }
+        let boot_args: Vec<String> = pool
+            .backend()
+            .calls()
+            .await
+            .into_iter()
+            .filter_map(|call| match call {
+                MockCall::Spawn { config } => Some(config.boot_args),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(boot_args, [format!("{} init=/init -- /bin/sh", config.boot_args)]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn failed_prepare_tears_down_clones_and_snapshot() {
+        let backend = MockBackend::new().fail_operation_after(MockOperation::Restore, 2, || {
+            ExecutorError::SpawnFailed("restore failed".to_owned())
+        });
+        let pool = WarmPool::new(backend);
+        let config = test_config();
+        let result = pool.prepare(&config, settings(3, RefillPolicy::Manual)).await;
+        assert!(matches!(result, Err(ExecutorError::SpawnFailed(_))), "got {result:?}");
+        assert_eq!(pool.backend().call_count(MockOperation::Restore).await, 3);
+        assert_eq!(pool.backend().live_vm_count().await, 0, "restored clones must be terminated");
+        assert!(pool.backend().snapshots().await.is_empty(), "base snapshot must be deleted");
+        assert!(pool.base_snapshots().await.is_empty());
+        assert_eq!(pool.ready_count(&config).await, 0);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn failed_base_terminate_deletes_the_snapshot() {
+        let backend = MockBackend::new().fail_operation(MockOperation::Terminate, || {
+            ExecutorError::VmNotFound(uuid::Uuid::nil())
+        });
+        let pool = WarmPool::new(backend);
+        let result = pool.prepare(&test_config(), settings(1, RefillPolicy::Manual)).await;
+        assert!(matches!(result, Err(ExecutorError::VmNotFound(_))), "got {result:?}");
+        assert_eq!(pool.backend().call_count(MockOperation::Restore).await, 0);
+        assert!(pool.backend().snapshots().await.is_empty(), "base snapshot must be deleted");
+        assert!(pool.base_snapshots().await.is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn execute_counts_hits_then_misses_without_refill() {
+        let pool = WarmPool::new(MockBackend::new());
+        let config = test_config();
+        if let Err(e) = pool.prepare(&config, settings(1, RefillPolicy::Manual)).await {
+            panic!("prepare failed: {e}");
+        }
+
+        for _ in 0..2 {
+            let output = match pool.execute_command(&config, "echo hi", Duration::ZERO).await {
+                Ok(o) => o,
+                Err(e) => panic!("execution failed: {e}"),
+            };
+            assert_eq!(output.stdout, b"echo hi");
+        }
+
+        let metrics = pool.metrics().await;
+        assert_eq!(metrics.hits, 1, "first execution must hit the ready clone");
+        assert_eq!(metrics.misses, 1, "second execution must restore on demand");
+        assert!((metrics.hit_rate() - 0.5).abs() < f64::EPSILON);
+        assert_eq!(pool.backend().call_count(MockOperation::ExecuteCommand).await, 0);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn eager_refill_keeps_pool_full() {
+        let pool = WarmPool::new(MockBackend::new());
+        let config = test_config();
+        if let Err(e) = pool.prepare(&config, settings(2, RefillPolicy::Eager)).await {
+            panic!("prepare failed: {e}");
+        }
+        for _ in 0..3 {
+            if let Err(e) = pool.execute_command(&config, "true", Duration::ZERO).await {
+                panic!("execution failed: {e}");
+            }
+        }
+        assert_eq!(pool.ready_count(&config).await, 2, "eager refill must restore each clone");
+        assert_eq!(pool.metrics().await.hits, 3);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn watermark_refill_waits_for_low_water() {
+        let pool = WarmPool::new(MockBackend::new());
+        let config = test_config();
+        if let Err(e) = pool.prepare(&config, settings(3, RefillPolicy::Watermark(2))).await {
+            panic!("prepare failed: {e}");
+        }
+        if let Err(e) = pool.execute_command(&config, "true", Duration::ZERO).await {
+            panic!("execution failed: {e}");
+        }
+        assert_eq!(pool.ready_count(&config).await, 2, "at watermark, no refill yet");
+        if let Err(e) = pool.execute_command(&config, "true", Duration::ZERO).await {
+            panic!("execution failed: {e}");
+        }
+        assert_eq!(pool.ready_count(&config).await, 3, "below watermark, pool is topped up");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn unpooled_config_falls_back_to_cold_boot() {
+        let pool = WarmPool::new(MockBackend::new());
+        if let Err(e) = pool.execute_command(&test_config(), "true", Duration::ZERO).await {
+            panic!("execution failed: {e}");
+        }
+        let metrics = pool.metrics().await;
+        assert_eq!(metrics.cold_boots, 1);
+        assert_eq!(metrics.hits + metrics.misses, 0, "cold boots are not pool lookups");
+        assert_eq!(pool.backend().call_count(MockOperation::ExecuteCommand).await, 1);
+    }
+
+    #[test]
+    fn pool_metrics_mean_restore_latency() {
+        let mut metrics = PoolMetrics::default();
+        assert_eq!(metrics.mean_restore_latency(), None);
+        metrics.record_restore(Duration::from_millis(10));
+        metrics.record_restore(Duration::from_millis(30));
+        assert_eq!(metrics.mean_restore_latency(), Some(Duration::from_millis(20)));
+        assert_eq!(metrics.max_restore_latency, Duration::from_millis(30));
+    }
+}
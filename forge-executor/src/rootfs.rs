@@ -0,0 +1,173 @@
+//! Root filesystem image extraction.
+//!
+//! Backends that run the guest from a host directory rather than a block
+//! device (libkrun, process sandboxes) unpack the ext4 image referenced by
+//! [`VmConfig::rootfs_path`](crate::VmConfig::rootfs_path) with `debugfs`
+//! from e2fsprogs, which reads the image without mounting it and so needs
+//! no privileges. The store layers in
+//! [`VmConfig::layer_paths`](crate::VmConfig::layer_paths) are unpacked
+//! into the tree's `/nix/store`, where a VM's init would overlay them.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+use crate::host::which_binary;
+use crate::ExecutorError;
+
+/// Extract the ext4 image at `image` into the directory `dest`.
+///
+/// `dest` is created if missing; existing entries are overwritten.
+///
+/// # Errors
+/// Returns [`ExecutorError::BinaryNotFound`] if `debugfs` is not installed.
+/// Returns [`ExecutorError::SpawnFailed`] if the image is missing or cannot
+/// be read.
+pub async fn extract_rootfs(image: &Path, dest: &Path) -> Result<(), ExecutorError> {
+    which_binary(Path::new("debugfs"))?;
+
+    if !image.exists() {
+        return Err(ExecutorError::SpawnFailed(format!(
+            "rootfs image not found: {}",
+            image.display()
+        )));
+    }
+    tokio::fs::create_dir_all(dest).await?;
+
+    let output = Command::new("debugfs")
+        .arg("-R")
+        .arg(format!("rdump / {}", dest.display()))
+        .arg(image)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .map_err(|e| ExecutorError::SpawnFailed(format!("exec debugfs: {e}")))?;
+
+    // debugfs exits 0 even when a request fails; errors are only reported on
+    // stderr, after the version banner.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(line) =
+        stderr.lines().find(|l| l.starts_with("rdump:") || l.starts_with("debugfs:"))
+    {
+        return Err(ExecutorError::SpawnFailed(format!("extract {}: {line}", image.display())));
+    }
+
+    tracing::info!(image = %image.display(), dest = %dest.display(), "rootfs extracted");
+
+    Ok(())
+}
+
+/// Extract the rootfs image at `image` into `dest`, then each of the ext4
+/// store `layers` into `dest/nix/store`.
+///
+/// # Errors
+/// See [`extract_rootfs`].
+pub async fn extract_layered_rootfs(
+    image: &Path,
+    layers: &[PathBuf],
+    dest: &Path,
+) -> Result<(), ExecutorError> {
+    extract_rootfs(image, dest).await?;
+    let store = dest.join("nix/store");
+    for layer in layers {
+        extract_rootfs(layer, &store).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("forge-rootfs-test-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn extract_rootfs_missing_image_is_error() {
+        let dir = temp_dir();
+        let result = extract_rootfs(&dir.join("missing.ext4"), &dir.join("root")).await;
+        assert!(
+            matches!(
+                result,
+                Err(ExecutorError::SpawnFailed(_) | ExecutorError::BinaryNotFound { .. })
+            ),
+            "missing image must fail, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires e2fsprogs"]
+    async fn extract_rootfs_unpacks_image_contents() {
+        let dir = temp_dir();
+        let src = dir.join("src");
+        tokio::fs::create_dir_all(src.join("etc")).await.unwrap_or_else(|e| panic!("mkdir: {e}"));
+        tokio::fs::write(src.join("etc/hostname"), b"forge\n")
+            .await
+            .unwrap_or_else(|e| panic!("write: {e}"));
+
+        let image = dir.join("rootfs.ext4");
+        mke2fs(&src, &image).await;
+
+        let root = dir.join("root");
+        extract_rootfs(&image, &root).await.unwrap_or_else(|e| panic!("extract failed: {e}"));
+
+        let hostname = tokio::fs::read(root.join("etc/hostname"))
+            .await
+            .unwrap_or_else(|e| panic!("read extracted file: {e}"));
+        assert_eq!(hostname, b"forge\n", "extracted file must match the image contents");
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    /// Pack `dir` into the ext4 image `image`.
+    async fn mke2fs(dir: &Path, image: &Path) {
+        let status = Command::new("mke2fs")
+            .args(["-q", "-t", "ext4", "-d"])
+            .arg(dir)
+            .arg(image)
+            .arg("4M")
+            .status()
+            .await
+            .unwrap_or_else(|e| panic!("exec mke2fs: {e}"));
+        assert!(status.success(), "mke2fs must build the test image");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires e2fsprogs"]
+    async fn extract_layered_rootfs_places_layers_in_the_store() {
+        let dir = temp_dir();
+        let (src, layer_src) = (dir.join("src"), dir.join("layer"));
+        for path in [src.join("nix/store"), layer_src.join("abc-lib")] {
+            tokio::fs::create_dir_all(path).await.unwrap_or_else(|e| panic!("mkdir: {e}"));
+        }
+        tokio::fs::write(layer_src.join("abc-lib/lib.so"), b"lib\n")
+            .await
+            .unwrap_or_else(|e| panic!("write: {e}"));
+        let (image, layer) = (dir.join("rootfs.ext4"), dir.join("layer.ext4"));
+        mke2fs(&src, &image).await;
+        mke2fs(&layer_src, &layer).await;
+
+        let root = dir.join("root");
+        extract_layered_rootfs(&image, &[layer], &root)
+            .await
+            .unwrap_or_else(|e| panic!("extract failed: {e}"));
+
+        let lib = tokio::fs::read(root.join("nix/store/abc-lib/lib.so"))
+            .await
+            .unwrap_or_else(|e| panic!("read extracted layer: {e}"));
+        assert_eq!(lib, b"lib\n", "layer contents must land in the store");
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
@@ -2,31 +2,55 @@
 //!
 //! The runner embeds the block's command in the kernel boot args, boots a
 //! Firecracker microVM, captures serial console output, and computes a
-//! SHA-256 `output_hash` for determinism verification.
+//! versioned SHA-256 `output_hash` (see [`crate::hashing`]) for determinism
+//! verification.
 //!
 //! See `docs/ARCHITECTURE.md` §3 for design rationale.
 
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use chrono::Utc;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use sha2::{Digest, Sha256};
 
 use forge_core::block::Block;
-use forge_core::execution::{ExecutionRecord, ExecutionStatus};
+use forge_core::execution::{Artifact, ExecutionRecord, ExecutionStatus, HashScheme};
 use forge_core::id::{ContentHash, UserId};
 
-use crate::{ExecutorError, VmConfig, VmmBackend};
+use crate::artifacts::ArtifactStore;
+use crate::backend::ExecutionOutput;
+use crate::cache::{CacheKey, ExecutionCache};
+use crate::canonical::canonicalize;
+use crate::determinism::{BootKind, BootMode, DeterminismReport};
+use crate::guest::{collect_outputs_command, is_collectable_path, split_collected_outputs};
+use crate::hashing::{hash_input, hash_output, FileDigest};
+use crate::hermetic::{guest_environment, hermetic_command};
+use crate::pipeline::{stage_input, Pipeline, PipelineExecution};
+use crate::store::ExecutionStore;
+use crate::{ExecutorError, SnapshotId, VmConfig, VmmBackend};
 
 /// Default execution timeout: 30 seconds per VM run.
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How long the base VM for restored verification runs boots before it is
+/// snapshotted.
+const SNAPSHOT_BOOT_SETTLE: Duration = Duration::from_secs(1);
+
 /// Executes a block inside a microVM and captures the output.
 ///
 /// The runner:
-/// 1. Spawns a VM using the configured backend with the command in boot args
+/// 1. Spawns a VM using the configured backend with the command in boot args,
+///    after checking the kernel and rootfs against the hashes the
+///    [`VmConfig`] names them by, if any. The command runs in the fixed
+///    environment [`crate::hermetic`] describes, seeded from the input hash
 /// 2. Captures serial console output (stdout)
-/// 3. Computes `output_hash` (SHA-256 of captured output)
-/// 4. Records an [`ExecutionRecord`]
+/// 3. Collects the files the manifest declares as outputs, storing them in
+///    the [`ArtifactStore`] if one is attached
+/// 4. Computes `output_hash` (SHA-256 of captured output and files) and, if
+///    the manifest declares canonicalisation rules, `canonical_output_hash`
+/// 5. Records an [`ExecutionRecord`], including the guest environment,
+///    feeding it to the [`ExecutionStore`] if one is attached
 ///
 /// # Cancel Safety
 /// Cancel safe. Dropping the future will terminate the VM process via
@@ -35,19 +59,63 @@ pub struct BlockRunner<B: VmmBackend> {
     backend: B,
     vm_config: VmConfig,
     timeout: Duration,
+    store: Option<Arc<dyn ExecutionStore>>,
+    artifacts: Option<ArtifactStore>,
+    cache: Option<Arc<ExecutionCache>>,
 }
 
 impl<B: VmmBackend> BlockRunner<B> {
     /// Create a new runner with the given backend and VM configuration.
     #[must_use]
     pub const fn new(backend: B, vm_config: VmConfig) -> Self {
-        Self { backend, vm_config, timeout: DEFAULT_TIMEOUT }
+        Self {
+            backend,
+            vm_config,
+            timeout: DEFAULT_TIMEOUT,
+            store: None,
+            artifacts: None,
+            cache: None,
+        }
     }
 
     /// Create a runner with a custom execution timeout.
     #[must_use]
     pub const fn with_timeout(backend: B, vm_config: VmConfig, timeout: Duration) -> Self {
-        Self { backend, vm_config, timeout }
+        Self { backend, vm_config, timeout, store: None, artifacts: None, cache: None }
+    }
+
+    /// Return the backend VMs are started with.
+    #[must_use]
+    pub const fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Feed every execution record and determinism report into `store`.
+    #[must_use]
+    pub fn with_store(mut self, store: Arc<dyn ExecutionStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Keep the files blocks produce in `artifacts`.
+    ///
+    /// Without an artifact store, declared outputs are still collected and
+    /// hashed, but their contents are discarded.
+    #[must_use]
+    pub fn with_artifact_store(mut self, artifacts: ArtifactStore) -> Self {
+        self.artifacts = Some(artifacts);
+        self
+    }
+
+    /// Serve [`Self::execute`] from `cache` when the same block, input and
+    /// environment have run before.
+    ///
+    /// Determinism verification always runs; it exists to check that the
+    /// cache's premise holds.
+    #[must_use]
+    pub fn with_cache(mut self, cache: Arc<ExecutionCache>) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
     /// Execute a block and return the execution record.
@@ -55,41 +123,356 @@ pub const fn with_timeout(backend: B, vm_config: VmConfig, timeout: Duration) ->
     /// The block's `manifest.name` is used as the command to run inside the VM.
     /// For the MVP, the command is `echo <block-name>` to prove determinism.
     ///
+    /// With an [`ExecutionCache`] attached, a previous record for the same
+    /// [`CacheKey`] is returned marked [`cached`](ExecutionRecord::cached)
+    /// without booting a VM, as long as its artifacts are still in the
+    /// attached [`ArtifactStore`]. Cache hits are not fed to the
+    /// [`ExecutionStore`]; no execution took place.
+    ///
     /// # Errors
-    /// Returns [`ExecutorError::SpawnFailed`] if the VM cannot start.
-    /// Returns [`ExecutorError::Io`] on timeout or I/O failure.
+    /// Returns [`ExecutorError::SpawnFailed`] if the VM cannot start, and
+    /// [`ExecutorError::ImageHashMismatch`] if a content-addressed kernel
+    /// or rootfs image has changed.
+    /// Returns [`ExecutorError::Io`] on timeout or I/O failure, or if the
+    /// kernel, rootfs or a layer image cannot be hashed for the cache key.
+    /// Returns [`ExecutorError::InvalidCanonicalization`] if a manifest rule
+    /// cannot be applied, and [`ExecutorError::InvalidOutputPath`] if a
+    /// declared output path cannot be collected.
+    /// Propagates errors from the attached [`ExecutionStore`] and
+    /// [`ArtifactStore`].
     pub async fn execute(
         &self,
         block: &Block,
         input: &[u8],
     ) -> Result<ExecutionRecord, ExecutorError> {
-        let input_hash = compute_hash(input, b"");
+        let cached = match &self.cache {
+            Some(cache) => {
+                let input_hash = hash_input(HashScheme::CURRENT, input)?;
+                let key =
+                    cache.key(block, input_hash, &self.vm_config, self.backend.isolation()).await?;
+                if let Some(record) = self.cache_hit(cache, &key).await? {
+                    tracing::info!(block = %block.manifest.name, execution = %record.id, "cache hit");
+                    return Ok(record);
+                }
+                Some((cache, key))
+            }
+            None => None,
+        };
+
+        let (record, _) = self.run_once(block, input, None).await?;
+        if let Some(store) = &self.store {
+            store.record_execution(&record).await?;
+        }
+        if let Some((cache, key)) = cached {
+            cache.insert(key, record.clone()).await?;
+        }
+        Ok(record)
+    }
+
+    /// The cached record for `key`, if there is one whose artifacts are all
+    /// still stored. Entries with missing artifacts are invalidated.
+    async fn cache_hit(
+        &self,
+        cache: &ExecutionCache,
+        key: &CacheKey,
+    ) -> Result<Option<ExecutionRecord>, ExecutorError> {
+        let Some(record) = cache.get(key).await else {
+            return Ok(None);
+        };
+        if let Some(artifacts) = &self.artifacts {
+            for artifact in &record.artifacts {
+                if !artifacts.contains(&artifact.hash).await? {
+                    tracing::warn!(
+                        execution = %record.id,
+                        hash = %artifact.hash,
+                        "cached artifact missing from store, re-running"
+                    );
+                    cache.invalidate(key).await;
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(record))
+    }
+
+    /// Execute a block `runs` times on fresh VMs and report whether every
+    /// run produced the same output.
+    ///
+    /// Equivalent to [`Self::verify_determinism_with`] using
+    /// [`BootMode::Fresh`].
+    ///
+    /// # Errors
+    /// See [`Self::verify_determinism_with`].
+    pub async fn verify_determinism(
+        &self,
+        block: &Block,
+        input: &[u8],
+        runs: usize,
+    ) -> Result<DeterminismReport, ExecutorError> {
+        self.verify_determinism_with(block, input, runs, BootMode::Fresh).await
+    }
+
+    /// Execute a block `runs` times, starting each VM as `mode` dictates,
+    /// and report whether every run produced the same output.
+    ///
+    /// Restored runs all restore one snapshot of a base VM booted with a
+    /// shell as init (see [`VmConfig::init_args`]), taken before the first
+    /// run and deleted afterwards, so they exercise the same guest state a
+    /// warm pool hands out.
+    ///
+    /// Every run's record and the finished report are fed to the attached
+    /// [`ExecutionStore`]. A divergent block is not an error; check
+    /// [`DeterminismReport::is_deterministic`].
+    ///
+    /// # Errors
+    /// Returns the first execution, snapshot or restore failure; runs that
+    /// completed before it are not stored. Returns
+    /// [`ExecutorError::Unsupported`] for restored runs on backends without
+    /// snapshots. Propagates errors from the attached [`ExecutionStore`].
+    pub async fn verify_determinism_with(
+        &self,
+        block: &Block,
+        input: &[u8],
+        runs: usize,
+        mode: BootMode,
+    ) -> Result<DeterminismReport, ExecutorError> {
+        tracing::info!(block = %block.manifest.name, runs, ?mode, "verifying determinism");
+
+        let base_snapshot =
+            if mode.needs_snapshot(runs) { Some(self.base_snapshot().await?) } else { None };
+
+        let mut report = DeterminismReport::new(block.id, hash_input(HashScheme::CURRENT, input)?);
+        let mut failure = None;
+        for index in 0..runs {
+            let boot = mode.boot_for(index);
+            let restore_from = base_snapshot.as_ref().filter(|_| boot == BootKind::Restored);
+            match self.run_once(block, input, restore_from).await {
+                Ok((record, output)) => {
+                    report.push(boot, record, &output.stdout, &output.stderr, output.exit_code);
+                }
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(snapshot_id) = base_snapshot {
+            match self.backend.delete_snapshot(&snapshot_id).await {
+                Ok(()) | Err(ExecutorError::Unsupported(_)) => {}
+                Err(e) => {
+                    tracing::warn!(%snapshot_id, error = %e, "failed to delete base snapshot");
+                }
+            }
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+
+        tracing::info!(
+            block = %block.manifest.name,
+            deterministic = report.is_deterministic(),
+            distinct_outputs = report.outputs.len(),
+            "determinism verification complete"
+        );
+
+        for divergence in report.diagnose() {
+            tracing::warn!(
+                block = %block.manifest.name,
+                output_hash = %divergence.output_hash,
+                runs = ?divergence.runs,
+                stdout_offset = divergence.stdout.as_ref().map(|d| d.first_difference),
+                stderr_offset = divergence.stderr.as_ref().map(|d| d.first_difference),
+                hints = ?divergence.hints,
+                "divergent output"
+            );
+        }
+
+        if let Some(store) = &self.store {
+            for run in &report.runs {
+                store.record_execution(&run.record).await?;
+            }
+            store.record_determinism(&report).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// Execute the stages of the composite block `composite`, resolved from
+    /// `blocks`, as a pipeline.
+    ///
+    /// Each stage starts once every stage it depends on has finished; see
+    /// [`crate::pipeline`] for how dependencies and stage inputs are
+    /// derived. Stages always run, bypassing the [`ExecutionCache`], because
+    /// their outputs are piped onward and the cache keeps only records.
+    ///
+    /// Every stage record and the aggregate record are fed to the attached
+    /// [`ExecutionStore`].
+    ///
+    /// # Errors
+    /// Returns [`ExecutorError::InvalidPipeline`] if the stages cannot be
+    /// resolved or ordered. Returns the first stage failure, dropping (and
+    /// so terminating) stages still running; records of stages that
+    /// completed before it have already been stored. Propagates errors from
+    /// the attached [`ExecutionStore`] and [`ArtifactStore`].
+    pub async fn execute_pipeline(
+        &self,
+        composite: &Block,
+        blocks: &[Block],
+        input: &[u8],
+    ) -> Result<PipelineExecution, ExecutorError> {
+        let pipeline = Pipeline::new(composite, blocks)?;
+        let stages = pipeline.stages();
+        tracing::info!(block = %composite.manifest.name, stages = stages.len(), "starting pipeline");
+        let started_at = Utc::now();
+        let wall_start = Instant::now();
+
+        let run_stage = |index: usize, stage_input: Vec<u8>| async move {
+            (index, self.run_once(stages[index], &stage_input, None).await)
+        };
+        let mut waiting: Vec<usize> =
+            (0..stages.len()).map(|index| pipeline.upstream(index).len()).collect();
+        let mut running: FuturesUnordered<_> = (0..stages.len())
+            .filter(|&index| waiting[index] == 0)
+            .map(|index| run_stage(index, input.to_vec()))
+            .collect();
+
+        let mut finished: Vec<Option<(ExecutionRecord, ExecutionOutput)>> =
+            vec![None; stages.len()];
+        while let Some((index, result)) = running.next().await {
+            let (record, output) = result?;
+            if let Some(store) = &self.store {
+                store.record_execution(&record).await?;
+            }
+            finished[index] = Some((record, output));
+
+            for &next in pipeline.downstream(index) {
+                waiting[next] -= 1;
+                if waiting[next] == 0 {
+                    let next_input = piped_input(&pipeline, &finished, pipeline.upstream(next));
+                    running.push(run_stage(next, next_input));
+                }
+            }
+        }
+
+        let output = ExecutionOutput {
+            stdout: piped_input(&pipeline, &finished, &pipeline.sinks()),
+            stderr: Vec::new(),
+            exit_code: 0,
+        };
+        let stage_records: Vec<ExecutionRecord> =
+            finished.into_iter().flatten().map(|(record, _)| record).collect();
+        let record = ExecutionRecord::new(
+            composite.id,
+            UserId::new("forge-runner"),
+            hash_input(HashScheme::CURRENT, input)?,
+            hash_output(HashScheme::CURRENT, &output, &[])?,
+            started_at,
+            wall_start.elapsed(),
+            ExecutionStatus::Succeeded,
+        )
+        .with_hash_scheme(HashScheme::CURRENT)
+        .with_children(stage_records.iter().map(|record| record.id).collect())
+        .with_isolation(self.backend.isolation())
+        .with_image_hashes(self.vm_config.kernel_hash, self.vm_config.rootfs_hash)
+        .with_cpu_template_hash(self.vm_config.cpu_template_hash().await?);
+
+        tracing::info!(
+            block = %composite.manifest.name,
+            output_hash = %record.output_hash,
+            elapsed_ms = record.duration.as_millis(),
+            "pipeline complete"
+        );
+        if let Some(store) = &self.store {
+            store.record_execution(&record).await?;
+        }
+        Ok(PipelineExecution { record, stages: stage_records })
+    }
+
+    /// Boot a base VM with a shell as init and snapshot it.
+    async fn base_snapshot(&self) -> Result<SnapshotId, ExecutorError> {
+        let mut base_config = self.vm_config.clone();
+        let init = self.vm_config.init_args("/bin/sh");
+        base_config.boot_args = format!("{} {init}", self.vm_config.boot_args);
+
+        self.vm_config.verify_images().await?;
+        let base = self.backend.spawn(&base_config).await?;
+        tokio::time::sleep(SNAPSHOT_BOOT_SETTLE).await;
+        let snapshot = self.backend.snapshot(&base).await;
+        self.backend.terminate(base).await?;
+        snapshot
+    }
+
+    /// Execute a block once, in a fresh VM or one restored from
+    /// `restore_from`, and build its record. Returns the canonicalised
+    /// output.
+    async fn run_once(
+        &self,
+        block: &Block,
+        input: &[u8],
+        restore_from: Option<&SnapshotId>,
+    ) -> Result<(ExecutionRecord, ExecutionOutput), ExecutorError> {
+        let input_hash = hash_input(HashScheme::CURRENT, input)?;
         let started_at = Utc::now();
         let wall_start = Instant::now();
 
         // Use the block name as the command for MVP determinism proof.
         // A real implementation would look up the block's Nix derivation.
-        let command = build_command(&block.manifest.name);
+        let outputs = &block.manifest.outputs;
+        let command = if outputs.is_empty() {
+            build_command(&block.manifest.name)
+        } else {
+            if let Some(path) = outputs.iter().find(|path| !is_collectable_path(path)) {
+                return Err(ExecutorError::InvalidOutputPath(path.clone()));
+            }
+            collect_outputs_command(&build_command(&block.manifest.name), outputs)
+        };
+        let environment = guest_environment(&input_hash, self.backend.isolation());
+        let command = hermetic_command(&environment, &command);
 
         tracing::info!(
             block = %block.manifest.name,
             %command,
+            restored = restore_from.is_some(),
             "starting block execution"
         );
 
-        let output = self.backend.execute_command(&self.vm_config, &command, self.timeout).await?;
+        let cpu_template_hash = self.vm_config.cpu_template_hash().await?;
+        let mut output = if let Some(snapshot_id) = restore_from {
+            let mut handle = self.backend.restore(snapshot_id).await?;
+            let result = self.backend.execute_in_vm(&mut handle, &command, self.timeout).await;
+            let vm_id = handle.id;
+            if let Err(e) = self.backend.terminate(handle).await {
+                tracing::warn!(%vm_id, error = %e, "failed to terminate restored VM");
+            }
+            result?
+        } else {
+            match &self.cache {
+                Some(cache) => self.vm_config.verify_images_with(cache.image_hashes()).await?,
+                None => self.vm_config.verify_images().await?,
+            }
+            self.backend.execute_command(&self.vm_config, &command, self.timeout).await?
+        };
 
         let duration = wall_start.elapsed();
-        let output_hash = compute_hash(&output.stdout, &output.stderr);
+        let artifacts = self.store_outputs(block, &mut output).await?;
+        let files: Vec<_> =
+            artifacts.iter().map(|a| FileDigest { path: a.path.clone(), sha256: a.hash }).collect();
+
+        let output_hash = hash_output(HashScheme::CURRENT, &output, &files)?;
+        let rules = &block.manifest.canonicalization;
+        let canonical = canonicalize(rules, &output)?;
+        let canonical_hash = hash_output(HashScheme::CURRENT, &canonical, &files)?;
 
         tracing::info!(
             block = %block.manifest.name,
             output_hash = %output_hash,
+            canonical_hash = %canonical_hash,
             elapsed_ms = duration.as_millis(),
             "block execution complete"
         );
 
-        Ok(ExecutionRecord::new(
+        let mut record = ExecutionRecord::new(
             block.id,
             UserId::new("forge-runner"),
             input_hash,
@@ -97,14 +480,73 @@ pub async fn execute(
             started_at,
             duration,
             ExecutionStatus::Succeeded,
-        ))
+        )
+        .with_hash_scheme(HashScheme::CURRENT)
+        .with_artifacts(artifacts)
+        .with_isolation(self.backend.isolation())
+        .with_image_hashes(self.vm_config.kernel_hash, self.vm_config.rootfs_hash)
+        .with_cpu_template_hash(cpu_template_hash)
+        .with_guest_environment(environment);
+        if !rules.is_empty() {
+            record = record.with_canonical_output_hash(canonical_hash);
+        }
+
+        Ok((record, canonical))
+    }
+
+    /// Take the files collected for `block` off `output.stdout`, store them
+    /// if an artifact store is attached, and describe them in manifest order.
+    async fn store_outputs(
+        &self,
+        block: &Block,
+        output: &mut ExecutionOutput,
+    ) -> Result<Vec<Artifact>, ExecutorError> {
+        if block.manifest.outputs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut collected = split_collected_outputs(&mut output.stdout);
+        let mut artifacts = Vec::with_capacity(collected.len());
+        for path in &block.manifest.outputs {
+            let Some(index) = collected.iter().position(|(p, _)| p == path) else {
+                tracing::warn!(block = %block.manifest.name, %path, "declared output not produced");
+                continue;
+            };
+            let (path, contents) = collected.swap_remove(index);
+            let hash = match &self.artifacts {
+                Some(store) => store.put(&contents).await?,
+                None => ContentHash::new(Sha256::digest(&contents).into()),
+            };
+            artifacts.push(Artifact::new(path, hash, contents.len() as u64));
+        }
+        Ok(artifacts)
     }
 }
 
+/// Encode the outputs of the finished stages `from` with [`stage_input`].
+fn piped_input(
+    pipeline: &Pipeline<'_>,
+    finished: &[Option<(ExecutionRecord, ExecutionOutput)>],
+    from: &[usize],
+) -> Vec<u8> {
+    let upstream: Vec<_> = from
+        .iter()
+        .filter_map(|&index| {
+            let (record, output) = finished[index].as_ref()?;
+            Some((pipeline.stages()[index], record, output))
+        })
+        .collect();
+    stage_input(&upstream)
+}
+
 /// Compute SHA-256 hash of stdout + stderr concatenated.
 ///
 /// `S(output) = SHA-256(stdout || stderr)`
 ///
+/// This is [`HashScheme::Legacy`], kept for verifying historical records.
+/// New records are hashed with [`hash_output`] under
+/// [`HashScheme::CURRENT`].
+///
 /// # Complexity
 /// O(n) where n = len(stdout) + len(stderr).
 #[must_use]
@@ -162,6 +604,513 @@ fn compute_hash_empty_input_is_sha256_of_empty() {
         );
     }
 
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn execute_records_backend_output_isolation_and_cpu_template() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+        use forge_core::execution::IsolationLevel;
+
+        use crate::config::{CpuTemplate, StaticCpuTemplate};
+        use crate::mock::{MockBackend, MockResponse};
+
+        let block = &example_blocks()[0];
+        let command = build_command(&block.manifest.name);
+        let backend = MockBackend::new()
+            .on_command(command, MockResponse::success("v1\n"))
+            .with_isolation(IsolationLevel::ProcessSandbox);
+        let template = CpuTemplate::Static(StaticCpuTemplate::T2);
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"))
+            .with_cpu_template(template.clone());
+        let runner = BlockRunner::new(backend, config);
+
+        let record = match runner.execute(block, b"").await {
+            Ok(r) => r,
+            Err(e) => panic!("execution failed: {e}"),
+        };
+        let output = ExecutionOutput { stdout: b"v1\n".to_vec(), stderr: Vec::new(), exit_code: 0 };
+        assert_eq!(record.hash_scheme, HashScheme::CURRENT);
+        assert_eq!(
+            record.output_hash,
+            hash_output(HashScheme::CURRENT, &output, &[])
+                .unwrap_or_else(|e| panic!("hash failed: {e}"))
+        );
+        assert_eq!(record.isolation, IsolationLevel::ProcessSandbox);
+        assert_eq!(record.cpu_template_hash, template.hash().await.ok());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn guests_run_hermetically_with_a_seed_from_the_input() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+        use forge_core::execution::IsolationLevel;
+
+        use crate::hermetic::GUEST_EPOCH;
+        use crate::mock::MockBackend;
+
+        let block = &example_blocks()[0];
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let runner = BlockRunner::new(MockBackend::new(), config);
+
+        let mut seeds = Vec::new();
+        for input in [b"a", b"a", b"b"] {
+            let record = runner.execute(block, input).await.unwrap_or_else(|e| panic!("{e}"));
+            let Some(guest) = record.guest else {
+                panic!("the guest environment must be recorded");
+            };
+            assert_eq!(guest, guest_environment(&record.input_hash, IsolationLevel::MicroVm));
+            assert_eq!(guest.epoch, GUEST_EPOCH);
+            assert!(guest.clock_set, "a microVM's clock must be set");
+            seeds.push(guest.seed);
+        }
+        assert_eq!(seeds[0], seeds[1], "equal inputs must get equal seeds");
+        assert_ne!(seeds[0], seeds[2], "different inputs must get different seeds");
+
+        let commands = runner.backend.commands().await;
+        let expected = hermetic_command(
+            &guest_environment(
+                &hash_input(HashScheme::CURRENT, b"a")
+                    .unwrap_or_else(|e| panic!("hash failed: {e}")),
+                IsolationLevel::MicroVm,
+            ),
+            &build_command(&block.manifest.name),
+        );
+        assert_eq!(commands[0], expected, "the command must run behind the preamble");
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn verify_determinism_reports_divergence_and_feeds_store() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+
+        use crate::mock::{MockBackend, MockOperation, MockResponse};
+        use crate::store::InMemoryExecutionStore;
+
+        let block = &example_blocks()[0];
+        let command = build_command(&block.manifest.name);
+        let backend = MockBackend::new()
+            .on_command(command.clone(), MockResponse::success("same\n"))
+            .on_command(command.clone(), MockResponse::success("same\n"))
+            .on_command(command, MockResponse::success("drift\n"));
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let store = Arc::new(InMemoryExecutionStore::new());
+        let runner = BlockRunner::new(backend, config).with_store(store.clone());
+
+        let report =
+            match runner.verify_determinism_with(block, b"", 4, BootMode::Alternating).await {
+                Ok(r) => r,
+                Err(e) => panic!("verification failed: {e}"),
+            };
+
+        assert!(!report.is_deterministic(), "drifting output must be reported");
+        let boots: Vec<_> = report.runs.iter().map(|r| r.boot).collect();
+        assert_eq!(
+            boots,
+            [BootKind::Fresh, BootKind::Restored, BootKind::Fresh, BootKind::Restored]
+        );
+        assert_eq!(report.outputs[0].runs, [0, 1]);
+        assert_eq!(report.divergent()[0].stdout, b"drift\n");
+        assert_eq!(report.divergent()[0].runs, [2, 3]);
+        let diagnosis = report.diagnose();
+        assert_eq!(diagnosis.len(), 1);
+        assert_eq!(diagnosis[0].stdout.as_ref().map(|d| d.first_difference), Some(0));
+
+        assert_eq!(runner.backend.call_count(MockOperation::Restore).await, 2);
+        assert_eq!(runner.backend.live_vm_count().await, 0, "every VM must be terminated");
+        assert!(runner.backend.snapshots().await.is_empty(), "base snapshot must be deleted");
+
+        let executions =
+            store.executions(block.id).await.unwrap_or_else(|e| panic!("read failed: {e}"));
+        assert_eq!(executions.len(), 4);
+        let reports = store
+            .determinism_reports(block.id)
+            .await
+            .unwrap_or_else(|e| panic!("read failed: {e}"));
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].outputs, report.outputs);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn verify_determinism_on_fresh_boots_needs_no_snapshot() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+
+        use crate::mock::{MockBackend, MockOperation};
+
+        let block = &example_blocks()[0];
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let runner = BlockRunner::new(MockBackend::new(), config);
+
+        let report = match runner.verify_determinism(block, b"", 3).await {
+            Ok(r) => r,
+            Err(e) => panic!("verification failed: {e}"),
+        };
+        assert!(report.is_deterministic());
+        assert_eq!(report.runs.len(), 3);
+        assert_eq!(runner.backend.call_count(MockOperation::Snapshot).await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn restored_runs_keep_their_output_when_terminate_fails() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+
+        use crate::mock::{MockBackend, MockOperation};
+
+        // The base VM terminates; every restored run's VM then fails to.
+        let backend = MockBackend::new().fail_operation_after(MockOperation::Terminate, 1, || {
+            ExecutorError::SpawnFailed("terminate failed".to_owned())
+        });
+        let block = &example_blocks()[0];
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let runner = BlockRunner::new(backend, config);
+
+        let report = runner
+            .verify_determinism_with(block, b"", 2, BootMode::Restored)
+            .await
+            .unwrap_or_else(|e| panic!("a terminate failure must not fail the run: {e}"));
+        assert_eq!(report.runs.len(), 2);
+        assert_eq!(runner.backend.call_count(MockOperation::Terminate).await, 3);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn canonicalization_rules_decide_determinism_and_keep_raw_hash() {
+        use std::path::PathBuf;
+
+        use forge_core::block::CanonicalizationRule;
+        use forge_core::examples::example_blocks;
+
+        use crate::mock::{MockBackend, MockResponse};
+
+        let mut block = example_blocks()[0].clone();
+        block.manifest.canonicalization = vec![CanonicalizationRule::Redact {
+            pattern: r"at \d+".to_owned(),
+            replacement: "at <time>".to_owned(),
+        }];
+        let command = build_command(&block.manifest.name);
+        let backend = MockBackend::new()
+            .on_command(command.clone(), MockResponse::success("built at 1700000001\n"))
+            .on_command(command, MockResponse::success("built at 1700000002\n"));
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let runner = BlockRunner::new(backend, config);
+
+        let report = match runner.verify_determinism(&block, b"", 2).await {
+            Ok(r) => r,
+            Err(e) => panic!("verification failed: {e}"),
+        };
+        assert!(report.is_deterministic(), "redacted timestamps must not break determinism");
+        let [first, second] = [&report.runs[0].record, &report.runs[1].record];
+        assert_ne!(first.output_hash, second.output_hash, "raw hashes must reflect raw output");
+        let canonical = ExecutionOutput {
+            stdout: b"built at <time>\n".to_vec(),
+            stderr: Vec::new(),
+            exit_code: 0,
+        };
+        assert_eq!(
+            first.canonical_output_hash,
+            Some(
+                hash_output(HashScheme::CURRENT, &canonical, &[])
+                    .unwrap_or_else(|e| panic!("hash failed: {e}"))
+            )
+        );
+        assert_eq!(report.outputs[0].stdout, b"built at <time>\n");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn declared_outputs_are_stored_hashed_and_listed() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+        use uuid::Uuid;
+
+        use crate::mock::{MockBackend, MockResponse};
+
+        let mut block = example_blocks()[0].clone();
+        block.manifest.outputs = vec!["/out/report".to_owned(), "/out/missing".to_owned()];
+        let stdout =
+            "log\n\nFORGE_OUTPUTS_START\nFORGE_FILE:/out/report\ncmVwb3J0\nFORGE_FILE_END\n";
+        let backend = MockBackend::new().with_fallback(MockResponse::success(stdout));
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let store = ArtifactStore::new(
+            std::env::temp_dir().join(format!("forge-runner-artifacts-{}", Uuid::new_v4())),
+        );
+        let runner = BlockRunner::new(backend, config).with_artifact_store(store.clone());
+
+        let record = match runner.execute(&block, b"").await {
+            Ok(r) => r,
+            Err(e) => panic!("execution failed: {e}"),
+        };
+        let report = FileDigest::of("/out/report", b"report");
+        assert_eq!(record.artifacts.len(), 1, "missing outputs must not be listed");
+        assert_eq!(record.artifacts[0].path, "/out/report");
+        assert_eq!(record.artifacts[0].hash, report.sha256);
+        assert_eq!(record.artifacts[0].size, 6);
+        let stored =
+            store.get(&report.sha256).await.unwrap_or_else(|e| panic!("artifact missing: {e}"));
+        assert_eq!(stored, b"report");
+
+        let output =
+            ExecutionOutput { stdout: b"log\n".to_vec(), stderr: Vec::new(), exit_code: 0 };
+        assert_eq!(
+            record.output_hash,
+            hash_output(HashScheme::CURRENT, &output, &[report])
+                .unwrap_or_else(|e| panic!("hash failed: {e}"))
+        );
+
+        block.manifest.outputs = vec!["/out/$(id)".to_owned()];
+        let result = runner.execute(&block, b"").await;
+        assert!(
+            matches!(&result, Err(ExecutorError::InvalidOutputPath(p)) if p == "/out/$(id)"),
+            "unquotable paths must be rejected, got {result:?}"
+        );
+
+        let _ = std::fs::remove_dir_all(store.dir());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn content_addressed_images_are_verified_and_recorded() {
+        use forge_core::examples::example_blocks;
+        use uuid::Uuid;
+
+        use crate::images::ImageStore;
+        use crate::mock::{MockBackend, MockOperation, MockResponse};
+
+        let dir = std::env::temp_dir().join(format!("forge-runner-images-{}", Uuid::new_v4()));
+        let images = ImageStore::new(dir.join("images"));
+        let import = |name: &'static str| {
+            let (source, images) = (dir.join(name), images.clone());
+            async move {
+                if let Err(e) = std::fs::write(&source, name) {
+                    panic!("source write failed: {e}");
+                }
+                images.import(&source).await.unwrap_or_else(|e| panic!("import failed: {e}"))
+            }
+        };
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            panic!("mkdir failed: {e}");
+        }
+        let (kernel, rootfs) = (import("vmlinux").await, import("rootfs.ext4").await);
+
+        let block = &example_blocks()[0];
+        let backend = MockBackend::new().with_fallback(MockResponse::success("ok\n"));
+        let runner = BlockRunner::new(backend, VmConfig::from_images(&images, kernel, rootfs));
+        let record =
+            runner.execute(block, b"").await.unwrap_or_else(|e| panic!("execution failed: {e}"));
+        assert_eq!((record.kernel_hash, record.rootfs_hash), (Some(kernel), Some(rootfs)));
+
+        if let Err(e) = std::fs::write(images.path(&rootfs), b"tampered") {
+            panic!("tamper failed: {e}");
+        }
+        let result = runner.execute(block, b"").await;
+        assert!(
+            matches!(result, Err(ExecutorError::ImageHashMismatch { .. })),
+            "a tampered rootfs must not boot, got {result:?}"
+        );
+        assert_eq!(runner.backend.call_count(MockOperation::ExecuteCommand).await, 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn cached_executions_skip_the_vm_until_invalidated() {
+        use forge_core::examples::example_blocks;
+        use uuid::Uuid;
+
+        use crate::mock::{MockBackend, MockOperation, MockResponse};
+
+        let dir = std::env::temp_dir().join(format!("forge-runner-cache-{}", Uuid::new_v4()));
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            panic!("mkdir failed: {e}");
+        }
+        let [kernel, rootfs, layer] =
+            ["vmlinux", "rootfs.ext4", "layer-0.img"].map(|name| dir.join(name));
+        let write = |path: &std::path::PathBuf, contents: &[u8]| {
+            if let Err(e) = std::fs::write(path, contents) {
+                panic!("{} write failed: {e}", path.display());
+            }
+        };
+        write(&kernel, b"kernel");
+        write(&rootfs, b"rootfs");
+        write(&layer, b"layer");
+
+        let mut block = example_blocks()[0].clone();
+        block.manifest.outputs = vec!["/out/a".to_owned()];
+        let stdout = "\nFORGE_OUTPUTS_START\nFORGE_FILE:/out/a\nYQ==\nFORGE_FILE_END\n";
+        let backend = MockBackend::new().with_fallback(MockResponse::success(stdout));
+        let mut config = VmConfig::new(kernel.clone(), rootfs.clone());
+        config.layer_paths = vec![layer.clone()];
+        let artifacts = ArtifactStore::new(dir.join("artifacts"));
+        let cache = Arc::new(ExecutionCache::new(1 << 20));
+        let runner = BlockRunner::new(backend, config)
+            .with_artifact_store(artifacts.clone())
+            .with_cache(cache.clone());
+        let execute = |input: &'static [u8]| {
+            let (runner, block) = (&runner, &block);
+            async move {
+                runner.execute(block, input).await.unwrap_or_else(|e| panic!("execution: {e}"))
+            }
+        };
+
+        let first = execute(b"in").await;
+        let second = execute(b"in").await;
+        assert!(!first.cached);
+        assert!(second.cached, "a repeated execution must be served from the cache");
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.artifacts, first.artifacts);
+        assert!(!execute(b"other").await.cached, "a different input must miss");
+        assert_eq!(runner.backend.call_count(MockOperation::ExecuteCommand).await, 2);
+
+        if let Err(e) = std::fs::remove_file(artifacts.path(&first.artifacts[0].hash)) {
+            panic!("artifact removal failed: {e}");
+        }
+        assert!(!execute(b"in").await.cached, "missing artifacts must force a re-run");
+        assert!(execute(b"in").await.cached);
+
+        write(&kernel, b"patched kernel");
+        assert!(!execute(b"in").await.cached, "a different kernel must miss");
+        write(&rootfs, b"rebuilt rootfs");
+        assert!(!execute(b"in").await.cached, "a rootfs rebuilt in place must miss");
+        write(&layer, b"rebuilt layer");
+        assert!(!execute(b"in").await.cached, "a layer rebuilt in place must miss");
+        assert_eq!(runner.backend.call_count(MockOperation::ExecuteCommand).await, 6);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn cached_executions_are_kept_apart_by_isolation_level() {
+        use forge_core::examples::example_blocks;
+        use forge_core::execution::IsolationLevel;
+        use uuid::Uuid;
+
+        use crate::mock::MockBackend;
+
+        let dir = std::env::temp_dir().join(format!("forge-runner-isolation-{}", Uuid::new_v4()));
+        let [kernel, rootfs] = ["vmlinux", "rootfs.ext4"].map(|name| dir.join(name));
+        let written = std::fs::create_dir_all(&dir)
+            .and_then(|()| std::fs::write(&kernel, b"kernel"))
+            .and_then(|()| std::fs::write(&rootfs, b"rootfs"));
+        if let Err(e) = written {
+            panic!("image write failed: {e}");
+        }
+
+        let block = &example_blocks()[0];
+        let cache = Arc::new(ExecutionCache::new(1 << 20));
+        let mut cached = Vec::new();
+        for isolation in [IsolationLevel::MicroVm, IsolationLevel::ProcessSandbox] {
+            let backend = MockBackend::new().with_isolation(isolation);
+            let runner = BlockRunner::new(backend, VmConfig::new(kernel.clone(), rootfs.clone()))
+                .with_cache(cache.clone());
+            let record = runner
+                .execute(block, b"in")
+                .await
+                .unwrap_or_else(|e| panic!("execution failed: {e}"));
+            cached.push(record.cached);
+        }
+        assert_eq!(cached, [false, false], "a sandboxed run must not reuse a microVM record");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[cfg_attr(miri, ignore)]
+    async fn pipelines_pipe_outputs_and_run_branches_concurrently() {
+        use std::path::PathBuf;
+
+        use forge_core::examples::example_blocks;
+        use forge_core::id::BlockId;
+
+        use crate::mock::{MockBackend, MockResponse};
+        use crate::store::InMemoryExecutionStore;
+
+        // git-env ← rust-dev-env ← bose-search, plus an independent lint-env.
+        let mut blocks = example_blocks();
+        let mut lint = blocks[0].clone();
+        lint.id = BlockId::new();
+        lint.manifest.name = "lint-env".to_owned();
+        lint.manifest.provides[0].name = "lint".to_owned();
+        blocks.push(lint);
+        let mut composite = blocks[2].clone();
+        composite.id = BlockId::new();
+        composite.manifest.name = "search-stack".to_owned();
+        composite.composed_of = Some(blocks.iter().map(|b| b.id).collect());
+
+        let slow = Duration::from_secs(10);
+        let mut backend = MockBackend::new();
+        for (block, stdout) in blocks.iter().zip(["git\n", "rust\n", "search\n", "lint\n"]) {
+            let response = MockResponse::success(stdout);
+            let response = if block.manifest.requires.is_empty() {
+                response.with_latency(slow)
+            } else {
+                response
+            };
+            backend = backend.on_command(build_command(&block.manifest.name), response);
+        }
+        let config = VmConfig::new(PathBuf::from("/tmp/k"), PathBuf::from("/tmp/r"));
+        let store = Arc::new(InMemoryExecutionStore::new());
+        let runner = BlockRunner::new(backend, config).with_store(store.clone());
+
+        let start = tokio::time::Instant::now();
+        let execution = match runner.execute_pipeline(&composite, &blocks, b"in").await {
+            Ok(e) => e,
+            Err(e) => panic!("pipeline failed: {e}"),
+        };
+        assert_eq!(start.elapsed(), slow, "independent roots must run concurrently");
+
+        let stages = &execution.stages;
+        let ids: Vec<_> = stages.iter().map(|r| r.block_id).collect();
+        assert_eq!(ids, blocks.iter().map(|b| b.id).collect::<Vec<_>>());
+        assert_eq!(
+            stages[0].input_hash,
+            hash_input(HashScheme::CURRENT, b"in").unwrap_or_else(|e| panic!("hash failed: {e}"))
+        );
+        let git_output =
+            ExecutionOutput { stdout: b"git\n".to_vec(), stderr: Vec::new(), exit_code: 0 };
+        let piped = stage_input(&[(&blocks[0], &stages[0], &git_output)]);
+        assert_eq!(
+            stages[1].input_hash,
+            hash_input(HashScheme::CURRENT, &piped).unwrap_or_else(|e| panic!("hash failed: {e}"))
+        );
+
+        let record = &execution.record;
+        assert_eq!(record.block_id, composite.id);
+        assert_eq!(record.children, stages.iter().map(|r| r.id).collect::<Vec<_>>());
+        let output = |stdout: &[u8]| ExecutionOutput {
+            stdout: stdout.to_vec(),
+            stderr: Vec::new(),
+            exit_code: 0,
+        };
+        let sinks = stage_input(&[
+            (&blocks[2], &stages[2], &output(b"search\n")),
+            (&blocks[3], &stages[3], &output(b"lint\n")),
+        ]);
+        assert_eq!(
+            record.output_hash,
+            hash_output(HashScheme::CURRENT, &output(&sinks), &[])
+                .unwrap_or_else(|e| panic!("hash failed: {e}"))
+        );
+
+        let stored = store.executions(composite.id).await.unwrap_or_else(|e| panic!("read: {e}"));
+        assert_eq!(stored.len(), 1);
+        let stage_stored =
+            store.executions(blocks[1].id).await.unwrap_or_else(|e| panic!("read: {e}"));
+        assert_eq!(stage_stored.len(), 1);
+    }
+
     #[test]
     fn build_command_wraps_block_name() {
         let cmd = build_command("git-env");
@@ -0,0 +1,302 @@
+//! Unprivileged process-sandbox backend for hosts without KVM.
+//!
+//! Runs each guest as an ordinary host process confined by Linux namespaces
+//! (user, mount, PID, network and UTS) and rooted in a private copy of the
+//! [`VmConfig::rootfs_path`] image, using `unshare` from util-linux. No
+//! privileges are needed beyond unprivileged user namespaces, so developers
+//! and CI runners can exercise the full execution path without `/dev/kvm`.
+//!
+//! # Isolation
+//! The guest shares the host kernel, so this is weaker than a microVM: a
+//! kernel bug is a sandbox escape. Every execution is therefore labelled
+//! [`IsolationLevel::ProcessSandbox`] in its
+//! [`ExecutionRecord`](forge_core::execution::ExecutionRecord).
+//!
+//! Inside the sandbox the command runs as root of its user namespace, as
+//! PID 1 of a fresh PID namespace with its own `/proc`, with no network
+//! interfaces beyond a down loopback, with its own hostname, and with a
+//! cleared environment.
+//! `/dev` is not populated.
+//!
+//! [`VmConfig::kernel_path`], [`VmConfig::boot_args`], the CPU template and
+//! the CPU and memory sizing are ignored. Snapshots are unsupported.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use forge_core::execution::IsolationLevel;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::backend::{ExecutionOutput, VmmBackend};
+use crate::guest::{capture_script, parse_execution_output, read_to_end_within, run_on_console};
+use crate::host::which_binary;
+use crate::rootfs::extract_layered_rootfs;
+use crate::{ExecutorError, SnapshotId, VmConfig, VmHandle};
+
+/// `PATH` given to sandboxed commands.
+const SANDBOX_PATH: &str = "/usr/local/bin:/usr/bin:/bin:/usr/local/sbin:/usr/sbin:/sbin";
+
+/// Namespace process-sandbox backend.
+///
+/// Spawns one `unshare` process per sandbox, communicating with the guest
+/// shell over its standard streams.
+#[derive(Debug, Clone)]
+pub struct SandboxBackend {
+    /// Path to the `unshare` binary.
+    unshare_path: PathBuf,
+
+    /// Directory where per-sandbox root filesystems are extracted.
+    work_dir: PathBuf,
+}
+
+impl SandboxBackend {
+    /// Create a new backend with the given paths.
+    ///
+    /// # Arguments
+    /// - `unshare_path`: path to the util-linux `unshare` binary
+    /// - `work_dir`: directory for per-sandbox root filesystems (must be writable)
+    #[must_use]
+    pub const fn new(unshare_path: PathBuf, work_dir: PathBuf) -> Self {
+        Self { unshare_path, work_dir }
+    }
+
+    /// Create a backend using system defaults.
+    ///
+    /// Looks for `unshare` in `$PATH` and uses `/tmp/forge-sandbox` as the
+    /// work directory.
+    #[must_use]
+    pub fn with_defaults() -> Self {
+        Self::new(PathBuf::from("unshare"), PathBuf::from("/tmp/forge-sandbox"))
+    }
+
+    fn root_dir(&self, sandbox_id: Uuid) -> PathBuf {
+        self.work_dir.join(sandbox_id.to_string())
+    }
+
+    /// The `unshare` invocation that confines a process to `root`.
+    fn unshare_command(&self, root: &Path) -> Command {
+        let mut command = Command::new(&self.unshare_path);
+        command
+            .arg("--map-root-user")
+            .arg("--mount")
+            .arg("--pid")
+            .arg("--net")
+            .arg("--uts")
+            .arg("--kill-child")
+            .arg(format!("--root={}", root.display()))
+            // Mounted after the root change, so this is the sandbox's /proc.
+            .arg("--mount-proc")
+            .env_clear()
+            .env("PATH", SANDBOX_PATH)
+            .env("HOME", "/root")
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        command
+    }
+
+    /// Extract a private root filesystem for `sandbox_id` and build the
+    /// command that enters it.
+    async fn prepare(
+        &self,
+        config: &VmConfig,
+        sandbox_id: Uuid,
+    ) -> Result<(Command, PathBuf), ExecutorError> {
+        which_binary(&self.unshare_path)?;
+
+        let root = self.root_dir(sandbox_id);
+        let extracted = async {
+            extract_layered_rootfs(&config.rootfs_path, &config.layer_paths, &root).await?;
+            // Mount points and scratch space the sandbox relies on.
+            tokio::fs::create_dir_all(root.join("proc")).await?;
+            tokio::fs::create_dir_all(root.join("tmp")).await?;
+            Ok::<_, ExecutorError>(())
+        };
+        if let Err(e) = extracted.await {
+            let _ = tokio::fs::remove_dir_all(&root).await;
+            return Err(e);
+        }
+
+        Ok((self.unshare_command(&root), root))
+    }
+
+    /// Run `script` as the sandbox's init process and collect its output.
+    async fn run_to_completion(
+        mut command: Command,
+        script: &str,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ExecutorError> {
+        let mut process = command
+            .arg("/bin/sh")
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| ExecutorError::SpawnFailed(format!("exec unshare: {e}")))?;
+
+        let stdout_handle = process
+            .stdout
+            .take()
+            .ok_or_else(|| ExecutorError::SpawnFailed("stdout not piped".to_owned()))?;
+
+        let raw_output = read_to_end_within(stdout_handle, timeout).await?;
+        let _ = process.wait().await;
+
+        Ok(raw_output)
+    }
+}
+
+#[async_trait]
+impl VmmBackend for SandboxBackend {
+    async fn spawn(&self, config: &VmConfig) -> Result<VmHandle, ExecutorError> {
+        let sandbox_id = Uuid::new_v4();
+        let (mut command, root) = self.prepare(config, sandbox_id).await?;
+
+        tracing::info!(vm_id = %sandbox_id, root = %root.display(), "spawning process sandbox");
+
+        // The sandbox runs a shell reading from stdin so commands can be sent
+        // with `execute_in_vm`.
+        let process =
+            match command.arg("/bin/sh").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+                Ok(process) => process,
+                Err(e) => {
+                    let _ = tokio::fs::remove_dir_all(&root).await;
+                    return Err(ExecutorError::SpawnFailed(format!("exec unshare: {e}")));
+                }
+            };
+
+        // Sandboxes have no management socket.
+        Ok(VmHandle::new(sandbox_id, PathBuf::new(), process).with_config(config.clone()))
+    }
+
+    async fn snapshot(&self, handle: &VmHandle) -> Result<SnapshotId, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("snapshot of process sandbox {}", handle.id)))
+    }
+
+    async fn restore(&self, snapshot_id: &SnapshotId) -> Result<VmHandle, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!("process sandbox restore from {snapshot_id}")))
+    }
+
+    async fn terminate(&self, mut handle: VmHandle) -> Result<(), ExecutorError> {
+        tracing::info!(vm_id = %handle.id, "terminating process sandbox");
+
+        handle.process.kill().await?;
+        let _ = tokio::fs::remove_dir_all(self.root_dir(handle.id)).await;
+
+        Ok(())
+    }
+
+    /// Checks the binaries and that this host permits unprivileged user,
+    /// mount, PID and network namespaces.
+    async fn health_check(&self) -> Result<(), ExecutorError> {
+        which_binary(&self.unshare_path)?;
+        which_binary(Path::new("debugfs"))?;
+
+        let probe = Command::new(&self.unshare_path)
+            .args(["--map-root-user", "--mount", "--pid", "--net", "--fork", "true"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .map_err(|e| ExecutorError::SpawnFailed(format!("exec unshare: {e}")))?;
+
+        if probe.success() {
+            Ok(())
+        } else {
+            Err(ExecutorError::Unsupported(
+                "unprivileged user namespaces are disabled on this host".to_owned(),
+            ))
+        }
+    }
+
+    async fn execute_command(
+        &self,
+        config: &VmConfig,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let sandbox_id = Uuid::new_v4();
+        let (unshare, root) = self.prepare(config, sandbox_id).await?;
+
+        tracing::info!(vm_id = %sandbox_id, %command, "executing command in process sandbox");
+
+        // The shell is PID 1 of its namespace; everything in the sandbox is
+        // killed when it exits.
+        let result = Self::run_to_completion(unshare, &capture_script(command), timeout).await;
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let raw_output = result?;
+
+        tracing::info!(vm_id = %sandbox_id, bytes = raw_output.len(), "sandbox execution complete");
+
+        let (stdout, stderr, exit_code) = parse_execution_output(&raw_output);
+
+        Ok(ExecutionOutput { stdout, stderr, exit_code })
+    }
+
+    async fn execute_in_vm(
+        &self,
+        handle: &mut VmHandle,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecutionOutput, ExecutorError> {
+        let (Some(shell_in), Some(shell_out)) =
+            (handle.process.stdin.as_mut(), handle.process.stdout.as_mut())
+        else {
+            return Err(ExecutorError::Unsupported(format!(
+                "sandbox {} has no shell attached",
+                handle.id
+            )));
+        };
+
+        let raw_output = run_on_console(shell_in, shell_out, command, timeout).await?;
+        let (stdout, stderr, exit_code) = parse_execution_output(&raw_output);
+
+        Ok(ExecutionOutput { stdout, stderr, exit_code })
+    }
+
+    fn isolation(&self) -> IsolationLevel {
+        IsolationLevel::ProcessSandbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_backend() -> SandboxBackend {
+        SandboxBackend::new(
+            PathBuf::from("unshare"),
+            std::env::temp_dir().join(format!("forge-sandbox-test-{}", Uuid::new_v4())),
+        )
+    }
+
+    #[test]
+    fn sandbox_is_labelled_as_process_sandbox() {
+        assert_eq!(test_backend().isolation(), IsolationLevel::ProcessSandbox);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn restore_is_unsupported() {
+        let result = test_backend().restore(&SnapshotId::new()).await;
+        assert!(
+            matches!(result, Err(ExecutorError::Unsupported(_))),
+            "sandbox restore must be unsupported, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn execute_command_with_missing_image_cleans_up() {
+        let backend = test_backend();
+        let config = VmConfig::new(PathBuf::from("/unused"), PathBuf::from("/nonexistent.ext4"));
+        let result = backend.execute_command(&config, "true", Duration::from_secs(1)).await;
+        assert!(result.is_err(), "missing rootfs image must fail");
+        let leftovers = std::fs::read_dir(&backend.work_dir).map_or(0, Iterator::count);
+        assert_eq!(leftovers, 0, "failed execution must not leave a root filesystem behind");
+    }
+}
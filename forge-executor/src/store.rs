@@ -0,0 +1,122 @@
+//! Execution store — where execution records and determinism reports go.
+//!
+//! [`BlockRunner`](crate::BlockRunner) feeds every [`ExecutionRecord`] it
+//! produces, and every [`DeterminismReport`] from
+//! [`BlockRunner::verify_determinism`](crate::BlockRunner::verify_determinism),
+//! into the [`ExecutionStore`] it was given. [`InMemoryExecutionStore`] keeps
+//! them in process for tests and single-node use.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use forge_core::execution::ExecutionRecord;
+use forge_core::id::BlockId;
+
+use crate::determinism::DeterminismReport;
+use crate::ExecutorError;
+
+/// Persistent history of block executions.
+#[async_trait]
+pub trait ExecutionStore: Send + Sync {
+    /// Store the record of one execution.
+    async fn record_execution(&self, record: &ExecutionRecord) -> Result<(), ExecutorError>;
+
+    /// Store the outcome of a determinism verification.
+    async fn record_determinism(&self, report: &DeterminismReport) -> Result<(), ExecutorError>;
+
+    /// Every stored execution of `block_id`, oldest first.
+    async fn executions(&self, block_id: BlockId) -> Result<Vec<ExecutionRecord>, ExecutorError>;
+
+    /// Every stored determinism report for `block_id`, oldest first.
+    async fn determinism_reports(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Vec<DeterminismReport>, ExecutorError>;
+}
+
+#[derive(Debug, Default)]
+struct StoreState {
+    executions: HashMap<BlockId, Vec<ExecutionRecord>>,
+    reports: HashMap<BlockId, Vec<DeterminismReport>>,
+}
+
+/// [`ExecutionStore`] held in memory.
+#[derive(Debug, Default)]
+pub struct InMemoryExecutionStore {
+    state: Mutex<StoreState>,
+}
+
+impl InMemoryExecutionStore {
+    /// Create an empty store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ExecutionStore for InMemoryExecutionStore {
+    async fn record_execution(&self, record: &ExecutionRecord) -> Result<(), ExecutorError> {
+        self.state.lock().await.executions.entry(record.block_id).or_default().push(record.clone());
+        Ok(())
+    }
+
+    async fn record_determinism(&self, report: &DeterminismReport) -> Result<(), ExecutorError> {
+        self.state.lock().await.reports.entry(report.block_id).or_default().push(report.clone());
+        Ok(())
+    }
+
+    async fn executions(&self, block_id: BlockId) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        Ok(self.state.lock().await.executions.get(&block_id).cloned().unwrap_or_default())
+    }
+
+    async fn determinism_reports(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Vec<DeterminismReport>, ExecutorError> {
+        Ok(self.state.lock().await.reports.get(&block_id).cloned().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use forge_core::execution::ExecutionStatus;
+    use forge_core::id::UserId;
+
+    use super::*;
+    use crate::compute_hash;
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn records_are_kept_per_block_in_order() {
+        let store = InMemoryExecutionStore::new();
+        let block = BlockId::new();
+        let other = BlockId::new();
+        for (id, out) in [(block, b"1"), (other, b"x"), (block, b"2")] {
+            let record = ExecutionRecord::new(
+                id,
+                UserId::new("test"),
+                compute_hash(b"", b""),
+                compute_hash(out, b""),
+                Utc::now(),
+                Duration::ZERO,
+                ExecutionStatus::Succeeded,
+            );
+            if let Err(e) = store.record_execution(&record).await {
+                panic!("record failed: {e}");
+            }
+        }
+
+        let stored = store.executions(block).await.unwrap_or_else(|e| panic!("read failed: {e}"));
+        let hashes: Vec<_> = stored.iter().map(|r| r.output_hash).collect();
+        assert_eq!(hashes, [compute_hash(b"1", b""), compute_hash(b"2", b"")]);
+        let none =
+            store.determinism_reports(block).await.unwrap_or_else(|e| panic!("read failed: {e}"));
+        assert!(none.is_empty());
+    }
+}
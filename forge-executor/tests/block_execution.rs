@@ -3,13 +3,18 @@
 //! Verifies the core MVP property: same block + same input = same output hash
 //! across 5 independent VM runs.
 //!
-//! Requires: KVM (`/dev/kvm`) and Firecracker binary at `/usr/local/bin/firecracker`.
+//! The microVM tests require KVM (`/dev/kvm`) and the Firecracker binary at
+//! `/usr/local/bin/firecracker`. The process-sandbox test needs unprivileged
+//! user namespaces, `unshare` and e2fsprogs.
+
+mod common;
 
 use std::path::PathBuf;
 use std::time::Duration;
 
 use forge_core::examples::example_blocks;
-use forge_executor::{BlockRunner, FirecrackerBackend, VmConfig};
+use forge_core::execution::IsolationLevel;
+use forge_executor::{BlockRunner, FirecrackerBackend, SandboxBackend, VmConfig, VmmBackend};
 
 fn make_backend() -> FirecrackerBackend {
     FirecrackerBackend::new(
@@ -23,11 +28,11 @@ fn make_vm_config() -> VmConfig {
     VmConfig::new(
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
             .parent()
-            .expect("workspace root exists")
+            .unwrap_or_else(|| panic!("workspace root must exist"))
             .join("test-assets/vmlinux.bin"),
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
             .parent()
-            .expect("workspace root exists")
+            .unwrap_or_else(|| panic!("workspace root must exist"))
             .join("test-assets/rootfs.ext4"),
     )
 }
@@ -47,44 +52,42 @@ async fn git_block_five_runs_produce_identical_hash() {
     let git_block = &blocks[0];
     assert_eq!(git_block.manifest.name, "git-env");
 
-    let mut hashes = Vec::with_capacity(5);
-    let mut durations = Vec::with_capacity(5);
-
-    for run in 1..=5u32 {
-        let record = runner
-            .execute(git_block, b"")
-            .await
-            .unwrap_or_else(|e| panic!("run {run} failed: {e}"));
-
-        hashes.push(record.output_hash);
-        durations.push(record.duration);
+    let report = runner
+        .verify_determinism(git_block, b"", 5)
+        .await
+        .unwrap_or_else(|e| panic!("determinism verification failed: {e}"));
 
-        eprintln!(
-            "  Run {run}: hash={} duration={}ms",
-            record.output_hash,
-            record.duration.as_millis()
-        );
-    }
-
-    // Print determinism report.
-    let all_identical = hashes.windows(2).all(|w| w[0] == w[1]);
     eprintln!("\n=== Determinism Verification Report ===");
     eprintln!("Block: {}", git_block.manifest.name);
     eprintln!("Command: echo 'git-env'");
-    eprintln!("Runs: 5");
+    eprintln!("Runs: {}", report.runs.len());
     eprintln!("Results:");
-    for (i, (hash, dur)) in hashes.iter().zip(durations.iter()).enumerate() {
-        eprintln!("  Run {}: hash={hash} duration={}ms", i + 1, dur.as_millis());
+    for (i, run) in report.runs.iter().enumerate() {
+        eprintln!(
+            "  Run {}: hash={} duration={}ms",
+            i + 1,
+            run.record.output_hash,
+            run.record.duration.as_millis()
+        );
+    }
+    eprintln!(
+        "Deterministic: {}",
+        if report.is_deterministic() { "YES (all hashes identical)" } else { "NO" }
+    );
+    for divergence in report.diagnose() {
+        eprintln!("Divergent runs {:?}: {:?}", divergence.runs, divergence.hints);
+        if let Some(diff) = &divergence.stdout {
+            eprintln!("  stdout differs at byte {}: {:?}", diff.first_difference, diff.changes);
+        }
     }
-    eprintln!("Deterministic: {}", if all_identical { "YES (all hashes identical)" } else { "NO" });
     eprintln!("===\n");
 
-    if !all_identical {
-        panic!(
-            "non-deterministic execution detected — hashes differ:\n{:#?}",
-            hashes.iter().map(|h| h.to_string()).collect::<Vec<_>>()
-        );
-    }
+    assert_eq!(report.runs.len(), 5);
+    assert!(
+        report.is_deterministic(),
+        "non-deterministic execution detected — hashes differ:\n{:#?}",
+        report.outputs.iter().map(|o| o.output_hash.to_string()).collect::<Vec<_>>()
+    );
 }
 
 /// Smoke test: a single VM execution completes without error.
@@ -97,9 +100,42 @@ async fn single_vm_execution_completes() {
     let runner = BlockRunner::with_timeout(backend, vm_config, Duration::from_secs(30));
 
     let blocks = example_blocks();
-    let record = runner.execute(&blocks[0], b"").await.expect("execution should succeed");
+    let record = runner
+        .execute(&blocks[0], b"")
+        .await
+        .unwrap_or_else(|e| panic!("execution should succeed: {e}"));
 
     assert_eq!(record.block_id, blocks[0].id);
     // output_hash must be non-zero (SHA-256 of non-empty output)
     assert_ne!(record.output_hash.as_bytes(), &[0u8; 32]);
 }
+
+/// The determinism proof without KVM: repeated sandboxed runs of the same
+/// block agree, and every record is labelled as process-sandboxed.
+#[tokio::test]
+#[ignore = "requires unshare and e2fsprogs"]
+#[cfg_attr(miri, ignore)]
+async fn sandboxed_block_runs_are_deterministic_and_labelled() {
+    let work_dir = common::work_dir("sandbox");
+    let backend = SandboxBackend::new(PathBuf::from("unshare"), work_dir.clone());
+    if let Err(e) = backend.health_check().await {
+        panic!("sandbox health check failed: {e}");
+    }
+    let rootfs =
+        common::build_host_rootfs().unwrap_or_else(|| panic!("cannot build the test rootfs"));
+
+    let vm_config = VmConfig::new(PathBuf::from("/unused"), rootfs.clone());
+    let runner = BlockRunner::with_timeout(backend, vm_config, Duration::from_secs(30));
+    let block = &example_blocks()[0];
+
+    let report = runner.verify_determinism(block, b"", 3).await;
+
+    common::remove_rootfs(&rootfs);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    let report = report.unwrap_or_else(|e| panic!("sandboxed verification failed: {e}"));
+    assert!(
+        report.runs.iter().all(|run| run.record.isolation == IsolationLevel::ProcessSandbox),
+        "sandboxed runs must be labelled as such"
+    );
+    assert!(report.is_deterministic(), "sandboxed runs must agree: {:?}", report.outputs);
+}
@@ -0,0 +1,67 @@
+//! Helpers shared by the integration tests.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use uuid::Uuid;
+
+/// Host binaries copied into the minimal test rootfs, along with the shared
+/// libraries they link against.
+const ROOTFS_BINARIES: &[&str] =
+    &["sh", "base64", "mktemp", "rm", "ls", "sort", "sleep", "cat", "printf", "true"];
+
+/// Build a minimal ext4 rootfs from host binaries in a fresh temp directory.
+///
+/// Lets the process sandbox run the real execution path without the
+/// Firecracker test assets. Returns `None` if `mke2fs` is unavailable.
+pub fn build_host_rootfs() -> Option<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("forge-test-rootfs-{}", Uuid::new_v4()));
+    let tree = dir.join("tree");
+    let image = dir.join("rootfs.ext4");
+
+    let script = format!(
+        r#"set -e
+tree="$1"
+mkdir -p "$tree/usr/bin" "$tree/tmp" "$tree/proc"
+ln -s usr/bin "$tree/bin"
+for name in {binaries}; do
+    path=$(command -v "$name") || continue
+    case "$path" in /*) ;; *) continue ;; esac
+    cp -L "$path" "$tree/usr/bin/$name"
+    for lib in $(ldd "$path" 2>/dev/null | grep -o '/[^ ]*'); do
+        mkdir -p "$tree$(dirname "$lib")"
+        cp -L "$lib" "$tree$lib"
+    done
+done
+chmod 1777 "$tree/tmp"
+mke2fs -q -t ext4 -d "$tree" "$2" 32M
+"#,
+        binaries = ROOTFS_BINARIES.join(" ")
+    );
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("build-rootfs")
+        .arg(&tree)
+        .arg(&image)
+        .env("PATH", std::env::var("PATH").unwrap_or_default() + ":/sbin:/usr/sbin")
+        .stdout(Stdio::null())
+        .status()
+        .ok()?;
+
+    let _ = std::fs::remove_dir_all(&tree);
+    status.success().then_some(image)
+}
+
+/// A fresh work directory for a backend under test.
+pub fn work_dir(label: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("forge-test-{label}-{}", Uuid::new_v4()))
+}
+
+/// Remove the temp directory created by [`build_host_rootfs`].
+pub fn remove_rootfs(image: &Path) {
+    if let Some(dir) = image.parent() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,69 @@
+//! Shared `VmmBackend` conformance suite, run against each real backend.
+//!
+//! The microVM backends require KVM plus the respective VMM binary.
+//! Run them with: `cargo test --test conformance -- --ignored`
+//!
+//! The process sandbox needs unprivileged user namespaces, `unshare` and
+//! e2fsprogs, and runs against a rootfs built from host binaries.
+
+mod common;
+
+use std::path::PathBuf;
+
+use forge_executor::conformance;
+use forge_executor::{FirecrackerBackend, LibkrunBackend, SandboxBackend, VmConfig, VmmBackend};
+
+fn test_asset(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap_or_else(|| panic!("workspace root must exist"))
+        .join("test-assets")
+        .join(name)
+}
+
+fn test_config() -> VmConfig {
+    let mut config = VmConfig::new(test_asset("vmlinux.bin"), test_asset("rootfs.ext4"));
+    // Spawned VMs must run a shell on the console for `execute_in_vm`.
+    config.boot_args.push_str(" init=/bin/sh");
+    config
+}
+
+#[tokio::test]
+#[ignore = "requires KVM and Firecracker binary"]
+#[cfg_attr(miri, ignore)]
+async fn firecracker_conforms() {
+    let backend = FirecrackerBackend::new(
+        PathBuf::from("firecracker"),
+        PathBuf::from("/tmp/forge-test-sockets"),
+        PathBuf::from("/tmp/forge-test-snapshots"),
+    );
+    conformance::run_all(&backend, &test_config()).await;
+}
+
+#[tokio::test]
+#[ignore = "requires KVM, a libkrun launcher and debugfs"]
+#[cfg_attr(miri, ignore)]
+async fn libkrun_conforms() {
+    let backend =
+        LibkrunBackend::new(PathBuf::from("krun-launcher"), PathBuf::from("/tmp/forge-test-krun"));
+    conformance::run_all(&backend, &test_config()).await;
+}
+
+#[tokio::test]
+#[ignore = "requires unshare and e2fsprogs"]
+#[cfg_attr(miri, ignore)]
+async fn sandbox_conforms() {
+    let work_dir = common::work_dir("sandbox");
+    let backend = SandboxBackend::new(PathBuf::from("unshare"), work_dir.clone());
+    if let Err(e) = backend.health_check().await {
+        panic!("sandbox health check failed: {e}");
+    }
+    let rootfs =
+        common::build_host_rootfs().unwrap_or_else(|| panic!("cannot build the test rootfs"));
+
+    let config = VmConfig::new(PathBuf::from("/unused"), rootfs.clone());
+    conformance::run_all(&backend, &config).await;
+
+    common::remove_rootfs(&rootfs);
+    let _ = std::fs::remove_dir_all(&work_dir);
+}
@@ -4,9 +4,11 @@
 //! Run with: `cargo test --test firecracker_lifecycle -- --ignored`
 
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use forge_executor::{FirecrackerBackend, VmConfig, VmmBackend};
+use forge_executor::{
+    FirecrackerBackend, PoolSettings, VmConfig, VmOrchestrator, VmmBackend, WarmPool,
+};
 
 fn test_backend() -> FirecrackerBackend {
     FirecrackerBackend::new(
@@ -31,7 +33,7 @@ async fn spawn_vm_starts_and_responds() {
     let config = test_config();
 
     let start = Instant::now();
-    let handle = backend.spawn(&config).await.expect("VM spawn failed");
+    let handle = backend.spawn(&config).await.unwrap_or_else(|e| panic!("VM spawn failed: {e}"));
     let boot_time = start.elapsed();
 
     println!("VM boot time: {boot_time:?}");
@@ -41,7 +43,7 @@ async fn spawn_vm_starts_and_responds() {
     // Verify process is alive
     assert!(handle.socket_path.exists(), "socket should exist while VM is running");
 
-    backend.terminate(handle).await.expect("terminate failed");
+    backend.terminate(handle).await.unwrap_or_else(|e| panic!("terminate failed: {e}"));
 }
 
 #[tokio::test]
@@ -51,13 +53,14 @@ async fn snapshot_creates_recoverable_state() {
     let backend = test_backend();
     let config = test_config();
 
-    let handle = backend.spawn(&config).await.expect("VM spawn failed");
+    let handle = backend.spawn(&config).await.unwrap_or_else(|e| panic!("VM spawn failed: {e}"));
     let vm_id = handle.id;
 
     // Give the VM a moment to fully boot
     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
-    let snapshot_id = backend.snapshot(&handle).await.expect("snapshot failed");
+    let snapshot_id =
+        backend.snapshot(&handle).await.unwrap_or_else(|e| panic!("snapshot failed: {e}"));
     println!("Snapshot id: {snapshot_id}");
 
     // Verify snapshot files exist
@@ -69,7 +72,7 @@ async fn snapshot_creates_recoverable_state() {
 
     println!("Snapshot mem size: {} bytes", mem_path.metadata().map(|m| m.len()).unwrap_or(0));
 
-    backend.terminate(handle).await.expect("terminate failed");
+    backend.terminate(handle).await.unwrap_or_else(|e| panic!("terminate failed: {e}"));
     println!("Snapshot test passed for VM {vm_id}");
 }
 
@@ -81,21 +84,23 @@ async fn restore_from_snapshot_succeeds() {
     let config = test_config();
 
     // Spawn original VM
-    let handle = backend.spawn(&config).await.expect("VM spawn failed");
+    let handle = backend.spawn(&config).await.unwrap_or_else(|e| panic!("VM spawn failed: {e}"));
     let original_id = handle.id;
 
     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
     // Snapshot
-    let snapshot_id = backend.snapshot(&handle).await.expect("snapshot failed");
+    let snapshot_id =
+        backend.snapshot(&handle).await.unwrap_or_else(|e| panic!("snapshot failed: {e}"));
 
     // Terminate original
-    backend.terminate(handle).await.expect("terminate original failed");
+    backend.terminate(handle).await.unwrap_or_else(|e| panic!("terminate original failed: {e}"));
     println!("Original VM {original_id} terminated");
 
     // Restore from snapshot
     let start = Instant::now();
-    let restored = backend.restore(&snapshot_id).await.expect("restore failed");
+    let restored =
+        backend.restore(&snapshot_id).await.unwrap_or_else(|e| panic!("restore failed: {e}"));
     let restore_time = start.elapsed();
 
     println!("Restore time: {restore_time:?}");
@@ -104,5 +109,63 @@ async fn restore_from_snapshot_succeeds() {
     assert!(restored.socket_path.exists(), "restored VM socket should exist");
     assert_ne!(restored.id, original_id, "restored VM should have a new ID");
 
-    backend.terminate(restored).await.expect("terminate restored failed");
+    backend.terminate(restored).await.unwrap_or_else(|e| panic!("terminate restored failed: {e}"));
+}
+
+#[tokio::test]
+#[ignore = "requires KVM and Firecracker binary"]
+#[cfg_attr(miri, ignore)]
+async fn fork_branches_write_to_private_root_disks() {
+    let orchestrator = VmOrchestrator::new(test_backend());
+    let parent =
+        orchestrator.spawn(&test_config()).await.unwrap_or_else(|e| panic!("VM spawn failed: {e}"));
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let timeout = Duration::from_secs(10);
+    let mut fork = orchestrator
+        .fork(&parent, &["echo left >/forked && sync", "echo right >/forked && sync"], timeout)
+        .await
+        .unwrap_or_else(|e| panic!("fork failed: {e}"));
+
+    // Both branches have written before either reads back.
+    let mut seen = Vec::new();
+    for branch in &mut fork.branches {
+        if let Err(e) = &branch.result {
+            panic!("branch {} failed: {e}", branch.command);
+        }
+        let output = test_backend()
+            .execute_in_vm(&mut branch.handle, "cat /forked", timeout)
+            .await
+            .unwrap_or_else(|e| panic!("read back failed: {e}"));
+        seen.push(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    assert_eq!(seen, ["left\n", "right\n"], "each branch must see only its own write");
+
+    orchestrator.abandon_fork(fork).await.unwrap_or_else(|e| panic!("abandon failed: {e}"));
+    orchestrator.terminate(parent).await.unwrap_or_else(|e| panic!("terminate failed: {e}"));
+}
+
+#[tokio::test]
+#[ignore = "requires KVM and Firecracker binary"]
+#[cfg_attr(miri, ignore)]
+async fn pooled_clones_do_not_see_earlier_writes() {
+    let pool = WarmPool::new(test_backend());
+    let config = test_config();
+    if let Err(e) = pool.prepare(&config, PoolSettings::new(2)).await {
+        panic!("prepare failed: {e}");
+    }
+
+    let timeout = Duration::from_secs(10);
+    let written = pool
+        .execute_command(&config, "echo leaked >/pooled && sync", timeout)
+        .await
+        .unwrap_or_else(|e| panic!("write failed: {e}"));
+    assert_eq!(written.exit_code, 0);
+    let read = pool
+        .execute_command(&config, "cat /pooled", timeout)
+        .await
+        .unwrap_or_else(|e| panic!("read failed: {e}"));
+    assert_ne!(read.exit_code, 0, "a later clone must not see an earlier clone's file");
+
+    pool.shutdown().await;
 }
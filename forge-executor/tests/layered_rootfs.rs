@@ -0,0 +1,80 @@
+//! Integration test: a layered rootfs boots with its whole closure in
+//! `/nix/store`.
+//!
+//! Builds a layered image with `forge_nix` for a store path whose closure
+//! splits into a root image and layers, boots it in Firecracker and lists
+//! the guest's store. Requires KVM, the Firecracker binary, Nix (with
+//! `<nixpkgs>` on `NIX_PATH`) and e2fsprogs.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+use forge_executor::{FirecrackerBackend, VmConfig, VmmBackend};
+use forge_nix::RootfsBuilder;
+use uuid::Uuid;
+
+/// A root providing no binaries itself: its shell and `mount` come from
+/// busybox, which stays in the root image, and jq's closure goes to layers.
+const LAYERED_ROOT: &str = r#"with import <nixpkgs> {};
+runCommand "forge-layered-test" {} ''
+  mkdir $out
+  ln -s ${busybox} $out/busybox
+  ln -s ${jq.bin} $out/jq
+''"#;
+
+fn kernel() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap_or_else(|| panic!("workspace root must exist"))
+        .join("test-assets/vmlinux.bin")
+}
+
+/// Realise [`LAYERED_ROOT`] and return its store path.
+fn realise_root() -> PathBuf {
+    let output = Command::new("nix-build")
+        .args(["--no-out-link", "-E", LAYERED_ROOT])
+        .output()
+        .unwrap_or_else(|e| panic!("exec nix-build: {e}"));
+    assert!(
+        output.status.success(),
+        "nix-build failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+#[tokio::test]
+#[ignore = "requires KVM, Firecracker binary, Nix and e2fsprogs"]
+#[cfg_attr(miri, ignore)]
+async fn layered_guests_see_their_whole_closure() {
+    let out_dir = std::env::temp_dir().join(format!("forge-test-layered-{}", Uuid::new_v4()));
+    let rootfs = RootfsBuilder::new(out_dir.clone())
+        .build_layered(&realise_root())
+        .await
+        .unwrap_or_else(|e| panic!("layered build failed: {e}"));
+    assert!(!rootfs.layers.is_empty(), "the test closure must be split into layers");
+
+    let mut config = VmConfig::new(kernel(), rootfs.root.path.clone());
+    config.layer_paths = rootfs.layer_paths();
+    let backend = FirecrackerBackend::new(
+        PathBuf::from("firecracker"),
+        PathBuf::from("/tmp/forge-test-sockets"),
+        PathBuf::from("/tmp/forge-test-snapshots"),
+    );
+    let output = backend.execute_command(&config, "ls /nix/store", Duration::from_secs(30)).await;
+    let _ = std::fs::remove_dir_all(&out_dir);
+    let output = output.unwrap_or_else(|e| panic!("execution failed: {e}"));
+    assert_eq!(output.exit_code, 0, "ls failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let seen: BTreeSet<String> =
+        String::from_utf8_lossy(&output.stdout).lines().map(str::to_owned).collect();
+    for path in &rootfs.root.closure {
+        let name = path
+            .file_name()
+            .unwrap_or_else(|| panic!("store path {} has no name", path.display()))
+            .to_string_lossy();
+        assert!(seen.contains(name.as_ref()), "{name} missing from the guest store: {seen:?}");
+    }
+}
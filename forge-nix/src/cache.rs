@@ -0,0 +1,87 @@
+//! Cache of built rootfs images and layers.
+//!
+//! Images are stored under a key hashing everything that determines their
+//! contents — the format, the layout and the store paths they hold (see
+//! [`closure_hash`](crate::rootfs::closure_hash)):
+//!
+//! ```text
+//! <dir>/<key>.<extension>
+//! ```
+//!
+//! A [`RootfsBuilder`](crate::RootfsBuilder) with a cache attached looks
+//! the key up before staging anything, so a closure, or a layer, that was
+//! packed before is never packed again. Store paths are immutable, so
+//! entries never go stale.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use forge_core::id::ContentHash;
+
+use crate::rootfs::ImageFormat;
+use crate::NixError;
+
+/// Counters describing cache effectiveness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImageCacheStats {
+    /// Lookups that found a built image.
+    pub hits: u64,
+    /// Lookups that found nothing, so the image was built.
+    pub misses: u64,
+}
+
+/// A directory of built images keyed by content.
+#[derive(Debug)]
+pub struct ImageCache {
+    dir: PathBuf,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ImageCache {
+    /// Open the cache in `dir`. The directory is created on the first build.
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self { dir, hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    /// Directory holding the cache.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the `format` image with `key` is, or would be, stored.
+    #[must_use]
+    pub fn path(&self, key: &ContentHash, format: ImageFormat) -> PathBuf {
+        self.dir.join(format!("{key}.{}", format.extension()))
+    }
+
+    /// The stored `format` image with `key`, if there is one.
+    ///
+    /// # Errors
+    /// Returns [`NixError::Io`] if the cache cannot be inspected.
+    pub async fn lookup(
+        &self,
+        key: &ContentHash,
+        format: ImageFormat,
+    ) -> Result<Option<PathBuf>, NixError> {
+        let path = self.path(key, format);
+        if tokio::fs::try_exists(&path).await? {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            Ok(Some(path))
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            Ok(None)
+        }
+    }
+
+    /// Hits and misses since the cache was opened.
+    #[must_use]
+    pub fn stats(&self) -> ImageCacheStats {
+        ImageCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
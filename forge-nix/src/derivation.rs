@@ -0,0 +1,338 @@
+//! Parsing `.drv` files.
+//!
+//! Nix writes derivations in `ATerm` syntax:
+//!
+//! ```text
+//! Derive([outputs], [input derivations], [input sources],
+//!        "system", "builder", [args], [env])
+//!
+//! output           = ("name", "path", "hash algorithm", "hash")
+//! input derivation = ("path", ["output", ...])
+//! env entry        = ("name", "value")
+//! ```
+//!
+//! Strings are double-quoted with `\"`, `\\`, `\n`, `\r` and `\t` escapes.
+//! [`Derivation::parse`] reads this without a Nix daemon, so a block's build
+//! environment can be inspected on any machine that has its `.drv` file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::store_path::{StorePath, DEFAULT_STORE_DIR};
+use crate::NixError;
+
+/// A parsed Nix derivation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Derivation {
+    /// Outputs by name, e.g. `out`, `dev`.
+    pub outputs: BTreeMap<String, DerivationOutput>,
+
+    /// Derivations this one depends on, with the outputs it uses from each.
+    pub input_derivations: BTreeMap<StorePath, BTreeSet<String>>,
+
+    /// Store paths used directly as sources, e.g. builder scripts.
+    pub input_sources: BTreeSet<StorePath>,
+
+    /// Platform the derivation builds on, e.g. `x86_64-linux`.
+    pub system: String,
+
+    /// Program run to build the derivation.
+    pub builder: String,
+
+    /// Arguments passed to the builder.
+    pub args: Vec<String>,
+
+    /// Environment the builder runs in.
+    pub env: BTreeMap<String, String>,
+}
+
+/// One output of a [`Derivation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DerivationOutput {
+    /// Where the output is stored; `None` for content-addressed outputs
+    /// whose path is only known after building.
+    pub path: Option<StorePath>,
+
+    /// Hash algorithm of a fixed or content-addressed output, e.g.
+    /// `sha256` or `r:sha256` (recursive, i.e. over the NAR serialisation).
+    pub hash_algorithm: Option<String>,
+
+    /// Expected hash of a fixed-output derivation's output.
+    pub hash: Option<String>,
+}
+
+impl DerivationOutput {
+    /// Whether the output's content is fixed in advance, as for fetchers.
+    #[must_use]
+    pub const fn is_fixed(&self) -> bool {
+        self.hash.is_some()
+    }
+}
+
+impl Derivation {
+    /// Parse a derivation whose store paths are in [`DEFAULT_STORE_DIR`].
+    ///
+    /// # Errors
+    /// See [`Self::parse_in`].
+    pub fn parse(text: &str) -> Result<Self, NixError> {
+        Self::parse_in(text, Path::new(DEFAULT_STORE_DIR))
+    }
+
+    /// Parse a derivation whose store paths are in `store_dir`.
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidDerivation`] if `text` is not a
+    /// derivation in `ATerm` syntax, and [`NixError::InvalidStorePath`] if it
+    /// refers to a malformed store path or one outside `store_dir`.
+    pub fn parse_in(text: &str, store_dir: &Path) -> Result<Self, NixError> {
+        let mut parser = Parser { text, pos: 0 };
+        let store_path = |path: String| StorePath::from_path(Path::new(&path), store_dir);
+
+        parser.expect("Derive(")?;
+        let mut outputs = BTreeMap::new();
+        parser.list(|p| {
+            p.expect("(")?;
+            let name = p.string()?;
+            p.expect(",")?;
+            let path = p.string()?;
+            p.expect(",")?;
+            let hash_algorithm = p.string()?;
+            p.expect(",")?;
+            let hash = p.string()?;
+            p.expect(")")?;
+            let output = DerivationOutput {
+                path: non_empty(path).map(store_path).transpose()?,
+                hash_algorithm: non_empty(hash_algorithm),
+                hash: non_empty(hash),
+            };
+            p.insert_unique(&mut outputs, name, output, "output")
+        })?;
+        parser.expect(",")?;
+
+        let mut input_derivations = BTreeMap::new();
+        parser.list(|p| {
+            p.expect("(")?;
+            let path = store_path(p.string()?)?;
+            p.expect(",")?;
+            let mut names = BTreeSet::new();
+            p.list(|p| {
+                names.insert(p.string()?);
+                Ok(())
+            })?;
+            p.expect(")")?;
+            p.insert_unique(&mut input_derivations, path, names, "input derivation")
+        })?;
+        parser.expect(",")?;
+
+        let mut input_sources = BTreeSet::new();
+        parser.list(|p| {
+            input_sources.insert(store_path(p.string()?)?);
+            Ok(())
+        })?;
+        parser.expect(",")?;
+        let system = parser.string()?;
+        parser.expect(",")?;
+        let builder = parser.string()?;
+        parser.expect(",")?;
+
+        let mut args = Vec::new();
+        parser.list(|p| {
+            args.push(p.string()?);
+            Ok(())
+        })?;
+        parser.expect(",")?;
+
+        let mut env = BTreeMap::new();
+        parser.list(|p| {
+            p.expect("(")?;
+            let name = p.string()?;
+            p.expect(",")?;
+            let value = p.string()?;
+            p.expect(")")?;
+            p.insert_unique(&mut env, name, value, "environment variable")
+        })?;
+        parser.expect(")")?;
+        if parser.pos != text.len() {
+            return Err(parser.error("trailing data after the derivation"));
+        }
+
+        Ok(Self { outputs, input_derivations, input_sources, system, builder, args, env })
+    }
+
+    /// Every store path the derivation depends on: its input derivations
+    /// and input sources.
+    pub fn inputs(&self) -> impl Iterator<Item = &StorePath> {
+        self.input_derivations.keys().chain(&self.input_sources)
+    }
+
+    /// Whether every output is fixed in advance, as for fetchers. Such
+    /// derivations may use the network while building.
+    #[must_use]
+    pub fn is_fixed_output(&self) -> bool {
+        !self.outputs.is_empty() && self.outputs.values().all(DerivationOutput::is_fixed)
+    }
+}
+
+fn non_empty(s: String) -> Option<String> {
+    (!s.is_empty()).then_some(s)
+}
+
+/// A cursor over `ATerm` text.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: impl Into<String>) -> NixError {
+        NixError::InvalidDerivation { offset: self.pos, reason: reason.into() }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), NixError> {
+        if self.text[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    /// A comma-separated, bracketed list, each element read by `element`.
+    fn list(
+        &mut self,
+        mut element: impl FnMut(&mut Self) -> Result<(), NixError>,
+    ) -> Result<(), NixError> {
+        self.expect("[")?;
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            element(self)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, NixError> {
+        self.expect("\"")?;
+        let mut value = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((at, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += at + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    /// Insert into a map whose keys must be unique.
+    fn insert_unique<K: Ord + std::fmt::Display, V>(
+        &self,
+        map: &mut BTreeMap<K, V>,
+        key: K,
+        value: V,
+        what: &str,
+    ) -> Result<(), NixError> {
+        if map.contains_key(&key) {
+            return Err(self.error(format!("{what} {key} is listed twice")));
+        }
+        map.insert(key, value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = include_str!("../tests/fixtures/hello-2.12.1.drv");
+    const TARBALL: &str = include_str!("../tests/fixtures/hello-2.12.1.tar.gz.drv");
+
+    fn parse(text: &str) -> Derivation {
+        Derivation::parse(text).unwrap_or_else(|e| panic!("derivation rejected: {e}"))
+    }
+
+    #[test]
+    fn parses_a_derivation() {
+        let drv = parse(HELLO);
+        assert_eq!(drv.system, "x86_64-linux");
+        assert_eq!(
+            drv.builder,
+            "/nix/store/0hm2f1psjpcwg8fijsmr4wwxrx59s092-bash-5.2-p15/bin/bash"
+        );
+        assert_eq!(drv.args.len(), 2);
+
+        let out = &drv.outputs["out"];
+        assert_eq!(
+            out.path.as_ref().map(ToString::to_string).as_deref(),
+            Some("sl141d1g77wvhr050ah87lcyz2czdxa3-hello-2.12.1")
+        );
+        assert!(!out.is_fixed() && !drv.is_fixed_output());
+
+        let inputs: Vec<String> = drv.inputs().map(|p| p.name().to_owned()).collect();
+        assert_eq!(
+            inputs,
+            [
+                "bash-5.2-p15.drv",
+                "stdenv-linux.drv",
+                "hello-2.12.1.tar.gz.drv",
+                "default-builder.sh"
+            ]
+        );
+        assert!(drv.input_derivations.values().all(|outputs| outputs.contains("out")));
+        assert_eq!(drv.env["name"], "hello-2.12.1");
+    }
+
+    #[test]
+    fn string_escapes_are_decoded() {
+        let drv = parse(HELLO);
+        assert_eq!(drv.env["preConfigure"], "echo \"configuring\"\n\texport LC_ALL=C\\n");
+    }
+
+    #[test]
+    fn fixed_output_derivations_are_recognised() {
+        let drv = parse(TARBALL);
+        assert!(drv.is_fixed_output());
+        assert_eq!(drv.outputs["out"].hash_algorithm.as_deref(), Some("sha256"));
+        assert_eq!(drv.inputs().count(), 0);
+    }
+
+    #[test]
+    fn malformed_derivations_are_rejected() {
+        let truncated = &HELLO[..HELLO.len() - 1];
+        let trailing = format!("{HELLO}\n");
+        let foreign = HELLO.replace("/nix/store/9krl", "/tmp/9krl");
+        let duplicate = HELLO.replace("(\"name\",", "(\"system\",");
+        for bad in ["", "Derive([]", truncated, &trailing, &duplicate] {
+            assert!(
+                matches!(Derivation::parse(bad), Err(NixError::InvalidDerivation { .. })),
+                "{bad:?} must be rejected"
+            );
+        }
+        assert!(matches!(Derivation::parse(&foreign), Err(NixError::InvalidStorePath { .. })));
+    }
+}
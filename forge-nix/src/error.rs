@@ -0,0 +1,109 @@
+//! Error types for the Nix crate.
+
+use std::path::PathBuf;
+
+/// Errors that can occur while reading Nix metadata or building images
+/// from Nix store paths.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum NixError {
+    /// A required host tool is not installed.
+    #[error("{name} not found in PATH or sbin directories")]
+    BinaryNotFound {
+        /// The tool that was looked for.
+        name: String,
+    },
+
+    /// A host tool ran but reported failure.
+    #[error("{command} failed: {stderr}")]
+    CommandFailed {
+        /// The command line that failed.
+        command: String,
+        /// What the tool printed on stderr.
+        stderr: String,
+    },
+
+    /// A value is not a valid Nix store path.
+    #[error("invalid store path {path:?}: {reason}")]
+    InvalidStorePath {
+        /// The rejected path or base name.
+        path: String,
+        /// Why it was rejected.
+        reason: String,
+    },
+
+    /// A value is not valid nixbase32.
+    #[error("invalid nixbase32 {value:?}: {reason}")]
+    InvalidNixBase32 {
+        /// The rejected value.
+        value: String,
+        /// Why it was rejected.
+        reason: String,
+    },
+
+    /// A `.drv` file is not a derivation in `ATerm` syntax.
+    #[error("invalid derivation at byte {offset}: {reason}")]
+    InvalidDerivation {
+        /// Byte offset of the error in the file.
+        offset: usize,
+        /// What was wrong.
+        reason: String,
+    },
+
+    /// A `.narinfo` file is malformed or lacks a required field.
+    #[error("invalid narinfo at line {line}: {reason}")]
+    InvalidNarInfo {
+        /// Line of the error, counting from 1; 0 for a missing field.
+        line: usize,
+        /// What was wrong.
+        reason: String,
+    },
+
+    /// A NAR is malformed, or a tree cannot be serialised as one.
+    #[error("invalid NAR: {0}")]
+    InvalidNar(String),
+
+    /// A store path's contents do not match the NAR hash it should have.
+    #[error("NAR hash of {path} is {actual}, expected {expected}")]
+    NarHashMismatch {
+        /// The tree that was hashed.
+        path: PathBuf,
+        /// The hash recorded in the narinfo.
+        expected: String,
+        /// The hash of the tree.
+        actual: String,
+    },
+
+    /// An image does not hold the closure it was checked against.
+    #[error("image {image} does not match its closure: {reason}")]
+    ImageMismatch {
+        /// The image that was checked.
+        image: PathBuf,
+        /// What differs.
+        reason: String,
+    },
+
+    /// No store path matches the requested derivation.
+    #[error("no store path for {0} in the Nix store")]
+    StorePathNotFound(String),
+
+    /// The closure contains no `bin/sh` for the guest to boot into.
+    #[error("closure of {root} provides no bin/sh")]
+    MissingShell {
+        /// The store path whose closure was searched.
+        root: PathBuf,
+    },
+
+    /// The closure lacks a program the image's init needs.
+    #[error("closure of {root} provides no bin/{binary}")]
+    MissingBinary {
+        /// The store path whose closure was searched.
+        root: PathBuf,
+        /// The program that was looked for.
+        binary: String,
+    },
+
+    /// Underlying I/O error.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
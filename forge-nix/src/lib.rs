@@ -1,4 +1,29 @@
 //! Nix derivation builder for the Forge deterministic execution fabric.
 //!
-//! Responsible for generating and evaluating Nix expressions that produce
-//! reproducible build environments for block execution.
+//! Responsible for turning Nix derivations into reproducible build
+//! environments for block execution: [`RootfsBuilder`] packs a store path's
+//! runtime closure into a bootable root filesystem image for
+//! `VmConfig::rootfs_path`, and [`StorePath`] validates the store paths it
+//! is given. [`Derivation`] and [`NarInfo`] read `.drv` files and binary
+//! cache metadata without a Nix daemon, and [`nar_hash`] checks a tree's
+//! contents against the NAR hash a narinfo records. An [`ImageCache`] keeps
+//! built images and layers so unchanged closures are never packed twice.
+
+pub mod cache;
+pub mod derivation;
+pub mod error;
+pub mod nar;
+pub mod narinfo;
+pub mod nixbase32;
+pub mod rootfs;
+pub mod store_path;
+
+pub use cache::{ImageCache, ImageCacheStats};
+pub use derivation::{Derivation, DerivationOutput};
+pub use error::NixError;
+pub use nar::{nar_hash, restore_nar, verify_nar, write_nar, NarDigest};
+pub use narinfo::{NarInfo, Sha256Hash};
+pub use rootfs::{
+    closure_hash, ImageFormat, LayeredRootfs, RootfsBuilder, RootfsImage, RootfsLayer,
+};
+pub use store_path::{StorePath, DEFAULT_STORE_DIR};
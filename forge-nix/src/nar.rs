@@ -0,0 +1,464 @@
+//! The Nix archive (NAR) format.
+//!
+//! A NAR is the canonical serialisation of a store path: it keeps only
+//! what Nix considers part of a path's contents — file data, the
+//! executable bit, symlink targets and directory structure — so the NAR
+//! hash recorded in a `.narinfo` identifies the contents independently of
+//! timestamps, ownership or the filesystem they live on.
+//!
+//! ```text
+//! nar       = str("nix-archive-1") node
+//! node      = str("(") str("type") kind str(")")
+//! kind      = str("regular") [str("executable") str("")] str("contents") str(data)
+//!           | str("symlink") str("target") str(target)
+//!           | str("directory") { str("entry") str("(") str("name") str(name)
+//!                                str("node") node str(")") }
+//! str(s)    = u64le(len(s)) || s || zero padding to a multiple of 8
+//! ```
+//!
+//! Directory entries are sorted by name. [`write_nar`] serialises a tree,
+//! [`restore_nar`] unpacks one, and [`nar_hash`] and [`verify_nar`] check a
+//! tree against its narinfo.
+
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::narinfo::{NarInfo, Sha256Hash};
+use crate::NixError;
+
+const MAGIC: &[u8] = b"nix-archive-1";
+
+/// Longest string [`restore_nar`] accepts outside file contents.
+const MAX_TOKEN_LEN: u64 = 4096;
+
+/// Hash and size of a NAR serialisation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NarDigest {
+    /// SHA-256 of the NAR.
+    pub hash: Sha256Hash,
+    /// Length of the NAR in bytes.
+    pub size: u64,
+}
+
+/// Serialise the tree at `path` as a NAR into `out`.
+///
+/// # Errors
+/// Returns [`NixError::InvalidNar`] if the tree contains anything other
+/// than regular files, directories and symlinks, or a file changes size
+/// while it is read, and [`NixError::Io`] if reading or writing fails.
+pub fn write_nar(path: &Path, out: &mut impl Write) -> Result<(), NixError> {
+    write_str(out, MAGIC)?;
+    write_node(path, out)
+}
+
+fn write_node(path: &Path, out: &mut impl Write) -> Result<(), NixError> {
+    let metadata = path.symlink_metadata()?;
+    write_str(out, b"(")?;
+    write_str(out, b"type")?;
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        write_str(out, b"symlink")?;
+        write_str(out, b"target")?;
+        write_str(out, std::fs::read_link(path)?.as_os_str().as_bytes())?;
+    } else if file_type.is_file() {
+        write_str(out, b"regular")?;
+        if metadata.permissions().mode() & 0o100 != 0 {
+            write_str(out, b"executable")?;
+            write_str(out, b"")?;
+        }
+        write_str(out, b"contents")?;
+        let len = metadata.len();
+        out.write_all(&len.to_le_bytes())?;
+        let copied = io::copy(&mut std::fs::File::open(path)?.take(len), out)?;
+        if copied != len {
+            return Err(NixError::InvalidNar(format!("{} changed while reading", path.display())));
+        }
+        out.write_all(padding(len))?;
+    } else if file_type.is_dir() {
+        write_str(out, b"directory")?;
+        let mut names = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        for name in names {
+            write_str(out, b"entry")?;
+            write_str(out, b"(")?;
+            write_str(out, b"name")?;
+            write_str(out, name.as_bytes())?;
+            write_str(out, b"node")?;
+            write_node(&path.join(name), out)?;
+            write_str(out, b")")?;
+        }
+    } else {
+        return Err(NixError::InvalidNar(format!(
+            "{} is not a regular file, directory or symlink",
+            path.display()
+        )));
+    }
+    write_str(out, b")")
+}
+
+fn write_str(out: &mut impl Write, s: &[u8]) -> Result<(), NixError> {
+    // usize is at most 64 bits on every supported target.
+    let len = s.len() as u64;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(s)?;
+    out.write_all(padding(len))?;
+    Ok(())
+}
+
+fn padding(len: u64) -> &'static [u8] {
+    const ZEROS: [u8; 8] = [0; 8];
+    // The remainder is below 8, so the cast cannot truncate.
+    #[allow(clippy::cast_possible_truncation)]
+    let used = (len % 8) as usize;
+    &ZEROS[..(8 - used) % 8]
+}
+
+/// Hash the NAR serialisation of the tree at `path`.
+///
+/// # Errors
+/// See [`write_nar`].
+pub fn nar_hash(path: &Path) -> Result<NarDigest, NixError> {
+    let mut hasher = HashingWriter { hasher: Sha256::new(), size: 0 };
+    let mut out = io::BufWriter::new(&mut hasher);
+    write_nar(path, &mut out)?;
+    out.flush()?;
+    drop(out);
+    Ok(NarDigest { hash: Sha256Hash(hasher.hasher.finalize().into()), size: hasher.size })
+}
+
+/// Check that the tree at `path` is the store path `info` describes.
+///
+/// # Errors
+/// Returns [`NixError::NarHashMismatch`] if the tree's NAR hash or size
+/// differs from `info`'s, or an error from [`nar_hash`].
+pub fn verify_nar(path: &Path, info: &NarInfo) -> Result<(), NixError> {
+    let digest = nar_hash(path)?;
+    if digest.hash != info.nar_hash || digest.size != info.nar_size {
+        return Err(NixError::NarHashMismatch {
+            path: path.to_owned(),
+            expected: info.nar_hash.to_string(),
+            actual: digest.hash.to_string(),
+        });
+    }
+    Ok(())
+}
+
+struct HashingWriter {
+    hasher: Sha256,
+    size: u64,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Unpack the NAR read from `input` to `dest`, which must not exist.
+///
+/// Files are created read-only, mode 0444 or 0555 if executable, as in
+/// the Nix store; directories get the default mode.
+///
+/// # Errors
+/// Returns [`NixError::InvalidNar`] if `input` is not a well-formed NAR —
+/// including entry names that are empty, `.`, `..`, contain `/` or NUL,
+/// or are not sorted — and [`NixError::Io`] if reading or writing fails.
+pub fn restore_nar(input: &mut impl Read, dest: &Path) -> Result<(), NixError> {
+    let mut reader = NarReader { input };
+    reader.expect(MAGIC)?;
+    reader.node(dest)?;
+    let mut trailing = [0u8; 1];
+    if reader.input.read(&mut trailing)? != 0 {
+        return Err(NixError::InvalidNar("trailing data after the archive".to_owned()));
+    }
+    Ok(())
+}
+
+struct NarReader<'a, R> {
+    input: &'a mut R,
+}
+
+impl<R: Read> NarReader<'_, R> {
+    fn node(&mut self, dest: &Path) -> Result<(), NixError> {
+        self.expect(b"(")?;
+        self.expect(b"type")?;
+        match self.token()?.as_slice() {
+            b"regular" => {
+                let mut executable = false;
+                let mut tag = self.token()?;
+                if tag == b"executable" {
+                    self.expect(b"")?;
+                    executable = true;
+                    tag = self.token()?;
+                }
+                if tag != b"contents" {
+                    return Err(unexpected(&tag, "contents"));
+                }
+                let len = self.len()?;
+                let mut file = std::fs::File::create_new(dest)?;
+                let copied = io::copy(&mut self.input.by_ref().take(len), &mut file)?;
+                if copied != len {
+                    return Err(NixError::InvalidNar("truncated file contents".to_owned()));
+                }
+                self.skip_padding(len)?;
+                let mode = if executable { 0o555 } else { 0o444 };
+                file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            }
+            b"symlink" => {
+                self.expect(b"target")?;
+                let target = self.token()?;
+                std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&target), dest)?;
+            }
+            b"directory" => {
+                std::fs::create_dir(dest)?;
+                let mut previous: Option<Vec<u8>> = None;
+                loop {
+                    match self.token()?.as_slice() {
+                        b")" => return Ok(()),
+                        b"entry" => {}
+                        other => return Err(unexpected(other, "entry")),
+                    }
+                    self.expect(b"(")?;
+                    self.expect(b"name")?;
+                    let name = self.token()?;
+                    if name.is_empty()
+                        || name == b"."
+                        || name == b".."
+                        || name.contains(&b'/')
+                        || name.contains(&0)
+                    {
+                        return Err(NixError::InvalidNar(format!(
+                            "invalid entry name {:?}",
+                            String::from_utf8_lossy(&name)
+                        )));
+                    }
+                    if previous.as_ref().is_some_and(|previous| *previous >= name) {
+                        return Err(NixError::InvalidNar(format!(
+                            "entry {:?} is out of order",
+                            String::from_utf8_lossy(&name)
+                        )));
+                    }
+                    self.expect(b"node")?;
+                    self.node(&dest.join(std::ffi::OsStr::from_bytes(&name)))?;
+                    self.expect(b")")?;
+                    previous = Some(name);
+                }
+            }
+            other => return Err(unexpected(other, "a node type")),
+        }
+        self.expect(b")")
+    }
+
+    fn len(&mut self) -> Result<u64, NixError> {
+        let mut len = [0u8; 8];
+        self.input.read_exact(&mut len).map_err(truncated)?;
+        Ok(u64::from_le_bytes(len))
+    }
+
+    fn token(&mut self) -> Result<Vec<u8>, NixError> {
+        let len = self.len()?;
+        if len > MAX_TOKEN_LEN {
+            return Err(NixError::InvalidNar(format!("string of {len} bytes is too long")));
+        }
+        // Bounded by MAX_TOKEN_LEN, so the cast cannot truncate.
+        #[allow(clippy::cast_possible_truncation)]
+        let mut token = vec![0u8; len as usize];
+        self.input.read_exact(&mut token).map_err(truncated)?;
+        self.skip_padding(len)?;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &[u8]) -> Result<(), NixError> {
+        let token = self.token()?;
+        if token != expected {
+            return Err(unexpected(&token, &format!("{:?}", String::from_utf8_lossy(expected))));
+        }
+        Ok(())
+    }
+
+    fn skip_padding(&mut self, len: u64) -> Result<(), NixError> {
+        let mut padding_bytes = [0u8; 8];
+        let padding_bytes = &mut padding_bytes[..padding(len).len()];
+        self.input.read_exact(padding_bytes).map_err(truncated)?;
+        if padding_bytes.iter().any(|&b| b != 0) {
+            return Err(NixError::InvalidNar("non-zero padding".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+fn unexpected(token: &[u8], expected: &str) -> NixError {
+    NixError::InvalidNar(format!("expected {expected}, found {:?}", String::from_utf8_lossy(token)))
+}
+
+fn truncated(e: io::Error) -> NixError {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        NixError::InvalidNar("truncated archive".to_owned())
+    } else {
+        NixError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// A scratch directory removed on drop.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("forge-nar-test-{}", Uuid::new_v4()));
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                panic!("mkdir failed: {e}");
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// `hello` with an executable `bin/hello`, a data file and a symlink.
+    fn package(at: &Path) {
+        let result = std::fs::create_dir_all(at.join("bin"))
+            .and_then(|()| std::fs::write(at.join("bin/hello"), "#!/bin/sh\necho hello\n"))
+            .and_then(|()| {
+                std::fs::set_permissions(
+                    at.join("bin/hello"),
+                    std::fs::Permissions::from_mode(0o755),
+                )
+            })
+            .and_then(|()| std::fs::write(at.join("README"), "hi"))
+            .and_then(|()| std::os::unix::fs::symlink("bin/hello", at.join("greet")));
+        if let Err(e) = result {
+            panic!("fixture setup failed: {e}");
+        }
+    }
+
+    fn nar_of(path: &Path) -> Vec<u8> {
+        let mut nar = Vec::new();
+        write_nar(path, &mut nar).unwrap_or_else(|e| panic!("write_nar failed: {e}"));
+        nar
+    }
+
+    fn str8(s: &[u8]) -> Vec<u8> {
+        let mut out = (s.len() as u64).to_le_bytes().to_vec();
+        out.extend_from_slice(s);
+        out.resize(out.len().next_multiple_of(8), 0);
+        out
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn regular_files_serialise_as_nix_does() {
+        let dir = TempDir::new();
+        let file = dir.0.join("file");
+        if let Err(e) = std::fs::write(&file, "hi") {
+            panic!("write failed: {e}");
+        }
+        let expected: Vec<u8> =
+            [&b"nix-archive-1"[..], b"(", b"type", b"regular", b"contents", b"hi", b")"]
+                .iter()
+                .flat_map(|s| str8(s))
+                .collect();
+        assert_eq!(nar_of(&file), expected);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires nix-store"]
+    fn matches_nix_store_dump() {
+        let dir = TempDir::new();
+        package(&dir.0.join("hello"));
+        let dump = std::process::Command::new("nix-store")
+            .arg("--dump")
+            .arg(dir.0.join("hello"))
+            .output()
+            .unwrap_or_else(|e| panic!("nix-store --dump failed: {e}"));
+        assert_eq!(nar_of(&dir.0.join("hello")), dump.stdout);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn restoring_a_nar_reproduces_the_tree() {
+        let dir = TempDir::new();
+        package(&dir.0.join("hello"));
+        let nar = nar_of(&dir.0.join("hello"));
+
+        let restored = dir.0.join("restored");
+        restore_nar(&mut nar.as_slice(), &restored)
+            .unwrap_or_else(|e| panic!("restore_nar failed: {e}"));
+        assert_eq!(nar_of(&restored), nar);
+        let mode = std::fs::metadata(restored.join("bin/hello")).map(|m| m.permissions().mode());
+        assert_eq!(mode.ok().map(|m| m & 0o777), Some(0o555));
+        assert_eq!(std::fs::read_link(restored.join("greet")).ok(), Some("bin/hello".into()));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn malformed_nars_are_rejected() {
+        let dir = TempDir::new();
+        package(&dir.0.join("hello"));
+        let nar = nar_of(&dir.0.join("hello"));
+
+        let mut escaping = nar.clone();
+        let at = nar.windows(6).position(|w| w == b"README").unwrap_or_else(|| panic!("no README"));
+        escaping[at..at + 6].copy_from_slice(b"../x\0\0");
+        escaping[at - 8] = 4;
+        for (name, bad) in [
+            ("truncated", &nar[..nar.len() - 8]),
+            ("bad magic", &nar[8..]),
+            ("escaping entry", escaping.as_slice()),
+        ] {
+            let dest = dir.0.join(name);
+            assert!(
+                matches!(restore_nar(&mut &bad[..], &dest), Err(NixError::InvalidNar(_))),
+                "{name} NAR must be rejected"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn verification_compares_against_the_narinfo() {
+        let dir = TempDir::new();
+        let path = dir.0.join("sl141d1g77wvhr050ah87lcyz2czdxa3-hello-2.12.1");
+        package(&path);
+        let digest = nar_hash(&path).unwrap_or_else(|e| panic!("nar_hash failed: {e}"));
+        assert_eq!(digest.size, nar_of(&path).len() as u64);
+
+        let narinfo = |hash: Sha256Hash| {
+            NarInfo::parse(&format!(
+                "StorePath: /nix/store/sl141d1g77wvhr050ah87lcyz2czdxa3-hello-2.12.1\n\
+                 URL: nar/hello.nar\nNarHash: {hash}\nNarSize: {}\n",
+                digest.size
+            ))
+            .unwrap_or_else(|e| panic!("narinfo rejected: {e}"))
+        };
+        assert!(verify_nar(&path, &narinfo(digest.hash)).is_ok());
+
+        if let Err(e) = std::fs::write(path.join("README"), "tampered") {
+            panic!("write failed: {e}");
+        }
+        let result = verify_nar(&path, &narinfo(digest.hash));
+        assert!(matches!(result, Err(NixError::NarHashMismatch { .. })), "got {result:?}");
+    }
+}
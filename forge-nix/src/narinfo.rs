@@ -0,0 +1,274 @@
+//! Parsing `.narinfo` files.
+//!
+//! A binary cache describes each store path it serves in a `<hash
+//! part>.narinfo` file of `Key: value` lines:
+//!
+//! ```text
+//! StorePath: /nix/store/<hash part>-<name>
+//! URL: nar/<file hash>.nar.xz
+//! Compression: xz
+//! FileHash: sha256:<nixbase32>
+//! FileSize: <bytes>
+//! NarHash: sha256:<nixbase32>
+//! NarSize: <bytes>
+//! References: <base name> <base name> ...
+//! Deriver: <base name>.drv
+//! Sig: <key name>:<base64 signature>
+//! ```
+//!
+//! [`NarInfo::parse`] reads these so a path's closure references and
+//! contents hash can be checked without a Nix daemon. Unknown keys are
+//! ignored, as Nix does.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::store_path::{StorePath, DEFAULT_STORE_DIR};
+use crate::{nixbase32, NixError};
+
+/// A SHA-256 hash as Nix prints it: `sha256:<nixbase32>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sha256Hash(pub [u8; 32]);
+
+impl Sha256Hash {
+    /// Parse `sha256:` followed by the digest in nixbase32 or base16.
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidNixBase32`] if `value` is not a SHA-256
+    /// hash in either encoding.
+    pub fn parse(value: &str) -> Result<Self, NixError> {
+        let invalid = |reason: &str| NixError::InvalidNixBase32 {
+            value: value.to_owned(),
+            reason: reason.to_owned(),
+        };
+        let digest = value.strip_prefix("sha256:").ok_or_else(|| invalid("expected `sha256:`"))?;
+        let bytes = if digest.len() == 64 {
+            decode_base16(digest).ok_or_else(|| invalid("not base16"))?
+        } else {
+            nixbase32::decode(digest)?
+        };
+        bytes.try_into().map(Self).map_err(|_| invalid("not a SHA-256 digest"))
+    }
+}
+
+impl FromStr for Sha256Hash {
+    type Err = NixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Sha256Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256:{}", nixbase32::encode(&self.0))
+    }
+}
+
+fn decode_base16(digest: &str) -> Option<Vec<u8>> {
+    let nibble = |c: u8| char::from(c).to_digit(16).and_then(|d| u8::try_from(d).ok());
+    digest
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| Some((nibble(pair[0])? << 4) | nibble(*pair.get(1)?)?))
+        .collect()
+}
+
+/// Binary cache metadata for one store path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NarInfo {
+    /// The path described.
+    pub store_path: StorePath,
+
+    /// Where the compressed NAR is found, relative to the cache.
+    pub url: String,
+
+    /// How the NAR at `url` is compressed, e.g. `xz`; Nix assumes `bzip2`
+    /// when unset.
+    pub compression: String,
+
+    /// Hash of the compressed NAR.
+    pub file_hash: Option<Sha256Hash>,
+
+    /// Size of the compressed NAR in bytes.
+    pub file_size: Option<u64>,
+
+    /// Hash of the uncompressed NAR serialisation of the path.
+    pub nar_hash: Sha256Hash,
+
+    /// Size of the uncompressed NAR in bytes.
+    pub nar_size: u64,
+
+    /// Store paths the path refers to at runtime, possibly including itself.
+    pub references: BTreeSet<StorePath>,
+
+    /// The derivation that built the path, if known.
+    pub deriver: Option<StorePath>,
+
+    /// Signatures over the path's metadata, `<key name>:<signature>`.
+    pub signatures: Vec<String>,
+
+    /// Content address, for content-addressed paths.
+    pub ca: Option<String>,
+}
+
+impl NarInfo {
+    /// Parse a narinfo whose `StorePath` is in [`DEFAULT_STORE_DIR`].
+    ///
+    /// # Errors
+    /// See [`Self::parse_in`].
+    pub fn parse(text: &str) -> Result<Self, NixError> {
+        Self::parse_in(text, Path::new(DEFAULT_STORE_DIR))
+    }
+
+    /// Parse a narinfo whose `StorePath` is in `store_dir`.
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidNarInfo`] if a line is not `Key: value`,
+    /// a key other than `Sig` repeats, a size is not a number, or
+    /// `StorePath`, `URL`, `NarHash` or `NarSize` is missing;
+    /// [`NixError::InvalidStorePath`] for a malformed path; and
+    /// [`NixError::InvalidNixBase32`] for a malformed hash.
+    pub fn parse_in(text: &str, store_dir: &Path) -> Result<Self, NixError> {
+        let mut store_path = None;
+        let mut url = None;
+        let mut compression = None;
+        let mut file_hash = None;
+        let mut file_size = None;
+        let mut nar_hash = None;
+        let mut nar_size = None;
+        let mut references = None;
+        let mut deriver = None;
+        let mut signatures = Vec::new();
+        let mut ca = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let invalid = |reason: String| NixError::InvalidNarInfo { line: line_no, reason };
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) =
+                line.split_once(": ").ok_or_else(|| invalid("expected `Key: value`".to_owned()))?;
+            let size = || value.parse::<u64>().map_err(|e| invalid(format!("{key}: {e}")));
+            match key {
+                "StorePath" => {
+                    let path = StorePath::from_path(Path::new(value), store_dir)?;
+                    set_once(&mut store_path, path, key, line_no)?;
+                }
+                "URL" => set_once(&mut url, value.to_owned(), key, line_no)?,
+                "Compression" => set_once(&mut compression, value.to_owned(), key, line_no)?,
+                "FileHash" => set_once(&mut file_hash, Sha256Hash::parse(value)?, key, line_no)?,
+                "FileSize" => set_once(&mut file_size, size()?, key, line_no)?,
+                "NarHash" => set_once(&mut nar_hash, Sha256Hash::parse(value)?, key, line_no)?,
+                "NarSize" => set_once(&mut nar_size, size()?, key, line_no)?,
+                "References" => {
+                    let paths: BTreeSet<StorePath> =
+                        value.split_whitespace().map(StorePath::parse).collect::<Result<_, _>>()?;
+                    set_once(&mut references, paths, key, line_no)?;
+                }
+                "Deriver" if value != "unknown-deriver" => {
+                    set_once(&mut deriver, StorePath::parse(value)?, key, line_no)?;
+                }
+                "Sig" => signatures.push(value.to_owned()),
+                "CA" => set_once(&mut ca, value.to_owned(), key, line_no)?,
+                _ => {}
+            }
+        }
+
+        let missing =
+            |key: &str| NixError::InvalidNarInfo { line: 0, reason: format!("{key} is missing") };
+        Ok(Self {
+            store_path: store_path.ok_or_else(|| missing("StorePath"))?,
+            url: url.ok_or_else(|| missing("URL"))?,
+            compression: compression.unwrap_or_else(|| "bzip2".to_owned()),
+            file_hash,
+            file_size,
+            nar_hash: nar_hash.ok_or_else(|| missing("NarHash"))?,
+            nar_size: nar_size.ok_or_else(|| missing("NarSize"))?,
+            references: references.unwrap_or_default(),
+            deriver,
+            signatures,
+            ca,
+        })
+    }
+
+    /// The paths this one refers to, other than itself.
+    pub fn dependencies(&self) -> impl Iterator<Item = &StorePath> {
+        self.references.iter().filter(move |path| **path != self.store_path)
+    }
+}
+
+/// Fill the field for `key`, which must not have been given before.
+fn set_once<T>(slot: &mut Option<T>, value: T, key: &str, line: usize) -> Result<(), NixError> {
+    if slot.is_some() {
+        return Err(NixError::InvalidNarInfo { line, reason: format!("{key} is given twice") });
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = include_str!("../tests/fixtures/sl141d1g77wvhr050ah87lcyz2czdxa3.narinfo");
+
+    fn parse(text: &str) -> Result<NarInfo, NixError> {
+        NarInfo::parse(text)
+    }
+
+    #[test]
+    fn parses_a_narinfo() {
+        let info = parse(HELLO).unwrap_or_else(|e| panic!("narinfo rejected: {e}"));
+        assert_eq!(info.store_path.to_string(), "sl141d1g77wvhr050ah87lcyz2czdxa3-hello-2.12.1");
+        assert_eq!(info.compression, "xz");
+        assert_eq!(info.nar_size, 226_560);
+        assert_eq!(info.file_size, Some(50_264));
+        assert_eq!(
+            info.nar_hash.to_string(),
+            "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        );
+        assert_eq!(info.references.len(), 2);
+        let dependencies: Vec<&str> = info.dependencies().map(StorePath::name).collect();
+        assert_eq!(dependencies, ["glibc-2.37-8"]);
+        assert!(info.deriver.as_ref().is_some_and(StorePath::is_derivation));
+        assert_eq!(info.signatures.len(), 1);
+    }
+
+    #[test]
+    fn hashes_parse_from_nixbase32_and_base16() {
+        let base32 =
+            Sha256Hash::parse("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73");
+        let base16 = Sha256Hash::parse(
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        );
+        assert!(base32.is_ok());
+        assert_eq!(base32.ok(), base16.ok());
+        assert!(Sha256Hash::parse("md5:0mdqa9w1p6cmli6976v4wi0sw9r4").is_err());
+        assert!(Sha256Hash::parse("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4").is_err());
+    }
+
+    #[test]
+    fn malformed_narinfos_are_rejected() {
+        let without_nar_hash =
+            HELLO.lines().filter(|l| !l.starts_with("NarHash")).collect::<Vec<_>>().join("\n");
+        let repeated = format!("{HELLO}URL: nar/other.nar\n");
+        for bad in [
+            without_nar_hash.as_str(),
+            &repeated,
+            &HELLO.replace("NarSize: 226560", "NarSize: lots"),
+            &HELLO.replace("Compression: xz", "Compression xz"),
+        ] {
+            assert!(
+                matches!(parse(bad), Err(NixError::InvalidNarInfo { .. })),
+                "{bad:?} must be rejected"
+            );
+        }
+        let bad_reference = HELLO.replace("-glibc-2.37-8", "_glibc");
+        assert!(matches!(parse(&bad_reference), Err(NixError::InvalidStorePath { .. })));
+    }
+}
@@ -0,0 +1,100 @@
+//! Nix's base-32 encoding.
+//!
+//! Nix prints hashes in base 32 over [`NIXBASE32_ALPHABET`], which omits
+//! `e`, `o`, `t` and `u` to avoid spelling words. Unlike RFC 4648, digits
+//! are emitted from the most significant end of the little-endian bit
+//! string, so the last byte of the input comes first in the output.
+
+use forge_core::id::NIXBASE32_ALPHABET;
+
+use crate::NixError;
+
+/// Characters needed to encode `len` bytes.
+#[must_use]
+pub const fn encoded_len(len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        (len * 8 - 1) / 5 + 1
+    }
+}
+
+/// Encode `bytes` in nixbase32.
+#[must_use]
+pub fn encode(bytes: &[u8]) -> String {
+    (0..encoded_len(bytes.len()))
+        .rev()
+        .map(|n| {
+            let (i, j) = (n * 5 / 8, n * 5 % 8);
+            let low = bytes[i] >> j;
+            let high = bytes.get(i + 1).map_or(0, |&b| if j == 0 { 0 } else { b << (8 - j) });
+            char::from(NIXBASE32_ALPHABET[usize::from((low | high) & 0x1f)])
+        })
+        .collect()
+}
+
+/// Decode nixbase32 `encoded`.
+///
+/// # Errors
+/// Returns [`NixError::InvalidNixBase32`] if `encoded` contains a character
+/// outside the alphabet or encodes bits beyond its byte length.
+pub fn decode(encoded: &str) -> Result<Vec<u8>, NixError> {
+    let invalid = |reason: String| NixError::InvalidNixBase32 { value: encoded.to_owned(), reason };
+    let mut bytes = vec![0u8; encoded.len() * 5 / 8];
+    for (n, c) in encoded.bytes().rev().enumerate() {
+        let digit = NIXBASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| invalid(format!("{:?} is not a nixbase32 character", char::from(c))))?;
+        // `digit` is below 32, so the cast cannot truncate.
+        #[allow(clippy::cast_possible_truncation)]
+        let digit = digit as u16;
+        let (i, j) = (n * 5 / 8, n * 5 % 8);
+        let shifted = digit << j;
+        match bytes.get_mut(i) {
+            Some(byte) => *byte |= shifted.to_le_bytes()[0],
+            None if digit != 0 => return Err(invalid("non-zero padding bits".to_owned())),
+            None => {}
+        }
+        let carry = shifted.to_le_bytes()[1];
+        match bytes.get_mut(i + 1) {
+            Some(byte) => *byte |= carry,
+            None if carry != 0 => return Err(invalid("non-zero padding bits".to_owned())),
+            None => {}
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    #[test]
+    fn sha256_of_empty_input_matches_nix() {
+        // `nix-hash --type sha256 --to-base32 $(sha256sum </dev/null)`
+        let digest = Sha256::digest(b"");
+        assert_eq!(encode(&digest), "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73");
+    }
+
+    #[test]
+    fn decode_inverts_encode() {
+        for len in [0, 1, 5, 20, 32, 64] {
+            let bytes: Vec<u8> =
+                (0u8..).take(len).map(|i| i.wrapping_mul(37).wrapping_add(11)).collect();
+            let encoded = encode(&bytes);
+            assert_eq!(encoded.len(), encoded_len(len));
+            assert_eq!(decode(&encoded).ok(), Some(bytes), "roundtrip of {len} bytes");
+        }
+    }
+
+    #[test]
+    fn decode_rejects_foreign_characters_and_overflow() {
+        assert!(matches!(decode("0e"), Err(NixError::InvalidNixBase32 { .. })));
+        // Two characters carry 10 bits: one byte plus two bits that must be 0.
+        assert!(decode("0z").is_ok());
+        assert!(matches!(decode("zz"), Err(NixError::InvalidNixBase32 { .. })));
+    }
+}
@@ -0,0 +1,1491 @@
+//! Derivation-to-rootfs image builder.
+//!
+//! [`RootfsBuilder`] turns a Nix store path into a bootable root filesystem:
+//!
+//! 1. Realise the path with `nix-store --realise`, building or substituting
+//!    it if needed
+//! 2. Query its runtime closure with `nix-store --query --requisites`
+//! 3. Stage exactly that closure plus a minimal init in a scratch tree
+//! 4. Pack the tree into an ext4 (`mke2fs -d`) or squashfs (`mksquashfs`)
+//!    image, and hash it
+//!
+//! The image contains:
+//!
+//! ```text
+//! /nix/store/<path>   every path in the closure
+//! /bin/<name>         a symlink per executable in the root path's bin/
+//! /bin/sh             a shell from the closure; the executor boots into it
+//! /init               mounts /proc, /sys and /dev, then runs its arguments
+//!                     or /bin/sh
+//! /proc /sys /dev /tmp /etc
+//! ```
+//!
+//! Every entry gets the Nix store's timestamp of 1 and the filesystem is
+//! created with a UUID and hash seed derived from the image's cache key and
+//! a fixed clock, so rebuilding the same closure produces the same image.
+//! Building ext4 images therefore also needs `debugfs`, to reset the inode
+//! change times `mke2fs` copies from the staging tree.
+//!
+//! [`RootfsBuilder::verify`] checks an image against a derivation: the
+//! store paths it holds must be exactly its closure as recorded in binary
+//! cache narinfo files, each with the recorded NAR hash.
+//! [`RootfsBuilder::verify_layered`] does the same for a layered root,
+//! whose closure is split between the root image and its layers. The
+//! executor does not depend on this crate and never runs either check; a
+//! caller that wants only verified images booted must verify an image
+//! before handing it over, then pin it by content hash (import it into
+//! `forge_executor::ImageStore` and boot it via `VmConfig::from_images`),
+//! so the executor refuses an image that changed after it was verified.
+//!
+//! # Caching and layers
+//!
+//! With an [`ImageCache`] attached, images are stored under their
+//! [`closure_hash`] and a closure that was packed before is not packed
+//! again. [`RootfsBuilder::build_layered`] goes further and splits the
+//! closure: the largest store paths each get a read-only layer image of
+//! their own, cached independently, and the root image keeps the rest.
+//! Bumping one dependency then rebuilds the small root image and the
+//! layers that changed, and reuses every other layer. The root image's
+//! init mounts layer `N` from `/dev/vd<b + N>` and overlays them all onto
+//! `/nix/store` before running anything, so the guest sees the full
+//! closure. A command line naming another `init=` bypasses it: run a
+//! command in a layered guest with `init=/init -- <command>`, as
+//! `forge_executor::VmConfig::init_args` does.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+
+use rustix::fs::{AtFlags, Timespec, Timestamps, CWD};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use forge_core::id::{ContentHash, DerivationHash};
+
+use crate::cache::{ImageCache, ImageCacheStats};
+use crate::nar::verify_nar;
+use crate::narinfo::NarInfo;
+use crate::store_path::{StorePath, DEFAULT_STORE_DIR as NIX_STORE_DIR};
+use crate::NixError;
+
+/// Directories searched for tools that are often outside an unprivileged
+/// user's `PATH`.
+const SBIN_DIRS: &[&str] = &["/usr/sbin", "/sbin"];
+
+/// Modification time the Nix store gives every path, applied to every
+/// staged entry.
+const STORE_MTIME: i64 = 1;
+
+/// Slack added to ext4 images for metadata: a quarter of the content.
+const EXT4_SLACK_DIVISOR: u64 = 4;
+
+/// Fixed overhead added to every ext4 image, in KiB.
+const EXT4_BASE_KIB: u64 = 16 * 1024;
+
+/// Per-entry overhead assumed when sizing an ext4 image, in bytes.
+const EXT4_INODE_BYTES: u64 = 4096;
+
+/// Minimal init, run when the kernel command line names no `init=`.
+const INIT_SCRIPT: &str = "#!/bin/sh
+mount -t proc proc /proc 2>/dev/null
+mount -t sysfs sysfs /sys 2>/dev/null
+mount -t devtmpfs devtmpfs /dev 2>/dev/null
+[ \"$#\" -gt 0 ] && exec \"$@\"
+exec /bin/sh
+";
+
+/// Layer images [`RootfsBuilder::build_layered`] makes by default.
+pub const DEFAULT_MAX_LAYERS: usize = 8;
+
+/// Most layer images a root image can mount: `/dev/vdb` to `/dev/vdz`.
+pub const MAX_LAYERS: usize = 25;
+
+/// Hash domains of the cache keys of full images, layers and layered roots.
+const ROOTFS_DOMAIN: &[u8] = b"forge.rootfs.v1\0";
+const LAYER_DOMAIN: &[u8] = b"forge.rootfs-layer.v1\0";
+const LAYERED_DOMAIN: &[u8] = b"forge.rootfs-layered.v1\0";
+
+/// Filesystem the rootfs image is packed into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ImageFormat {
+    /// Writable ext4, built with `mke2fs -d` from e2fsprogs.
+    #[default]
+    Ext4,
+    /// Compressed, read-only squashfs, built with `mksquashfs`.
+    Squashfs,
+}
+
+impl ImageFormat {
+    /// File extension of images in this format.
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Ext4 => "ext4",
+            Self::Squashfs => "squashfs",
+        }
+    }
+}
+
+/// A built rootfs image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RootfsImage {
+    /// Where the image was written; use it as `VmConfig::rootfs_path`.
+    pub path: PathBuf,
+
+    /// SHA-256 of the image file.
+    pub hash: ContentHash,
+
+    /// Filesystem of the image.
+    pub format: ImageFormat,
+
+    /// The realised store path the image was built for.
+    pub root: PathBuf,
+
+    /// Every store path the guest sees, sorted. For a layered image this
+    /// includes the paths held by its layers.
+    pub closure: Vec<PathBuf>,
+
+    /// Key the image is cached under: the [`closure_hash`] for a full
+    /// image; for a layered root it also covers the layers it mounts.
+    pub key: ContentHash,
+
+    /// Whether the image came from the cache rather than being packed.
+    pub cached: bool,
+}
+
+/// A read-only image holding one store path, mounted by a layered root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RootfsLayer {
+    /// Where the image was written; attach it to the VM as a read-only
+    /// drive, in order after the root.
+    pub path: PathBuf,
+
+    /// SHA-256 of the image file.
+    pub hash: ContentHash,
+
+    /// Key the layer is cached under.
+    pub key: ContentHash,
+
+    /// The store path the layer holds.
+    pub store_path: PathBuf,
+
+    /// Whether the layer came from the cache rather than being packed.
+    pub cached: bool,
+}
+
+/// A root image and the layers its init overlays onto `/nix/store`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LayeredRootfs {
+    /// The bootable root image.
+    pub root: RootfsImage,
+
+    /// Layers in the order they must be attached, so layer `N` is
+    /// `/dev/vd<b + N>` in the guest.
+    pub layers: Vec<RootfsLayer>,
+}
+
+impl LayeredRootfs {
+    /// Paths of the layer images, in attachment order; use them as
+    /// `VmConfig::layer_paths`.
+    #[must_use]
+    pub fn layer_paths(&self) -> Vec<PathBuf> {
+        self.layers.iter().map(|layer| layer.path.clone()).collect()
+    }
+}
+
+/// The key a full `format` image of `root`'s `closure` is cached under.
+///
+/// It hashes the format and the base names of the root and of every path
+/// in the closure. Store path names include the hash of everything that
+/// went into them, so equal keys mean identical image contents.
+#[must_use]
+pub fn closure_hash(format: ImageFormat, root: &Path, closure: &[PathBuf]) -> ContentHash {
+    let mut names: Vec<&Path> = closure.iter().map(PathBuf::as_path).collect();
+    names.sort_unstable();
+    image_key(ROOTFS_DOMAIN, format, std::iter::once(root).chain(names))
+}
+
+/// Hash `domain`, `format` and the base name of each of `paths`,
+/// length-prefixed so no two sequences of names collide.
+fn image_key<'a>(
+    domain: &[u8],
+    format: ImageFormat,
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> ContentHash {
+    let mut hasher = Sha256::new();
+    hasher.update(domain);
+    hasher.update(format.extension().as_bytes());
+    for path in paths {
+        let name = path.file_name().unwrap_or(path.as_os_str()).as_encoded_bytes();
+        hasher.update((name.len() as u64).to_be_bytes());
+        hasher.update(name);
+    }
+    ContentHash::new(hasher.finalize().into())
+}
+
+/// What an image is staged from.
+enum Layout {
+    /// A bootable root: `paths` under `/nix/store`, the root's binaries and
+    /// `shell` in `/bin`, and an init mounting `layers` layer images with the
+    /// `mount` binary at that store path.
+    Root {
+        root: PathBuf,
+        paths: Vec<PathBuf>,
+        shell: PathBuf,
+        layers: usize,
+        mount: Option<PathBuf>,
+    },
+    /// A layer: the store path at the top of the image.
+    Layer { path: PathBuf },
+}
+
+/// Builds rootfs images from Nix store paths.
+///
+/// # Example
+/// ```no_run
+/// # async fn build() -> Result<(), forge_nix::NixError> {
+/// use std::path::{Path, PathBuf};
+///
+/// use forge_nix::RootfsBuilder;
+///
+/// let builder = RootfsBuilder::new(PathBuf::from("/var/lib/forge/images"));
+/// let image = builder.build(Path::new("/nix/store/ywi5ib7yrjba3k3b26yfnbx7gappr3dg-git-env")).await?;
+/// println!("{} {}", image.path.display(), image.hash);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RootfsBuilder {
+    out_dir: PathBuf,
+    store_dir: PathBuf,
+    nix_store: PathBuf,
+    format: ImageFormat,
+    cache: Option<Arc<ImageCache>>,
+    max_layers: usize,
+}
+
+impl RootfsBuilder {
+    /// Create a builder writing ext4 images into `out_dir`.
+    #[must_use]
+    pub fn new(out_dir: PathBuf) -> Self {
+        Self {
+            out_dir,
+            store_dir: PathBuf::from(NIX_STORE_DIR),
+            nix_store: PathBuf::from("nix-store"),
+            format: ImageFormat::default(),
+            cache: None,
+            max_layers: DEFAULT_MAX_LAYERS,
+        }
+    }
+
+    /// Store images in `cache` under their key, and reuse images already
+    /// there instead of packing them again.
+    #[must_use]
+    pub fn with_cache(mut self, cache: Arc<ImageCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Give at most `max_layers` store paths a layer of their own in
+    /// [`Self::build_layered`], up to [`MAX_LAYERS`].
+    #[must_use]
+    pub fn with_max_layers(mut self, max_layers: usize) -> Self {
+        self.max_layers = max_layers.min(MAX_LAYERS);
+        self
+    }
+
+    /// Pack images into `format` instead of ext4.
+    #[must_use]
+    pub const fn with_format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Read store paths from `store_dir` instead of `/nix/store`.
+    ///
+    /// Paths are always placed under `/nix/store` inside the image, where
+    /// the binaries in them expect to be.
+    #[must_use]
+    pub fn with_store_dir(mut self, store_dir: PathBuf) -> Self {
+        self.store_dir = store_dir;
+        self
+    }
+
+    /// Run `nix_store` instead of the `nix-store` found in `PATH`.
+    #[must_use]
+    pub fn with_nix_store(mut self, nix_store: PathBuf) -> Self {
+        self.nix_store = nix_store;
+        self
+    }
+
+    /// Directory images are written to.
+    #[must_use]
+    pub fn out_dir(&self) -> &Path {
+        &self.out_dir
+    }
+
+    /// Hits and misses of the attached cache, if there is one.
+    #[must_use]
+    pub fn cache_stats(&self) -> Option<ImageCacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Find the store path whose hash part is `derivation`.
+    ///
+    /// # Errors
+    /// Returns [`NixError::StorePathNotFound`] if no valid store path in
+    /// the store has that hash part, or [`NixError::Io`] if the store cannot
+    /// be listed.
+    pub async fn resolve(&self, derivation: &DerivationHash) -> Result<PathBuf, NixError> {
+        let mut entries = tokio::fs::read_dir(&self.store_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            match StorePath::from_path(&path, &self.store_dir) {
+                Ok(store_path) if store_path.hash_part() == derivation => return Ok(path),
+                _ => {}
+            }
+        }
+        Err(NixError::StorePathNotFound(derivation.to_string()))
+    }
+
+    /// Build an image for the store path whose hash part is `derivation`.
+    ///
+    /// # Errors
+    /// See [`Self::resolve`] and [`Self::build`].
+    pub async fn build_derivation(
+        &self,
+        derivation: &DerivationHash,
+    ) -> Result<RootfsImage, NixError> {
+        let store_path = self.resolve(derivation).await?;
+        self.build(&store_path).await
+    }
+
+    /// Build an image containing the runtime closure of `store_path`.
+    ///
+    /// A `.drv` path is realised first and its first output used as the
+    /// root. Without a cache the image is written to `<out_dir>/<root
+    /// name>.<extension>`, replacing any previous image for the same root;
+    /// with one it is looked up, or stored, under its [`closure_hash`].
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidStorePath`] if `store_path` or a path in
+    /// its closure is not a valid store path in the store directory,
+    /// [`NixError::BinaryNotFound`] if `nix-store` or the image
+    /// tools are not installed, [`NixError::CommandFailed`] if one fails,
+    /// [`NixError::MissingShell`] if the closure provides no `bin/sh`, and
+    /// [`NixError::Io`] if staging or writing the image fails.
+    pub async fn build(&self, store_path: &Path) -> Result<RootfsImage, NixError> {
+        StorePath::from_path(store_path, &self.store_dir)?;
+        let root = self.realise(store_path).await?;
+        let closure = self.closure(&root).await?;
+        let shell = find_provider(&root, &closure, "sh")
+            .ok_or_else(|| NixError::MissingShell { root: root.clone() })?
+            .join("bin/sh");
+        tracing::info!(root = %root.display(), paths = closure.len(), "building rootfs");
+
+        let key = closure_hash(self.format, &root, &closure);
+        let layout = Layout::Root {
+            root: root.clone(),
+            paths: closure.clone(),
+            shell,
+            layers: 0,
+            mount: None,
+        };
+        let (path, hash, cached) = self.produce(&key, &base_name(&root), layout).await?;
+        Ok(RootfsImage { path, hash, format: self.format, root, closure, key, cached })
+    }
+
+    /// Build a root image for `store_path` and layer images for the
+    /// largest paths in its closure, as described in the
+    /// [module docs](self).
+    ///
+    /// The root image always holds the root path and the closures of the
+    /// paths providing `bin/sh` and `bin/mount`, which its init needs
+    /// before the layers are mounted. Of the other paths, the
+    /// `max_layers` largest each get a layer; the rest stay in the root.
+    /// Without a cache, layers are written to `<out_dir>/layer-<key>.<extension>`.
+    ///
+    /// # Errors
+    /// As [`Self::build`], and [`NixError::MissingBinary`] if the closure
+    /// provides no `bin/mount`.
+    pub async fn build_layered(&self, store_path: &Path) -> Result<LayeredRootfs, NixError> {
+        StorePath::from_path(store_path, &self.store_dir)?;
+        let root = self.realise(store_path).await?;
+        let closure = self.closure(&root).await?;
+        let shell = find_provider(&root, &closure, "sh")
+            .ok_or_else(|| NixError::MissingShell { root: root.clone() })?;
+        let mount = find_provider(&root, &closure, "mount").ok_or_else(|| {
+            NixError::MissingBinary { root: root.clone(), binary: "mount".to_owned() }
+        })?;
+
+        let mut pinned = BTreeSet::from([root.clone()]);
+        for provider in [&shell, &mount] {
+            pinned.extend(self.closure(provider).await?);
+        }
+        let candidates: Vec<PathBuf> =
+            closure.iter().filter(|path| !pinned.contains(*path)).cloned().collect();
+        let mut sized = tokio::task::spawn_blocking(move || {
+            candidates
+                .into_iter()
+                .map(|path| Ok((tree_size(&path)?, path)))
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        sized.sort_unstable_by(|(a_size, a), (b_size, b)| {
+            (Reverse(a_size), a).cmp(&(Reverse(b_size), b))
+        });
+        let mut layered: Vec<PathBuf> =
+            sized.into_iter().take(self.max_layers).map(|(_, path)| path).collect();
+        layered.sort();
+        tracing::info!(
+            root = %root.display(),
+            paths = closure.len(),
+            layers = layered.len(),
+            "building layered rootfs"
+        );
+
+        let mut layers = Vec::with_capacity(layered.len());
+        for store_path in layered {
+            let key = image_key(LAYER_DOMAIN, self.format, [store_path.as_path()]);
+            let layout = Layout::Layer { path: store_path.clone() };
+            let (path, hash, cached) = self.produce(&key, &format!("layer-{key}"), layout).await?;
+            layers.push(RootfsLayer { path, hash, key, store_path, cached });
+        }
+
+        let paths: Vec<PathBuf> = closure
+            .iter()
+            .filter(|path| !layers.iter().any(|layer| layer.store_path == **path))
+            .cloned()
+            .collect();
+        let layer_keys: Vec<PathBuf> =
+            layers.iter().map(|layer| PathBuf::from(layer.key.to_string())).collect();
+        let key = image_key(
+            LAYERED_DOMAIN,
+            self.format,
+            std::iter::once(root.as_path())
+                .chain(paths.iter().map(PathBuf::as_path))
+                .chain(layer_keys.iter().map(PathBuf::as_path)),
+        );
+        let layout = Layout::Root {
+            root: root.clone(),
+            paths,
+            shell: shell.join("bin/sh"),
+            layers: layers.len(),
+            mount: Some(mount.join("bin/mount")),
+        };
+        let (path, hash, cached) = self.produce(&key, &base_name(&root), layout).await?;
+        let root = RootfsImage { path, hash, format: self.format, root, closure, key, cached };
+        Ok(LayeredRootfs { root, layers })
+    }
+
+    /// Return the image for `key` from the cache, or stage `layout` and pack
+    /// it, into the cache if there is one and `<out_dir>/<name>.<extension>`
+    /// otherwise. Returns the image's path, its hash and whether it was
+    /// cached.
+    async fn produce(
+        &self,
+        key: &ContentHash,
+        name: &str,
+        layout: Layout,
+    ) -> Result<(PathBuf, ContentHash, bool), NixError> {
+        let path = match &self.cache {
+            Some(cache) => {
+                if let Some(path) = cache.lookup(key, self.format).await? {
+                    let hash = hash_file(path.clone()).await?;
+                    tracing::info!(image = %path.display(), %key, "rootfs cache hit");
+                    return Ok((path, hash, true));
+                }
+                tokio::fs::create_dir_all(cache.dir()).await?;
+                cache.path(key, self.format)
+            }
+            None => self.out_dir.join(format!("{name}.{}", self.format.extension())),
+        };
+
+        tokio::fs::create_dir_all(&self.out_dir).await?;
+        let staging = self.out_dir.join(format!(".staging-{}", Uuid::new_v4()));
+        let partial = path.with_extension(format!("tmp-{}", Uuid::new_v4()));
+
+        let result = self.assemble(key, layout, &staging, &partial).await;
+        remove_staging(&staging).await;
+        if let Err(e) = result {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&partial, &path).await?;
+
+        let hash = hash_file(path.clone()).await?;
+        tracing::info!(image = %path.display(), %hash, "rootfs built");
+        Ok((path, hash, false))
+    }
+
+    /// The runtime closure of `store_path`, sorted.
+    ///
+    /// # Errors
+    /// Returns [`NixError::BinaryNotFound`] if `nix-store` is not installed,
+    /// [`NixError::CommandFailed`] if the query fails, and
+    /// [`NixError::InvalidStorePath`] if it prints anything but store paths
+    /// in the store directory.
+    pub async fn closure(&self, store_path: &Path) -> Result<Vec<PathBuf>, NixError> {
+        let stdout = self
+            .nix_store(&["--query".as_ref(), "--requisites".as_ref(), store_path.as_os_str()])
+            .await?;
+        let mut closure = stdout
+            .lines()
+            .map(|line| {
+                let path = PathBuf::from(line);
+                StorePath::from_path(&path, &self.store_dir).map(|_| path)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        closure.sort();
+        closure.dedup();
+        Ok(closure)
+    }
+
+    /// Check that the image at `image`, in this builder's format, holds
+    /// exactly the runtime closure of `derivation` with the contents the
+    /// binary cache recorded for it.
+    ///
+    /// The closure is read from `narinfo_dir`, which holds a `<hash
+    /// part>.narinfo` per store path as a binary cache lays them out. The
+    /// image's `/nix/store` is extracted to a scratch directory in the
+    /// output directory and every path in it is NAR-hashed.
+    ///
+    /// A layered root's store is split between images, so checking its root
+    /// image alone reports the layered paths missing; use
+    /// [`Self::verify_layered`] for it.
+    ///
+    /// Nothing calls this before a VM boots. Run it on the image before
+    /// booting it, and pin the image by content hash so that what boots is
+    /// what was checked; see the [module docs](self).
+    ///
+    /// # Errors
+    /// Returns [`NixError::ImageMismatch`] if a narinfo is missing or the
+    /// image holds a different set of store paths, and
+    /// [`NixError::NarHashMismatch`] if a path's contents differ from its
+    /// narinfo. Extracting needs `debugfs` for ext4 and `unsquashfs` for
+    /// squashfs images.
+    pub async fn verify(
+        &self,
+        image: &Path,
+        derivation: &DerivationHash,
+        narinfo_dir: &Path,
+    ) -> Result<(), NixError> {
+        self.verify_layered(image, &[], derivation, narinfo_dir).await
+    }
+
+    /// As [`Self::verify`], for the root image at `root` and the layer
+    /// images at `layers`: together they must hold the closure, each path
+    /// in exactly one image.
+    ///
+    /// # Errors
+    /// As [`Self::verify`]. A store path found in more than one image is an
+    /// [`NixError::ImageMismatch`] of the root image.
+    ///
+    /// The executor pins only the root image by hash, not its layers, so a
+    /// layer changed after this check boots unnoticed; keep verified layers
+    /// where nothing else writes them.
+    pub async fn verify_layered(
+        &self,
+        root: &Path,
+        layers: &[PathBuf],
+        derivation: &DerivationHash,
+        narinfo_dir: &Path,
+    ) -> Result<(), NixError> {
+        let closure = closure_from_narinfos(root, derivation, narinfo_dir).await?;
+
+        tokio::fs::create_dir_all(&self.out_dir).await?;
+        let scratch = self.out_dir.join(format!(".verify-{}", Uuid::new_v4()));
+        let result = self.verify_extracted(root, layers, &closure, &scratch).await;
+        remove_staging(&scratch).await;
+        result?;
+        tracing::info!(
+            image = %root.display(),
+            layers = layers.len(),
+            paths = closure.len(),
+            "rootfs verified"
+        );
+        Ok(())
+    }
+
+    async fn verify_extracted(
+        &self,
+        root: &Path,
+        layers: &[PathBuf],
+        closure: &BTreeMap<StorePath, NarInfo>,
+        scratch: &Path,
+    ) -> Result<(), NixError> {
+        let mismatch = |reason: String| NixError::ImageMismatch { image: root.to_owned(), reason };
+
+        let mut stores =
+            vec![extract_dir(self.format, root, NIX_STORE_DIR, &scratch.join("root")).await?];
+        for (index, layer) in layers.iter().enumerate() {
+            stores.push(
+                extract_dir(self.format, layer, "/", &scratch.join(format!("layer{index}")))
+                    .await?,
+            );
+        }
+
+        let mut found = BTreeMap::new();
+        for store in &stores {
+            let mut entries = tokio::fs::read_dir(store).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                // ext4 layer images have a lost+found at the top.
+                if entry.file_name() == "lost+found" {
+                    continue;
+                }
+                let path = StorePath::from_path(&entry.path(), store)
+                    .map_err(|e| mismatch(format!("unexpected store entry: {e}")))?;
+                if !closure.contains_key(&path) {
+                    return Err(mismatch(format!("{path} is not in the closure")));
+                }
+                if found.insert(path.clone(), store).is_some() {
+                    return Err(mismatch(format!("{path} is in more than one image")));
+                }
+            }
+        }
+        if let Some(missing) = closure.keys().find(|path| !found.contains_key(*path)) {
+            return Err(mismatch(format!("{missing} is missing")));
+        }
+
+        for (path, info) in closure {
+            let (tree, info) = (path.to_path(found[path]), info.clone());
+            tokio::task::spawn_blocking(move || verify_nar(&tree, &info))
+                .await
+                .map_err(std::io::Error::other)??;
+        }
+        Ok(())
+    }
+
+    /// Realise `store_path`, returning its first output.
+    async fn realise(&self, store_path: &Path) -> Result<PathBuf, NixError> {
+        let stdout = self.nix_store(&["--realise".as_ref(), store_path.as_os_str()]).await?;
+        let output =
+            stdout.lines().next().map(PathBuf::from).ok_or_else(|| NixError::CommandFailed {
+                command: format!("nix-store --realise {}", store_path.display()),
+                stderr: "no output path printed".to_owned(),
+            })?;
+        StorePath::from_path(&output, &self.store_dir)?;
+        Ok(output)
+    }
+
+    async fn nix_store(&self, args: &[&std::ffi::OsStr]) -> Result<String, NixError> {
+        let binary = find_binary(&self.nix_store)?;
+        let mut command = Command::new(binary);
+        command.args(args);
+        run(command, "nix-store").await
+    }
+
+    /// Stage `layout` in `staging` and pack it into `image`, seeding the
+    /// filesystem from `key`.
+    async fn assemble(
+        &self,
+        key: &ContentHash,
+        layout: Layout,
+        staging: &Path,
+        image: &Path,
+    ) -> Result<(), NixError> {
+        let (store_dir, staging_owned) = (self.store_dir.clone(), staging.to_owned());
+        let content_bytes =
+            tokio::task::spawn_blocking(move || stage(&store_dir, &layout, &staging_owned))
+                .await
+                .map_err(std::io::Error::other)??;
+
+        match self.format {
+            ImageFormat::Ext4 => {
+                let seed = filesystem_uuid(key);
+                let size_kib = ext4_size_kib(content_bytes);
+                let mut command = Command::new(find_binary(Path::new("mke2fs"))?);
+                command
+                    .args(["-q", "-t", "ext4", "-U", &seed, "-E"])
+                    .arg(format!("hash_seed={seed},root_owner=0:0"))
+                    .arg("-d")
+                    .arg(staging)
+                    .arg(image)
+                    .arg(format!("{size_kib}k"))
+                    .env("E2FSPROGS_FAKE_TIME", STORE_MTIME.to_string())
+                    .env("SOURCE_DATE_EPOCH", STORE_MTIME.to_string());
+                run(command, "mke2fs").await?;
+                reset_ctimes(staging, image).await?;
+            }
+            ImageFormat::Squashfs => {
+                let mut command = Command::new(find_binary(Path::new("mksquashfs"))?);
+                command
+                    .arg(staging)
+                    .arg(image)
+                    .args(["-noappend", "-quiet", "-no-progress", "-all-root", "-no-xattrs"])
+                    .args(["-mkfs-time", "1", "-all-time", "1"]);
+                run(command, "mksquashfs").await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `command`, returning its stdout, or its stderr as the error.
+async fn run(mut command: Command, name: &str) -> Result<String, NixError> {
+    let output =
+        command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).output().await?;
+    if !output.status.success() {
+        return Err(NixError::CommandFailed {
+            command: format!("{name} ({})", output.status),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The closure of `derivation` according to the narinfo files in
+/// `narinfo_dir`, keyed by store path.
+async fn closure_from_narinfos(
+    image: &Path,
+    derivation: &DerivationHash,
+    narinfo_dir: &Path,
+) -> Result<BTreeMap<StorePath, NarInfo>, NixError> {
+    let mut closure = BTreeMap::new();
+    let mut pending = vec![derivation.clone()];
+    let mut seen: BTreeSet<DerivationHash> = pending.iter().cloned().collect();
+    while let Some(hash) = pending.pop() {
+        let file = narinfo_dir.join(format!("{hash}.narinfo"));
+        let text = tokio::fs::read_to_string(&file).await.map_err(|e| NixError::ImageMismatch {
+            image: image.to_owned(),
+            reason: format!("no narinfo for {hash}: {e}"),
+        })?;
+        let info = NarInfo::parse(&text)?;
+        if *info.store_path.hash_part() != hash {
+            return Err(NixError::ImageMismatch {
+                image: image.to_owned(),
+                reason: format!("{} describes {}", file.display(), info.store_path),
+            });
+        }
+        for reference in &info.references {
+            if seen.insert(reference.hash_part().clone()) {
+                pending.push(reference.hash_part().clone());
+            }
+        }
+        closure.insert(info.store_path.clone(), info);
+    }
+    Ok(closure)
+}
+
+/// Extract the directory `dir` of the `format` image at `image` into
+/// `scratch`, returning where its contents were written.
+async fn extract_dir(
+    format: ImageFormat,
+    image: &Path,
+    dir: &str,
+    scratch: &Path,
+) -> Result<PathBuf, NixError> {
+    tokio::fs::create_dir_all(scratch).await?;
+    let relative = dir.trim_start_matches('/');
+    match format {
+        ImageFormat::Ext4 => {
+            let output = Command::new(find_binary(Path::new("debugfs"))?)
+                .arg("-R")
+                .arg(format!("rdump {dir} {}", scratch.display()))
+                .arg(image)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .output()
+                .await?;
+            // debugfs exits 0 even when a request fails; failures are
+            // reported on stderr after the version banner.
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if let Some(line) = stderr.lines().find(|line| !line.starts_with("debugfs ")) {
+                return Err(NixError::CommandFailed {
+                    command: format!("debugfs rdump {}", image.display()),
+                    stderr: line.to_owned(),
+                });
+            }
+            // rdump writes a directory under its own name, and the root's
+            // contents straight into `scratch`.
+            Ok(Path::new(relative)
+                .file_name()
+                .map_or_else(|| scratch.to_owned(), |name| scratch.join(name)))
+        }
+        ImageFormat::Squashfs => {
+            let root = scratch.join("root");
+            let mut command = Command::new(find_binary(Path::new("unsquashfs"))?);
+            command.args(["-no-progress", "-quiet", "-d"]).arg(&root).arg(image);
+            if !relative.is_empty() {
+                command.arg(dir);
+            }
+            run(command, "unsquashfs").await?;
+            Ok(root.join(relative))
+        }
+    }
+}
+
+/// Locate `binary`: as given if absolute, otherwise in `PATH` or the sbin
+/// directories.
+fn find_binary(binary: &Path) -> Result<PathBuf, NixError> {
+    let not_found = || NixError::BinaryNotFound { name: binary.display().to_string() };
+    if binary.is_absolute() {
+        return binary.exists().then(|| binary.to_owned()).ok_or_else(not_found);
+    }
+    let path = std::env::var("PATH").unwrap_or_default();
+    path.split(':')
+        .filter(|dir| !dir.is_empty())
+        .chain(SBIN_DIRS.iter().copied())
+        .map(|dir| Path::new(dir).join(binary))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(not_found)
+}
+
+/// The store path providing `bin/<program>`: the root itself, otherwise
+/// the first in the closure.
+fn find_provider(root: &Path, closure: &[PathBuf], program: &str) -> Option<PathBuf> {
+    std::iter::once(root)
+        .chain(closure.iter().map(PathBuf::as_path))
+        .find(|path| path.join("bin").join(program).exists())
+        .map(Path::to_owned)
+}
+
+/// The base name of a store path, used to name images built for it.
+fn base_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| "rootfs".to_owned(), |name| name.to_string_lossy().into_owned())
+}
+
+/// Init for a root image mounting `layers` layer images: as
+/// [`INIT_SCRIPT`], after overlaying the layers onto `/nix/store`.
+///
+/// The kernel starts init without a `PATH`, and `/bin` need not hold
+/// `mount`, so it is called by its path in the image, `mount`.
+fn layered_init(layers: usize, mount: &Path) -> String {
+    let mount = mount.display();
+    let mut script = format!(
+        "#!/bin/sh
+{mount} -t proc proc /proc 2>/dev/null
+{mount} -t sysfs sysfs /sys 2>/dev/null
+{mount} -t devtmpfs devtmpfs /dev 2>/dev/null
+"
+    );
+    let mut lower = String::from(NIX_STORE_DIR);
+    for (layer, device) in (b'b'..=b'z').take(layers).enumerate() {
+        let device = char::from(device);
+        script.push_str(&format!("{mount} -o ro /dev/vd{device} /layers/{layer} || exit 1\n"));
+        lower.push_str(&format!(":/layers/{layer}"));
+    }
+    script.push_str(&format!(
+        "{mount} -t overlay overlay -o lowerdir={lower} {NIX_STORE_DIR} || exit 1
+[ \"$#\" -gt 0 ] && exec \"$@\"
+exec /bin/sh
+"
+    ));
+    script
+}
+
+/// Lay out the image tree for `layout` in `staging`, returning the space
+/// its contents need. Paths in `layout` are in the host store `store_dir`.
+fn stage(store_dir: &Path, layout: &Layout, staging: &Path) -> std::io::Result<u64> {
+    let (root, paths, shell, layers, mount) = match layout {
+        Layout::Layer { path } => {
+            std::fs::create_dir_all(staging)?;
+            let bytes = copy_tree(path, &staging.join(store_name(path)?))?;
+            set_store_mtime(staging)?;
+            return Ok(bytes);
+        }
+        Layout::Root { root, paths, shell, layers, mount } => (root, paths, shell, *layers, mount),
+    };
+
+    let store = staging.join(NIX_STORE_DIR.trim_start_matches('/'));
+    std::fs::create_dir_all(&store)?;
+    let mut bytes = 0;
+    for path in paths {
+        bytes += copy_tree(path, &store.join(store_name(path)?))?;
+    }
+
+    for dir in ["bin", "proc", "sys", "dev", "tmp", "etc"] {
+        std::fs::create_dir_all(staging.join(dir))?;
+    }
+    set_mode(&staging.join("tmp"), 0o1777)?;
+    for layer in 0..layers {
+        std::fs::create_dir_all(staging.join("layers").join(layer.to_string()))?;
+    }
+
+    if let Ok(entries) = std::fs::read_dir(root.join("bin")) {
+        for entry in entries {
+            let entry = entry?;
+            let target = image_path(&entry.path(), store_dir)?;
+            std::os::unix::fs::symlink(target, staging.join("bin").join(entry.file_name()))?;
+        }
+    }
+    let sh = staging.join("bin/sh");
+    if sh.symlink_metadata().is_err() {
+        std::os::unix::fs::symlink(image_path(shell, store_dir)?, sh)?;
+    }
+
+    let init = staging.join("init");
+    match mount {
+        Some(mount) if layers > 0 => {
+            std::fs::write(&init, layered_init(layers, &image_path(mount, store_dir)?))?;
+        }
+        _ => std::fs::write(&init, INIT_SCRIPT)?,
+    }
+    set_mode(&init, 0o755)?;
+
+    set_store_mtime(staging)?;
+    Ok(bytes)
+}
+
+/// The base name of the store path `path`.
+fn store_name(path: &Path) -> std::io::Result<&std::ffi::OsStr> {
+    path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("not a store path: {}", path.display()),
+        )
+    })
+}
+
+/// Where `path`, inside the host store `store_dir`, is found in the image.
+fn image_path(path: &Path, store_dir: &Path) -> std::io::Result<PathBuf> {
+    let relative = path.strip_prefix(store_dir).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not in {}", path.display(), store_dir.display()),
+        )
+    })?;
+    Ok(Path::new(NIX_STORE_DIR).join(relative))
+}
+
+/// Copy `src` to `dest` recursively, keeping symlinks and file modes.
+/// Directories are made owner-writable so the staging tree can be removed.
+/// Returns the space the copy needs in an image: the bytes copied plus
+/// [`EXT4_INODE_BYTES`] per entry.
+fn copy_tree(src: &Path, dest: &Path) -> std::io::Result<u64> {
+    let metadata = src.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(src)?, dest)?;
+        return Ok(EXT4_INODE_BYTES);
+    }
+    if metadata.is_file() {
+        return Ok(std::fs::copy(src, dest)? + EXT4_INODE_BYTES);
+    }
+
+    std::fs::create_dir(dest)?;
+    let mut bytes = EXT4_INODE_BYTES;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        bytes += copy_tree(&entry.path(), &dest.join(entry.file_name()))?;
+    }
+    set_mode(dest, metadata_mode(&metadata) | 0o700)?;
+    Ok(bytes)
+}
+
+/// The space `path` needs in an image, counted as [`copy_tree`] does.
+fn tree_size(path: &Path) -> std::io::Result<u64> {
+    let metadata = path.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len() + EXT4_INODE_BYTES);
+    }
+    let mut bytes = EXT4_INODE_BYTES;
+    for entry in std::fs::read_dir(path)? {
+        bytes += tree_size(&entry?.path())?;
+    }
+    Ok(bytes)
+}
+
+fn metadata_mode(metadata: &std::fs::Metadata) -> u32 {
+    std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777
+}
+
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(mode))
+}
+
+/// Give every entry under `dir`, and `dir` itself, the store mtime.
+/// Children go first so setting a directory's time is the last write to it.
+fn set_store_mtime(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            set_store_mtime(&entry.path())?;
+        } else {
+            touch(&entry.path())?;
+        }
+    }
+    touch(dir)
+}
+
+fn touch(path: &Path) -> std::io::Result<()> {
+    let time = Timespec { tv_sec: STORE_MTIME, tv_nsec: 0 };
+    let times = Timestamps { last_access: time, last_modification: time };
+    rustix::fs::utimensat(CWD, path, &times, AtFlags::SYMLINK_NOFOLLOW)?;
+    Ok(())
+}
+
+/// Set the ctime of every inode in the ext4 `image` built from `staging` to
+/// the store mtime.
+///
+/// `mke2fs -d` copies each source file's ctime, which no userspace call can
+/// set, so without this the image would record when it was staged.
+async fn reset_ctimes(staging: &Path, image: &Path) -> Result<(), NixError> {
+    let staging_owned = staging.to_owned();
+    let entries = tokio::task::spawn_blocking(move || {
+        let mut entries = vec![PathBuf::from("/")];
+        list_tree(&staging_owned, Path::new("/"), &mut entries)?;
+        Ok::<_, std::io::Error>(entries)
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+
+    let mut requests = String::new();
+    for entry in &entries {
+        requests.push_str(&format!("sif \"{}\" ctime {STORE_MTIME}\n", entry.display()));
+    }
+
+    let mut child = Command::new(find_binary(Path::new("debugfs"))?)
+        .args(["-w", "-f", "-"])
+        .arg(image)
+        .env("E2FSPROGS_FAKE_TIME", STORE_MTIME.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(requests.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+
+    // debugfs exits 0 even when a request fails; failures are reported on
+    // stderr after the version banner.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(line) = stderr.lines().find(|line| !line.starts_with("debugfs ")) {
+        return Err(NixError::CommandFailed {
+            command: format!("debugfs -w {}", image.display()),
+            stderr: line.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Append the image path of every entry under `dir`, found at `at` in the
+/// image, to `entries`.
+fn list_tree(dir: &Path, at: &Path, entries: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = at.join(entry.file_name());
+        entries.push(path.clone());
+        if entry.file_type()?.is_dir() {
+            list_tree(&entry.path(), &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a staging tree, logging rather than failing if it cannot be.
+async fn remove_staging(staging: &Path) {
+    if let Err(e) = tokio::fs::remove_dir_all(staging).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!(staging = %staging.display(), error = %e, "failed to remove staging tree");
+        }
+    }
+}
+
+/// A filesystem UUID derived from the image's key, so rebuilds of the same
+/// contents produce the same superblock.
+fn filesystem_uuid(key: &ContentHash) -> String {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&key.as_bytes()[..16]);
+    uuid::Builder::from_random_bytes(bytes).into_uuid().to_string()
+}
+
+/// ext4 image size for contents needing `content_bytes`, in KiB.
+const fn ext4_size_kib(content_bytes: u64) -> u64 {
+    let padded = content_bytes + content_bytes / EXT4_SLACK_DIVISOR;
+    padded.div_ceil(1024) + EXT4_BASE_KIB
+}
+
+/// SHA-256 of the file at `path`, read on the blocking pool.
+async fn hash_file(path: PathBuf) -> std::io::Result<ContentHash> {
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(ContentHash::new(hasher.finalize().into()))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHELL: &str = "ywi5ib7yrjba3k3b26yfnbx7gappr3dg-shell";
+    const HELLO: &str = "3b26yfnbx7gappr3dgywi5ib7yrjba3k-hello";
+
+    /// A fake store holding a shell and a `hello` package depending on it,
+    /// with a scripted `nix-store` that reports each path's closure from
+    /// `closures/<name>`.
+    struct FakeStore {
+        dir: PathBuf,
+    }
+
+    impl FakeStore {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("forge-nix-test-{}", Uuid::new_v4()));
+            let script = "#!/bin/sh\ncase \"$1\" in\n--realise) echo \"$2\" ;;\n--query) cat \"$(dirname \"$0\")/closures/$(basename \"$3\")\" ;;\nesac\n";
+            let nix_store = dir.join("nix-store");
+            if let Err(e) = std::fs::create_dir_all(dir.join("closures"))
+                .and_then(|()| std::fs::write(&nix_store, script))
+                .and_then(|()| set_mode(&nix_store, 0o755))
+            {
+                panic!("script write failed: {e}");
+            }
+            let store = Self { dir };
+            store.add(SHELL, "sh", "#!fake shell\n", &[]);
+            store.add(HELLO, "hello", "#!/bin/sh\necho hello\n", &[SHELL]);
+            store
+        }
+
+        /// Add a package providing `bin/<bin>`, whose closure is itself and
+        /// `dependencies`.
+        fn add(&self, name: &str, bin: &str, contents: &str, dependencies: &[&str]) {
+            let bin_dir = self.path(name).join("bin");
+            if let Err(e) = std::fs::create_dir_all(&bin_dir) {
+                panic!("mkdir failed: {e}");
+            }
+            if let Err(e) = std::fs::write(bin_dir.join(bin), contents) {
+                panic!("write failed: {e}");
+            }
+            let mut closure = String::new();
+            for path in std::iter::once(name).chain(dependencies.iter().copied()) {
+                closure.push_str(&format!("{}\n", self.path(path).display()));
+            }
+            if let Err(e) = std::fs::write(self.dir.join("closures").join(name), closure) {
+                panic!("closure write failed: {e}");
+            }
+        }
+
+        fn builder(&self, out: &str) -> RootfsBuilder {
+            RootfsBuilder::new(self.dir.join(out))
+                .with_store_dir(self.dir.join("store"))
+                .with_nix_store(self.dir.join("nix-store"))
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.join("store").join(name)
+        }
+    }
+
+    impl Drop for FakeStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn debugfs(image: &Path, request: &str) -> Option<String> {
+        let binary = find_binary(Path::new("debugfs")).ok()?;
+        let output = std::process::Command::new(binary)
+            .args(["-R", request])
+            .arg(image)
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires mke2fs"]
+    async fn image_contains_exactly_the_closure_and_init() {
+        let store = FakeStore::new();
+        let image = match store.builder("out").build(&store.path(HELLO)).await {
+            Ok(image) => image,
+            Err(e) => panic!("build failed: {e}"),
+        };
+
+        assert_eq!(image.path, store.dir.join("out").join(format!("{HELLO}.ext4")));
+        assert_eq!(image.closure, [store.path(HELLO), store.path(SHELL)]);
+        let bytes = std::fs::read(&image.path).unwrap_or_else(|e| panic!("read failed: {e}"));
+        assert_eq!(image.hash, ContentHash::new(Sha256::digest(&bytes).into()));
+        let leftovers = std::fs::read_dir(store.dir.join("out")).map_or(0, Iterator::count);
+        assert_eq!(leftovers, 1, "staging tree and partial image must be removed");
+
+        let Some(listing) = debugfs(&image.path, "ls -l /nix/store") else {
+            return;
+        };
+        assert!(listing.contains(HELLO) && listing.contains(SHELL), "closure missing: {listing}");
+        let root = debugfs(&image.path, "ls -l /").unwrap_or_default();
+        assert!(root.contains("init") && root.contains("proc"), "layout missing: {root}");
+        let hello = debugfs(&image.path, "stat /bin/hello").unwrap_or_default();
+        assert!(hello.contains(&format!("/nix/store/{HELLO}/bin/hello")), "bad link: {hello}");
+        let sh = debugfs(&image.path, "stat /bin/sh").unwrap_or_default();
+        assert!(sh.contains(&format!("/nix/store/{SHELL}/bin/sh")), "bad shell link: {sh}");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires mke2fs"]
+    async fn rebuilding_a_closure_is_reproducible() {
+        let store = FakeStore::new();
+        let mut hashes = Vec::new();
+        for out in ["first", "second"] {
+            match store.builder(out).build(&store.path(HELLO)).await {
+                Ok(image) => hashes.push(image.hash),
+                Err(e) => panic!("build failed: {e}"),
+            }
+        }
+        assert_eq!(hashes[0], hashes[1], "the same closure must produce the same image");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn closures_without_a_shell_are_rejected() {
+        let store = FakeStore::new();
+        if let Err(e) = std::fs::remove_file(store.path(SHELL).join("bin/sh")) {
+            panic!("remove failed: {e}");
+        }
+        let result = store.builder("out").build(&store.path(HELLO)).await;
+        assert!(
+            matches!(&result, Err(NixError::MissingShell { root }) if *root == store.path(HELLO)),
+            "a closure without bin/sh must be rejected, got {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn derivation_hashes_resolve_to_store_paths() {
+        let store = FakeStore::new();
+        let builder = store.builder("out");
+        let hash = |hash: &str| {
+            DerivationHash::new(hash).unwrap_or_else(|e| panic!("invalid test hash: {e}"))
+        };
+        let found = builder.resolve(&hash("ywi5ib7yrjba3k3b26yfnbx7gappr3dg")).await;
+        assert_eq!(found.ok(), Some(store.path(SHELL)));
+
+        let missing = builder.resolve(&hash("00000000000000000000000000000000")).await;
+        assert!(matches!(missing, Err(NixError::StorePathNotFound(_))), "got {missing:?}");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn paths_outside_the_store_are_rejected() {
+        let store = FakeStore::new();
+        let builder = store.builder("out");
+        let result = builder.build(&store.dir.join(HELLO)).await;
+        assert!(
+            matches!(result, Err(NixError::InvalidStorePath { .. })),
+            "a path outside the store must be rejected, got {result:?}"
+        );
+    }
+
+    /// Write a narinfo for each of `packages`, a fake store path and the
+    /// paths other than itself it refers to, as a binary cache would serve
+    /// them.
+    fn write_narinfos(store: &FakeStore, packages: &[(&str, &[&str])]) -> PathBuf {
+        let dir = store.dir.join("narinfo");
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            panic!("mkdir failed: {e}");
+        }
+        for &(name, references) in packages {
+            let references = std::iter::once(name)
+                .chain(references.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let digest = crate::nar::nar_hash(&store.path(name))
+                .unwrap_or_else(|e| panic!("nar_hash failed: {e}"));
+            let text = format!(
+                "StorePath: /nix/store/{name}\nURL: nar/{name}.nar\nNarHash: {}\nNarSize: {}\nReferences: {references}\n",
+                digest.hash, digest.size
+            );
+            let file = dir.join(format!("{}.narinfo", &name[..DerivationHash::LEN]));
+            if let Err(e) = std::fs::write(file, text) {
+                panic!("narinfo write failed: {e}");
+            }
+        }
+        dir
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires e2fsprogs"]
+    async fn images_verify_against_their_narinfos() {
+        let store = FakeStore::new();
+        let builder = store.builder("out");
+        let image = match builder.build(&store.path(HELLO)).await {
+            Ok(image) => image,
+            Err(e) => panic!("build failed: {e}"),
+        };
+        let narinfos = write_narinfos(&store, &[(SHELL, &[]), (HELLO, &[SHELL])]);
+        let hello = DerivationHash::new(&HELLO[..DerivationHash::LEN])
+            .unwrap_or_else(|e| panic!("invalid test hash: {e}"));
+
+        let verified = builder.verify(&image.path, &hello, &narinfos).await;
+        assert!(verified.is_ok(), "a faithful image must verify, got {verified:?}");
+        let leftovers = std::fs::read_dir(store.dir.join("out")).map_or(0, Iterator::count);
+        assert_eq!(leftovers, 1, "the extracted store must be removed");
+
+        // The shell changes after the narinfos were published.
+        if let Err(e) = std::fs::write(store.path(SHELL).join("bin/sh"), "#!tampered\n") {
+            panic!("write failed: {e}");
+        }
+        let tampered = match builder.build(&store.path(HELLO)).await {
+            Ok(image) => image,
+            Err(e) => panic!("build failed: {e}"),
+        };
+        let result = builder.verify(&tampered.path, &hello, &narinfos).await;
+        assert!(matches!(result, Err(NixError::NarHashMismatch { .. })), "got {result:?}");
+
+        // A narinfo closure without the shell does not match the image.
+        if let Err(e) = std::fs::remove_file(
+            narinfos.join(format!("{}.narinfo", &SHELL[..DerivationHash::LEN])),
+        ) {
+            panic!("remove failed: {e}");
+        }
+        let result = builder.verify(&tampered.path, &hello, &narinfos).await;
+        assert!(matches!(result, Err(NixError::ImageMismatch { .. })), "got {result:?}");
+    }
+
+    const LIB: &str = "gappr3dgywi5ib7yrjba3k3b26yfnbx7-lib";
+    const APP: &str = "yrjba3k3b26yfnbx7gappr3dgywi5ib7-app";
+    const APP_BUMPED: &str = "fnbx7gappr3dgywi5ib7yrjba3k3b26y-app";
+
+    /// Add a large library and an app using it, with `mount` in the shell.
+    fn add_layered_packages(store: &FakeStore) {
+        store.add(LIB, "lib", &"x".repeat(256 * 1024), &[]);
+        store.add(APP, "app", "#!/bin/sh\necho app\n", &[LIB, SHELL]);
+        if let Err(e) = std::fs::write(store.path(SHELL).join("bin/mount"), "#!fake mount\n") {
+            panic!("write failed: {e}");
+        }
+    }
+
+    #[test]
+    fn closure_hashes_depend_on_format_root_and_paths() {
+        let (root, other) = (PathBuf::from(HELLO), PathBuf::from(SHELL));
+        let closure = [root.clone(), other.clone()];
+        let hash = closure_hash(ImageFormat::Ext4, &root, &closure);
+        let reordered = [other.clone(), root.clone()];
+        assert_eq!(hash, closure_hash(ImageFormat::Ext4, &root, &reordered));
+        assert_ne!(hash, closure_hash(ImageFormat::Squashfs, &root, &closure));
+        assert_ne!(hash, closure_hash(ImageFormat::Ext4, &other, &closure));
+        assert_ne!(hash, closure_hash(ImageFormat::Ext4, &root, &closure[..1]));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires mke2fs"]
+    async fn cached_images_are_not_rebuilt() {
+        let store = FakeStore::new();
+        let cache = Arc::new(ImageCache::new(store.dir.join("cache")));
+        let builder = store.builder("out").with_cache(Arc::clone(&cache));
+        let mut images = Vec::new();
+        for _ in 0..2 {
+            match builder.build(&store.path(HELLO)).await {
+                Ok(image) => images.push(image),
+                Err(e) => panic!("build failed: {e}"),
+            }
+        }
+
+        assert!(!images[0].cached && images[1].cached);
+        assert_eq!(images[0].path, cache.path(&images[0].key, ImageFormat::Ext4));
+        assert_eq!(
+            images[0].key,
+            closure_hash(ImageFormat::Ext4, &images[0].root, &images[0].closure)
+        );
+        assert_eq!(
+            (images[0].path.clone(), images[0].hash),
+            (images[1].path.clone(), images[1].hash)
+        );
+        assert_eq!(builder.cache_stats(), Some(ImageCacheStats { hits: 1, misses: 1 }));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires e2fsprogs"]
+    async fn layers_are_shared_between_roots() {
+        let store = FakeStore::new();
+        add_layered_packages(&store);
+        let cache = Arc::new(ImageCache::new(store.dir.join("cache")));
+        let builder = store.builder("out").with_cache(cache);
+
+        let first = match builder.build_layered(&store.path(APP)).await {
+            Ok(layered) => layered,
+            Err(e) => panic!("build failed: {e}"),
+        };
+        assert_eq!(first.layers.len(), 1, "only the library is neither root nor init");
+        assert_eq!(first.layers[0].store_path, store.path(LIB));
+        assert_eq!(first.root.closure, [store.path(LIB), store.path(APP), store.path(SHELL)]);
+        assert_eq!(first.layer_paths(), [first.layers[0].path.clone()]);
+
+        let root_store = debugfs(&first.root.path, "ls /nix/store").unwrap_or_default();
+        assert!(!root_store.contains(LIB) && root_store.contains(APP), "bad root: {root_store}");
+        let layer = debugfs(&first.layers[0].path, "ls /").unwrap_or_default();
+        assert!(layer.contains(LIB), "bad layer: {layer}");
+        let init = debugfs(&first.root.path, "cat /init").unwrap_or_default();
+        assert!(init.contains("/dev/vdb /layers/0"), "bad init: {init}");
+        assert!(init.contains("lowerdir=/nix/store:/layers/0"), "bad init: {init}");
+        // The kernel gives init no PATH, and /bin does not link mount.
+        let mount = format!("/nix/store/{SHELL}/bin/mount");
+        let calls = init.lines().filter(|line| line.contains("mount")).collect::<Vec<_>>();
+        assert!(calls.iter().all(|line| line.starts_with(&mount)), "bare mount in init: {init}");
+
+        // A new version of the app on the same library reuses its layer.
+        store.add(APP_BUMPED, "app", "#!/bin/sh\necho app 2\n", &[LIB, SHELL]);
+        let bumped = match builder.build_layered(&store.path(APP_BUMPED)).await {
+            Ok(layered) => layered,
+            Err(e) => panic!("build failed: {e}"),
+        };
+        assert!(!bumped.root.cached && bumped.layers[0].cached);
+        assert_eq!(bumped.layers[0].hash, first.layers[0].hash);
+        assert_ne!(bumped.root.key, first.root.key);
+        assert_eq!(builder.cache_stats(), Some(ImageCacheStats { hits: 1, misses: 3 }));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    #[ignore = "requires e2fsprogs"]
+    async fn layered_roots_verify_with_their_layers() {
+        let store = FakeStore::new();
+        add_layered_packages(&store);
+        let builder = store.builder("out");
+        let layered = match builder.build_layered(&store.path(APP)).await {
+            Ok(layered) => layered,
+            Err(e) => panic!("build failed: {e}"),
+        };
+        let narinfos = write_narinfos(&store, &[(SHELL, &[]), (LIB, &[]), (APP, &[LIB, SHELL])]);
+        let app = DerivationHash::new(&APP[..DerivationHash::LEN])
+            .unwrap_or_else(|e| panic!("invalid test hash: {e}"));
+
+        let root = &layered.root.path;
+        let verified = builder.verify_layered(root, &layered.layer_paths(), &app, &narinfos).await;
+        assert!(verified.is_ok(), "a faithful layered root must verify, got {verified:?}");
+
+        let without_layers = builder.verify(root, &app, &narinfos).await;
+        assert!(
+            matches!(&without_layers, Err(NixError::ImageMismatch { reason, .. }) if reason.contains(LIB)),
+            "the root alone lacks the layered library, got {without_layers:?}"
+        );
+        let twice = [layered.layer_paths(), layered.layer_paths()].concat();
+        let duplicated = builder.verify_layered(root, &twice, &app, &narinfos).await;
+        assert!(matches!(duplicated, Err(NixError::ImageMismatch { .. })), "got {duplicated:?}");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn layered_closures_without_mount_are_rejected() {
+        let store = FakeStore::new();
+        let result = store.builder("out").build_layered(&store.path(HELLO)).await;
+        assert!(
+            matches!(&result, Err(NixError::MissingBinary { binary, .. }) if binary == "mount"),
+            "a layered root without mount must be rejected, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn ext4_size_covers_content_and_overhead() {
+        assert_eq!(ext4_size_kib(0), EXT4_BASE_KIB);
+        assert_eq!(ext4_size_kib(4 * 1024 * 1024), 5 * 1024 + EXT4_BASE_KIB);
+    }
+}
@@ -0,0 +1,193 @@
+//! Nix store paths.
+//!
+//! A store path is `<store dir>/<hash part>-<name>`: a 32-character
+//! nixbase32 hash part (a [`DerivationHash`]) and a name of at most
+//! [`MAX_NAME_LEN`] characters from `[A-Za-z0-9+-._?=]`. [`StorePath`] holds
+//! the `<hash part>-<name>` base name; the store directory is supplied when
+//! converting to and from filesystem paths.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use forge_core::id::DerivationHash;
+
+use crate::NixError;
+
+/// Where Nix keeps its store unless configured otherwise.
+pub const DEFAULT_STORE_DIR: &str = "/nix/store";
+
+/// Longest name Nix accepts in a store path.
+pub const MAX_NAME_LEN: usize = 211;
+
+/// A validated Nix store path base name, `<hash part>-<name>`.
+///
+/// Store paths order as their base names do.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StorePath {
+    hash: DerivationHash,
+    name: String,
+}
+
+impl StorePath {
+    /// Combine a hash part and a name.
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidStorePath`] if `name` is empty, too long,
+    /// contains a character Nix forbids, or is `.`/`..`-like.
+    pub fn new(hash: DerivationHash, name: &str) -> Result<Self, NixError> {
+        let invalid =
+            |reason: String| NixError::InvalidStorePath { path: format!("{hash}-{name}"), reason };
+        if name.is_empty() {
+            return Err(invalid("name is empty".to_owned()));
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(invalid(format!("name is longer than {MAX_NAME_LEN} characters")));
+        }
+        if let Some(c) = name.chars().find(|&c| !c.is_ascii_alphanumeric() && !"+-._?=".contains(c))
+        {
+            return Err(invalid(format!("{c:?} is not allowed in a name")));
+        }
+        if name == "." || name == ".." || name.starts_with(".-") || name.starts_with("..-") {
+            return Err(invalid("name must not be `.` or `..`".to_owned()));
+        }
+        Ok(Self { hash, name: name.to_owned() })
+    }
+
+    /// Parse a `<hash part>-<name>` base name.
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidStorePath`] if the hash part or name is
+    /// malformed.
+    pub fn parse(base_name: &str) -> Result<Self, NixError> {
+        let invalid =
+            |reason: String| NixError::InvalidStorePath { path: base_name.to_owned(), reason };
+        let (hash, name) = base_name
+            .split_at_checked(DerivationHash::LEN)
+            .ok_or_else(|| invalid("shorter than a hash part".to_owned()))?;
+        let name = name
+            .strip_prefix('-')
+            .ok_or_else(|| invalid("hash part is not followed by `-`".to_owned()))?;
+        let hash = DerivationHash::new(hash).map_err(|e| invalid(e.to_string()))?;
+        Self::new(hash, name)
+    }
+
+    /// Parse the filesystem path of a store entry in `store_dir`.
+    ///
+    /// # Errors
+    /// Returns [`NixError::InvalidStorePath`] unless `path` is a direct
+    /// child of `store_dir` with a valid base name.
+    pub fn from_path(path: &Path, store_dir: &Path) -> Result<Self, NixError> {
+        let invalid = |reason: String| NixError::InvalidStorePath {
+            path: path.display().to_string(),
+            reason,
+        };
+        if path.parent() != Some(store_dir) {
+            return Err(invalid(format!("not an entry of {}", store_dir.display())));
+        }
+        let base_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid("base name is not UTF-8".to_owned()))?;
+        Self::parse(base_name)
+    }
+
+    /// The hash part.
+    #[must_use]
+    pub const fn hash_part(&self) -> &DerivationHash {
+        &self.hash
+    }
+
+    /// The name following the hash part.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is a derivation (`.drv`) rather than a build output.
+    #[must_use]
+    pub fn is_derivation(&self) -> bool {
+        // Nix only treats the lowercase extension as a derivation.
+        #[allow(clippy::case_sensitive_file_extension_comparisons)]
+        self.name.ends_with(".drv")
+    }
+
+    /// Where the path is found in `store_dir`.
+    #[must_use]
+    pub fn to_path(&self, store_dir: &Path) -> PathBuf {
+        store_dir.join(self.to_string())
+    }
+}
+
+impl FromStr for StorePath {
+    type Err = NixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for StorePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.hash, self.name)
+    }
+}
+
+impl From<StorePath> for DerivationHash {
+    fn from(path: StorePath) -> Self {
+        path.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIT: &str = "ywi5ib7yrjba3k3b26yfnbx7gappr3dg-git-2.43.0";
+
+    #[test]
+    fn parses_and_converts_between_forms() {
+        let path = StorePath::parse(GIT).unwrap_or_else(|e| panic!("rejected: {e}"));
+        assert_eq!(path.hash_part().as_str(), "ywi5ib7yrjba3k3b26yfnbx7gappr3dg");
+        assert_eq!(path.name(), "git-2.43.0");
+        assert!(!path.is_derivation());
+        assert_eq!(path.to_string(), GIT);
+
+        let store = Path::new(DEFAULT_STORE_DIR);
+        let full = path.to_path(store);
+        assert_eq!(full, Path::new("/nix/store").join(GIT));
+        assert_eq!(StorePath::from_path(&full, store).ok(), Some(path.clone()));
+        let hash: DerivationHash = path.into();
+        assert_eq!(hash.as_str(), "ywi5ib7yrjba3k3b26yfnbx7gappr3dg");
+    }
+
+    #[test]
+    fn malformed_paths_are_rejected() {
+        for bad in [
+            "ywi5ib7yrjba3k3b26yfnbx7gappr3dg",
+            "ywi5ib7yrjba3k3b26yfnbx7gappr3dg-",
+            "ywi5ib7yrjba3k3b26yfnbx7gappr3de-git",
+            "ywi5ib7yrjba3k3b26yfnbx7gappr3dg_git",
+            "ywi5ib7yrjba3k3b26yfnbx7gappr3dg-git env",
+            "ywi5ib7yrjba3k3b26yfnbx7gappr3dg-..",
+            "short-git",
+        ] {
+            assert!(
+                matches!(StorePath::parse(bad), Err(NixError::InvalidStorePath { .. })),
+                "{bad:?} must be rejected"
+            );
+        }
+        let long = format!("ywi5ib7yrjba3k3b26yfnbx7gappr3dg-{}", "a".repeat(MAX_NAME_LEN + 1));
+        assert!(StorePath::parse(&long).is_err());
+    }
+
+    #[test]
+    fn from_path_requires_a_direct_store_entry() {
+        let store = Path::new(DEFAULT_STORE_DIR);
+        let nested = store.join(GIT).join("bin/git");
+        assert!(StorePath::from_path(&nested, store).is_err());
+        assert!(StorePath::from_path(&Path::new("/tmp").join(GIT), store).is_err());
+        let drv = store.join("ywi5ib7yrjba3k3b26yfnbx7gappr3dg-git-2.43.0.drv");
+        assert!(StorePath::from_path(&drv, store).is_ok_and(|p| p.is_derivation()));
+    }
+}